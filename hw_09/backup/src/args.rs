@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+/// Structure representing program arguments.
+///
+/// # Fields
+///
+/// * `database_url` - database connection string (default = `DATABASE_URL` environment variable)
+/// * `command` - `export` or `import`
+///
+/// # Example
+///
+/// ```
+/// use clap::Parser;
+/// use backup::args::{Args, Command};
+///
+/// let args = Args::parse_from(["backup", "import", "chat.json", "--dry-run"]);
+///
+/// match args.command {
+///     Command::Import { archive, dry_run } => {
+///         assert_eq!(archive.to_str(), Some("chat.json"));
+///         assert!(dry_run);
+///     }
+///     command => panic!("unexpected command: {:?}", command),
+/// }
+/// ```
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Backs up and restores chat data", long_about = None)]
+pub struct Args {
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true, global = true)]
+    pub database_url: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Writes users and messages to archive
+    Export {
+        /// File that archive is written to (default = standard output)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Restores archive into empty database
+    Import {
+        /// File with archive written by `export`
+        archive: PathBuf,
+
+        /// Only reports conflicts, nothing is imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
@@ -0,0 +1,340 @@
+//! Backup of chat data and its restoration into empty database.
+//!
+//! Archive contains users (with password hashes, colors, roles and disabled flags) and messages
+//! with their original times. Ids are not kept, users get new ids when archive is imported and
+//! authors of messages are remapped to them. Sessions, sanctions, public keys and direct messages
+//! are not part of archive (users log in again and publish their keys again).
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use database::store::{ChatStore, ImportedMessage, ImportedUser, MessageFilter, StoreError};
+use libs::role::Role;
+
+/// Program arguments
+pub mod args;
+
+/// Version of archives written by `export`, `import` accepts only this version.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("Archive version {0} is not supported (supported version is {ARCHIVE_VERSION})")]
+    Version(u32),
+    #[error("Archive can not be imported, {} conflicts found", .0.len())]
+    Conflicts(Vec<Conflict>),
+}
+
+/// Chat data written by `export`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Archive {
+    pub version: u32,
+    pub created_at: SystemTime,
+    /// Users ordered by id.
+    pub users: Vec<ArchivedUser>,
+    /// Messages in order in which they were sent.
+    pub messages: Vec<ArchivedMessage>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivedUser {
+    /// Id in exported database, it is used only by messages in archive.
+    pub id: i32,
+    pub username: String,
+    pub password_hash: String,
+    /// `None` when user did not choose color (default color of the server is used for him).
+    pub color: Option<(u8, u8, u8)>,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ArchivedMessage {
+    /// Id in exported database.
+    pub id: i32,
+    /// Id of author in archive, `None` when author deleted his account.
+    pub user_id: Option<i32>,
+    pub text: String,
+    pub created_at: SystemTime,
+}
+
+/// Reason why archive can not be imported into database.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+    /// Database already contains users
+    ExistingUsers(usize),
+    /// Database already contains messages
+    ExistingMessages,
+    /// User with the same username (case of letters does not matter) is in database
+    UsernameTaken(String),
+    /// Username is in archive more than once (case of letters does not matter)
+    DuplicateUsername(String),
+    /// Message has author that is not in archive
+    UnknownAuthor { message_id: i32, user_id: i32 },
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::ExistingUsers(count) => {
+                write!(f, "database already contains {} users", count)
+            }
+            Conflict::ExistingMessages => write!(f, "database already contains messages"),
+            Conflict::UsernameTaken(username) => {
+                write!(f, "username '{}' is already taken in database", username)
+            }
+            Conflict::DuplicateUsername(username) => {
+                write!(f, "username '{}' is in archive more than once", username)
+            }
+            Conflict::UnknownAuthor {
+                message_id,
+                user_id,
+            } => write!(
+                f,
+                "author {} of message {} is not in archive",
+                user_id, message_id
+            ),
+        }
+    }
+}
+
+/// Numbers of imported records.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub users: usize,
+    pub messages: usize,
+}
+
+/// Reads all users and messages from `store` into archive.
+///
+/// # Errors
+///
+/// Returns error when can't read users, colors or messages from `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use backup::{export, ARCHIVE_VERSION};
+///
+/// let store = MemoryStore::default();
+/// let alice = store.register("alice", "password", (255, 0, 0)).unwrap();
+/// store.insert_message(alice.id, "Hello").unwrap();
+///
+/// let archive = export(&store).unwrap();
+///
+/// assert_eq!(archive.version, ARCHIVE_VERSION);
+/// assert_eq!(archive.users[0].username, "alice");
+/// assert_eq!(archive.users[0].password_hash, alice.password);
+/// assert_eq!(archive.messages[0].user_id, Some(alice.id));
+/// ```
+pub fn export(store: &dyn ChatStore) -> Result<Archive, StoreError> {
+    let mut users = vec![];
+
+    for user in store.users()? {
+        users.push(ArchivedUser {
+            id: user.id,
+            color: store.user_color(&user)?,
+            role: user.role(),
+            disabled: user.disabled,
+            username: user.username,
+            password_hash: user.password,
+        });
+    }
+
+    let messages = store
+        .messages_matching(&MessageFilter::default())?
+        .into_iter()
+        .map(|message| ArchivedMessage {
+            id: message.id,
+            user_id: message.user_id,
+            text: message.text,
+            created_at: message.created_at,
+        })
+        .collect();
+
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        created_at: SystemTime::now(),
+        users,
+        messages,
+    })
+}
+
+/// Returns conflicts that prevent import of archive into `store`, nothing is written. Archive can
+/// be imported only into empty database.
+///
+/// # Errors
+///
+/// Returns error when archive has unsupported version or can't read users or messages from
+/// `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use backup::{check, export, Conflict};
+///
+/// let source = MemoryStore::default();
+/// source.register("alice", "password", (255, 0, 0)).unwrap();
+/// let archive = export(&source).unwrap();
+///
+/// assert!(check(&MemoryStore::default(), &archive).unwrap().is_empty());
+///
+/// let target = MemoryStore::default();
+/// target.register("Alice", "password", (0, 0, 255)).unwrap();
+///
+/// assert_eq!(
+///     check(&target, &archive).unwrap(),
+///     vec![Conflict::ExistingUsers(1), Conflict::UsernameTaken("alice".to_string())]
+/// );
+/// ```
+pub fn check(store: &dyn ChatStore, archive: &Archive) -> Result<Vec<Conflict>, BackupError> {
+    if archive.version != ARCHIVE_VERSION {
+        return Err(BackupError::Version(archive.version));
+    }
+
+    let mut conflicts = vec![];
+
+    let existing_users = store.users()?;
+
+    if !existing_users.is_empty() {
+        conflicts.push(Conflict::ExistingUsers(existing_users.len()));
+    }
+
+    if !store.messages(None, 1)?.is_empty() {
+        conflicts.push(Conflict::ExistingMessages);
+    }
+
+    // usernames are unique regardless of case of letters
+    let taken: HashSet<String> = existing_users
+        .iter()
+        .map(|user| user.username.to_lowercase())
+        .collect();
+    let mut archived: HashSet<String> = HashSet::new();
+
+    for user in &archive.users {
+        let username = user.username.to_lowercase();
+
+        if taken.contains(&username) {
+            conflicts.push(Conflict::UsernameTaken(user.username.clone()));
+        }
+
+        if !archived.insert(username) {
+            conflicts.push(Conflict::DuplicateUsername(user.username.clone()));
+        }
+    }
+
+    let user_ids: HashSet<i32> = archive.users.iter().map(|user| user.id).collect();
+
+    for message in &archive.messages {
+        match message.user_id {
+            Some(user_id) if !user_ids.contains(&user_id) => {
+                conflicts.push(Conflict::UnknownAuthor {
+                    message_id: message.id,
+                    user_id,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Imports archive into empty `store`, users get new ids and messages keep their authors and
+/// times. Everything is imported at once (see `ChatStore::import`), so import that fails leaves
+/// `store` unchanged.
+///
+/// # Errors
+///
+/// Returns `BackupError::Conflicts` without writing anything when `check` finds conflicts, other
+/// errors when archive has unsupported version or records can't be written to `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use libs::role::Role;
+/// use backup::{export, import, ImportSummary};
+///
+/// let source = MemoryStore::default();
+/// let alice = source.register("alice", "password", (255, 0, 0)).unwrap();
+/// source.set_user_role(alice.id, Role::Moderator).unwrap();
+/// source.insert_message(alice.id, "Hello").unwrap();
+/// let archive = export(&source).unwrap();
+///
+/// let target = MemoryStore::default();
+/// // ids in target differ from ids in source
+/// let bob = target.register("bob", "password", (0, 0, 255)).unwrap();
+/// target.delete_user(bob.id).unwrap();
+///
+/// assert_eq!(import(&target, &archive).unwrap(), ImportSummary { users: 1, messages: 1 });
+///
+/// // password hash is kept, so password stays the same
+/// let restored = target.login("alice", "password").unwrap().unwrap();
+/// assert_ne!(restored.id, alice.id);
+/// assert_eq!(restored.role(), Role::Moderator);
+/// assert_eq!(target.user_color(&restored).unwrap(), Some((255, 0, 0)));
+///
+/// let messages = target.recent_messages(10).unwrap();
+/// assert_eq!(messages[0].user_id, Some(restored.id));
+/// assert_eq!(messages[0].created_at, archive.messages[0].created_at);
+///
+/// // second import is refused
+/// assert!(import(&target, &archive).is_err());
+/// ```
+pub fn import(store: &dyn ChatStore, archive: &Archive) -> Result<ImportSummary, BackupError> {
+    let conflicts = check(store, archive)?;
+
+    if !conflicts.is_empty() {
+        return Err(BackupError::Conflicts(conflicts));
+    }
+
+    // id in archive -> index in imported users
+    let authors: HashMap<i32, usize> = archive
+        .users
+        .iter()
+        .enumerate()
+        .map(|(index, user)| (user.id, index))
+        .collect();
+
+    let users: Vec<ImportedUser> = archive
+        .users
+        .iter()
+        .map(|user| ImportedUser {
+            username: user.username.clone(),
+            password_hash: user.password_hash.clone(),
+            color: user.color,
+            role: user.role,
+            disabled: user.disabled,
+        })
+        .collect();
+
+    let messages: Vec<ImportedMessage> = archive
+        .messages
+        .iter()
+        .map(|message| ImportedMessage {
+            author: message
+                .user_id
+                .and_then(|user_id| authors.get(&user_id).copied()),
+            text: message.text.clone(),
+            created_at: message.created_at,
+        })
+        .collect();
+
+    store.import(&users, &messages)?;
+
+    Ok(ImportSummary {
+        users: users.len(),
+        messages: messages.len(),
+    })
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use backup::{
+    args::{Args, Command},
+    check, export, import, BackupError,
+};
+use database::store::postgres::PgStore;
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let store = match &args.database_url {
+        Some(url) => PgStore::connect(url)?,
+        None => PgStore::connect_from_env()?,
+    };
+
+    match args.command {
+        Command::Export { output } => {
+            let archive = export(&store)?;
+
+            let mut writer: Box<dyn Write> = match &output {
+                Some(path) => {
+                    Box::new(BufWriter::new(File::create(path).with_context(|| {
+                        format!("Could not create {}", path.display())
+                    })?))
+                }
+                None => Box::new(BufWriter::new(io::stdout().lock())),
+            };
+
+            serde_json::to_writer(&mut writer, &archive)?;
+            writer.flush()?;
+
+            eprintln!(
+                "{} users and {} messages exported.",
+                archive.users.len(),
+                archive.messages.len()
+            );
+        }
+        Command::Import { archive, dry_run } => {
+            let file = File::open(&archive)
+                .with_context(|| format!("Could not open {}", archive.display()))?;
+            let archive =
+                serde_json::from_reader(BufReader::new(file)).context("Could not read archive")?;
+
+            if dry_run {
+                let conflicts = check(&store, &archive)?;
+
+                for conflict in &conflicts {
+                    eprintln!("Conflict: {}.", conflict);
+                }
+
+                if !conflicts.is_empty() {
+                    bail!(
+                        "{} conflicts found, archive can not be imported",
+                        conflicts.len()
+                    );
+                }
+
+                eprintln!(
+                    "No conflicts found, {} users and {} messages can be imported.",
+                    archive.users.len(),
+                    archive.messages.len()
+                );
+
+                return Ok(());
+            }
+
+            match import(&store, &archive) {
+                Ok(summary) => eprintln!(
+                    "{} users and {} messages imported.",
+                    summary.users, summary.messages
+                ),
+                Err(BackupError::Conflicts(conflicts)) => {
+                    for conflict in &conflicts {
+                        eprintln!("Conflict: {}.", conflict);
+                    }
+
+                    bail!("{} conflicts found, nothing was imported", conflicts.len());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Archive written by `export` is restored by `import` without changes.
+
+use std::time::{Duration, SystemTime};
+
+use backup::{export, import, Archive, ImportSummary};
+use database::store::{memory::MemoryStore, ChatStore, ImportedMessage, ImportedUser};
+use libs::role::Role;
+
+#[test]
+fn export_import_export_gives_same_archive() {
+    let source = MemoryStore::default();
+    let sent_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    // source is filled by import too, so records get the same ids in both stores
+    source
+        .import(
+            &[
+                ImportedUser {
+                    username: "alice".to_string(),
+                    password_hash: "<alice hash>".to_string(),
+                    color: Some((255, 0, 0)),
+                    role: Role::Moderator,
+                    disabled: false,
+                },
+                ImportedUser {
+                    username: "bob".to_string(),
+                    password_hash: "<bob hash>".to_string(),
+                    color: None,
+                    role: Role::Member,
+                    disabled: true,
+                },
+            ],
+            &[
+                ImportedMessage {
+                    author: Some(0),
+                    text: "Hello".to_string(),
+                    created_at: sent_at,
+                },
+                ImportedMessage {
+                    author: Some(1),
+                    text: "Hi".to_string(),
+                    created_at: sent_at + Duration::from_secs(1),
+                },
+                ImportedMessage {
+                    author: None,
+                    text: "Bye".to_string(),
+                    created_at: sent_at + Duration::from_secs(2),
+                },
+            ],
+        )
+        .unwrap();
+
+    let archive = export(&source).unwrap();
+
+    // user without color stays without it
+    assert_eq!(archive.users[1].color, None);
+
+    let target = MemoryStore::default();
+
+    assert_eq!(
+        import(&target, &archive).unwrap(),
+        ImportSummary {
+            users: 2,
+            messages: 3
+        }
+    );
+
+    let restored = export(&target).unwrap();
+
+    assert_eq!(
+        Archive {
+            created_at: archive.created_at,
+            ..restored
+        },
+        archive
+    );
+}
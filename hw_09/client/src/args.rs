@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
 #[derive(Parser)]
@@ -8,4 +10,12 @@ pub struct Args {
 
     #[arg(long, default_value = "localhost")]
     pub hostname: String,
+
+    /// File with cached session tokens (default `$HOME/.chat_tokens`)
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
+
+    /// File with keys for direct messages (default `$HOME/.chat_keys`)
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
 }
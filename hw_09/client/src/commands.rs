@@ -1,10 +1,12 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
 use regex::Regex;
 
+use libs::role::Role;
+
 use crate::errors::FromStrError;
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum CommandType {
     File(String),
     Image(String),
@@ -12,6 +14,98 @@ pub enum CommandType {
     Quit,
     Username(String),
     Color((u8, u8, u8)),
+    Status(String),
+    Who,
+    PasswordChange(String, String, String),
+    DeleteAccount(String),
+    Logout,
+    Sessions,
+    Revoke(i32),
+    Role(String, Role),
+    Kick(String),
+    Ban(String, Option<Duration>, String),
+    Unban(String),
+    Mute(String, Option<Duration>, String),
+    Unmute(String),
+    DeleteMessages(String, u32),
+    DirectMessage(String, String),
+    Fingerprint(Option<String>),
+    Trust(String),
+}
+
+/// Commands with passwords in arguments, their arguments are never shown or sent as text.
+const PASSWORD_COMMANDS: [&str; 2] = [".passwd", ".delete-account"];
+
+/// Returns arguments of command when input starts with it.
+fn command_arguments<'a>(string: &'a str, command: &str) -> Option<&'a str> {
+    let arguments = string.trim_end().strip_prefix(command)?;
+
+    match arguments.is_empty() || arguments.starts_with(' ') {
+        true => Some(arguments.trim_start()),
+        false => None,
+    }
+}
+
+/// Returns username that is only argument of command.
+fn username_argument(arguments: &str, command: &str) -> Result<String, FromStrError> {
+    match arguments.is_empty() || arguments.contains(' ') {
+        true => Err(FromStrError::Usage(format!("{} <username>", command))),
+        false => Ok(arguments.to_string()),
+    }
+}
+
+/// Parses duration with unit, for example `30s`, `10m`, `2h` or `7d`.
+fn parse_duration(string: &str) -> Option<Duration> {
+    let unit = string.chars().last()?;
+    let value = string[..string.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .ok()?;
+
+    let seconds = match unit {
+        's' => value,
+        'm' => value.checked_mul(60)?,
+        'h' => value.checked_mul(60 * 60)?,
+        'd' => value.checked_mul(24 * 60 * 60)?,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(seconds))
+}
+
+/// Returns username, optional duration and reason (can be empty) of ban or mute command.
+fn sanction_arguments(
+    arguments: &str,
+    command: &str,
+) -> Result<(String, Option<Duration>, String), FromStrError> {
+    let (username, rest) = arguments.split_once(' ').unwrap_or((arguments, ""));
+
+    if username.is_empty() {
+        return Err(FromStrError::Usage(format!(
+            "{} <username> [duration like 30m, 2h or 7d] [reason]",
+            command
+        )));
+    }
+
+    // sanction without duration is permanent
+    let (duration, reason) = match rest.split_once(' ').unwrap_or((rest, "")) {
+        (duration, reason) if parse_duration(duration).is_some() => {
+            (parse_duration(duration), reason.trim())
+        }
+        _ => (None, rest.trim()),
+    };
+
+    Ok((username.to_string(), duration, reason.to_string()))
+}
+
+/// Returns input with passwords replaced by asterisks, so it can be printed.
+pub fn mask_passwords(input: &str) -> String {
+    for command in PASSWORD_COMMANDS {
+        if command_arguments(input, command).is_some() {
+            return format!("{} ***", command);
+        }
+    }
+
+    input.to_string()
 }
 
 impl FromStr for CommandType {
@@ -22,11 +116,138 @@ impl FromStr for CommandType {
         // - .file <filename>
         // - .image <filename>
         // - .username <new username>
+        // - .status <online|away|busy|custom status>
+        // - .who
         // - .quit
         // - .color <r> <g> <b>
+        // - .passwd <old password> <new password> <new password>
+        // - .delete-account <password>
+        // - .logout
+        // - .sessions
+        // - .revoke <session id>
+        // - .role <username> <admin|moderator|member|guest>
+        // - .kick <username>
+        // - .ban <username> [duration] [reason]
+        // - .unban <username>
+        // - .mute <username> [duration] [reason]
+        // - .unmute <username>
+        // - .delete-messages <username> <count>
+        // - .dm <username> <text>
+        // - .fingerprint [username]
+        // - .trust <username>
         // - <other text is send as message>
 
-        let regex_expr = r"((?<cmd>.file|.image|.username) (?<name>.+)|(?<quit>.quit)|(?<color>.color (?<r>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)) (?<g>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)) (?<b>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)))|(?<text>.+))";
+        // commands with passwords are parsed by hand, invalid one must not be sent as text message
+        if let Some(arguments) = command_arguments(string, ".passwd") {
+            return match arguments.split(' ').collect::<Vec<&str>>()[..] {
+                [old, new, renew] if !old.is_empty() && !new.is_empty() => {
+                    Ok(CommandType::PasswordChange(
+                        old.to_string(),
+                        new.to_string(),
+                        renew.to_string(),
+                    ))
+                }
+                _ => Err(FromStrError::Usage(
+                    ".passwd <old password> <new password> <new password>".to_string(),
+                )),
+            };
+        }
+
+        if let Some(password) = command_arguments(string, ".delete-account") {
+            return match password.is_empty() {
+                true => Err(FromStrError::Usage(
+                    ".delete-account <password>".to_string(),
+                )),
+                false => Ok(CommandType::DeleteAccount(password.to_string())),
+            };
+        }
+
+        // session commands are parsed by hand too, so they are not sent as text by mistake
+        if command_arguments(string, ".logout") == Some("") {
+            return Ok(CommandType::Logout);
+        }
+
+        if command_arguments(string, ".sessions") == Some("") {
+            return Ok(CommandType::Sessions);
+        }
+
+        if let Some(session_id) = command_arguments(string, ".revoke") {
+            return match session_id.parse::<i32>() {
+                Ok(session_id) => Ok(CommandType::Revoke(session_id)),
+                Err(_) => Err(FromStrError::Usage(".revoke <session id>".to_string())),
+            };
+        }
+
+        // moderation commands
+        if let Some(arguments) = command_arguments(string, ".role") {
+            let usage = || {
+                FromStrError::Usage(".role <username> <admin|moderator|member|guest>".to_string())
+            };
+
+            let (username, role) = arguments.split_once(' ').ok_or_else(usage)?;
+            let role = role.parse::<Role>().map_err(|_| usage())?;
+
+            return Ok(CommandType::Role(username.to_string(), role));
+        }
+
+        if let Some(username) = command_arguments(string, ".kick") {
+            return Ok(CommandType::Kick(username_argument(username, ".kick")?));
+        }
+
+        if let Some(arguments) = command_arguments(string, ".ban") {
+            let (username, duration, reason) = sanction_arguments(arguments, ".ban")?;
+
+            return Ok(CommandType::Ban(username, duration, reason));
+        }
+
+        if let Some(username) = command_arguments(string, ".unban") {
+            return Ok(CommandType::Unban(username_argument(username, ".unban")?));
+        }
+
+        if let Some(arguments) = command_arguments(string, ".mute") {
+            let (username, duration, reason) = sanction_arguments(arguments, ".mute")?;
+
+            return Ok(CommandType::Mute(username, duration, reason));
+        }
+
+        if let Some(username) = command_arguments(string, ".unmute") {
+            return Ok(CommandType::Unmute(username_argument(username, ".unmute")?));
+        }
+
+        if let Some(arguments) = command_arguments(string, ".delete-messages") {
+            let usage = || FromStrError::Usage(".delete-messages <username> <count>".to_string());
+
+            let (username, count) = arguments.split_once(' ').ok_or_else(usage)?;
+            let count = count.parse::<u32>().map_err(|_| usage())?;
+
+            return Ok(CommandType::DeleteMessages(username.to_string(), count));
+        }
+
+        // direct messages
+        if let Some(arguments) = command_arguments(string, ".dm") {
+            return match arguments.split_once(' ') {
+                Some((username, text)) if !text.trim().is_empty() => Ok(
+                    CommandType::DirectMessage(username.to_string(), text.trim().to_string()),
+                ),
+                _ => Err(FromStrError::Usage(".dm <username> <text>".to_string())),
+            };
+        }
+
+        if let Some(username) = command_arguments(string, ".fingerprint") {
+            return match username.is_empty() {
+                true => Ok(CommandType::Fingerprint(None)),
+                false => Ok(CommandType::Fingerprint(Some(username_argument(
+                    username,
+                    ".fingerprint",
+                )?))),
+            };
+        }
+
+        if let Some(username) = command_arguments(string, ".trust") {
+            return Ok(CommandType::Trust(username_argument(username, ".trust")?));
+        }
+
+        let regex_expr = r"((?<cmd>.file|.image|.username|\.status) (?<name>.+)|(?<quit>.quit)|(?<who>\.who)|(?<color>.color (?<r>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)) (?<g>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)) (?<b>(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)))|(?<text>.+))";
 
         let Ok(re) = Regex::new(regex_expr) else {
             return Err(FromStrError::RegexCreate);
@@ -38,8 +259,8 @@ impl FromStr for CommandType {
             return Err(FromStrError::RegexParse);
         };
 
-        match &caps.name("cmd") {
-            Some(cmd) => match cmd.as_str() {
+        if let Some(cmd) = &caps.name("cmd") {
+            match cmd.as_str() {
                 ".file" => {
                     return Ok(CommandType::File(caps["name"].to_string()));
                 }
@@ -49,40 +270,40 @@ impl FromStr for CommandType {
                 ".username" => {
                     return Ok(CommandType::Username(caps["name"].to_string()));
                 }
+                ".status" => {
+                    return Ok(CommandType::Status(caps["name"].to_string()));
+                }
                 _ => {
                     return Err(FromStrError::Internal(
                         "This should never happen.".to_string(),
                     ));
                 }
-            },
-            None => {}
+            }
         }
 
-        match &caps.name("quit") {
-            Some(_) => {
-                return Ok(CommandType::Quit);
-            }
-            None => {}
-        }
-
-        match &caps.name("color") {
-            Some(_) => {
-                let Ok(r) = u8::from_str_radix(&caps["r"], 10) else {
-                    return Err(FromStrError::StringToNumber);
-                };
-                let Ok(g) = u8::from_str_radix(&caps["g"], 10) else {
-                    return Err(FromStrError::StringToNumber);
-                };
-                let Ok(b) = u8::from_str_radix(&caps["b"], 10) else {
-                    return Err(FromStrError::StringToNumber);
-                };
-                return Ok(CommandType::Color((r, g, b)));
-            }
-            None => {}
+        if caps.name("quit").is_some() {
+            return Ok(CommandType::Quit);
+        }
+
+        if caps.name("who").is_some() {
+            return Ok(CommandType::Who);
+        }
+
+        if caps.name("color").is_some() {
+            let Ok(r) = caps["r"].parse::<u8>() else {
+                return Err(FromStrError::StringToNumber);
+            };
+            let Ok(g) = caps["g"].parse::<u8>() else {
+                return Err(FromStrError::StringToNumber);
+            };
+            let Ok(b) = caps["b"].parse::<u8>() else {
+                return Err(FromStrError::StringToNumber);
+            };
+            return Ok(CommandType::Color((r, g, b)));
         }
 
         // there should not be any other option
-        return Ok(CommandType::Text(caps["text"].to_string()));
+        Ok(CommandType::Text(caps["text"].to_string()))
     }
 }
 
@@ -119,14 +340,18 @@ impl FromStr for LogRegCommandType {
             ));
         }
 
-        if caps.name("register").is_some() {
-            let Ok(r) = u8::from_str_radix(&caps["r"], 10) else {
+        // regex is not anchored, so for example color component `1337` would be matched as `133`,
+        // register command is valid only when whole input (without trailing new line) is matched
+        let whole_input_matched = caps[0].len() == string.trim_end().len();
+
+        if caps.name("register").is_some() && whole_input_matched {
+            let Ok(r) = caps["r"].parse::<u8>() else {
                 return Err(FromStrError::StringToNumber);
             };
-            let Ok(g) = u8::from_str_radix(&caps["g"], 10) else {
+            let Ok(g) = caps["g"].parse::<u8>() else {
                 return Err(FromStrError::StringToNumber);
             };
-            let Ok(b) = u8::from_str_radix(&caps["b"], 10) else {
+            let Ok(b) = caps["b"].parse::<u8>() else {
                 return Err(FromStrError::StringToNumber);
             };
 
@@ -151,6 +376,205 @@ impl FromStr for LogRegCommandType {
 mod tests {
     use super::*;
 
+    #[test]
+    fn create_status_command_type_from_string_returns_ok() {
+        let input = ".status in a meeting";
+        let expected = CommandType::Status("in a meeting".to_string());
+
+        let actual = CommandType::from_str(input).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn create_who_command_type_from_string_returns_ok() {
+        let input = ".who";
+        let expected = CommandType::Who;
+
+        let actual = CommandType::from_str(input).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn create_passwd_command_type_from_string_returns_ok() {
+        let input = ".passwd old-password new-password new-password";
+        let expected = CommandType::PasswordChange(
+            "old-password".to_string(),
+            "new-password".to_string(),
+            "new-password".to_string(),
+        );
+
+        let actual = CommandType::from_str(input).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn create_passwd_command_type_from_string_returns_err() {
+        let input = ".passwd old-password new-password";
+
+        let actual = CommandType::from_str(input).unwrap_err();
+
+        assert!(matches!(actual, FromStrError::Usage(_)));
+    }
+
+    #[test]
+    fn create_delete_account_command_type_from_string_returns_ok() {
+        let input = ".delete-account my password";
+        let expected = CommandType::DeleteAccount("my password".to_string());
+
+        let actual = CommandType::from_str(input).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn create_delete_account_command_type_from_string_returns_err() {
+        let input = ".delete-account";
+
+        let actual = CommandType::from_str(input).unwrap_err();
+
+        assert!(matches!(actual, FromStrError::Usage(_)));
+    }
+
+    #[test]
+    fn create_session_command_types_from_string_returns_ok() {
+        assert_eq!(
+            CommandType::from_str(".logout").unwrap(),
+            CommandType::Logout
+        );
+        assert_eq!(
+            CommandType::from_str(".sessions\n").unwrap(),
+            CommandType::Sessions
+        );
+        assert_eq!(
+            CommandType::from_str(".revoke 42").unwrap(),
+            CommandType::Revoke(42)
+        );
+    }
+
+    #[test]
+    fn create_revoke_command_type_from_string_returns_err() {
+        let input = ".revoke last";
+
+        let actual = CommandType::from_str(input).unwrap_err();
+
+        assert!(matches!(actual, FromStrError::Usage(_)));
+    }
+
+    #[test]
+    fn create_moderation_command_types_from_string_returns_ok() {
+        assert_eq!(
+            CommandType::from_str(".role alice moderator").unwrap(),
+            CommandType::Role("alice".to_string(), Role::Moderator)
+        );
+        assert_eq!(
+            CommandType::from_str(".kick alice").unwrap(),
+            CommandType::Kick("alice".to_string())
+        );
+        assert_eq!(
+            CommandType::from_str(".ban alice").unwrap(),
+            CommandType::Ban("alice".to_string(), None, String::new())
+        );
+        assert_eq!(
+            CommandType::from_str(".ban alice 2h spam and insults").unwrap(),
+            CommandType::Ban(
+                "alice".to_string(),
+                Some(Duration::from_secs(2 * 60 * 60)),
+                "spam and insults".to_string()
+            )
+        );
+        assert_eq!(
+            CommandType::from_str(".unban alice").unwrap(),
+            CommandType::Unban("alice".to_string())
+        );
+        assert_eq!(
+            CommandType::from_str(".mute alice 10m").unwrap(),
+            CommandType::Mute(
+                "alice".to_string(),
+                Some(Duration::from_secs(10 * 60)),
+                String::new()
+            )
+        );
+        // reason without duration makes mute permanent
+        assert_eq!(
+            CommandType::from_str(".mute alice 2 many messages").unwrap(),
+            CommandType::Mute("alice".to_string(), None, "2 many messages".to_string())
+        );
+        assert_eq!(
+            CommandType::from_str(".unmute alice").unwrap(),
+            CommandType::Unmute("alice".to_string())
+        );
+        assert_eq!(
+            CommandType::from_str(".delete-messages alice 5").unwrap(),
+            CommandType::DeleteMessages("alice".to_string(), 5)
+        );
+    }
+
+    #[test]
+    fn create_moderation_command_types_from_string_returns_err() {
+        for input in [
+            ".role alice boss",
+            ".role alice",
+            ".kick",
+            ".ban",
+            ".mute",
+            ".unmute alice bob",
+            ".delete-messages alice all",
+        ] {
+            let actual = CommandType::from_str(input).unwrap_err();
+
+            assert!(matches!(actual, FromStrError::Usage(_)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn create_direct_message_command_types_from_string_returns_ok() {
+        assert_eq!(
+            CommandType::from_str(".dm bob Hello Bob").unwrap(),
+            CommandType::DirectMessage("bob".to_string(), "Hello Bob".to_string())
+        );
+        assert_eq!(
+            CommandType::from_str(".fingerprint").unwrap(),
+            CommandType::Fingerprint(None)
+        );
+        assert_eq!(
+            CommandType::from_str(".fingerprint bob").unwrap(),
+            CommandType::Fingerprint(Some("bob".to_string()))
+        );
+        assert_eq!(
+            CommandType::from_str(".trust bob").unwrap(),
+            CommandType::Trust("bob".to_string())
+        );
+    }
+
+    #[test]
+    fn create_direct_message_command_types_from_string_returns_err() {
+        for input in [
+            ".dm",
+            ".dm bob",
+            ".dm bob  ",
+            ".trust",
+            ".fingerprint bob alice",
+        ] {
+            let actual = CommandType::from_str(input).unwrap_err();
+
+            assert!(matches!(actual, FromStrError::Usage(_)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn mask_passwords_hides_arguments_of_password_commands() {
+        assert_eq!(mask_passwords(".passwd old new new"), ".passwd ***");
+        assert_eq!(
+            mask_passwords(".delete-account secret"),
+            ".delete-account ***"
+        );
+        assert_eq!(mask_passwords(".passwdx hello"), ".passwdx hello");
+        assert_eq!(mask_passwords("hello"), "hello");
+    }
+
     #[test]
     fn create_login_command_type_from_string_returns_ok() {
         let input = ".login username password";
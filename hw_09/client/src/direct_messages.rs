@@ -0,0 +1,601 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, ErrorKind, Read, Write},
+    path::PathBuf,
+};
+
+use libs::{
+    e2e::{fingerprint, key_from_hex, key_to_hex, KeyPair, KEY_LENGTH},
+    message::{EncryptedMessage, Message, MessageType, UserInfo},
+};
+
+use crate::commands::CommandType;
+
+/// File with private keys of this client and public keys of contacts (hex encoded), lines are
+/// `own <server> <username> <private key>` and `contact <server> <username> <public key>`.
+pub struct KeyStore {
+    path: PathBuf,
+}
+
+/// Line of key file: kind (`own` or `contact`), server, username and key.
+type KeyEntry = (String, String, String, String);
+
+impl KeyStore {
+    pub fn new(path: PathBuf) -> Self {
+        KeyStore { path }
+    }
+
+    /// Returns `$HOME/.chat_keys`, `None` when home directory is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".chat_keys"))
+    }
+
+    /// Returns key pair of user on server, new key pair is generated and stored when there is none.
+    pub fn own_key_pair(&self, server: &str, username: &str) -> io::Result<KeyPair> {
+        let username = username.to_lowercase();
+        let mut entries = self.entries()?;
+
+        let stored = entries
+            .iter()
+            .find(|(kind, entry_server, entry_username, _)| {
+                kind == "own" && entry_server == server && *entry_username == username
+            })
+            .and_then(|(_, _, _, key)| key_from_hex(key));
+
+        if let Some(secret) = stored {
+            return Ok(KeyPair::from_secret(secret));
+        }
+
+        let key_pair = KeyPair::generate();
+
+        entries.push((
+            "own".to_string(),
+            server.to_string(),
+            username,
+            key_to_hex(&key_pair.secret()),
+        ));
+        self.write(&entries)?;
+
+        Ok(key_pair)
+    }
+
+    /// Returns known public keys of contacts on server by lowercase username.
+    pub fn contact_keys(&self, server: &str) -> io::Result<HashMap<String, [u8; KEY_LENGTH]>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|(kind, entry_server, _, _)| kind == "contact" && entry_server == server)
+            .filter_map(|(_, _, username, key)| Some((username, key_from_hex(&key)?)))
+            .collect())
+    }
+
+    /// Stores public key of contact, his previous key is replaced.
+    pub fn save_contact_key(
+        &self,
+        server: &str,
+        username: &str,
+        key: &[u8; KEY_LENGTH],
+    ) -> io::Result<()> {
+        let username = username.to_lowercase();
+        let mut entries = self.entries()?;
+
+        entries.retain(|(kind, entry_server, entry_username, _)| {
+            kind != "contact" || entry_server != server || *entry_username != username
+        });
+        entries.push((
+            "contact".to_string(),
+            server.to_string(),
+            username,
+            key_to_hex(key),
+        ));
+
+        self.write(&entries)
+    }
+
+    fn entries(&self) -> io::Result<Vec<KeyEntry>> {
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        // file could be created with other permissions (by user or by older client)
+        #[cfg(unix)]
+        restrict_permissions(&file)?;
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+
+        // invalid lines are skipped
+        Ok(content
+            .lines()
+            .filter_map(|line| match line.split(' ').collect::<Vec<&str>>()[..] {
+                [kind, server, username, key] => Some((
+                    kind.to_string(),
+                    server.to_string(),
+                    username.to_string(),
+                    key.to_string(),
+                )),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn write(&self, entries: &[KeyEntry]) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        // private keys must stay secret, only owner can read them
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&self.path)?;
+
+        // mode is used only when file is created
+        #[cfg(unix)]
+        restrict_permissions(&file)?;
+
+        for (kind, server, username, key) in entries {
+            writeln!(file, "{} {} {} {}", kind, server, username, key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes key file readable and writable only by its owner.
+#[cfg(unix)]
+fn restrict_permissions(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = file.metadata()?.permissions();
+
+    if permissions.mode() & 0o777 != 0o600 {
+        permissions.set_mode(0o600);
+        file.set_permissions(permissions)?;
+    }
+
+    Ok(())
+}
+
+/// End-to-end encrypted direct messages of logged in user.
+///
+/// Message typed by user waits until public key of recipient arrives from server. Keys of
+/// contacts are trusted on first use, when key of contact changes, user is warned and messages
+/// for him wait until user checks new fingerprint and trusts the key (`.trust <username>`).
+pub struct DirectMessages {
+    key_store: Option<KeyStore>,
+    server: String,
+    key_pair: KeyPair,
+    contacts: HashMap<String, [u8; KEY_LENGTH]>,
+    /// Messages waiting for public key of recipient.
+    pending: HashMap<String, Vec<String>>,
+    /// Changed keys of contacts that are not trusted yet.
+    changed: HashMap<String, [u8; KEY_LENGTH]>,
+}
+
+impl DirectMessages {
+    /// Loads keys of user from `key_store`, without key store keys are kept only in memory.
+    pub fn new(key_store: Option<KeyStore>, server: &str, username: &str) -> io::Result<Self> {
+        let (key_pair, contacts) = match &key_store {
+            Some(key_store) => (
+                key_store.own_key_pair(server, username)?,
+                key_store.contact_keys(server)?,
+            ),
+            None => (KeyPair::generate(), HashMap::new()),
+        };
+
+        Ok(DirectMessages {
+            key_store,
+            server: server.to_string(),
+            key_pair,
+            contacts,
+            pending: HashMap::new(),
+            changed: HashMap::new(),
+        })
+    }
+
+    /// Message publishing public key of this user, it is sent after login.
+    pub fn publish_key(&self) -> MessageType {
+        MessageType::PublicKeyPublish(self.key_pair.public_key().to_vec())
+    }
+
+    /// Returns fingerprint of own key (`None`) or known key of contact.
+    pub fn fingerprint(&self, username: Option<&str>) -> String {
+        match username {
+            None => format!("Your key: {}", fingerprint(&self.key_pair.public_key())),
+            Some(username) => match self.contacts.get(&username.to_lowercase()) {
+                Some(key) => format!("Key of {}: {}", username, fingerprint(key)),
+                None => format!("Key of {} is not known yet.", username),
+            },
+        }
+    }
+
+    /// Handles direct message command (`.dm`, `.fingerprint`, `.trust`), returns lines for user
+    /// and messages for server. Returns `None` for other commands.
+    pub fn handle_command(
+        &mut self,
+        command: &CommandType,
+    ) -> Option<(Vec<String>, Vec<MessageType>)> {
+        match command {
+            CommandType::DirectMessage(recipient, text) => {
+                Some((vec![], vec![self.queue(recipient, text)]))
+            }
+            CommandType::Fingerprint(username) => {
+                Some((vec![self.fingerprint(username.as_deref())], vec![]))
+            }
+            CommandType::Trust(username) => match self.trust(username) {
+                Ok(messages) => Some((
+                    vec![format!("Key of {} is trusted now.", username)],
+                    messages,
+                )),
+                Err(reason) => Some((vec![reason], vec![])),
+            },
+            _ => None,
+        }
+    }
+
+    /// Handles public key or direct message from server, returns lines for user and messages for
+    /// server. Returns `None` for other messages.
+    pub fn handle_message(&mut self, message: &Message) -> Option<(Vec<String>, Vec<MessageType>)> {
+        match &message.message {
+            MessageType::PublicKeyResponse(username, Ok(key)) => {
+                let (notice, messages) = self.key_received(username, key);
+
+                Some((notice.into_iter().collect(), messages))
+            }
+            MessageType::PublicKeyResponse(username, Err(reason)) => {
+                self.key_refused(username);
+
+                Some((
+                    vec![format!("Direct message was not sent: {}", reason)],
+                    vec![],
+                ))
+            }
+            MessageType::DirectMessage(encrypted) => {
+                Some((self.read(&message.user_info, encrypted), vec![]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Queues message for recipient, returns request for his public key.
+    pub fn queue(&mut self, recipient: &str, text: &str) -> MessageType {
+        self.pending
+            .entry(recipient.to_lowercase())
+            .or_default()
+            .push(text.to_string());
+
+        MessageType::PublicKeyRequest(recipient.to_string())
+    }
+
+    /// Handles public key of contact sent by server, returns notice for user (new or changed key)
+    /// and encrypted messages that were waiting for this key.
+    pub fn key_received(
+        &mut self,
+        username: &str,
+        key: &[u8],
+    ) -> (Option<String>, Vec<MessageType>) {
+        let Ok(key) = <[u8; KEY_LENGTH]>::try_from(key) else {
+            self.pending.remove(&username.to_lowercase());
+
+            return (
+                Some(format!("Server sent invalid key of {}.", username)),
+                vec![],
+            );
+        };
+
+        let notice = match self.check_contact_key(username, &key) {
+            Ok(notice) => notice,
+            Err(warning) => return (Some(warning), vec![]),
+        };
+
+        (notice, self.encrypt_pending(username, &key))
+    }
+
+    /// Drops messages for user whose key server could not send (for example he does not exist).
+    pub fn key_refused(&mut self, username: &str) {
+        self.pending.remove(&username.to_lowercase());
+    }
+
+    /// Trusts changed key of contact, returns messages that were waiting for it.
+    pub fn trust(&mut self, username: &str) -> Result<Vec<MessageType>, String> {
+        let key = self
+            .changed
+            .remove(&username.to_lowercase())
+            .ok_or_else(|| format!("Key of {} did not change.", username))?;
+
+        self.save_contact(username, &key);
+
+        Ok(self.encrypt_pending(username, &key))
+    }
+
+    /// Decrypts direct message, returns lines that should be printed.
+    pub fn read(&mut self, sender: &UserInfo, encrypted: &EncryptedMessage) -> Vec<String> {
+        let mut lines = vec![];
+
+        if encrypted.recipient_key != self.key_pair.public_key() {
+            lines.push(format!(
+                "Direct message from {} was encrypted for your other key, it can not be read.",
+                sender.username
+            ));
+
+            return lines;
+        }
+
+        let Ok(sender_key) = <[u8; KEY_LENGTH]>::try_from(encrypted.sender_key.as_slice()) else {
+            lines.push(format!(
+                "Direct message from {} has invalid key.",
+                sender.username
+            ));
+
+            return lines;
+        };
+
+        match self.check_contact_key(&sender.username, &sender_key) {
+            Ok(notice) => lines.extend(notice),
+            Err(warning) => lines.push(warning),
+        }
+
+        match self
+            .key_pair
+            .decrypt(&sender_key, &encrypted.nonce, &encrypted.ciphertext)
+        {
+            Ok(plaintext) => lines.push(format!(
+                "[direct] {}: {}",
+                sender.username,
+                String::from_utf8_lossy(&plaintext)
+            )),
+            Err(e) => lines.push(format!(
+                "Direct message from {} could not be read: {}",
+                sender.username, e
+            )),
+        }
+
+        lines
+    }
+
+    /// Compares key with known key of contact, unknown key is stored. Returns notice about new
+    /// key, or warning when key changed (it is not trusted until `trust`).
+    fn check_contact_key(
+        &mut self,
+        username: &str,
+        key: &[u8; KEY_LENGTH],
+    ) -> Result<Option<String>, String> {
+        match self.contacts.get(&username.to_lowercase()) {
+            Some(known) if known == key => Ok(None),
+            Some(known) => {
+                self.changed.insert(username.to_lowercase(), *key);
+
+                Err(format!(
+                    "WARNING: key of {} changed from {} to {}. Verify new fingerprint with {} and \
+                     use .trust {} to accept it, messages for {} wait until then.",
+                    username,
+                    fingerprint(known),
+                    fingerprint(key),
+                    username,
+                    username,
+                    username
+                ))
+            }
+            None => {
+                self.save_contact(username, key);
+
+                Ok(Some(format!(
+                    "New key of {}: {} (verify it with {}).",
+                    username,
+                    fingerprint(key),
+                    username
+                )))
+            }
+        }
+    }
+
+    fn save_contact(&mut self, username: &str, key: &[u8; KEY_LENGTH]) {
+        self.contacts.insert(username.to_lowercase(), *key);
+
+        // key is still trusted for this run when it can not be saved
+        if let Some(key_store) = &self.key_store {
+            let _ = key_store.save_contact_key(&self.server, username, key);
+        }
+    }
+
+    fn encrypt_pending(&mut self, username: &str, key: &[u8; KEY_LENGTH]) -> Vec<MessageType> {
+        self.pending
+            .remove(&username.to_lowercase())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|text| {
+                let (nonce, ciphertext) = self.key_pair.encrypt(key, text.as_bytes()).ok()?;
+
+                Some(MessageType::DirectMessage(EncryptedMessage {
+                    recipient: username.to_string(),
+                    recipient_id: 0,
+                    sender_key: self.key_pair.public_key().to_vec(),
+                    recipient_key: key.to_vec(),
+                    nonce,
+                    ciphertext,
+                }))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_store(name: &str) -> KeyStore {
+        let path = env::temp_dir().join(format!("chat_keys_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+
+        KeyStore::new(path)
+    }
+
+    fn user_info(username: &str) -> UserInfo {
+        UserInfo {
+            id: 1,
+            username: username.to_string(),
+            color: (0, 0, 0),
+        }
+    }
+
+    /// Sends all queued messages of `sender` to `recipient` as server would relay them.
+    fn deliver(sender: &mut DirectMessages, recipient: &mut DirectMessages) -> Vec<String> {
+        let (_, messages) = sender.key_received("bob", &recipient.key_pair.public_key());
+
+        messages
+            .iter()
+            .flat_map(|message| match message {
+                MessageType::DirectMessage(encrypted) => {
+                    recipient.read(&user_info("alice"), encrypted)
+                }
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn own_key_pair_is_generated_once_per_server_and_user() {
+        let key_store = key_store("own");
+
+        let first = key_store.own_key_pair("localhost:11111", "Alice").unwrap();
+        let second = key_store.own_key_pair("localhost:11111", "alice").unwrap();
+        let other = key_store
+            .own_key_pair("example.com:11111", "alice")
+            .unwrap();
+
+        assert_eq!(first.public_key(), second.public_key());
+        assert_ne!(first.public_key(), other.public_key());
+
+        fs::remove_file(&key_store.path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_key_file_is_made_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let key_store = key_store("permissions");
+        let mode = |path: &PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        fs::write(&key_store.path, "").unwrap();
+        fs::set_permissions(&key_store.path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        key_store.contact_keys("localhost:11111").unwrap();
+
+        assert_eq!(mode(&key_store.path), 0o600);
+
+        fs::remove_file(&key_store.path).unwrap();
+    }
+
+    #[test]
+    fn direct_message_is_sent_after_key_arrives_and_decrypted_by_recipient() {
+        let mut alice = DirectMessages::new(None, "localhost:11111", "alice").unwrap();
+        let mut bob = DirectMessages::new(None, "localhost:11111", "bob").unwrap();
+
+        assert_eq!(
+            alice.queue("bob", "Hello Bob"),
+            MessageType::PublicKeyRequest("bob".to_string())
+        );
+
+        let lines = deliver(&mut alice, &mut bob);
+
+        assert!(lines[0].starts_with("New key of alice: "));
+        assert_eq!(lines[1], "[direct] alice: Hello Bob");
+
+        // nothing waits anymore
+        assert!(alice
+            .key_received("bob", &bob.key_pair.public_key())
+            .1
+            .is_empty());
+    }
+
+    #[test]
+    fn changed_key_of_contact_is_not_used_until_trusted() {
+        let key_store = key_store("changed");
+        let mut alice = DirectMessages::new(Some(key_store), "localhost:11111", "alice").unwrap();
+        let bob = DirectMessages::new(None, "localhost:11111", "bob").unwrap();
+        let new_bob = DirectMessages::new(None, "localhost:11111", "bob").unwrap();
+
+        alice.queue("bob", "first");
+        let (notice, messages) = alice.key_received("bob", &bob.key_pair.public_key());
+
+        assert!(notice.unwrap().starts_with("New key of bob"));
+        assert_eq!(messages.len(), 1);
+
+        alice.queue("bob", "second");
+        let (notice, messages) = alice.key_received("bob", &new_bob.key_pair.public_key());
+
+        assert!(notice.unwrap().starts_with("WARNING: key of bob changed"));
+        assert!(messages.is_empty());
+        // old key is still used
+        assert!(alice
+            .fingerprint(Some("bob"))
+            .ends_with(&fingerprint(&bob.key_pair.public_key())));
+
+        assert_eq!(alice.trust("bob").unwrap().len(), 1);
+        assert!(alice.trust("bob").is_err());
+
+        // trusted key is stored for next run
+        let key_store = alice.key_store.take().unwrap();
+        let contacts = key_store.contact_keys("localhost:11111").unwrap();
+
+        assert_eq!(contacts["bob"], new_bob.key_pair.public_key());
+
+        fs::remove_file(&key_store.path).unwrap();
+    }
+
+    #[test]
+    fn unknown_recipient_drops_queued_messages() {
+        let mut alice = DirectMessages::new(None, "localhost:11111", "alice").unwrap();
+        let bob = DirectMessages::new(None, "localhost:11111", "bob").unwrap();
+
+        let command = CommandType::DirectMessage("bob".to_string(), "Hello".to_string());
+        let (lines, messages) = alice.handle_command(&command).unwrap();
+
+        assert!(lines.is_empty());
+        assert_eq!(
+            messages,
+            vec![MessageType::PublicKeyRequest("bob".to_string())]
+        );
+
+        let refused = Message::from(MessageType::PublicKeyResponse(
+            "bob".to_string(),
+            Err("User 'bob' does not exist.".to_string()),
+        ));
+
+        assert_eq!(
+            alice.handle_message(&refused).unwrap().0,
+            vec!["Direct message was not sent: User 'bob' does not exist."]
+        );
+
+        let key = Message::from(MessageType::PublicKeyResponse(
+            "bob".to_string(),
+            Ok(bob.key_pair.public_key().to_vec()),
+        ));
+
+        assert!(alice.handle_message(&key).unwrap().1.is_empty());
+        assert!(alice.handle_command(&CommandType::Who).is_none());
+    }
+
+    #[test]
+    fn message_for_other_key_can_not_be_read() {
+        let mut alice = DirectMessages::new(None, "localhost:11111", "alice").unwrap();
+        let mut bob = DirectMessages::new(None, "localhost:11111", "bob").unwrap();
+        let eve = DirectMessages::new(None, "localhost:11111", "eve").unwrap();
+
+        alice.queue("bob", "secret");
+        let (_, messages) = alice.key_received("bob", &eve.key_pair.public_key());
+
+        let MessageType::DirectMessage(encrypted) = &messages[0] else {
+            panic!("direct message expected");
+        };
+
+        assert_eq!(
+            bob.read(&user_info("alice"), encrypted),
+            vec!["Direct message from alice was encrypted for your other key, it can not be read."]
+        );
+    }
+}
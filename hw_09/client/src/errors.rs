@@ -28,11 +28,18 @@ pub enum SendMessageError {
     Internal(String),
     File(String),
     Message(#[from] MessageError),
+    Input(String),
 }
 
 impl Display for SendMessageError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "SendMessageError")
+        match self {
+            SendMessageError::MessageType(FromStrError::Usage(usage)) => {
+                write!(f, "Usage: {}", usage)
+            }
+            SendMessageError::Input(message) => write!(f, "{}", message),
+            _ => write!(f, "SendMessageError"),
+        }
     }
 }
 
@@ -42,6 +49,7 @@ pub enum FromStrError {
     RegexParse,
     StringToNumber,
     Internal(String),
+    Usage(String),
 }
 
 impl Display for FromStrError {
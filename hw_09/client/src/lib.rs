@@ -3,6 +3,7 @@ use std::{
     io::{self, Cursor, Read, Write},
     path::Path,
     str::FromStr,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Result;
@@ -15,23 +16,28 @@ use image::io::Reader;
 use viuer::Config;
 
 use commands::CommandType;
+use direct_messages::DirectMessages;
 use errors::{ReceiveMessageError, SendMessageError};
 use libs::{
-    message::{Message, MessageType},
+    message::{Message, MessageType, UserInfo},
     receiver::MessageReceiver,
     sender::MessageSender,
 };
 
 pub mod args;
 pub mod commands;
+pub mod direct_messages;
 pub mod errors;
+pub mod screen;
+pub mod token_cache;
+pub mod typing;
 
 pub async fn handle_send_message(
     sender: &mut MessageSender,
     input: &str,
 ) -> Result<bool, SendMessageError> {
     // create CommandType from String
-    let command_type = CommandType::from_str(&input)?;
+    let command_type = CommandType::from_str(input)?;
 
     // flag to determine if quit command is performed
     let mut quit = false;
@@ -91,6 +97,60 @@ pub async fn handle_send_message(
 
         // for CommandType::Color set values for red, green and blue
         CommandType::Color((r, g, b)) => MessageType::UserColorChange(r, g, b),
+
+        // for CommandType::Status set new status, known statuses are parsed, other are custom
+        CommandType::Status(status) => MessageType::UserStatusChange(status.as_str().into()),
+
+        // for CommandType::Who request list of online users
+        CommandType::Who => MessageType::PresenceRequest(),
+
+        // for CommandType::PasswordChange check that new password was entered twice the same
+        CommandType::PasswordChange(old_password, new_password, new_repassword) => {
+            if new_password != new_repassword {
+                return Err(SendMessageError::Input(
+                    "Passwords do not match.".to_string(),
+                ));
+            }
+
+            MessageType::PasswordChangeRequest(old_password, new_password)
+        }
+
+        // for CommandType::DeleteAccount send password, server disconnects client after deletion
+        CommandType::DeleteAccount(password) => MessageType::AccountDeleteRequest(password),
+
+        // for CommandType::Logout end session on server and quit (cached token is removed by caller)
+        CommandType::Logout => {
+            quit = true;
+            MessageType::LogoutRequest()
+        }
+
+        // for CommandType::Sessions request list of sessions of this user
+        CommandType::Sessions => MessageType::SessionsRequest(),
+
+        // for CommandType::Revoke end other session of this user
+        CommandType::Revoke(session_id) => MessageType::SessionRevokeRequest(session_id),
+
+        // for moderation commands send request, server checks role of user
+        CommandType::Role(username, role) => MessageType::RoleChangeRequest(username, role),
+        CommandType::Kick(username) => MessageType::KickRequest(username),
+        CommandType::Ban(username, duration, reason) => {
+            MessageType::BanRequest(username, duration, reason)
+        }
+        CommandType::Unban(username) => MessageType::UnbanRequest(username),
+        CommandType::Mute(username, duration, reason) => {
+            MessageType::MuteRequest(username, duration, reason)
+        }
+        CommandType::Unmute(username) => MessageType::UnmuteRequest(username),
+        CommandType::DeleteMessages(username, count) => {
+            MessageType::MessagesDeleteRequest(username, count)
+        }
+
+        // direct message commands need keys, they are handled by `DirectMessages`
+        CommandType::DirectMessage(..) | CommandType::Fingerprint(_) | CommandType::Trust(_) => {
+            return Err(SendMessageError::Internal(
+                "Direct message commands are handled by DirectMessages.".to_string(),
+            ));
+        }
     };
 
     // create message structure, user info and date time are default because this informations fills only server
@@ -114,6 +174,33 @@ pub async fn handle_receive_message(
     // read message from server
     let message = receiver.receive_message().await?;
 
+    handle_message(message)
+}
+
+/// Receives messages until login or register response arrives, returns user and session token if
+/// user is logged in.
+pub async fn handle_login_response(
+    receiver: &mut MessageReceiver,
+) -> Result<Option<(UserInfo, String)>, ReceiveMessageError> {
+    loop {
+        let message = receiver.receive_message().await?;
+
+        let token = match message.message {
+            MessageType::LoginResponse(ref result) | MessageType::RegisterResponse(ref result) => {
+                Some(result.as_ref().ok().cloned())
+            }
+            _ => None,
+        };
+
+        handle_message(message)?;
+
+        if let Some(token) = token {
+            return Ok(token);
+        }
+    }
+}
+
+pub fn handle_message(message: Message) -> Result<(), ReceiveMessageError> {
     // convert user's name color to Color enum
     let username_color = Color::Rgb {
         r: message.user_info.color.0,
@@ -132,7 +219,7 @@ pub async fn handle_receive_message(
         // for MessageType::File save file to ./files directory and print info about file to user
         MessageType::File(file_name, data) => {
             let mut my_file_name = "./files/".to_string();
-            my_file_name = my_file_name + file_name.as_str();
+            my_file_name += file_name.as_str();
 
             let mut file = File::create(my_file_name.clone())?;
 
@@ -208,6 +295,17 @@ pub async fn handle_receive_message(
             println!(".");
         }
 
+        // for MessageType::UserStatusChange print new status of user
+        MessageType::UserStatusChange(status) => {
+            print!("User '");
+            print_colored_string_to_stdout(message.user_info.username.as_str(), username_color)?;
+            println!("' is now {}.", status);
+        }
+
+        // for MessageType::UserTyping nothing should be printed, typing users are shown in status
+        // line (see `Screen`)
+        MessageType::UserTyping(_) => {}
+
         // for MessageType::RecoverableError print error message
         MessageType::RecoverableError(error) => {
             print_colored_string_to_stdout(&error, Color::Red)?;
@@ -225,13 +323,16 @@ pub async fn handle_receive_message(
         MessageType::LoginRequest(..) => {}
 
         // for MessageType::LoginResponse determine if login was successful and print message
-        MessageType::LoginResponse(success) => {
-            match success {
-                Some(_) => {
+        MessageType::LoginResponse(result) => {
+            match result {
+                Ok(_) => {
                     print_colored_string_to_stdout("Login was successful.", Color::Green)?;
                 }
-                None => {
-                    print_colored_string_to_stdout("Login failed.", Color::Red)?;
+                Err(reason) => {
+                    print_colored_string_to_stdout(
+                        &format!("Login failed: {}", reason),
+                        Color::Red,
+                    )?;
                 }
             }
 
@@ -242,22 +343,166 @@ pub async fn handle_receive_message(
         MessageType::RegisterRequest(..) => {}
 
         // for MessageType::LoginResponse determine if registration was successful and print message
-        MessageType::RegisterResponse(success) => {
-            match success {
-                Some(_) => {
+        MessageType::RegisterResponse(result) => {
+            match result {
+                Ok(_) => {
                     print_colored_string_to_stdout("Registration was successful.", Color::Green)?;
                 }
-                None => {
-                    print_colored_string_to_stdout("Registration failed.", Color::Red)?;
+                Err(reason) => {
+                    print_colored_string_to_stdout(
+                        &format!("Registration failed: {}", reason),
+                        Color::Red,
+                    )?;
+                }
+            }
+
+            println!();
+        }
+
+        // for MessageType::PasswordChangeRequest nothing should be done, this message is only client -> server
+        MessageType::PasswordChangeRequest(..) => {}
+
+        // for MessageType::PasswordChangeResponse print result of password change
+        MessageType::PasswordChangeResponse(result) => {
+            match result {
+                Ok(()) => {
+                    print_colored_string_to_stdout("Password was changed.", Color::Green)?;
+                }
+                Err(reason) => {
+                    print_colored_string_to_stdout(
+                        &format!("Password change failed: {}", reason),
+                        Color::Red,
+                    )?;
+                }
+            }
+
+            println!();
+        }
+
+        // for MessageType::AccountDeleteRequest nothing should be done, this message is only client -> server
+        MessageType::AccountDeleteRequest(_) => {}
+
+        // for MessageType::AccountDeleteResponse print result, after deletion server closes
+        // connection, so ClientError is returned
+        MessageType::AccountDeleteResponse(result) => match result {
+            Ok(()) => {
+                print_colored_string_to_stdout("Account was deleted.", Color::Green)?;
+                println!();
+                return Err(ReceiveMessageError::Server);
+            }
+            Err(reason) => {
+                print_colored_string_to_stdout(
+                    &format!("Account deletion failed: {}", reason),
+                    Color::Red,
+                )?;
+                println!();
+            }
+        },
+
+        // for session requests nothing should be done, these messages are only client -> server
+        MessageType::TokenLoginRequest(_)
+        | MessageType::LogoutRequest()
+        | MessageType::SessionsRequest()
+        | MessageType::SessionRevokeRequest(_) => {}
+
+        // for MessageType::SessionsResponse print sessions of this user, current one is marked
+        MessageType::SessionsResponse(sessions) => {
+            println!("Sessions:");
+
+            let now = SystemTime::now();
+
+            for session in sessions {
+                println!(
+                    "- {} (created {} ago, last used {} ago, expires in {}){}",
+                    session.id,
+                    format_duration(now.duration_since(session.created_at).unwrap_or_default()),
+                    format_duration(now.duration_since(session.last_used_at).unwrap_or_default()),
+                    format_duration(session.expires_at.duration_since(now).unwrap_or_default()),
+                    if session.current { " current" } else { "" }
+                );
+            }
+        }
+
+        // for MessageType::SessionRevokeResponse print result of revocation
+        MessageType::SessionRevokeResponse(result) => {
+            match result {
+                Ok(()) => {
+                    print_colored_string_to_stdout("Session was revoked.", Color::Green)?;
+                }
+                Err(reason) => {
+                    print_colored_string_to_stdout(
+                        &format!("Session revocation failed: {}", reason),
+                        Color::Red,
+                    )?;
                 }
             }
 
             println!();
         }
 
+        // for moderation requests nothing should be done, these messages are only client -> server
+        MessageType::RoleChangeRequest(..)
+        | MessageType::KickRequest(_)
+        | MessageType::BanRequest(..)
+        | MessageType::UnbanRequest(_)
+        | MessageType::MuteRequest(..)
+        | MessageType::UnmuteRequest(_)
+        | MessageType::MessagesDeleteRequest(..) => {}
+
+        // for MessageType::ActionResponse print result of action (or reason why it was refused)
+        MessageType::ActionResponse(result) => {
+            match result {
+                Ok(text) => print_colored_string_to_stdout(&text, Color::Green)?,
+                Err(reason) => print_colored_string_to_stdout(&reason, Color::Red)?,
+            }
+
+            println!();
+        }
+
+        // for public key requests nothing should be done, these messages are only client -> server
+        MessageType::PublicKeyPublish(_) | MessageType::PublicKeyRequest(_) => {}
+
+        // for MessageType::PublicKeyResponse and MessageType::DirectMessage nothing should be done,
+        // they are handled by `DirectMessages` which has keys of user
+        MessageType::PublicKeyResponse(..) | MessageType::DirectMessage(_) => {}
+
+        // for MessageType::OfflineMessages nothing should be done, they are handled by
+        // `handle_offline_messages` because direct messages need keys of user
+        MessageType::OfflineMessages(_) => {}
+
         // for MessageType::OldMessagesRequest nothing should be done, this message is only client -> server
         MessageType::OldMessagesRequest() => {}
 
+        // for MessageType::ClientHello nothing should be done, this message is only client -> server
+        MessageType::ClientHello(_) => {}
+
+        // for MessageType::PresenceRequest nothing should be done, this message is only client -> server
+        MessageType::PresenceRequest() => {}
+
+        // for MessageType::PresenceResponse print all online users with their status and idle time
+        MessageType::PresenceResponse(users) => {
+            println!("Online users:");
+
+            for presence in users {
+                let username_color = Color::Rgb {
+                    r: presence.user_info.color.0,
+                    g: presence.user_info.color.1,
+                    b: presence.user_info.color.2,
+                };
+
+                print!("- ");
+                print_colored_string_to_stdout(
+                    presence.user_info.username.as_str(),
+                    username_color,
+                )?;
+                println!(
+                    " ({}, idle {})",
+                    presence.status,
+                    format_duration(presence.idle)
+                );
+            }
+        }
+
         // for MessageType::OldMessagesResponse print all old messages send by server
         MessageType::OldMessagesResponse(messages) => {
             for message in messages {
@@ -277,6 +522,56 @@ pub async fn handle_receive_message(
     Ok(())
 }
 
+/// Prints summary of messages received while user was offline and then messages themselves,
+/// direct messages are decrypted by `direct_messages`.
+pub fn handle_offline_messages(
+    messages: Vec<Message>,
+    direct_messages: &mut DirectMessages,
+) -> Result<(), ReceiveMessageError> {
+    print_colored_string_to_stdout(&away_summary(&messages), Color::Yellow)?;
+    println!();
+
+    for message in messages {
+        match direct_messages.handle_message(&message) {
+            Some((lines, _)) => lines.iter().for_each(|line| println!("{}", line)),
+            None => handle_message(message)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns summary of messages received while user was offline, for example `While you were
+/// away: 2 direct messages, 1 mention.`
+pub fn away_summary(messages: &[Message]) -> String {
+    let direct = messages
+        .iter()
+        .filter(|message| matches!(message.message, MessageType::DirectMessage(_)))
+        .count();
+    let mentions = messages.len() - direct;
+
+    let count = |count: usize, name: &str| match count {
+        1 => format!("1 {}", name),
+        _ => format!("{} {}s", count, name),
+    };
+
+    format!(
+        "While you were away: {}, {}.",
+        count(direct, "direct message"),
+        count(mentions, "mention")
+    )
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
 pub fn print_colored_string_to_stdout(string: &str, color: Color) -> Result<(), std::io::Error> {
     // print string with set color and then reset color to the original
     execute!(
@@ -286,3 +581,32 @@ pub fn print_colored_string_to_stdout(string: &str, color: Color) -> Result<(),
         ResetColor
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use libs::message::EncryptedMessage;
+
+    use super::*;
+
+    #[test]
+    fn away_summary_counts_direct_messages_and_mentions() {
+        let direct = Message::from(MessageType::DirectMessage(EncryptedMessage {
+            recipient: "bob".to_string(),
+            recipient_id: 2,
+            sender_key: vec![],
+            recipient_key: vec![],
+            nonce: vec![],
+            ciphertext: vec![],
+        }));
+        let mention = Message::from(MessageType::Text("@bob hi".to_string()));
+
+        assert_eq!(
+            away_summary(&[direct.clone(), mention.clone(), direct]),
+            "While you were away: 2 direct messages, 1 mention."
+        );
+        assert_eq!(
+            away_summary(&[mention]),
+            "While you were away: 0 direct messages, 1 mention."
+        );
+    }
+}
@@ -1,28 +1,35 @@
 use std::{
     io::{self},
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 use clap::Parser;
 use crossterm::{
+    event::{Event, EventStream},
     execute,
     style::Color,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
-};
-use tokio::{
-    io::{self as aio, AsyncBufReadExt, BufReader},
-    select,
-    sync::mpsc,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use futures::StreamExt;
+use tokio::{select, sync::mpsc, time};
 
 use client::{
-    args::Args, commands::LogRegCommandType, errors::ReceiveMessageError, handle_receive_message,
-    handle_send_message, print_colored_string_to_stdout,
+    args::Args,
+    commands::{mask_passwords, CommandType, LogRegCommandType},
+    direct_messages::{DirectMessages, KeyStore},
+    errors::ReceiveMessageError,
+    handle_login_response, handle_message, handle_offline_messages, handle_send_message,
+    print_colored_string_to_stdout,
+    screen::{InputAction, Screen},
+    token_cache::TokenCache,
+    typing::TypingNotifier,
 };
 use libs::{
     builder::MessageReceiverSenderBuilder,
-    message::{Message, MessageType},
+    message::{Message, MessageType, PROTOCOL_VERSION},
     remove_new_line,
 };
 
@@ -34,6 +41,13 @@ async fn main() -> Result<()> {
     // parse program arguments
     let args = Args::parse();
 
+    // tokens are cached per server
+    let server = format!("{}:{}", args.hostname, args.port);
+    let token_cache = args
+        .token_file
+        .or_else(TokenCache::default_path)
+        .map(TokenCache::new);
+
     // connect to specified address and port
     let receiver_sender_builder =
         MessageReceiverSenderBuilder::from_socket_addr((args.hostname, args.port))
@@ -42,162 +56,216 @@ async fn main() -> Result<()> {
     let mut message_receiver = receiver_sender_builder.message_receiver();
     let mut message_sender = receiver_sender_builder.message_sender();
 
+    // server refuses connection when it uses other version of protocol
+    message_sender
+        .send_message(&Message::from(MessageType::ClientHello(PROTOCOL_VERSION)))
+        .await
+        .unwrap();
+
     let (tx_sender_to_receiver, mut rx_sender_to_receiver) = mpsc::channel(1);
     let (tx_receiver_to_sender, mut rx_receiver_to_sender) = mpsc::channel(1);
 
+    // try cached token of previous session first, password is not needed then
+    let mut user = None;
+
+    if let Some(token) = token_cache.as_ref().and_then(|cache| cache.load(&server)) {
+        message_sender
+            .send_message(&Message::from(MessageType::TokenLoginRequest(token)))
+            .await
+            .unwrap();
+
+        match handle_login_response(&mut message_receiver).await {
+            Ok(Some((user_info, _))) => user = Some(user_info),
+            // session expired or was revoked
+            _ => remove_cached_token(&token_cache, &server),
+        }
+    }
+
     // login or register
-    println!("Login or register?");
-    println!("- .login <username> <password>");
-    println!("- .register <username> <password> <password> <r> <g> <b>");
+    if user.is_none() {
+        println!("Login or register?");
+        println!("- .login <username> <password>");
+        println!("- .register <username> <password> <password> <r> <g> <b>");
 
-    let username;
-    //let color;
+        // loop until is user logged in or registered
+        loop {
+            let mut buf = Default::default();
 
-    // loop until is user logged in or registered
-    loop {
-        let mut buf = Default::default();
-
-        if io::stdin().read_line(&mut buf).is_ok() {
-            match LogRegCommandType::from_str(buf.as_str()) {
-                Ok(cmd) => match cmd {
-                    LogRegCommandType::Login(username_, mut password) => {
-                        remove_new_line(&mut password);
-
-                        message_sender
-                            .send_message(&Message::from(MessageType::LoginRequest(
-                                username_.clone(),
-                                password,
-                            )))
-                            .await
-                            .unwrap();
-
-                        match handle_receive_message(&mut message_receiver).await {
-                            Ok(_) => {
-                                username = username_;
-                                break;
-                            }
-                            Err(_) => {}
-                        }
+            if io::stdin().read_line(&mut buf).is_ok() {
+                match LogRegCommandType::from_str(buf.as_str()) {
+                    Ok(cmd) => match cmd {
+                        LogRegCommandType::Login(username, mut password) => {
+                            remove_new_line(&mut password);
 
-                        // TODO: fix - with code below colored username works fine
-                        // match message_receiver.receive_message().await {
-                        //     Ok(message) => match message.message {
-                        //         MessageType::LoginResponse(res) => {
-                        //             let res = res.unwrap();
-
-                        //             username = res.username;
-                        //             color = res.color;
-
-                        //             println!("Login was successful.");
-
-                        //             break;
-                        //         }
-                        //         _ => {
-                        //             println!("Login failed.");
-                        //         }
-                        //     },
-                        //     Err(e) => {
-                        //         println!("Login failed. {:?}", e);
-                        //     }
-                        // }
-                    }
-                    LogRegCommandType::Register(username_, password, repassword, r, g, b) => {
-                        if password == repassword {
                             message_sender
-                                .send_message(&Message::from(MessageType::RegisterRequest(
-                                    username_.clone(),
-                                    password,
-                                    r,
-                                    g,
-                                    b,
+                                .send_message(&Message::from(MessageType::LoginRequest(
+                                    username, password,
                                 )))
                                 .await
                                 .unwrap();
 
-                            match handle_receive_message(&mut message_receiver).await {
-                                Ok(_) => {
-                                    username = username_;
+                            if let Ok(Some((user_info, token))) =
+                                handle_login_response(&mut message_receiver).await
+                            {
+                                save_cached_token(&token_cache, &server, &token);
+                                user = Some(user_info);
+                                break;
+                            }
+                        }
+                        LogRegCommandType::Register(username, password, repassword, r, g, b) => {
+                            if password == repassword {
+                                message_sender
+                                    .send_message(&Message::from(MessageType::RegisterRequest(
+                                        username, password, r, g, b,
+                                    )))
+                                    .await
+                                    .unwrap();
+
+                                if let Ok(Some((user_info, token))) =
+                                    handle_login_response(&mut message_receiver).await
+                                {
+                                    save_cached_token(&token_cache, &server, &token);
+                                    user = Some(user_info);
                                     break;
                                 }
-                                Err(_) => {}
+                            } else {
+                                println!("Passwords do not match.");
                             }
-
-                            // TODO: fix - with code below colored username works fine
-                            // match message_receiver.receive_message().await {
-                            //     Ok(message) => match message.message {
-                            //         MessageType::RegisterResponse(res) => {
-                            //             let res = res.unwrap();
-
-                            //             username = res.username;
-                            //             color = res.color;
-
-                            //             println!("Registration was successful.");
-
-                            //             break;
-                            //         }
-                            //         _ => {
-                            //             println!("Registration failed.");
-                            //         }
-                            //     },
-                            //     Err(e) => {
-                            //         println!("Registration failed. {:?}", e);
-                            //     }
-                            // }
-                        } else {
-                            println!("Passwords do not match.");
                         }
+                    },
+                    Err(e) => {
+                        println!("{:?}", e);
                     }
-                },
-                Err(e) => {
-                    println!("{:?}", e);
                 }
+            } else {
+                println!("Internal error, try to enter command again.");
             }
-        } else {
-            println!("Internal error, try to enter command again.");
         }
     }
 
-    // send messages with username and color from login or register
+    // keys for direct messages are loaded for logged in user, his public key is published so other
+    // users can send him direct messages
+    let username = user.map(|user| user.username).unwrap_or_default();
+    let key_store = args
+        .key_file
+        .or_else(KeyStore::default_path)
+        .map(KeyStore::new);
+    let direct_messages = match DirectMessages::new(key_store, &server, &username) {
+        Ok(direct_messages) => direct_messages,
+        Err(e) => {
+            println!("Could not load keys, they are kept only until exit: {}", e);
+            DirectMessages::new(None, &server, &username)?
+        }
+    };
+
     message_sender
-        .send_message(&Message::from(MessageType::UserNameChange(username)))
+        .send_message(&Message::from(direct_messages.publish_key()))
         .await
         .unwrap();
 
-    // TODO: fix - with code below colored username works fine
-    // message_sender
-    //     .send_message(&Message::from(MessageType::UserColorChange(
-    //         color.0, color.1, color.2,
-    //     )))
-    //     .await
-    //     .unwrap();
+    let direct_messages = Arc::new(Mutex::new(direct_messages));
+
+    // bottom line with status area and input line, shared by both tasks
+    let screen = Arc::new(Mutex::new(Screen::default()));
+
+    // messages are received in separate task, select below could otherwise cancel receiving in the
+    // middle of message
+    let (tx_messages, mut rx_messages) = mpsc::channel(8);
+
+    tokio::spawn(async move {
+        while let Ok(message) = message_receiver.receive_message().await {
+            if tx_messages.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
 
     // task for handling messages other clients
-    let handle = tokio::spawn(async move {
-        loop {
-            select! {
-                Some(_) = rx_sender_to_receiver.recv() => {
-                    break;
-                }
-                res = handle_receive_message(&mut message_receiver) => match res {
-                    Ok(_) => {}
-                    Err(e) => {
-                        match e {
-                            ReceiveMessageError::Server => {
-                                tx_receiver_to_sender.send(true).await.unwrap();
+    let handle = {
+        let screen = screen.clone();
+        let direct_messages = direct_messages.clone();
+        let mut message_sender = message_sender.clone();
+
+        tokio::spawn(async move {
+            // removes typing indicators of users who stopped sending them
+            let mut typing_expiry = time::interval(Duration::from_secs(1));
+
+            loop {
+                select! {
+                    Some(_) = rx_sender_to_receiver.recv() => {
+                        break;
+                    }
+                    _ = typing_expiry.tick() => {
+                        let mut screen = screen.lock().unwrap();
 
-                                // in sender handler is waiting on io::stdin().read_line method so user has
-                                // to press enter
-                                println!("Press 'enter' to close client.");
+                        if screen.typing_users().remove_expired(Instant::now()) {
+                            let _ = screen.redraw();
+                        }
+                    }
+                    Some(message) = rx_messages.recv() => {
+                        // messages received while user was offline are printed together
+                        if let MessageType::OfflineMessages(messages) = message.message {
+                            let res = {
+                                let mut direct_messages = direct_messages.lock().unwrap();
+
+                                screen
+                                    .lock()
+                                    .unwrap()
+                                    .print_above(|| handle_offline_messages(messages, &mut direct_messages))
+                            };
+
+                            if let Err(ReceiveMessageError::Server) = res {
+                                tx_receiver_to_sender.send(true).await.unwrap();
 
                                 break;
                             }
-                            _ => {}
+
+                            continue;
+                        }
+
+                        // public keys and direct messages need keys of user
+                        let handled = direct_messages.lock().unwrap().handle_message(&message);
+
+                        if let Some((lines, replies)) = handled {
+                            let _ = screen.lock().unwrap().print_above(|| print_lines(&lines));
+
+                            for reply in replies {
+                                let _ = message_sender.send_message(&Message::from(reply)).await;
+                            }
+
+                            continue;
+                        }
+
+                        let res = {
+                            let mut screen = screen.lock().unwrap();
+
+                            match message.message {
+                                MessageType::UserTyping(is_typing) => {
+                                    screen
+                                        .typing_users()
+                                        .set_typing(&message.user_info.username, is_typing, Instant::now());
+                                    screen.redraw().map_err(ReceiveMessageError::from)
+                                }
+                                _ => {
+                                    // user who sent message is not typing anymore
+                                    screen
+                                        .typing_users()
+                                        .set_typing(&message.user_info.username, false, Instant::now());
+                                    screen.print_above(|| handle_message(message))
+                                }
+                            }
+                        };
+
+                        if let Err(ReceiveMessageError::Server) = res {
+                            tx_receiver_to_sender.send(true).await.unwrap();
+
+                            break;
                         }
                     }
-                }
-            };
-        }
-    });
+                };
+            }
+        })
+    };
 
     // request old messages from server
     message_sender
@@ -205,27 +273,83 @@ async fn main() -> Result<()> {
         .await
         .unwrap();
 
+    // raw mode is needed to know what user is typing before pressing enter
+    enable_raw_mode()?;
+    screen.lock().unwrap().redraw()?;
+
+    let mut events = EventStream::new();
+    let mut typing_notifier = TypingNotifier::default();
+
     // loop for handling user input
     loop {
-        let mut input = String::new();
-        let mut reader = BufReader::new(aio::stdin());
-
         select! {
             Some(_) = rx_receiver_to_sender.recv() => {
                 break;
             }
-            Ok(_) = reader.read_line(&mut input) => {
-                remove_new_line(&mut input);
+            Some(Ok(Event::Key(key))) = events.next() => {
+                let (action, input_is_empty) = {
+                    let mut screen = screen.lock().unwrap();
+                    let action = screen.handle_key(key);
+
+                    if action == InputAction::Changed {
+                        screen.redraw()?;
+                    }
+
+                    (action, screen.input_is_empty())
+                };
+
+                // notify other users that this user is (or is not anymore) typing
+                if let Some(is_typing) = typing_notifier.update(input_is_empty, Instant::now()) {
+                    let _ = message_sender
+                        .send_message(&Message::from(MessageType::UserTyping(is_typing)))
+                        .await;
+                }
+
+                let input = match action {
+                    InputAction::None | InputAction::Changed => continue,
+                    InputAction::Submit(input) => input,
+                    InputAction::Quit => ".quit".to_string(),
+                };
+
+                // input line is cleared after submitting, so print submitted line above it
+                screen.lock().unwrap().print_above(|| {
+                    println!("> {}", mask_passwords(&input));
+                    Ok::<(), io::Error>(())
+                })?;
+
+                // direct message commands need keys of user
+                let handled = CommandType::from_str(&input)
+                    .ok()
+                    .and_then(|command| direct_messages.lock().unwrap().handle_command(&command));
+
+                if let Some((lines, messages)) = handled {
+                    screen.lock().unwrap().print_above(|| print_lines(&lines))?;
+
+                    for message in messages {
+                        let _ = message_sender.send_message(&Message::from(message)).await;
+                    }
+
+                    continue;
+                }
 
                 let should_quit = match handle_send_message(&mut message_sender, &input).await {
                     Ok(o) => o,
                     Err(e) => {
-                        print_colored_string_to_stdout(e.to_string().as_str(), Color::Red)?;
+                        screen.lock().unwrap().print_above(|| {
+                            print_colored_string_to_stdout(e.to_string().as_str(), Color::Red)?;
+                            println!();
+                            Ok::<(), io::Error>(())
+                        })?;
                         continue;
                     }
                 };
 
                 if should_quit {
+                    // session ended on server, its token is useless
+                    if let Ok(CommandType::Logout) = CommandType::from_str(&input) {
+                        remove_cached_token(&token_cache, &server);
+                    }
+
                     tx_sender_to_receiver.send(true).await.unwrap();
                     break;
                 }
@@ -233,6 +357,8 @@ async fn main() -> Result<()> {
         }
     }
 
+    disable_raw_mode()?;
+
     // wait until is task completed
     handle.await.unwrap();
 
@@ -241,3 +367,27 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn print_lines(lines: &[String]) -> io::Result<()> {
+    for line in lines {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn save_cached_token(token_cache: &Option<TokenCache>, server: &str, token: &str) {
+    if let Some(cache) = token_cache {
+        if let Err(e) = cache.save(server, token) {
+            println!("Could not save session token: {}", e);
+        }
+    }
+}
+
+fn remove_cached_token(token_cache: &Option<TokenCache>, server: &str) {
+    if let Some(cache) = token_cache {
+        if let Err(e) = cache.remove(server) {
+            println!("Could not remove session token: {}", e);
+        }
+    }
+}
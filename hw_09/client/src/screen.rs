@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
+};
+
+use crate::typing::TypingUsers;
+
+#[derive(Debug, PartialEq)]
+pub enum InputAction {
+    None,
+    Changed,
+    Submit(String),
+    Quit,
+}
+
+/// Bottom line of the terminal with status area and line that user is writing.
+///
+/// Terminal is in raw mode while user is writing (otherwise it would not be possible to know what
+/// is user typing before pressing enter), so input line has to be rendered by client.
+#[derive(Default)]
+pub struct Screen {
+    input: String,
+    typing_users: TypingUsers,
+}
+
+impl Screen {
+    pub fn input_is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn typing_users(&mut self) -> &mut TypingUsers {
+        &mut self.typing_users
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> InputAction {
+        if key.kind != KeyEventKind::Press {
+            return InputAction::None;
+        }
+
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d')
+                if key.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                InputAction::Quit
+            }
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                InputAction::Changed
+            }
+            KeyCode::Backspace => match self.input.pop() {
+                Some(_) => InputAction::Changed,
+                None => InputAction::None,
+            },
+            KeyCode::Esc if !self.input.is_empty() => {
+                self.input.clear();
+                InputAction::Changed
+            }
+            KeyCode::Enter if !self.input.is_empty() => {
+                InputAction::Submit(std::mem::take(&mut self.input))
+            }
+            _ => InputAction::None,
+        }
+    }
+
+    pub fn redraw(&self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout();
+
+        queue!(stdout, Print("\r"), Clear(ClearType::CurrentLine))?;
+
+        if let Some(status) = self.typing_users.status_text() {
+            queue!(
+                stdout,
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("({}) ", status)),
+                ResetColor
+            )?;
+        }
+
+        queue!(stdout, Print("> "), Print(&self.input))?;
+
+        stdout.flush()
+    }
+
+    /// Clears input line, leaves raw mode and calls `print`. After that renders input line again.
+    pub fn print_above<T, E>(&self, print: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<io::Error>,
+    {
+        queue!(io::stdout(), Print("\r"), Clear(ClearType::CurrentLine))?;
+        disable_raw_mode()?;
+
+        let result = print();
+
+        enable_raw_mode()?;
+        self.redraw()?;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn typing_and_enter_submits_input() {
+        let mut screen = Screen::default();
+
+        assert_eq!(
+            screen.handle_key(press(KeyCode::Char('h'))),
+            InputAction::Changed
+        );
+        assert_eq!(
+            screen.handle_key(press(KeyCode::Char('i'))),
+            InputAction::Changed
+        );
+        assert!(!screen.input_is_empty());
+        assert_eq!(
+            screen.handle_key(press(KeyCode::Enter)),
+            InputAction::Submit("hi".to_string())
+        );
+        assert!(screen.input_is_empty());
+    }
+
+    #[test]
+    fn enter_on_empty_input_does_nothing() {
+        let mut screen = Screen::default();
+
+        assert_eq!(screen.handle_key(press(KeyCode::Enter)), InputAction::None);
+        assert_eq!(
+            screen.handle_key(press(KeyCode::Backspace)),
+            InputAction::None
+        );
+    }
+
+    #[test]
+    fn backspace_and_escape_edit_input() {
+        let mut screen = Screen::default();
+
+        screen.handle_key(press(KeyCode::Char('a')));
+        screen.handle_key(press(KeyCode::Char('b')));
+
+        assert_eq!(
+            screen.handle_key(press(KeyCode::Backspace)),
+            InputAction::Changed
+        );
+        assert_eq!(screen.input, "a");
+        assert_eq!(screen.handle_key(press(KeyCode::Esc)), InputAction::Changed);
+        assert!(screen.input_is_empty());
+    }
+
+    #[test]
+    fn ctrl_c_quits() {
+        let mut screen = Screen::default();
+
+        assert_eq!(
+            screen.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            InputAction::Quit
+        );
+    }
+}
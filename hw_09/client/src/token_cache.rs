@@ -0,0 +1,133 @@
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Write},
+    path::PathBuf,
+};
+
+/// File with session tokens, one line `<server> <token>` for every server, so user does not
+/// have to enter password again.
+pub struct TokenCache {
+    path: PathBuf,
+}
+
+impl TokenCache {
+    pub fn new(path: PathBuf) -> Self {
+        TokenCache { path }
+    }
+
+    /// Returns `$HOME/.chat_tokens`, `None` when home directory is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".chat_tokens"))
+    }
+
+    /// Returns cached token for server, missing or unreadable file means no token.
+    pub fn load(&self, server: &str) -> Option<String> {
+        self.entries()
+            .ok()?
+            .into_iter()
+            .find(|(entry_server, _)| entry_server == server)
+            .map(|(_, token)| token)
+    }
+
+    /// Stores token for server, previous token of this server is replaced.
+    pub fn save(&self, server: &str, token: &str) -> io::Result<()> {
+        let mut entries = self.entries()?;
+
+        entries.retain(|(entry_server, _)| entry_server != server);
+        entries.push((server.to_string(), token.to_string()));
+
+        self.write(&entries)
+    }
+
+    /// Removes token of server, for example after logout or when server does not accept it.
+    pub fn remove(&self, server: &str) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let count = entries.len();
+
+        entries.retain(|(entry_server, _)| entry_server != server);
+
+        match entries.len() == count {
+            true => Ok(()),
+            false => self.write(&entries),
+        }
+    }
+
+    fn entries(&self) -> io::Result<Vec<(String, String)>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        // invalid lines are skipped
+        Ok(content
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(server, token)| (server.to_string(), token.trim().to_string()))
+            .collect())
+    }
+
+    fn write(&self, entries: &[(String, String)]) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        // tokens are as secret as passwords, only owner can read them
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&self.path)?;
+
+        for (server, token) in entries {
+            writeln!(file, "{} {}", server, token)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(name: &str) -> TokenCache {
+        let path = env::temp_dir().join(format!("chat_tokens_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+
+        TokenCache::new(path)
+    }
+
+    #[test]
+    fn load_from_missing_file_returns_none() {
+        let cache = cache("missing");
+
+        assert_eq!(cache.load("localhost:11111"), None);
+    }
+
+    #[test]
+    fn save_replaces_token_of_same_server_only() {
+        let cache = cache("save");
+
+        cache.save("localhost:11111", "first").unwrap();
+        cache.save("example.com:11111", "other").unwrap();
+        cache.save("localhost:11111", "second").unwrap();
+
+        assert_eq!(cache.load("localhost:11111"), Some("second".to_string()));
+        assert_eq!(cache.load("example.com:11111"), Some("other".to_string()));
+
+        fs::remove_file(&cache.path).unwrap();
+    }
+
+    #[test]
+    fn remove_keeps_tokens_of_other_servers() {
+        let cache = cache("remove");
+
+        cache.save("localhost:11111", "token").unwrap();
+        cache.save("example.com:11111", "other").unwrap();
+        cache.remove("localhost:11111").unwrap();
+
+        assert_eq!(cache.load("localhost:11111"), None);
+        assert_eq!(cache.load("example.com:11111"), Some("other".to_string()));
+
+        fs::remove_file(&cache.path).unwrap();
+    }
+}
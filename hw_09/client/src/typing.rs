@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How often can client repeat typing notification while user is still typing.
+pub const TYPING_NOTIFY_INTERVAL: Duration = Duration::from_secs(3);
+
+/// After this time without new notification is user no longer considered as typing.
+pub const TYPING_EXPIRY: Duration = Duration::from_secs(5);
+
+/// Decides when typing notifications should be sent to server.
+///
+/// Notification `true` is sent when input line becomes non-empty and then repeated at most once
+/// per `TYPING_NOTIFY_INTERVAL` (other clients would expire it otherwise). Notification `false` is
+/// sent when input line becomes empty again (message was sent or text was deleted).
+#[derive(Default)]
+pub struct TypingNotifier {
+    last_sent: Option<Instant>,
+}
+
+impl TypingNotifier {
+    pub fn update(&mut self, input_is_empty: bool, now: Instant) -> Option<bool> {
+        if input_is_empty {
+            return self.last_sent.take().map(|_| false);
+        }
+
+        match self.last_sent {
+            Some(last_sent) if now.duration_since(last_sent) < TYPING_NOTIFY_INTERVAL => None,
+            _ => {
+                self.last_sent = Some(now);
+                Some(true)
+            }
+        }
+    }
+}
+
+/// Stores users who are typing right now together with time when their indicator expires.
+#[derive(Default)]
+pub struct TypingUsers {
+    users: HashMap<String, Instant>,
+}
+
+impl TypingUsers {
+    pub fn set_typing(&mut self, username: &str, is_typing: bool, now: Instant) {
+        if is_typing {
+            self.users.insert(username.to_string(), now + TYPING_EXPIRY);
+        } else {
+            self.users.remove(username);
+        }
+    }
+
+    /// Removes expired indicators, returns true if some indicator was removed.
+    pub fn remove_expired(&mut self, now: Instant) -> bool {
+        let count = self.users.len();
+
+        self.users.retain(|_, expires_at| *expires_at > now);
+
+        count != self.users.len()
+    }
+
+    pub fn status_text(&self) -> Option<String> {
+        let mut usernames: Vec<&str> = self.users.keys().map(|s| s.as_str()).collect();
+        usernames.sort();
+
+        match usernames.as_slice() {
+            [] => None,
+            [username] => Some(format!("{} is typing…", username)),
+            [first, second] => Some(format!("{} and {} are typing…", first, second)),
+            _ => Some(format!("{} people are typing…", usernames.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_notifier_is_rate_limited() {
+        let mut notifier = TypingNotifier::default();
+        let now = Instant::now();
+
+        assert_eq!(notifier.update(false, now), Some(true));
+        assert_eq!(notifier.update(false, now + Duration::from_secs(1)), None);
+        assert_eq!(
+            notifier.update(false, now + TYPING_NOTIFY_INTERVAL),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn typing_notifier_sends_stop_only_after_start() {
+        let mut notifier = TypingNotifier::default();
+        let now = Instant::now();
+
+        assert_eq!(notifier.update(true, now), None);
+        assert_eq!(notifier.update(false, now), Some(true));
+        assert_eq!(notifier.update(true, now), Some(false));
+        assert_eq!(notifier.update(true, now), None);
+    }
+
+    #[test]
+    fn typing_users_expire() {
+        let mut typing_users = TypingUsers::default();
+        let now = Instant::now();
+
+        typing_users.set_typing("alice", true, now);
+
+        assert_eq!(
+            typing_users.status_text(),
+            Some("alice is typing…".to_string())
+        );
+        assert!(!typing_users.remove_expired(now + Duration::from_secs(1)));
+        assert!(typing_users.remove_expired(now + TYPING_EXPIRY));
+        assert_eq!(typing_users.status_text(), None);
+    }
+
+    #[test]
+    fn typing_users_status_text_lists_users() {
+        let mut typing_users = TypingUsers::default();
+        let now = Instant::now();
+
+        typing_users.set_typing("bob", true, now);
+        typing_users.set_typing("alice", true, now);
+
+        assert_eq!(
+            typing_users.status_text(),
+            Some("alice and bob are typing…".to_string())
+        );
+
+        typing_users.set_typing("carol", true, now);
+
+        assert_eq!(
+            typing_users.status_text(),
+            Some("3 people are typing…".to_string())
+        );
+
+        typing_users.set_typing("carol", false, now);
+        typing_users.set_typing("bob", false, now);
+
+        assert_eq!(
+            typing_users.status_text(),
+            Some("alice is typing…".to_string())
+        );
+    }
+}
@@ -0,0 +1,3 @@
+-- usernames which differ only in case would be confused, duplicates have to be renamed before
+-- running this migration
+CREATE UNIQUE INDEX users_username_lower_idx ON users (LOWER(username));
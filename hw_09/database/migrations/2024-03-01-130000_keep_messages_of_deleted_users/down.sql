@@ -0,0 +1,2 @@
+DELETE FROM messages WHERE user_id IS NULL;
+ALTER TABLE messages ALTER COLUMN user_id SET NOT NULL;
@@ -0,0 +1,2 @@
+-- messages of deleted accounts are kept without author
+ALTER TABLE messages ALTER COLUMN user_id DROP NOT NULL;
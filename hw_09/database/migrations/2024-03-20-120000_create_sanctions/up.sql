@@ -0,0 +1,14 @@
+CREATE TABLE sanctions (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR(8) NOT NULL CHECK (kind IN ('ban', 'mute')),
+    user_id INT REFERENCES users(id) ON DELETE CASCADE,
+    ip VARCHAR(45),
+    reason TEXT NOT NULL,
+    created_by INT REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP,
+    CHECK (user_id IS NOT NULL OR ip IS NOT NULL)
+);
+
+CREATE INDEX sanctions_user_id_idx ON sanctions (user_id);
+CREATE INDEX sanctions_ip_idx ON sanctions (ip);
@@ -0,0 +1,2 @@
+DROP TABLE direct_messages;
+DROP TABLE public_keys;
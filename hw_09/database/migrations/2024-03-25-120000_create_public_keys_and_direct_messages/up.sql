@@ -0,0 +1,18 @@
+CREATE TABLE public_keys (
+    user_id INT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    public_key BYTEA NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE direct_messages (
+    id SERIAL PRIMARY KEY,
+    sender_id INT REFERENCES users(id) ON DELETE SET NULL,
+    recipient_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    sender_key BYTEA NOT NULL,
+    recipient_key BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    ciphertext BYTEA NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX direct_messages_recipient_id_idx ON direct_messages (recipient_id);
@@ -0,0 +1,12 @@
+-- direct messages and mentions for users who were offline, delivered at their next login
+CREATE TABLE deliveries (
+    id SERIAL PRIMARY KEY,
+    recipient_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id INT REFERENCES messages(id) ON DELETE CASCADE,
+    direct_message_id INT REFERENCES direct_messages(id) ON DELETE CASCADE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    delivered_at TIMESTAMP,
+    CHECK ((message_id IS NULL) <> (direct_message_id IS NULL))
+);
+
+CREATE INDEX deliveries_pending_idx ON deliveries (recipient_id) WHERE delivered_at IS NULL;
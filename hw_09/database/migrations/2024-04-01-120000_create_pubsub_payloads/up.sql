@@ -0,0 +1,6 @@
+-- payloads of events between server instances which are too long for NOTIFY
+CREATE TABLE pubsub_payloads (
+    id SERIAL PRIMARY KEY,
+    payload TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
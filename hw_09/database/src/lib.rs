@@ -1,14 +1,4 @@
-use std::env;
-
-use diesel::{pg::PgConnection, Connection};
-use dotenvy::dotenv;
-
 pub mod models;
+pub mod pubsub;
 pub mod schema;
-
-pub fn establish_connection() -> PgConnection {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").unwrap();
-    PgConnection::establish(&database_url).unwrap()
-}
+pub mod store;
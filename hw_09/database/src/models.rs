@@ -2,10 +2,24 @@ use std::time::SystemTime;
 
 use diesel::prelude::*;
 
-use crate::schema::{colors, messages, users};
-use libs::password::{hash_password, verify_password};
+use crate::{
+    schema::{
+        colors, deliveries, direct_messages, messages, public_keys, sanctions, sessions, users,
+    },
+    store::MessageFilter,
+};
+use libs::role::Role;
 
-#[derive(Queryable, Selectable)]
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+/// Username shown as author of messages whose author deleted his account (their `user_id` is
+/// null).
+pub const DELETED_USERNAME: &str = "<deleted user>";
+
+/// Color of users without row in `colors` and of deleted users.
+pub const DEFAULT_COLOR: (u8, u8, u8) = (255, 255, 255);
+
+#[derive(Queryable, Selectable, Clone, Debug)]
 #[diesel(table_name = colors)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Color {
@@ -22,14 +36,10 @@ impl Color {
     ) -> Result<Color, diesel::result::Error> {
         use crate::schema::colors::dsl::*;
 
-        Ok(colors
+        colors
             .filter(id.eq(color_id))
-            .limit(1)
             .select(Color::as_select())
-            .load(connection)
-            .unwrap()
-            .pop()
-            .unwrap())
+            .first(connection)
     }
 }
 
@@ -50,12 +60,13 @@ impl ColorNew {
     }
 }
 
-#[derive(Queryable, Selectable)]
+#[derive(Queryable, Selectable, Clone, Debug)]
 #[diesel(table_name = messages)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Message {
     pub id: i32,
-    pub user_id: i32,
+    /// `None` when author deleted his account.
+    pub user_id: Option<i32>,
     pub text: String,
     pub created_at: SystemTime,
 }
@@ -72,6 +83,92 @@ impl Message {
             .select(Message::as_select())
             .load(connection)
     }
+
+    /// Reads newest messages, optionally only messages of one user.
+    pub fn read_filtered(
+        connection: &mut PgConnection,
+        author_id: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<Message>, diesel::result::Error> {
+        use crate::schema::messages::dsl::*;
+
+        let mut query = messages.into_boxed();
+
+        if let Some(author_id) = author_id {
+            query = query.filter(user_id.eq(author_id));
+        }
+
+        query
+            .order((created_at.desc(), id.desc()))
+            .limit(limit)
+            .select(Message::as_select())
+            .load(connection)
+    }
+
+    /// Reads all messages selected by filter, oldest first.
+    pub fn read_matching(
+        connection: &mut PgConnection,
+        filter: &MessageFilter,
+    ) -> Result<Vec<Message>, diesel::result::Error> {
+        use crate::schema::messages::dsl::*;
+
+        let mut query = messages.into_boxed();
+
+        if let Some(author_id) = filter.author_id {
+            query = query.filter(user_id.eq(author_id));
+        }
+
+        if let Some(since) = filter.since {
+            query = query.filter(created_at.ge(since));
+        }
+
+        if let Some(until) = filter.until {
+            query = query.filter(created_at.lt(until));
+        }
+
+        query
+            .order((created_at.asc(), id.asc()))
+            .select(Message::as_select())
+            .load(connection)
+    }
+
+    /// Deletes message, returns number of deleted messages (0 when message does not exist).
+    pub fn delete(
+        connection: &mut PgConnection,
+        message_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::messages::dsl::*;
+
+        diesel::delete(messages.filter(id.eq(message_id))).execute(connection)
+    }
+
+    /// Deletes messages with given ids, returns number of deleted messages.
+    pub fn delete_many(
+        connection: &mut PgConnection,
+        message_ids: &[i32],
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::messages::dsl::*;
+
+        diesel::delete(messages.filter(id.eq_any(message_ids))).execute(connection)
+    }
+
+    /// Returns all messages except `keep` newest ones in order in which they were sent.
+    pub fn read_except_newest(
+        connection: &mut PgConnection,
+        keep: i64,
+    ) -> Result<Vec<Message>, diesel::result::Error> {
+        use crate::schema::messages::dsl::*;
+
+        let mut older = messages
+            .order((created_at.desc(), id.desc()))
+            .offset(keep.max(0))
+            .select(Message::as_select())
+            .load(connection)?;
+
+        older.reverse();
+
+        Ok(older)
+    }
 }
 
 #[derive(Insertable)]
@@ -90,7 +187,25 @@ impl MessageNew {
     }
 }
 
-#[derive(Queryable, Selectable)]
+/// Message restored from backup with its original time.
+#[derive(Insertable)]
+#[diesel(table_name = messages)]
+pub struct MessageImport {
+    pub user_id: Option<i32>,
+    pub text: String,
+    pub created_at: SystemTime,
+}
+
+impl MessageImport {
+    pub fn insert(&self, connection: &mut PgConnection) -> Result<Message, diesel::result::Error> {
+        diesel::insert_into(messages::table)
+            .values(self)
+            .returning(Message::as_returning())
+            .get_result(connection)
+    }
+}
+
+#[derive(Queryable, Selectable, Clone, Debug)]
 #[diesel(table_name = users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct User {
@@ -98,29 +213,37 @@ pub struct User {
     pub username: String,
     pub password: String,
     pub color_id: Option<i32>,
+    pub disabled: bool,
+    /// Name of role, use `User::role` to get it parsed.
+    pub role: String,
 }
 
 impl User {
+    /// Returns role of user, unknown role is treated as guest (role with least permissions).
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or(Role::Guest)
+    }
+
+    pub fn read_all(connection: &mut PgConnection) -> Result<Vec<User>, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        users
+            .order(id.asc())
+            .select(User::as_select())
+            .load(connection)
+    }
+
+    /// Reads user by username, case of letters does not matter.
     pub fn read(
         connection: &mut PgConnection,
         usernamee: &str,
     ) -> Result<User, diesel::result::Error> {
         use crate::schema::users::dsl::*;
 
-        let x = users
-            .filter(username.eq(usernamee))
-            .limit(1)
+        users
+            .filter(lower(username).eq(usernamee.to_lowercase()))
             .select(User::as_select())
-            .load(connection);
-
-        match x {
-            Ok(mut val) => {
-                return Ok(val.pop().unwrap());
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
+            .first(connection)
     }
 
     pub fn read_by_id(
@@ -129,34 +252,97 @@ impl User {
     ) -> Result<User, diesel::result::Error> {
         use crate::schema::users::dsl::*;
 
-        let x = users
+        users
             .filter(id.eq(user_id))
-            .limit(1)
             .select(User::as_select())
-            .load(connection);
+            .first(connection)
+    }
 
-        match x {
-            Ok(mut val) => {
-                return Ok(val.pop().unwrap());
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        }
+    /// Disables (or enables again) user, disabled user can not log in.
+    pub fn set_disabled(
+        connection: &mut PgConnection,
+        user_id: i32,
+        value: bool,
+    ) -> Result<User, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(disabled.eq(value))
+            .returning(User::as_returning())
+            .get_result(connection)
     }
 
-    pub fn login(
+    /// Sets role of user.
+    pub fn set_role(
         connection: &mut PgConnection,
-        username: &str,
-        password: &str,
-    ) -> Result<User, ()> {
-        let user = Self::read(connection, username).unwrap();
+        user_id: i32,
+        value: Role,
+    ) -> Result<User, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(role.eq(value.as_str()))
+            .returning(User::as_returning())
+            .get_result(connection)
+    }
+
+    /// Stores already hashed password.
+    pub fn update_password_hash(
+        connection: &mut PgConnection,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<User, diesel::result::Error> {
+        use crate::schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(password.eq(password_hash))
+            .returning(User::as_returning())
+            .get_result(connection)
+    }
+
+    /// Deletes user together with his messages, sessions and color.
+    pub fn delete(
+        connection: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<(), diesel::result::Error> {
+        connection.transaction(|connection| {
+            diesel::delete(messages::table.filter(messages::user_id.eq(user_id)))
+                .execute(connection)?;
+
+            Self::delete_with_sessions_and_color(connection, user_id)
+        })
+    }
+
+    /// Deletes user together with his sessions and color, his messages are kept without author.
+    pub fn delete_keep_messages(
+        connection: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<(), diesel::result::Error> {
+        connection.transaction(|connection| {
+            diesel::update(messages::table.filter(messages::user_id.eq(user_id)))
+                .set(messages::user_id.eq(None::<i32>))
+                .execute(connection)?;
 
-        if verify_password(password, user.password.as_str()).unwrap() {
-            return Ok(user);
+            Self::delete_with_sessions_and_color(connection, user_id)
+        })
+    }
+
+    fn delete_with_sessions_and_color(
+        connection: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<(), diesel::result::Error> {
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id)))
+            .execute(connection)?;
+
+        let user = diesel::delete(users::table.filter(users::id.eq(user_id)))
+            .returning(User::as_returning())
+            .get_result(connection)?;
+
+        if let Some(color_id) = user.color_id {
+            diesel::delete(colors::table.filter(colors::id.eq(color_id))).execute(connection)?;
         }
 
-        Err(())
+        Ok(())
     }
 }
 
@@ -165,35 +351,48 @@ impl User {
 pub struct UserNew {
     username: String,
     password: String,
-    color_id: i32,
+    color_id: Option<i32>,
 }
 
 impl UserNew {
-    pub fn register(
+    /// Inserts user with already hashed password and his color in one transaction.
+    pub fn insert_with_color(
         connection: &mut PgConnection,
         username: &str,
-        password: &str,
+        password_hash: &str,
         r: u8,
         g: u8,
         b: u8,
-    ) -> User {
-        let new_color = ColorNew {
-            r: r as i16,
-            g: g as i16,
-            b: b as i16,
-        };
-
-        let color = new_color.insert(connection).unwrap();
+    ) -> Result<User, diesel::result::Error> {
+        connection.transaction(|connection| {
+            let color = ColorNew {
+                r: r as i16,
+                g: g as i16,
+                b: b as i16,
+            }
+            .insert(connection)?;
 
-        let password_hash = hash_password(password).unwrap();
+            UserNew {
+                username: String::from(username),
+                password: String::from(password_hash),
+                color_id: Some(color.id),
+            }
+            .insert(connection)
+        })
+    }
 
-        let new_user = UserNew {
+    /// Inserts user with already hashed password without color (default color is used for him).
+    pub fn insert_without_color(
+        connection: &mut PgConnection,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<User, diesel::result::Error> {
+        UserNew {
             username: String::from(username),
-            password: password_hash,
-            color_id: color.id,
-        };
-
-        new_user.insert(connection).unwrap()
+            password: String::from(password_hash),
+            color_id: None,
+        }
+        .insert(connection)
     }
 
     fn insert(&self, connection: &mut PgConnection) -> Result<User, diesel::result::Error> {
@@ -203,3 +402,407 @@ impl UserNew {
             .get_result(connection)
     }
 }
+
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub last_used_at: SystemTime,
+}
+
+impl Session {
+    pub fn read_by_token_hash(
+        connection: &mut PgConnection,
+        hash: &str,
+    ) -> Result<Session, diesel::result::Error> {
+        use crate::schema::sessions::dsl::*;
+
+        sessions
+            .filter(token_hash.eq(hash))
+            .select(Session::as_select())
+            .first(connection)
+    }
+
+    pub fn read_by_user_id(
+        connection: &mut PgConnection,
+        owner_id: i32,
+    ) -> Result<Vec<Session>, diesel::result::Error> {
+        use crate::schema::sessions::dsl::*;
+
+        sessions
+            .filter(user_id.eq(owner_id))
+            .order(last_used_at.desc())
+            .select(Session::as_select())
+            .load(connection)
+    }
+
+    /// Sets time of last use to now.
+    pub fn touch(
+        connection: &mut PgConnection,
+        session_id: i32,
+    ) -> Result<Session, diesel::result::Error> {
+        use crate::schema::sessions::dsl::*;
+
+        diesel::update(sessions.filter(id.eq(session_id)))
+            .set(last_used_at.eq(SystemTime::now()))
+            .returning(Session::as_returning())
+            .get_result(connection)
+    }
+
+    /// Deletes session, returns number of deleted sessions (0 when session does not exist).
+    pub fn delete(
+        connection: &mut PgConnection,
+        session_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::sessions::dsl::*;
+
+        diesel::delete(sessions.filter(id.eq(session_id))).execute(connection)
+    }
+
+    /// Deletes sessions that expired before `now`, returns number of deleted sessions.
+    pub fn delete_expired(
+        connection: &mut PgConnection,
+        now: SystemTime,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::sessions::dsl::*;
+
+        diesel::delete(sessions.filter(expires_at.le(now))).execute(connection)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sessions)]
+pub struct SessionNew {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: SystemTime,
+}
+
+impl SessionNew {
+    pub fn insert(&self, connection: &mut PgConnection) -> Result<Session, diesel::result::Error> {
+        diesel::insert_into(sessions::table)
+            .values(self)
+            .returning(Session::as_returning())
+            .get_result(connection)
+    }
+}
+
+/// Kind of sanction, banned user can't log in and muted user can't post.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SanctionKind {
+    Ban,
+    Mute,
+}
+
+impl SanctionKind {
+    /// Name of kind stored in database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SanctionKind::Ban => "ban",
+            SanctionKind::Mute => "mute",
+        }
+    }
+}
+
+/// Ban or mute of user or IP address, sanction without `expires_at` is permanent.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = sanctions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Sanction {
+    pub id: i32,
+    pub kind: String,
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub reason: String,
+    /// Moderator who created sanction, `None` for server administrator.
+    pub created_by: Option<i32>,
+    pub created_at: SystemTime,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Sanction {
+    /// Kind of sanction, unknown kind is handled as ban.
+    pub fn kind(&self) -> SanctionKind {
+        match self.kind.as_str() {
+            "mute" => SanctionKind::Mute,
+            _ => SanctionKind::Ban,
+        }
+    }
+
+    /// Returns true when sanction did not expire at `now`.
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Reads sanctions that did not expire at `now`, newest first.
+    pub fn read_active(
+        connection: &mut PgConnection,
+        now: SystemTime,
+    ) -> Result<Vec<Sanction>, diesel::result::Error> {
+        use crate::schema::sanctions::dsl::*;
+
+        sanctions
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .order(id.desc())
+            .select(Sanction::as_select())
+            .load(connection)
+    }
+
+    /// Reads sanctions of `sanction_kind` that target user or IP address and did not expire at
+    /// `now`.
+    pub fn read_active_for(
+        connection: &mut PgConnection,
+        sanction_kind: SanctionKind,
+        target_user_id: Option<i32>,
+        target_ip: Option<&str>,
+        now: SystemTime,
+    ) -> Result<Vec<Sanction>, diesel::result::Error> {
+        use crate::schema::sanctions::dsl::*;
+
+        let query = sanctions
+            .filter(kind.eq(sanction_kind.as_str()))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .select(Sanction::as_select())
+            .into_boxed();
+
+        let query = match (target_user_id, target_ip) {
+            (Some(target_user_id), Some(target_ip)) => {
+                query.filter(user_id.eq(target_user_id).or(ip.eq(target_ip)))
+            }
+            (Some(target_user_id), None) => query.filter(user_id.eq(target_user_id)),
+            (None, Some(target_ip)) => query.filter(ip.eq(target_ip)),
+            (None, None) => return Ok(Vec::new()),
+        };
+
+        query.load(connection)
+    }
+
+    /// Deletes sanction, returns number of deleted sanctions (0 when sanction does not exist).
+    pub fn delete(
+        connection: &mut PgConnection,
+        sanction_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::sanctions::dsl::*;
+
+        diesel::delete(sanctions.filter(id.eq(sanction_id))).execute(connection)
+    }
+
+    /// Deletes all sanctions of `sanction_kind` that target user, returns number of deleted
+    /// sanctions.
+    pub fn delete_of_user(
+        connection: &mut PgConnection,
+        sanction_kind: SanctionKind,
+        target_user_id: i32,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::sanctions::dsl::*;
+
+        diesel::delete(
+            sanctions
+                .filter(kind.eq(sanction_kind.as_str()))
+                .filter(user_id.eq(target_user_id)),
+        )
+        .execute(connection)
+    }
+
+    /// Deletes sanctions that expired before `now`, returns number of deleted sanctions.
+    pub fn delete_expired(
+        connection: &mut PgConnection,
+        now: SystemTime,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::sanctions::dsl::*;
+
+        diesel::delete(sanctions.filter(expires_at.le(now))).execute(connection)
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = sanctions)]
+pub struct SanctionNew {
+    pub kind: String,
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub reason: String,
+    pub created_by: Option<i32>,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl SanctionNew {
+    pub fn insert(&self, connection: &mut PgConnection) -> Result<Sanction, diesel::result::Error> {
+        diesel::insert_into(sanctions::table)
+            .values(self)
+            .returning(Sanction::as_returning())
+            .get_result(connection)
+    }
+}
+
+/// Public key of user for end-to-end encryption of direct messages.
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = public_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PublicKey {
+    pub user_id: i32,
+    pub public_key: Vec<u8>,
+    pub updated_at: SystemTime,
+}
+
+impl PublicKey {
+    pub fn read(
+        connection: &mut PgConnection,
+        owner_id: i32,
+    ) -> Result<PublicKey, diesel::result::Error> {
+        use crate::schema::public_keys::dsl::*;
+
+        public_keys
+            .filter(user_id.eq(owner_id))
+            .select(PublicKey::as_select())
+            .first(connection)
+    }
+
+    /// Inserts public key of user or replaces his previous key.
+    pub fn upsert(
+        &self,
+        connection: &mut PgConnection,
+    ) -> Result<PublicKey, diesel::result::Error> {
+        use crate::schema::public_keys::dsl::*;
+
+        diesel::insert_into(public_keys)
+            .values(self)
+            .on_conflict(user_id)
+            .do_update()
+            .set((
+                public_key.eq(&self.public_key),
+                updated_at.eq(self.updated_at),
+            ))
+            .returning(PublicKey::as_returning())
+            .get_result(connection)
+    }
+}
+
+/// Direct message encrypted by sender for recipient, only ciphertext is stored.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = direct_messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DirectMessage {
+    pub id: i32,
+    /// `None` when sender deleted his account.
+    pub sender_id: Option<i32>,
+    pub recipient_id: i32,
+    pub sender_key: Vec<u8>,
+    pub recipient_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub created_at: SystemTime,
+}
+
+impl DirectMessage {
+    /// Reads messages received by user, oldest first.
+    pub fn read_by_recipient(
+        connection: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<DirectMessage>, diesel::result::Error> {
+        use crate::schema::direct_messages::dsl::*;
+
+        direct_messages
+            .filter(recipient_id.eq(user_id))
+            .order(id.asc())
+            .select(DirectMessage::as_select())
+            .load(connection)
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = direct_messages)]
+pub struct DirectMessageNew {
+    pub sender_id: i32,
+    pub recipient_id: i32,
+    pub sender_key: Vec<u8>,
+    pub recipient_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl DirectMessageNew {
+    pub fn insert(
+        &self,
+        connection: &mut PgConnection,
+    ) -> Result<DirectMessage, diesel::result::Error> {
+        diesel::insert_into(direct_messages::table)
+            .values(self)
+            .returning(DirectMessage::as_returning())
+            .get_result(connection)
+    }
+}
+
+/// Direct message or mention queued for user who was offline, it is delivered at his next login.
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Delivery {
+    pub id: i32,
+    pub recipient_id: i32,
+    /// Message in which recipient was mentioned.
+    pub message_id: Option<i32>,
+    pub direct_message_id: Option<i32>,
+    pub created_at: SystemTime,
+    /// `None` until delivery is sent to recipient.
+    pub delivered_at: Option<SystemTime>,
+}
+
+/// Delivery with its queued message or direct message (exactly one of them is set).
+pub type PendingDelivery = (Delivery, Option<Message>, Option<DirectMessage>);
+
+impl Delivery {
+    /// Reads deliveries of user that were not delivered yet with their messages, oldest first.
+    pub fn read_pending(
+        connection: &mut PgConnection,
+        user_id: i32,
+    ) -> Result<Vec<PendingDelivery>, diesel::result::Error> {
+        deliveries::table
+            .left_join(messages::table)
+            .left_join(direct_messages::table)
+            .filter(deliveries::recipient_id.eq(user_id))
+            .filter(deliveries::delivered_at.is_null())
+            .order(deliveries::id.asc())
+            .select((
+                Delivery::as_select(),
+                Option::<Message>::as_select(),
+                Option::<DirectMessage>::as_select(),
+            ))
+            .load(connection)
+    }
+
+    /// Marks deliveries as delivered, returns number of marked deliveries.
+    pub fn mark_delivered(
+        connection: &mut PgConnection,
+        delivery_ids: &[i32],
+        now: SystemTime,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::deliveries::dsl::*;
+
+        diesel::update(deliveries.filter(id.eq_any(delivery_ids)))
+            .set(delivered_at.eq(now))
+            .execute(connection)
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = deliveries)]
+pub struct DeliveryNew {
+    pub recipient_id: i32,
+    pub message_id: Option<i32>,
+    pub direct_message_id: Option<i32>,
+}
+
+impl DeliveryNew {
+    pub fn insert(&self, connection: &mut PgConnection) -> Result<Delivery, diesel::result::Error> {
+        diesel::insert_into(deliveries::table)
+            .values(self)
+            .returning(Delivery::as_returning())
+            .get_result(connection)
+    }
+}
@@ -0,0 +1,48 @@
+//! Provides `PubSub` trait, channel of events shared by server instances.
+//!
+//! Implementations:
+//!
+//! - `postgres::PgPubSub` - PostgreSQL `LISTEN`/`NOTIFY`, instances share database
+//! - `memory::MemoryPubSub` - instances in one process, for development and tests
+
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Error, Debug)]
+pub enum PubSubError {
+    #[error("Database error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("Connection to database was closed")]
+    Closed,
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Channel of text payloads, every payload is delivered to all subscribers of all instances,
+/// including subscribers of instance that published it.
+///
+/// # Example
+///
+/// ```
+/// use database::pubsub::{memory::MemoryPubSub, PubSub};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let pubsub = MemoryPubSub::default();
+///     let mut rx = pubsub.subscribe();
+///
+///     pubsub.publish("hello".to_string());
+///
+///     assert_eq!(rx.recv().await.unwrap(), "hello");
+/// }
+/// ```
+pub trait PubSub: Send + Sync {
+    /// Publishes payload in background, errors are only logged.
+    fn publish(&self, payload: String);
+
+    /// Returns receiver of payloads published after this call.
+    fn subscribe(&self) -> broadcast::Receiver<String>;
+}
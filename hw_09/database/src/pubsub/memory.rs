@@ -0,0 +1,31 @@
+use tokio::sync::broadcast;
+
+use crate::pubsub::PubSub;
+
+/// Number of payloads kept for slow subscribers.
+const CAPACITY: usize = 1024;
+
+/// `PubSub` of instances running in one process, clones share the channel.
+#[derive(Clone)]
+pub struct MemoryPubSub {
+    tx: broadcast::Sender<String>,
+}
+
+impl Default for MemoryPubSub {
+    fn default() -> Self {
+        MemoryPubSub {
+            tx: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl PubSub for MemoryPubSub {
+    fn publish(&self, payload: String) {
+        // nobody has to listen
+        let _ = self.tx.send(payload);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+}
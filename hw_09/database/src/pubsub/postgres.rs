@@ -0,0 +1,254 @@
+use std::{env, time::Duration};
+
+use dotenvy::dotenv;
+use futures_util::{stream, StreamExt};
+use tokio::{
+    select,
+    sync::{broadcast, mpsc},
+    time,
+};
+use tokio_postgres::{config::SslMode, AsyncMessage, Client, NoTls};
+use tracing::{error, info, warn};
+
+use crate::pubsub::{PubSub, PubSubError};
+
+/// Payload of `NOTIFY` has to be shorter than 8000 bytes, longer payloads are stored in
+/// `pubsub_payloads` table and only their id is sent.
+const NOTIFY_LIMIT: usize = 7900;
+
+/// Stored payloads are read by listeners immediately, older ones are deleted.
+const STORED_PAYLOAD_TTL: &str = "1 minute";
+
+/// Time between attempts to connect to database.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Number of payloads kept for slow subscribers.
+const CAPACITY: usize = 1024;
+
+/// Checks that pub/sub can connect to database at `database_url`. Its connection is not encrypted,
+/// so URL that requires TLS (`sslmode=require`) is refused instead of failing at every reconnect.
+///
+/// # Errors
+///
+/// Returns `PubSubError::Invalid` when URL can't be parsed or requires TLS.
+///
+/// # Example
+///
+/// ```
+/// use database::pubsub::postgres::check_database_url;
+///
+/// assert!(check_database_url("postgres://chat@localhost/chat").is_ok());
+/// assert!(check_database_url("postgres://chat@localhost/chat?sslmode=prefer").is_ok());
+/// assert!(check_database_url("postgres://chat@localhost/chat?sslmode=require").is_err());
+/// ```
+pub fn check_database_url(database_url: &str) -> Result<(), PubSubError> {
+    let config: tokio_postgres::Config = database_url
+        .parse()
+        .map_err(|e| PubSubError::Invalid(format!("Invalid database URL: {}", e)))?;
+
+    match config.get_ssl_mode() {
+        SslMode::Disable | SslMode::Prefer => Ok(()),
+        _ => Err(PubSubError::Invalid(
+            "Cluster connects to database without TLS, database URL must not require it \
+             (sslmode=require)"
+                .to_string(),
+        )),
+    }
+}
+
+/// `PubSub` backed by PostgreSQL `LISTEN`/`NOTIFY`, every instance connected to the same database
+/// and channel receives all payloads.
+///
+/// Payloads are published and received by background task with its own connection, connection
+/// is opened again when it is lost (payloads published meanwhile wait, payloads of others are
+/// lost).
+pub struct PgPubSub {
+    publish_tx: mpsc::UnboundedSender<String>,
+    tx: broadcast::Sender<String>,
+}
+
+impl PgPubSub {
+    /// Starts background task listening on `channel`, it has to be called inside tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PubSubError::Invalid` when channel is not lowercase identifier (letters, digits and
+    /// `_`) or when database URL is refused by `check_database_url`, connection errors are only
+    /// logged and connection is retried.
+    pub fn connect(database_url: &str, channel: &str) -> Result<PgPubSub, PubSubError> {
+        check_database_url(database_url)?;
+
+        let valid = channel.starts_with(|c: char| c.is_ascii_lowercase())
+            && channel
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+        if !valid {
+            return Err(PubSubError::Invalid(format!(
+                "Invalid channel '{}', use lowercase letters, digits and '_'",
+                channel
+            )));
+        }
+
+        let (publish_tx, publish_rx) = mpsc::unbounded_channel();
+        let (tx, _) = broadcast::channel(CAPACITY);
+
+        tokio::spawn(run(
+            database_url.to_string(),
+            channel.to_string(),
+            publish_rx,
+            tx.clone(),
+        ));
+
+        Ok(PgPubSub { publish_tx, tx })
+    }
+
+    /// Same as `connect` with database set in `DATABASE_URL` environment variable (or `.env` file).
+    pub fn connect_from_env(channel: &str) -> Result<PgPubSub, PubSubError> {
+        dotenv().ok();
+
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| PubSubError::Invalid("DATABASE_URL is not set".to_string()))?;
+
+        Self::connect(&database_url, channel)
+    }
+}
+
+impl PubSub for PgPubSub {
+    fn publish(&self, payload: String) {
+        // background task ends only when this structure is dropped
+        let _ = self.publish_tx.send(payload);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+}
+
+/// Keeps connection to database open until all publishers are dropped.
+async fn run(
+    database_url: String,
+    channel: String,
+    mut publish_rx: mpsc::UnboundedReceiver<String>,
+    tx: broadcast::Sender<String>,
+) {
+    loop {
+        match listen(&database_url, &channel, &mut publish_rx, &tx).await {
+            Ok(()) => return,
+            Err(e) => {
+                error!(event = "error", error = %e, channel, "Pub/sub connection failed.");
+
+                time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Listens on channel and publishes payloads until connection fails (`Err`) or all publishers
+/// are dropped (`Ok`).
+async fn listen(
+    database_url: &str,
+    channel: &str,
+    publish_rx: &mut mpsc::UnboundedReceiver<String>,
+    tx: &broadcast::Sender<String>,
+) -> Result<(), PubSubError> {
+    let (client, mut connection) = tokio_postgres::connect(database_url, NoTls).await?;
+
+    // connection has to be polled by other task, queries of client would wait forever otherwise
+    let (notification_tx, mut notification_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+
+        while let Some(Ok(message)) = messages.next().await {
+            if let AsyncMessage::Notification(notification) = message {
+                if notification_tx
+                    .send(notification.payload().to_string())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+    });
+
+    // channel is validated, it can not contain quotes
+    client.batch_execute(&format!("LISTEN {}", channel)).await?;
+
+    info!(channel, "Listening to other server instances.");
+
+    loop {
+        select! {
+            payload = publish_rx.recv() => match payload {
+                Some(payload) => notify(&client, channel, payload).await?,
+                None => return Ok(()),
+            },
+            payload = notification_rx.recv() => match payload {
+                Some(payload) => {
+                    if let Some(payload) = load(&client, payload).await? {
+                        // nobody has to listen
+                        let _ = tx.send(payload);
+                    }
+                }
+                None => return Err(PubSubError::Closed),
+            },
+        }
+    }
+}
+
+async fn notify(client: &Client, channel: &str, payload: String) -> Result<(), PubSubError> {
+    let payload = match payload.len() <= NOTIFY_LIMIT {
+        true => payload,
+        false => {
+            client
+                .execute(
+                    &format!(
+                        "DELETE FROM pubsub_payloads WHERE created_at < NOW() - INTERVAL '{}'",
+                        STORED_PAYLOAD_TTL
+                    ),
+                    &[],
+                )
+                .await?;
+
+            let row = client
+                .query_one(
+                    "INSERT INTO pubsub_payloads (payload) VALUES ($1) RETURNING id",
+                    &[&payload],
+                )
+                .await?;
+
+            format!("@{}", row.get::<_, i32>(0))
+        }
+    };
+
+    client
+        .execute("SELECT pg_notify($1, $2)", &[&channel, &payload])
+        .await?;
+
+    Ok(())
+}
+
+/// Returns stored payload when notification contains only its id (`@<id>`). Returns `None` when
+/// stored payload does not exist anymore (it was deleted after `STORED_PAYLOAD_TTL`), such
+/// notification is skipped, so it can not stop listening.
+async fn load(client: &Client, payload: String) -> Result<Option<String>, PubSubError> {
+    let Some(id) = payload
+        .strip_prefix('@')
+        .and_then(|id| id.parse::<i32>().ok())
+    else {
+        return Ok(Some(payload));
+    };
+
+    let row = client
+        .query_opt("SELECT payload FROM pubsub_payloads WHERE id = $1", &[&id])
+        .await?;
+
+    if row.is_none() {
+        warn!(
+            id,
+            "Stored payload of notification does not exist, notification is skipped."
+        );
+    }
+
+    Ok(row.map(|row| row.get(0)))
+}
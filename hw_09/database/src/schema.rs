@@ -9,15 +9,82 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    deliveries (id) {
+        id -> Int4,
+        recipient_id -> Int4,
+        message_id -> Nullable<Int4>,
+        direct_message_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    direct_messages (id) {
+        id -> Int4,
+        sender_id -> Nullable<Int4>,
+        recipient_id -> Int4,
+        sender_key -> Bytea,
+        recipient_key -> Bytea,
+        nonce -> Bytea,
+        ciphertext -> Bytea,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     messages (id) {
         id -> Int4,
-        user_id -> Int4,
+        user_id -> Nullable<Int4>,
         text -> Text,
         created_at -> Timestamp,
     }
 }
 
+diesel::table! {
+    public_keys (user_id) {
+        user_id -> Int4,
+        public_key -> Bytea,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    pubsub_payloads (id) {
+        id -> Int4,
+        payload -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sanctions (id) {
+        id -> Int4,
+        #[max_length = 8]
+        kind -> Varchar,
+        user_id -> Nullable<Int4>,
+        #[max_length = 45]
+        ip -> Nullable<Varchar>,
+        reason -> Text,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        last_used_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -26,14 +93,28 @@ diesel::table! {
         #[max_length = 255]
         password -> Varchar,
         color_id -> Nullable<Int4>,
+        disabled -> Bool,
+        #[max_length = 16]
+        role -> Varchar,
     }
 }
 
+diesel::joinable!(deliveries -> direct_messages (direct_message_id));
+diesel::joinable!(deliveries -> messages (message_id));
+diesel::joinable!(deliveries -> users (recipient_id));
 diesel::joinable!(messages -> users (user_id));
+diesel::joinable!(public_keys -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
 diesel::joinable!(users -> colors (color_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     colors,
+    deliveries,
+    direct_messages,
     messages,
+    public_keys,
+    pubsub_payloads,
+    sanctions,
+    sessions,
     users,
 );
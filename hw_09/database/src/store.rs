@@ -0,0 +1,733 @@
+//! Provides `ChatStore` trait, storage of users, colors, messages, sessions, sanctions, public keys,
+//! encrypted direct messages and deliveries for offline users used by the server.
+//!
+//! Implementations:
+//!
+//! - `postgres::PgStore` - PostgreSQL database (tables from `migrations`)
+//! - `memory::MemoryStore` - data are kept only in memory, for development and tests
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+use thiserror::Error;
+
+use libs::{
+    password::{hash_password, needs_rehash, verify_password},
+    role::Role,
+    token::{generate_token, hash_token},
+};
+
+use crate::models::{
+    Color, Delivery, DeliveryNew, DirectMessage, DirectMessageNew, Message, PendingDelivery,
+    PublicKey, Sanction, SanctionKind, SanctionNew, Session, User,
+};
+
+pub mod memory;
+pub mod postgres;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Record not found")]
+    NotFound,
+    #[error("Database error: {0}")]
+    Database(diesel::result::Error),
+    #[error("Could not connect to database: {0}")]
+    Connection(String),
+    #[error("Could not hash password: {0}")]
+    Password(String),
+    #[error("Username is already taken")]
+    UsernameTaken,
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Password is not correct")]
+    WrongPassword,
+}
+
+impl From<diesel::result::Error> for StoreError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::NotFound => StoreError::NotFound,
+            error => StoreError::Database(error),
+        }
+    }
+}
+
+/// Returns hash that is verified instead of password of unknown user (result is ignored).
+fn dummy_password_hash() -> Result<&'static str, StoreError> {
+    static DUMMY_PASSWORD_HASH: OnceLock<String> = OnceLock::new();
+
+    if let Some(hash) = DUMMY_PASSWORD_HASH.get() {
+        return Ok(hash);
+    }
+
+    let hash = hash_password("dummy password").map_err(|e| StoreError::Password(e.to_string()))?;
+
+    Ok(DUMMY_PASSWORD_HASH.get_or_init(|| hash))
+}
+
+/// Usernames longer than this are rejected at registration.
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Passwords shorter than this are rejected at registration and password change.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Checks that username can be registered, returns `StoreError::Invalid` with reason otherwise.
+///
+/// # Example
+///
+/// ```
+/// use database::store::validate_username;
+///
+/// assert!(validate_username("alice").is_ok());
+/// assert!(validate_username("").is_err());
+/// assert!(validate_username("alice smith").is_err());
+/// ```
+pub fn validate_username(username: &str) -> Result<(), StoreError> {
+    if username.is_empty() {
+        return Err(StoreError::Invalid(
+            "Username must not be empty".to_string(),
+        ));
+    }
+
+    if username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(StoreError::Invalid(format!(
+            "Username must have at most {} characters",
+            MAX_USERNAME_LENGTH
+        )));
+    }
+
+    // login command separates username and password by space
+    if username
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(StoreError::Invalid(
+            "Username must not contain spaces".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that password is strong enough, returns `StoreError::Invalid` with reason otherwise.
+pub fn validate_password(password: &str) -> Result<(), StoreError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(StoreError::Invalid(format!(
+            "Password must have at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Selection of messages, fields that are `None` do not filter.
+///
+/// # Fields
+///
+/// * `author_id` - only messages of this user
+/// * `since` - only messages created at this time or later
+/// * `until` - only messages created before this time
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use database::store::{memory::MemoryStore, ChatStore, MessageFilter};
+///
+/// let store = MemoryStore::default();
+/// let alice = store.register("alice", "password", (255, 0, 0)).unwrap();
+/// let bob = store.register("bob", "password", (0, 0, 255)).unwrap();
+///
+/// store.insert_message(alice.id, "Hello").unwrap();
+/// store.insert_message(bob.id, "Hi").unwrap();
+///
+/// let filter = MessageFilter {
+///     author_id: Some(bob.id),
+///     ..Default::default()
+/// };
+/// let messages = store.messages_matching(&filter).unwrap();
+///
+/// assert_eq!(messages.len(), 1);
+/// assert_eq!(messages[0].text, "Hi");
+///
+/// let filter = MessageFilter {
+///     since: Some(SystemTime::now() + Duration::from_secs(60)),
+///     ..Default::default()
+/// };
+///
+/// assert!(store.messages_matching(&filter).unwrap().is_empty());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MessageFilter {
+    pub author_id: Option<i32>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl MessageFilter {
+    /// Returns whether message is selected by filter.
+    pub fn matches(&self, message: &Message) -> bool {
+        (self.author_id.is_none() || self.author_id == message.user_id)
+            && self.since.is_none_or(|since| message.created_at >= since)
+            && self.until.is_none_or(|until| message.created_at < until)
+    }
+}
+
+/// User restored from backup by `ChatStore::import`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedUser {
+    pub username: String,
+    pub password_hash: String,
+    /// `None` when user did not have color (default color is used for him).
+    pub color: Option<(u8, u8, u8)>,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+/// Message restored from backup by `ChatStore::import`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedMessage {
+    /// Index of author in imported users, `None` for deleted one.
+    pub author: Option<usize>,
+    pub text: String,
+    pub created_at: SystemTime,
+}
+
+/// Checks that authors of imported messages are imported users, returns `StoreError::Invalid`
+/// otherwise.
+fn check_import_authors(
+    users: &[ImportedUser],
+    messages: &[ImportedMessage],
+) -> Result<(), StoreError> {
+    match messages
+        .iter()
+        .find(|message| message.author.is_some_and(|author| author >= users.len()))
+    {
+        Some(message) => Err(StoreError::Invalid(format!(
+            "Author of imported message '{}' is not imported",
+            message.text
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Storage of users, colors, messages, sessions, sanctions, public keys and direct messages.
+///
+/// Methods returning one record return `StoreError::NotFound` when record does not exist.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore, StoreError};
+///
+/// let store = MemoryStore::default();
+///
+/// let user = store.register("alice", "password", (255, 0, 0)).unwrap();
+///
+/// assert!(store.login("alice", "password").unwrap().is_some());
+/// assert!(store.login("alice", "wrong").unwrap().is_none());
+/// assert!(store.login("bob", "password").unwrap().is_none());
+///
+/// // usernames are case insensitive
+/// assert!(matches!(
+///     store.register("Alice", "password", (0, 0, 255)),
+///     Err(StoreError::UsernameTaken)
+/// ));
+///
+/// store.insert_message(user.id, "Hello").unwrap();
+/// store.insert_message(user.id, "World").unwrap();
+///
+/// let texts: Vec<String> = store
+///     .recent_messages(10)
+///     .unwrap()
+///     .into_iter()
+///     .map(|message| message.text)
+///     .collect();
+///
+/// assert_eq!(texts, vec!["Hello", "World"]);
+/// ```
+pub trait ChatStore: Send + Sync {
+    /// Returns all users ordered by id.
+    fn users(&self) -> Result<Vec<User>, StoreError>;
+
+    fn user_by_id(&self, user_id: i32) -> Result<User, StoreError>;
+
+    /// Returns user with username, case of letters does not matter.
+    fn user_by_username(&self, username: &str) -> Result<User, StoreError>;
+
+    /// Inserts user with already hashed password, color of user is inserted too.
+    ///
+    /// Returns `StoreError::UsernameTaken` when username differing only in case exists.
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        color: (u8, u8, u8),
+    ) -> Result<User, StoreError>;
+
+    /// Disables (or enables again) user, disabled user can not log in.
+    fn set_user_disabled(&self, user_id: i32, disabled: bool) -> Result<User, StoreError>;
+
+    /// Sets role of user (new users are members).
+    fn set_user_role(&self, user_id: i32, role: Role) -> Result<User, StoreError>;
+
+    fn set_user_password_hash(&self, user_id: i32, password_hash: &str)
+        -> Result<User, StoreError>;
+
+    /// Deletes user together with his messages, sessions and color.
+    fn delete_user(&self, user_id: i32) -> Result<(), StoreError>;
+
+    /// Deletes user together with his sessions and color, his messages are kept without author.
+    fn delete_user_keep_messages(&self, user_id: i32) -> Result<(), StoreError>;
+
+    fn color(&self, color_id: i32) -> Result<Color, StoreError>;
+
+    fn insert_message(&self, user_id: i32, text: &str) -> Result<Message, StoreError>;
+
+    /// Inserts message with its original author (`None` for deleted one) and time, used when
+    /// messages are restored from backup.
+    fn import_message(
+        &self,
+        user_id: Option<i32>,
+        text: &str,
+        created_at: SystemTime,
+    ) -> Result<Message, StoreError>;
+
+    /// Inserts users (with colors, roles and disabled flags) and messages restored from backup,
+    /// either all of them or nothing. Returns inserted users in order of `users`.
+    ///
+    /// Returns `StoreError::UsernameTaken` when username of some user is taken (also by other
+    /// imported user), `StoreError::Invalid` when author of message is not in `users`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::SystemTime;
+    /// use database::store::{memory::MemoryStore, ChatStore, ImportedMessage, ImportedUser, StoreError};
+    /// use libs::role::Role;
+    ///
+    /// let store = MemoryStore::default();
+    ///
+    /// let alice = ImportedUser {
+    ///     username: "alice".to_string(),
+    ///     password_hash: "<hash>".to_string(),
+    ///     color: Some((255, 0, 0)),
+    ///     role: Role::Moderator,
+    ///     disabled: false,
+    /// };
+    /// let message = ImportedMessage {
+    ///     author: Some(0),
+    ///     text: "Hello".to_string(),
+    ///     created_at: SystemTime::UNIX_EPOCH,
+    /// };
+    ///
+    /// // nothing is inserted when some record can not be
+    /// assert!(matches!(
+    ///     store.import(&[alice.clone(), alice.clone()], &[message.clone()]),
+    ///     Err(StoreError::UsernameTaken)
+    /// ));
+    /// assert!(store.users().unwrap().is_empty());
+    /// assert!(store.recent_messages(10).unwrap().is_empty());
+    ///
+    /// let users = store.import(&[alice], &[message]).unwrap();
+    ///
+    /// assert_eq!(users[0].role(), Role::Moderator);
+    /// assert_eq!(store.recent_messages(10).unwrap()[0].user_id, Some(users[0].id));
+    /// ```
+    fn import(
+        &self,
+        users: &[ImportedUser],
+        messages: &[ImportedMessage],
+    ) -> Result<Vec<User>, StoreError>;
+
+    /// Returns newest messages (newest first), optionally only messages of one user.
+    fn messages(&self, author_id: Option<i32>, limit: i64) -> Result<Vec<Message>, StoreError>;
+
+    /// Returns all messages selected by filter in order in which they were sent.
+    fn messages_matching(&self, filter: &MessageFilter) -> Result<Vec<Message>, StoreError>;
+
+    fn delete_message(&self, message_id: i32) -> Result<(), StoreError>;
+
+    /// Returns all messages except `keep` newest ones in order in which they were sent.
+    fn messages_except_newest(&self, keep: i64) -> Result<Vec<Message>, StoreError>;
+
+    /// Deletes messages with given ids (ids of messages that do not exist are ignored), returns
+    /// number of deleted messages.
+    fn delete_messages(&self, message_ids: &[i32]) -> Result<usize, StoreError>;
+
+    fn insert_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: SystemTime,
+    ) -> Result<Session, StoreError>;
+
+    fn session_by_token_hash(&self, token_hash: &str) -> Result<Session, StoreError>;
+
+    /// Returns sessions of user, last used first.
+    fn sessions_of_user(&self, user_id: i32) -> Result<Vec<Session>, StoreError>;
+
+    /// Sets time of last use of session to now.
+    fn touch_session(&self, session_id: i32) -> Result<Session, StoreError>;
+
+    fn delete_session(&self, session_id: i32) -> Result<(), StoreError>;
+
+    /// Deletes expired sessions, returns number of deleted sessions.
+    fn delete_expired_sessions(&self) -> Result<usize, StoreError>;
+
+    fn insert_sanction(&self, sanction: SanctionNew) -> Result<Sanction, StoreError>;
+
+    /// Returns sanctions that did not expire, newest first.
+    fn sanctions(&self) -> Result<Vec<Sanction>, StoreError>;
+
+    /// Returns sanctions of `kind` that did not expire and target user or IP address.
+    fn active_sanctions(
+        &self,
+        kind: SanctionKind,
+        user_id: Option<i32>,
+        ip: Option<&str>,
+    ) -> Result<Vec<Sanction>, StoreError>;
+
+    fn delete_sanction(&self, sanction_id: i32) -> Result<(), StoreError>;
+
+    /// Deletes all sanctions of `kind` that target user, returns number of deleted sanctions.
+    fn delete_user_sanctions(&self, kind: SanctionKind, user_id: i32) -> Result<usize, StoreError>;
+
+    /// Deletes expired sanctions, returns number of deleted sanctions.
+    fn delete_expired_sanctions(&self) -> Result<usize, StoreError>;
+
+    /// Stores public key of user, his previous key is replaced.
+    fn set_public_key(&self, user_id: i32, public_key: &[u8]) -> Result<PublicKey, StoreError>;
+
+    fn public_key(&self, user_id: i32) -> Result<PublicKey, StoreError>;
+
+    fn insert_direct_message(&self, message: DirectMessageNew)
+        -> Result<DirectMessage, StoreError>;
+
+    /// Returns direct messages received by user, oldest first.
+    fn direct_messages(&self, recipient_id: i32) -> Result<Vec<DirectMessage>, StoreError>;
+
+    /// Queues message or direct message for offline user.
+    fn insert_delivery(&self, delivery: DeliveryNew) -> Result<Delivery, StoreError>;
+
+    /// Returns deliveries of user that were not delivered yet with their messages, oldest first.
+    fn pending_deliveries(&self, recipient_id: i32) -> Result<Vec<PendingDelivery>, StoreError>;
+
+    /// Marks deliveries as delivered, returns number of marked deliveries.
+    fn mark_delivered(&self, delivery_ids: &[i32]) -> Result<usize, StoreError>;
+
+    /// Checks username and password, hashes password and inserts new user.
+    fn register(
+        &self,
+        username: &str,
+        password: &str,
+        color: (u8, u8, u8),
+    ) -> Result<User, StoreError> {
+        validate_username(username)?;
+        validate_password(password)?;
+
+        // hashing is slow, insert would fail anyway
+        match self.user_by_username(username) {
+            Ok(_) => return Err(StoreError::UsernameTaken),
+            Err(StoreError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        let password_hash =
+            hash_password(password).map_err(|e| StoreError::Password(e.to_string()))?;
+
+        self.create_user(username, &password_hash, color)
+    }
+
+    /// Returns user when username and password are correct and user is not disabled.
+    ///
+    /// Password is verified even for unknown username, so it can not be recognized by response
+    /// time whether user exists. Legacy PBKDF2 hash (or Argon2id hash with other cost) is replaced
+    /// by hash with current cost.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use database::store::{memory::MemoryStore, ChatStore};
+    ///
+    /// let store = MemoryStore::default();
+    ///
+    /// // PBKDF2 hash of "password"
+    /// let legacy_hash =
+    ///     "$pbkdf2-sha256$i=1000,l=32$dGVzdHNhbHR0ZXN0c2FsdA$RB2dpfVEWj2VJoCXbPbSQzxcn+20ILr/HCSkvD7/EF8";
+    /// store.create_user("alice", legacy_hash, (255, 0, 0)).unwrap();
+    ///
+    /// assert!(store.login("alice", "wrong").unwrap().is_none());
+    ///
+    /// let user = store.login("alice", "password").unwrap().unwrap();
+    ///
+    /// assert!(user.password.starts_with("$argon2id$"));
+    /// assert!(store.login("alice", "password").unwrap().is_some());
+    ///
+    /// // unknown and disabled users can not log in
+    /// assert!(store.login("bob", "password").unwrap().is_none());
+    /// store.set_user_disabled(user.id, true).unwrap();
+    /// assert!(store.login("alice", "password").unwrap().is_none());
+    /// ```
+    fn login(&self, username: &str, password: &str) -> Result<Option<User>, StoreError> {
+        let user = match self.user_by_username(username) {
+            Ok(user) => user,
+            Err(StoreError::NotFound) => {
+                let _ = verify_password(password, dummy_password_hash()?);
+
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let password_is_correct = verify_password(password, &user.password)
+            .map_err(|e| StoreError::Password(e.to_string()))?;
+
+        if !password_is_correct || user.disabled {
+            return Ok(None);
+        }
+
+        // plain password is known only now
+        if needs_rehash(&user.password) {
+            let password_hash =
+                hash_password(password).map_err(|e| StoreError::Password(e.to_string()))?;
+
+            return Ok(Some(self.set_user_password_hash(user.id, &password_hash)?));
+        }
+
+        Ok(Some(user))
+    }
+
+    /// Checks password of user, returns `StoreError::WrongPassword` when it is not correct.
+    fn verify_user_password(&self, user_id: i32, password: &str) -> Result<User, StoreError> {
+        let user = self.user_by_id(user_id)?;
+
+        let password_is_correct = verify_password(password, &user.password)
+            .map_err(|e| StoreError::Password(e.to_string()))?;
+
+        match password_is_correct {
+            true => Ok(user),
+            false => Err(StoreError::WrongPassword),
+        }
+    }
+
+    /// Checks and hashes new password and stores it.
+    fn change_password(&self, user_id: i32, password: &str) -> Result<User, StoreError> {
+        validate_password(password)?;
+
+        let password_hash =
+            hash_password(password).map_err(|e| StoreError::Password(e.to_string()))?;
+
+        self.set_user_password_hash(user_id, &password_hash)
+    }
+
+    /// Changes password of user when his old password is correct.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use database::store::{memory::MemoryStore, ChatStore, StoreError};
+    ///
+    /// let store = MemoryStore::default();
+    ///
+    /// let user = store.register("alice", "password", (255, 0, 0)).unwrap();
+    ///
+    /// assert!(matches!(
+    ///     store.change_own_password(user.id, "wrong", "new password"),
+    ///     Err(StoreError::WrongPassword)
+    /// ));
+    ///
+    /// store.change_own_password(user.id, "password", "new password").unwrap();
+    ///
+    /// assert!(store.login("alice", "new password").unwrap().is_some());
+    /// ```
+    fn change_own_password(
+        &self,
+        user_id: i32,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<User, StoreError> {
+        self.verify_user_password(user_id, old_password)?;
+
+        self.change_password(user_id, new_password)
+    }
+
+    /// Deletes account of user when his password is correct, his messages are kept without
+    /// author.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use database::store::{memory::MemoryStore, ChatStore};
+    ///
+    /// let store = MemoryStore::default();
+    ///
+    /// let user = store.register("alice", "password", (255, 0, 0)).unwrap();
+    /// store.insert_message(user.id, "Hello").unwrap();
+    ///
+    /// store.delete_account(user.id, "password").unwrap();
+    ///
+    /// let message = store.recent_messages(10).unwrap().remove(0);
+    ///
+    /// assert_eq!(message.text, "Hello");
+    /// assert_eq!(message.user_id, None);
+    /// assert!(store.login("alice", "password").unwrap().is_none());
+    /// ```
+    fn delete_account(&self, user_id: i32, password: &str) -> Result<(), StoreError> {
+        self.verify_user_password(user_id, password)?;
+
+        self.delete_user_keep_messages(user_id)
+    }
+
+    /// Creates session of user, returns token of session (only its hash is stored).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use database::store::{memory::MemoryStore, ChatStore};
+    ///
+    /// let store = MemoryStore::default();
+    ///
+    /// let user = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+    ///
+    /// let (token, session) = store.create_session(user.id, Duration::from_secs(60)).unwrap();
+    ///
+    /// let (logged_in, used_session) = store.login_with_token(&token).unwrap().unwrap();
+    ///
+    /// assert_eq!(logged_in.id, user.id);
+    /// assert_eq!(used_session.id, session.id);
+    /// assert!(store.login_with_token("unknown token").unwrap().is_none());
+    ///
+    /// // expired session is deleted when it is used
+    /// let (token, session) = store.create_session(user.id, Duration::ZERO).unwrap();
+    ///
+    /// assert!(store.login_with_token(&token).unwrap().is_none());
+    /// assert!(store.session_by_token_hash(&session.token_hash).is_err());
+    ///
+    /// // expiration time has to be representable
+    /// assert!(store.create_session(user.id, Duration::MAX).is_err());
+    /// ```
+    fn create_session(&self, user_id: i32, ttl: Duration) -> Result<(String, Session), StoreError> {
+        let expires_at = SystemTime::now()
+            .checked_add(ttl)
+            .ok_or_else(|| StoreError::Invalid("Session lifetime is too long".to_string()))?;
+
+        let token = generate_token();
+        let session = self.insert_session(user_id, &hash_token(&token), expires_at)?;
+
+        Ok((token, session))
+    }
+
+    /// Returns user and his session when token belongs to session that did not expire and user is
+    /// not disabled, time of last use of session is updated.
+    fn login_with_token(&self, token: &str) -> Result<Option<(User, Session)>, StoreError> {
+        let session = match self.session_by_token_hash(&hash_token(token)) {
+            Ok(session) => session,
+            Err(StoreError::NotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if session.expires_at <= SystemTime::now() {
+            self.delete_session(session.id)?;
+
+            return Ok(None);
+        }
+
+        let user = self.user_by_id(session.user_id)?;
+
+        if user.disabled {
+            return Ok(None);
+        }
+
+        Ok(Some((user, self.touch_session(session.id)?)))
+    }
+
+    /// Returns sanction of `kind` for user or IP address that lasts longest (permanent sanction
+    /// first), `None` when there is no active sanction.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// use database::{
+    ///     models::{SanctionKind, SanctionNew},
+    ///     store::{memory::MemoryStore, ChatStore},
+    /// };
+    ///
+    /// let store = MemoryStore::default();
+    /// let user = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+    ///
+    /// let sanction = |ip: Option<&str>, expires_at: Option<SystemTime>| SanctionNew {
+    ///     kind: SanctionKind::Mute.as_str().to_string(),
+    ///     user_id: ip.is_none().then_some(user.id),
+    ///     ip: ip.map(String::from),
+    ///     reason: "spam".to_string(),
+    ///     created_by: None,
+    ///     expires_at,
+    /// };
+    ///
+    /// // expired sanction is ignored
+    /// store
+    ///     .insert_sanction(sanction(None, Some(SystemTime::now())))
+    ///     .unwrap();
+    ///
+    /// assert!(store
+    ///     .active_sanction(SanctionKind::Mute, Some(user.id), None)
+    ///     .unwrap()
+    ///     .is_none());
+    ///
+    /// let hour = SystemTime::now() + Duration::from_secs(3600);
+    /// store.insert_sanction(sanction(None, Some(hour))).unwrap();
+    /// let permanent = store
+    ///     .insert_sanction(sanction(Some("10.0.0.1"), None))
+    ///     .unwrap();
+    ///
+    /// let active = store
+    ///     .active_sanction(SanctionKind::Mute, Some(user.id), Some("10.0.0.1"))
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(active.id, permanent.id);
+    /// assert!(store
+    ///     .active_sanction(SanctionKind::Ban, Some(user.id), Some("10.0.0.1"))
+    ///     .unwrap()
+    ///     .is_none());
+    /// ```
+    fn active_sanction(
+        &self,
+        kind: SanctionKind,
+        user_id: Option<i32>,
+        ip: Option<&str>,
+    ) -> Result<Option<Sanction>, StoreError> {
+        Ok(self
+            .active_sanctions(kind, user_id, ip)?
+            .into_iter()
+            .max_by_key(|sanction| (sanction.expires_at.is_none(), sanction.expires_at)))
+    }
+
+    /// Returns color of user, `None` when user has no color.
+    fn user_color(&self, user: &User) -> Result<Option<(u8, u8, u8)>, StoreError> {
+        match user.color_id {
+            Some(color_id) => {
+                let color = self.color(color_id)?;
+
+                Ok(Some((color.r as u8, color.g as u8, color.b as u8)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns last `limit` messages in order in which they were sent.
+    fn recent_messages(&self, limit: i64) -> Result<Vec<Message>, StoreError> {
+        let mut messages = self.messages(None, limit)?;
+
+        messages.reverse();
+
+        Ok(messages)
+    }
+}
@@ -0,0 +1,728 @@
+use std::{sync::Mutex, time::SystemTime};
+
+use libs::role::Role;
+
+use crate::{
+    models::{
+        Color, Delivery, DeliveryNew, DirectMessage, DirectMessageNew, Message, PendingDelivery,
+        PublicKey, Sanction, SanctionKind, SanctionNew, Session, User,
+    },
+    store::{
+        check_import_authors, ChatStore, ImportedMessage, ImportedUser, MessageFilter, StoreError,
+    },
+};
+
+/// `ChatStore` that keeps data only in memory, everything is lost when it is dropped.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore, StoreError};
+///
+/// let store = MemoryStore::default();
+///
+/// let user = store.register("alice", "password", (0, 255, 0)).unwrap();
+///
+/// assert_eq!(store.user_color(&user).unwrap(), Some((0, 255, 0)));
+///
+/// store.delete_user(user.id).unwrap();
+///
+/// assert!(matches!(store.user_by_id(user.id), Err(StoreError::NotFound)));
+/// ```
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<MemoryData>,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    users: Vec<User>,
+    colors: Vec<Color>,
+    messages: Vec<Message>,
+    sessions: Vec<Session>,
+    sanctions: Vec<Sanction>,
+    public_keys: Vec<PublicKey>,
+    direct_messages: Vec<DirectMessage>,
+    deliveries: Vec<Delivery>,
+    last_id: i32,
+}
+
+impl MemoryData {
+    /// Ids are unique across all tables, ids in database start from 1 too.
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn user_mut(&mut self, user_id: i32) -> Result<&mut User, StoreError> {
+        self.users
+            .iter_mut()
+            .find(|user| user.id == user_id)
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn session_mut(&mut self, session_id: i32) -> Result<&mut Session, StoreError> {
+        self.sessions
+            .iter_mut()
+            .find(|session| session.id == session_id)
+            .ok_or(StoreError::NotFound)
+    }
+
+    /// Same as foreign keys in database, sanctions, public key and received direct messages of
+    /// deleted user are deleted, his sanctions of others and sent direct messages are kept without
+    /// author.
+    fn delete_records_of_user(&mut self, user_id: i32) {
+        self.public_keys.retain(|key| key.user_id != user_id);
+        self.direct_messages
+            .retain(|message| message.recipient_id != user_id);
+
+        for message in self.direct_messages.iter_mut() {
+            if message.sender_id == Some(user_id) {
+                message.sender_id = None;
+            }
+        }
+
+        self.sanctions
+            .retain(|sanction| sanction.user_id != Some(user_id));
+
+        for sanction in self.sanctions.iter_mut() {
+            if sanction.created_by == Some(user_id) {
+                sanction.created_by = None;
+            }
+        }
+
+        self.deliveries
+            .retain(|delivery| delivery.recipient_id != user_id);
+        self.delete_orphan_deliveries();
+    }
+
+    /// Same as foreign keys in database, deliveries of deleted messages are deleted.
+    fn delete_orphan_deliveries(&mut self) {
+        let messages = &self.messages;
+        let direct_messages = &self.direct_messages;
+
+        self.deliveries.retain(|delivery| {
+            delivery
+                .message_id
+                .is_none_or(|message_id| messages.iter().any(|message| message.id == message_id))
+                && delivery.direct_message_id.is_none_or(|message_id| {
+                    direct_messages
+                        .iter()
+                        .any(|message| message.id == message_id)
+                })
+        });
+    }
+}
+
+impl MemoryStore {
+    fn data(&self) -> std::sync::MutexGuard<'_, MemoryData> {
+        // data stay consistent even when other thread panicked, every change is one statement
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ChatStore for MemoryStore {
+    fn users(&self) -> Result<Vec<User>, StoreError> {
+        Ok(self.data().users.clone())
+    }
+
+    fn user_by_id(&self, user_id: i32) -> Result<User, StoreError> {
+        self.data().user_mut(user_id).map(|user| user.clone())
+    }
+
+    fn user_by_username(&self, username: &str) -> Result<User, StoreError> {
+        self.data()
+            .users
+            .iter()
+            .find(|user| user.username.to_lowercase() == username.to_lowercase())
+            .cloned()
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        color: (u8, u8, u8),
+    ) -> Result<User, StoreError> {
+        let mut data = self.data();
+
+        // same as unique index on lowercase username in database
+        if data
+            .users
+            .iter()
+            .any(|user| user.username.to_lowercase() == username.to_lowercase())
+        {
+            return Err(StoreError::UsernameTaken);
+        }
+
+        let color = Color {
+            id: data.next_id(),
+            r: color.0 as i16,
+            g: color.1 as i16,
+            b: color.2 as i16,
+        };
+
+        let user = User {
+            id: data.next_id(),
+            username: username.to_string(),
+            password: password_hash.to_string(),
+            color_id: Some(color.id),
+            disabled: false,
+            role: Role::default().to_string(),
+        };
+
+        data.colors.push(color);
+        data.users.push(user.clone());
+
+        Ok(user)
+    }
+
+    fn set_user_disabled(&self, user_id: i32, disabled: bool) -> Result<User, StoreError> {
+        let mut data = self.data();
+        let user = data.user_mut(user_id)?;
+
+        user.disabled = disabled;
+
+        Ok(user.clone())
+    }
+
+    fn set_user_role(&self, user_id: i32, role: Role) -> Result<User, StoreError> {
+        let mut data = self.data();
+        let user = data.user_mut(user_id)?;
+
+        user.role = role.to_string();
+
+        Ok(user.clone())
+    }
+
+    fn set_user_password_hash(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<User, StoreError> {
+        let mut data = self.data();
+        let user = data.user_mut(user_id)?;
+
+        user.password = password_hash.to_string();
+
+        Ok(user.clone())
+    }
+
+    fn delete_user(&self, user_id: i32) -> Result<(), StoreError> {
+        let mut data = self.data();
+        let color_id = data.user_mut(user_id)?.color_id;
+
+        data.messages
+            .retain(|message| message.user_id != Some(user_id));
+        data.sessions.retain(|session| session.user_id != user_id);
+        data.delete_records_of_user(user_id);
+        data.users.retain(|user| user.id != user_id);
+        data.colors.retain(|color| Some(color.id) != color_id);
+
+        Ok(())
+    }
+
+    fn delete_user_keep_messages(&self, user_id: i32) -> Result<(), StoreError> {
+        let mut data = self.data();
+        let color_id = data.user_mut(user_id)?.color_id;
+
+        for message in data.messages.iter_mut() {
+            if message.user_id == Some(user_id) {
+                message.user_id = None;
+            }
+        }
+
+        data.sessions.retain(|session| session.user_id != user_id);
+        data.delete_records_of_user(user_id);
+        data.users.retain(|user| user.id != user_id);
+        data.colors.retain(|color| Some(color.id) != color_id);
+
+        Ok(())
+    }
+
+    fn color(&self, color_id: i32) -> Result<Color, StoreError> {
+        self.data()
+            .colors
+            .iter()
+            .find(|color| color.id == color_id)
+            .cloned()
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn insert_message(&self, user_id: i32, text: &str) -> Result<Message, StoreError> {
+        let mut data = self.data();
+
+        // same as foreign key in database
+        data.user_mut(user_id)?;
+
+        let message = Message {
+            id: data.next_id(),
+            user_id: Some(user_id),
+            text: text.to_string(),
+            created_at: SystemTime::now(),
+        };
+
+        data.messages.push(message.clone());
+
+        Ok(message)
+    }
+
+    fn import_message(
+        &self,
+        user_id: Option<i32>,
+        text: &str,
+        created_at: SystemTime,
+    ) -> Result<Message, StoreError> {
+        let mut data = self.data();
+
+        if let Some(user_id) = user_id {
+            data.user_mut(user_id)?;
+        }
+
+        let message = Message {
+            id: data.next_id(),
+            user_id,
+            text: text.to_string(),
+            created_at,
+        };
+
+        data.messages.push(message.clone());
+
+        Ok(message)
+    }
+
+    fn import(
+        &self,
+        users: &[ImportedUser],
+        messages: &[ImportedMessage],
+    ) -> Result<Vec<User>, StoreError> {
+        check_import_authors(users, messages)?;
+
+        let mut data = self.data();
+
+        // everything is checked before first insert, so nothing is inserted when import fails
+        let mut usernames: Vec<String> = data
+            .users
+            .iter()
+            .map(|user| user.username.to_lowercase())
+            .collect();
+
+        for user in users {
+            let username = user.username.to_lowercase();
+
+            if usernames.contains(&username) {
+                return Err(StoreError::UsernameTaken);
+            }
+
+            usernames.push(username);
+        }
+
+        let mut inserted = vec![];
+
+        for imported in users {
+            let color = imported.color.map(|(r, g, b)| Color {
+                id: data.next_id(),
+                r: r as i16,
+                g: g as i16,
+                b: b as i16,
+            });
+
+            let user = User {
+                id: data.next_id(),
+                username: imported.username.clone(),
+                password: imported.password_hash.clone(),
+                color_id: color.as_ref().map(|color| color.id),
+                disabled: imported.disabled,
+                role: imported.role.to_string(),
+            };
+
+            data.colors.extend(color);
+            data.users.push(user.clone());
+            inserted.push(user);
+        }
+
+        for imported in messages {
+            let message = Message {
+                id: data.next_id(),
+                user_id: imported.author.map(|author| inserted[author].id),
+                text: imported.text.clone(),
+                created_at: imported.created_at,
+            };
+
+            data.messages.push(message);
+        }
+
+        Ok(inserted)
+    }
+
+    fn messages(&self, author_id: Option<i32>, limit: i64) -> Result<Vec<Message>, StoreError> {
+        // messages are stored in order in which they were inserted
+        Ok(self
+            .data()
+            .messages
+            .iter()
+            .rev()
+            .filter(|message| author_id.is_none() || author_id == message.user_id)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn messages_matching(&self, filter: &MessageFilter) -> Result<Vec<Message>, StoreError> {
+        Ok(self
+            .data()
+            .messages
+            .iter()
+            .filter(|message| filter.matches(message))
+            .cloned()
+            .collect())
+    }
+
+    fn delete_message(&self, message_id: i32) -> Result<(), StoreError> {
+        let mut data = self.data();
+        let count = data.messages.len();
+
+        data.messages.retain(|message| message.id != message_id);
+        data.delete_orphan_deliveries();
+
+        match data.messages.len() == count {
+            true => Err(StoreError::NotFound),
+            false => Ok(()),
+        }
+    }
+
+    fn messages_except_newest(&self, keep: i64) -> Result<Vec<Message>, StoreError> {
+        let data = self.data();
+
+        // messages are stored in order in which they were inserted
+        let count = data.messages.len().saturating_sub(keep.max(0) as usize);
+
+        Ok(data.messages[..count].to_vec())
+    }
+
+    fn delete_messages(&self, message_ids: &[i32]) -> Result<usize, StoreError> {
+        let mut data = self.data();
+        let count = data.messages.len();
+
+        data.messages
+            .retain(|message| !message_ids.contains(&message.id));
+        data.delete_orphan_deliveries();
+
+        Ok(count - data.messages.len())
+    }
+
+    fn insert_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: SystemTime,
+    ) -> Result<Session, StoreError> {
+        let mut data = self.data();
+
+        data.user_mut(user_id)?;
+
+        let now = SystemTime::now();
+
+        let session = Session {
+            id: data.next_id(),
+            user_id,
+            token_hash: token_hash.to_string(),
+            created_at: now,
+            expires_at,
+            last_used_at: now,
+        };
+
+        data.sessions.push(session.clone());
+
+        Ok(session)
+    }
+
+    fn session_by_token_hash(&self, token_hash: &str) -> Result<Session, StoreError> {
+        self.data()
+            .sessions
+            .iter()
+            .find(|session| session.token_hash == token_hash)
+            .cloned()
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn sessions_of_user(&self, user_id: i32) -> Result<Vec<Session>, StoreError> {
+        let mut sessions: Vec<Session> = self
+            .data()
+            .sessions
+            .iter()
+            .filter(|session| session.user_id == user_id)
+            .cloned()
+            .collect();
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_used_at));
+
+        Ok(sessions)
+    }
+
+    fn touch_session(&self, session_id: i32) -> Result<Session, StoreError> {
+        let mut data = self.data();
+        let session = data.session_mut(session_id)?;
+
+        session.last_used_at = SystemTime::now();
+
+        Ok(session.clone())
+    }
+
+    fn delete_session(&self, session_id: i32) -> Result<(), StoreError> {
+        let mut data = self.data();
+
+        data.session_mut(session_id)?;
+        data.sessions.retain(|session| session.id != session_id);
+
+        Ok(())
+    }
+
+    fn delete_expired_sessions(&self) -> Result<usize, StoreError> {
+        let mut data = self.data();
+        let now = SystemTime::now();
+        let count = data.sessions.len();
+
+        data.sessions.retain(|session| session.expires_at > now);
+
+        Ok(count - data.sessions.len())
+    }
+
+    fn insert_sanction(&self, sanction: SanctionNew) -> Result<Sanction, StoreError> {
+        let mut data = self.data();
+
+        for user_id in [sanction.user_id, sanction.created_by]
+            .into_iter()
+            .flatten()
+        {
+            data.user_mut(user_id)?;
+        }
+
+        // same as check in database
+        if sanction.user_id.is_none() && sanction.ip.is_none() {
+            return Err(StoreError::Invalid(
+                "Sanction has to target user or IP address".to_string(),
+            ));
+        }
+
+        let sanction = Sanction {
+            id: data.next_id(),
+            kind: sanction.kind,
+            user_id: sanction.user_id,
+            ip: sanction.ip,
+            reason: sanction.reason,
+            created_by: sanction.created_by,
+            created_at: SystemTime::now(),
+            expires_at: sanction.expires_at,
+        };
+
+        data.sanctions.push(sanction.clone());
+
+        Ok(sanction)
+    }
+
+    fn sanctions(&self) -> Result<Vec<Sanction>, StoreError> {
+        let now = SystemTime::now();
+
+        Ok(self
+            .data()
+            .sanctions
+            .iter()
+            .rev()
+            .filter(|sanction| sanction.is_active(now))
+            .cloned()
+            .collect())
+    }
+
+    fn active_sanctions(
+        &self,
+        kind: SanctionKind,
+        user_id: Option<i32>,
+        ip: Option<&str>,
+    ) -> Result<Vec<Sanction>, StoreError> {
+        let now = SystemTime::now();
+
+        Ok(self
+            .data()
+            .sanctions
+            .iter()
+            .filter(|sanction| sanction.kind() == kind && sanction.is_active(now))
+            .filter(|sanction| {
+                (user_id.is_some() && sanction.user_id == user_id)
+                    || (ip.is_some() && sanction.ip.as_deref() == ip)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn delete_sanction(&self, sanction_id: i32) -> Result<(), StoreError> {
+        let mut data = self.data();
+        let count = data.sanctions.len();
+
+        data.sanctions.retain(|sanction| sanction.id != sanction_id);
+
+        match data.sanctions.len() == count {
+            true => Err(StoreError::NotFound),
+            false => Ok(()),
+        }
+    }
+
+    fn delete_user_sanctions(&self, kind: SanctionKind, user_id: i32) -> Result<usize, StoreError> {
+        let mut data = self.data();
+        let count = data.sanctions.len();
+
+        data.sanctions
+            .retain(|sanction| sanction.kind() != kind || sanction.user_id != Some(user_id));
+
+        Ok(count - data.sanctions.len())
+    }
+
+    fn delete_expired_sanctions(&self) -> Result<usize, StoreError> {
+        let mut data = self.data();
+        let now = SystemTime::now();
+        let count = data.sanctions.len();
+
+        data.sanctions.retain(|sanction| sanction.is_active(now));
+
+        Ok(count - data.sanctions.len())
+    }
+
+    fn set_public_key(&self, user_id: i32, public_key: &[u8]) -> Result<PublicKey, StoreError> {
+        let mut data = self.data();
+
+        data.user_mut(user_id)?;
+        data.public_keys.retain(|key| key.user_id != user_id);
+
+        let key = PublicKey {
+            user_id,
+            public_key: public_key.to_vec(),
+            updated_at: SystemTime::now(),
+        };
+
+        data.public_keys.push(key.clone());
+
+        Ok(key)
+    }
+
+    fn public_key(&self, user_id: i32) -> Result<PublicKey, StoreError> {
+        self.data()
+            .public_keys
+            .iter()
+            .find(|key| key.user_id == user_id)
+            .cloned()
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn insert_direct_message(
+        &self,
+        message: DirectMessageNew,
+    ) -> Result<DirectMessage, StoreError> {
+        let mut data = self.data();
+
+        data.user_mut(message.sender_id)?;
+        data.user_mut(message.recipient_id)?;
+
+        let message = DirectMessage {
+            id: data.next_id(),
+            sender_id: Some(message.sender_id),
+            recipient_id: message.recipient_id,
+            sender_key: message.sender_key,
+            recipient_key: message.recipient_key,
+            nonce: message.nonce,
+            ciphertext: message.ciphertext,
+            created_at: SystemTime::now(),
+        };
+
+        data.direct_messages.push(message.clone());
+
+        Ok(message)
+    }
+
+    fn direct_messages(&self, recipient_id: i32) -> Result<Vec<DirectMessage>, StoreError> {
+        Ok(self
+            .data()
+            .direct_messages
+            .iter()
+            .filter(|message| message.recipient_id == recipient_id)
+            .cloned()
+            .collect())
+    }
+
+    fn insert_delivery(&self, delivery: DeliveryNew) -> Result<Delivery, StoreError> {
+        let mut data = self.data();
+
+        data.user_mut(delivery.recipient_id)?;
+
+        if delivery.message_id.is_some() == delivery.direct_message_id.is_some() {
+            return Err(StoreError::Invalid(
+                "Delivery has to have message or direct message".to_string(),
+            ));
+        }
+
+        let message_exists = delivery
+            .message_id
+            .is_none_or(|message_id| data.messages.iter().any(|message| message.id == message_id));
+        let direct_message_exists = delivery.direct_message_id.is_none_or(|message_id| {
+            data.direct_messages
+                .iter()
+                .any(|message| message.id == message_id)
+        });
+
+        if !message_exists || !direct_message_exists {
+            return Err(StoreError::NotFound);
+        }
+
+        let delivery = Delivery {
+            id: data.next_id(),
+            recipient_id: delivery.recipient_id,
+            message_id: delivery.message_id,
+            direct_message_id: delivery.direct_message_id,
+            created_at: SystemTime::now(),
+            delivered_at: None,
+        };
+
+        data.deliveries.push(delivery.clone());
+
+        Ok(delivery)
+    }
+
+    fn pending_deliveries(&self, recipient_id: i32) -> Result<Vec<PendingDelivery>, StoreError> {
+        let data = self.data();
+
+        Ok(data
+            .deliveries
+            .iter()
+            .filter(|delivery| {
+                delivery.recipient_id == recipient_id && delivery.delivered_at.is_none()
+            })
+            .map(|delivery| {
+                let message = data
+                    .messages
+                    .iter()
+                    .find(|message| Some(message.id) == delivery.message_id);
+                let direct_message = data
+                    .direct_messages
+                    .iter()
+                    .find(|message| Some(message.id) == delivery.direct_message_id);
+
+                (delivery.clone(), message.cloned(), direct_message.cloned())
+            })
+            .collect())
+    }
+
+    fn mark_delivered(&self, delivery_ids: &[i32]) -> Result<usize, StoreError> {
+        let mut data = self.data();
+        let now = SystemTime::now();
+        let mut count = 0;
+
+        for delivery in data.deliveries.iter_mut() {
+            if delivery_ids.contains(&delivery.id) {
+                delivery.delivered_at = Some(now);
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
@@ -0,0 +1,383 @@
+use std::{env, time::SystemTime};
+
+use diesel::{
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    result::DatabaseErrorKind,
+    Connection,
+};
+use dotenvy::dotenv;
+
+use libs::role::Role;
+
+use crate::{
+    models::{
+        Color, Delivery, DeliveryNew, DirectMessage, DirectMessageNew, Message, MessageImport,
+        MessageNew, PendingDelivery, PublicKey, Sanction, SanctionKind, SanctionNew, Session,
+        SessionNew, User, UserNew,
+    },
+    store::{
+        check_import_authors, ChatStore, ImportedMessage, ImportedUser, MessageFilter, StoreError,
+    },
+};
+
+/// `ChatStore` backed by PostgreSQL, it keeps pool of connections.
+pub struct PgStore {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PgStore {
+    /// Creates pool of connections to database.
+    ///
+    /// # Arguments
+    ///
+    /// * `database_url` - connection string
+    pub fn connect(database_url: &str) -> Result<PgStore, StoreError> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+
+        let pool = Pool::builder()
+            .build(manager)
+            .map_err(|e| StoreError::Connection(e.to_string()))?;
+
+        Ok(PgStore { pool })
+    }
+
+    /// Creates pool of connections to database set in `DATABASE_URL` environment variable (or
+    /// `.env` file).
+    pub fn connect_from_env() -> Result<PgStore, StoreError> {
+        dotenv().ok();
+
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| StoreError::Connection("DATABASE_URL is not set".to_string()))?;
+
+        Self::connect(&database_url)
+    }
+
+    fn connection(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>, StoreError> {
+        self.pool
+            .get()
+            .map_err(|e| StoreError::Connection(e.to_string()))
+    }
+}
+
+impl ChatStore for PgStore {
+    fn users(&self) -> Result<Vec<User>, StoreError> {
+        Ok(User::read_all(&mut *self.connection()?)?)
+    }
+
+    fn user_by_id(&self, user_id: i32) -> Result<User, StoreError> {
+        Ok(User::read_by_id(&mut *self.connection()?, user_id)?)
+    }
+
+    fn user_by_username(&self, username: &str) -> Result<User, StoreError> {
+        Ok(User::read(&mut *self.connection()?, username)?)
+    }
+
+    fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        color: (u8, u8, u8),
+    ) -> Result<User, StoreError> {
+        let (r, g, b) = color;
+
+        match UserNew::insert_with_color(&mut *self.connection()?, username, password_hash, r, g, b)
+        {
+            Ok(user) => Ok(user),
+            // only unique index of users is on lowercase username
+            Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(StoreError::UsernameTaken)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set_user_disabled(&self, user_id: i32, disabled: bool) -> Result<User, StoreError> {
+        Ok(User::set_disabled(
+            &mut *self.connection()?,
+            user_id,
+            disabled,
+        )?)
+    }
+
+    fn set_user_role(&self, user_id: i32, role: Role) -> Result<User, StoreError> {
+        Ok(User::set_role(&mut *self.connection()?, user_id, role)?)
+    }
+
+    fn set_user_password_hash(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<User, StoreError> {
+        Ok(User::update_password_hash(
+            &mut *self.connection()?,
+            user_id,
+            password_hash,
+        )?)
+    }
+
+    fn delete_user(&self, user_id: i32) -> Result<(), StoreError> {
+        Ok(User::delete(&mut *self.connection()?, user_id)?)
+    }
+
+    fn delete_user_keep_messages(&self, user_id: i32) -> Result<(), StoreError> {
+        Ok(User::delete_keep_messages(
+            &mut *self.connection()?,
+            user_id,
+        )?)
+    }
+
+    fn color(&self, color_id: i32) -> Result<Color, StoreError> {
+        Ok(Color::read(&mut *self.connection()?, color_id)?)
+    }
+
+    fn insert_message(&self, user_id: i32, text: &str) -> Result<Message, StoreError> {
+        let message_new = MessageNew {
+            user_id,
+            text: text.to_string(),
+        };
+
+        Ok(message_new.insert(&mut *self.connection()?)?)
+    }
+
+    fn import_message(
+        &self,
+        user_id: Option<i32>,
+        text: &str,
+        created_at: SystemTime,
+    ) -> Result<Message, StoreError> {
+        let message_import = MessageImport {
+            user_id,
+            text: text.to_string(),
+            created_at,
+        };
+
+        Ok(message_import.insert(&mut *self.connection()?)?)
+    }
+
+    fn import(
+        &self,
+        users: &[ImportedUser],
+        messages: &[ImportedMessage],
+    ) -> Result<Vec<User>, StoreError> {
+        check_import_authors(users, messages)?;
+
+        let result = self.connection()?.transaction(|connection| {
+            let mut inserted = vec![];
+
+            for imported in users {
+                let mut user = match imported.color {
+                    Some((r, g, b)) => UserNew::insert_with_color(
+                        connection,
+                        &imported.username,
+                        &imported.password_hash,
+                        r,
+                        g,
+                        b,
+                    )?,
+                    None => UserNew::insert_without_color(
+                        connection,
+                        &imported.username,
+                        &imported.password_hash,
+                    )?,
+                };
+
+                if imported.role != Role::default() {
+                    user = User::set_role(connection, user.id, imported.role)?;
+                }
+
+                if imported.disabled {
+                    user = User::set_disabled(connection, user.id, true)?;
+                }
+
+                inserted.push(user);
+            }
+
+            for imported in messages {
+                MessageImport {
+                    user_id: imported.author.map(|author| inserted[author].id),
+                    text: imported.text.clone(),
+                    created_at: imported.created_at,
+                }
+                .insert(connection)?;
+            }
+
+            Ok(inserted)
+        });
+
+        match result {
+            Ok(users) => Ok(users),
+            // only unique index of users is on lowercase username
+            Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(StoreError::UsernameTaken)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn messages(&self, author_id: Option<i32>, limit: i64) -> Result<Vec<Message>, StoreError> {
+        Ok(Message::read_filtered(
+            &mut *self.connection()?,
+            author_id,
+            limit,
+        )?)
+    }
+
+    fn messages_matching(&self, filter: &MessageFilter) -> Result<Vec<Message>, StoreError> {
+        Ok(Message::read_matching(&mut *self.connection()?, filter)?)
+    }
+
+    fn delete_message(&self, message_id: i32) -> Result<(), StoreError> {
+        match Message::delete(&mut *self.connection()?, message_id)? {
+            0 => Err(StoreError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn messages_except_newest(&self, keep: i64) -> Result<Vec<Message>, StoreError> {
+        Ok(Message::read_except_newest(&mut *self.connection()?, keep)?)
+    }
+
+    fn delete_messages(&self, message_ids: &[i32]) -> Result<usize, StoreError> {
+        Ok(Message::delete_many(&mut *self.connection()?, message_ids)?)
+    }
+
+    fn insert_session(
+        &self,
+        user_id: i32,
+        token_hash: &str,
+        expires_at: SystemTime,
+    ) -> Result<Session, StoreError> {
+        let session_new = SessionNew {
+            user_id,
+            token_hash: token_hash.to_string(),
+            expires_at,
+        };
+
+        Ok(session_new.insert(&mut *self.connection()?)?)
+    }
+
+    fn session_by_token_hash(&self, token_hash: &str) -> Result<Session, StoreError> {
+        Ok(Session::read_by_token_hash(
+            &mut *self.connection()?,
+            token_hash,
+        )?)
+    }
+
+    fn sessions_of_user(&self, user_id: i32) -> Result<Vec<Session>, StoreError> {
+        Ok(Session::read_by_user_id(&mut *self.connection()?, user_id)?)
+    }
+
+    fn touch_session(&self, session_id: i32) -> Result<Session, StoreError> {
+        Ok(Session::touch(&mut *self.connection()?, session_id)?)
+    }
+
+    fn delete_session(&self, session_id: i32) -> Result<(), StoreError> {
+        match Session::delete(&mut *self.connection()?, session_id)? {
+            0 => Err(StoreError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_expired_sessions(&self) -> Result<usize, StoreError> {
+        Ok(Session::delete_expired(
+            &mut *self.connection()?,
+            SystemTime::now(),
+        )?)
+    }
+
+    fn insert_sanction(&self, sanction: SanctionNew) -> Result<Sanction, StoreError> {
+        Ok(sanction.insert(&mut *self.connection()?)?)
+    }
+
+    fn sanctions(&self) -> Result<Vec<Sanction>, StoreError> {
+        Ok(Sanction::read_active(
+            &mut *self.connection()?,
+            SystemTime::now(),
+        )?)
+    }
+
+    fn active_sanctions(
+        &self,
+        kind: SanctionKind,
+        user_id: Option<i32>,
+        ip: Option<&str>,
+    ) -> Result<Vec<Sanction>, StoreError> {
+        Ok(Sanction::read_active_for(
+            &mut *self.connection()?,
+            kind,
+            user_id,
+            ip,
+            SystemTime::now(),
+        )?)
+    }
+
+    fn delete_sanction(&self, sanction_id: i32) -> Result<(), StoreError> {
+        match Sanction::delete(&mut *self.connection()?, sanction_id)? {
+            0 => Err(StoreError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_user_sanctions(&self, kind: SanctionKind, user_id: i32) -> Result<usize, StoreError> {
+        Ok(Sanction::delete_of_user(
+            &mut *self.connection()?,
+            kind,
+            user_id,
+        )?)
+    }
+
+    fn delete_expired_sanctions(&self) -> Result<usize, StoreError> {
+        Ok(Sanction::delete_expired(
+            &mut *self.connection()?,
+            SystemTime::now(),
+        )?)
+    }
+
+    fn set_public_key(&self, user_id: i32, public_key: &[u8]) -> Result<PublicKey, StoreError> {
+        let key = PublicKey {
+            user_id,
+            public_key: public_key.to_vec(),
+            updated_at: SystemTime::now(),
+        };
+
+        Ok(key.upsert(&mut *self.connection()?)?)
+    }
+
+    fn public_key(&self, user_id: i32) -> Result<PublicKey, StoreError> {
+        Ok(PublicKey::read(&mut *self.connection()?, user_id)?)
+    }
+
+    fn insert_direct_message(
+        &self,
+        message: DirectMessageNew,
+    ) -> Result<DirectMessage, StoreError> {
+        Ok(message.insert(&mut *self.connection()?)?)
+    }
+
+    fn direct_messages(&self, recipient_id: i32) -> Result<Vec<DirectMessage>, StoreError> {
+        Ok(DirectMessage::read_by_recipient(
+            &mut *self.connection()?,
+            recipient_id,
+        )?)
+    }
+
+    fn insert_delivery(&self, delivery: DeliveryNew) -> Result<Delivery, StoreError> {
+        Ok(delivery.insert(&mut *self.connection()?)?)
+    }
+
+    fn pending_deliveries(&self, recipient_id: i32) -> Result<Vec<PendingDelivery>, StoreError> {
+        Ok(Delivery::read_pending(
+            &mut *self.connection()?,
+            recipient_id,
+        )?)
+    }
+
+    fn mark_delivered(&self, delivery_ids: &[i32]) -> Result<usize, StoreError> {
+        Ok(Delivery::mark_delivered(
+            &mut *self.connection()?,
+            delivery_ids,
+            SystemTime::now(),
+        )?)
+    }
+}
@@ -0,0 +1,90 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Parser;
+
+use crate::format::Format;
+
+/// Structure representing program arguments.
+///
+/// Chat has only one room (messages have no room), so there is no `--room` argument and every
+/// export contains messages of that room.
+///
+/// # Fields
+///
+/// * `database_url` - database connection string (default = `DATABASE_URL` environment variable)
+/// * `format` - `jsonl`, `csv` or `html` (default = "jsonl")
+/// * `output` - file that export is written to (default = standard output)
+/// * `user` - only messages of user with this username
+/// * `since` - only messages sent at this time or later, date (`2024-01-31`, midnight UTC) or
+///   RFC 3339 time (`2024-01-31T12:00:00Z`)
+/// * `until` - only messages sent before this time, same format as `since`
+///
+/// # Example
+///
+/// ```
+/// use clap::Parser;
+/// use history::args::Args;
+/// use history::format::Format;
+///
+/// let args = Args::parse_from(["history", "--format", "csv", "--user", "alice"]);
+///
+/// assert_eq!(args.format, Format::Csv);
+/// assert_eq!(args.user.as_deref(), Some("alice"));
+/// assert!(args.since.is_none());
+/// ```
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Exports chat history", long_about = None)]
+pub struct Args {
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    pub database_url: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = Format::Jsonl)]
+    pub format: Format,
+
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long)]
+    pub user: Option<String>,
+
+    #[arg(long, value_parser = parse_time)]
+    pub since: Option<SystemTime>,
+
+    #[arg(long, value_parser = parse_time)]
+    pub until: Option<SystemTime>,
+}
+
+/// Parses date (midnight UTC) or RFC 3339 time.
+///
+/// # Errors
+///
+/// Returns error message when text is neither date nor RFC 3339 time.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use history::args::parse_time;
+///
+/// let day = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+///
+/// assert_eq!(parse_time("2024-01-01").unwrap(), day);
+/// assert_eq!(parse_time("2024-01-01T01:00:00+01:00").unwrap(), day);
+/// assert!(parse_time("yesterday").is_err());
+/// ```
+pub fn parse_time(text: &str) -> Result<SystemTime, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+
+        return Ok(midnight.into());
+    }
+
+    match DateTime::parse_from_rfc3339(text) {
+        Ok(time) => Ok(time.with_timezone(&Utc).into()),
+        Err(_) => Err(format!(
+            "'{}' is neither date (2024-01-31) nor RFC 3339 time (2024-01-31T12:00:00Z)",
+            text
+        )),
+    }
+}
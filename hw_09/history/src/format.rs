@@ -0,0 +1,206 @@
+use std::{
+    io::{self, Write},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::ExportedMessage;
+
+/// Format of exported messages.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma separated values with header
+    Csv,
+    /// Standalone HTML page with colored usernames
+    Html,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    id: i32,
+    created_at: String,
+    user_id: Option<i32>,
+    username: &'a str,
+    color: (u8, u8, u8),
+    text: &'a str,
+}
+
+/// Writes messages in format.
+///
+/// # Arguments
+///
+/// * `format` - format of output
+/// * `messages` - messages to be written, they are written in the same order
+/// * `output` - writer that messages are written to
+///
+/// # Errors
+///
+/// Returns error when can't write to `output`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use history::{format::{write, Format}, ExportedMessage};
+///
+/// let messages = vec![ExportedMessage {
+///     id: 1,
+///     user_id: Some(1),
+///     username: "alice".to_string(),
+///     color: (255, 0, 0),
+///     text: "Hello, <b>world</b>".to_string(),
+///     created_at: UNIX_EPOCH + Duration::from_secs(1_704_067_200),
+/// }];
+///
+/// let mut output = vec![];
+/// write(Format::Jsonl, &messages, &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "{\"id\":1,\"created_at\":\"2024-01-01T00:00:00Z\",\"user_id\":1,\"username\":\"alice\",\"color\":[255,0,0],\"text\":\"Hello, <b>world</b>\"}\n"
+/// );
+///
+/// let mut output = vec![];
+/// write(Format::Csv, &messages, &mut output).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "id,created_at,user_id,username,color,text\n1,2024-01-01T00:00:00Z,1,alice,#ff0000,\"Hello, <b>world</b>\"\n"
+/// );
+///
+/// let mut output = vec![];
+/// write(Format::Html, &messages, &mut output).unwrap();
+/// let html = String::from_utf8(output).unwrap();
+/// assert!(html.contains("<span class=\"username\" style=\"color: #ff0000\">alice</span>"));
+/// assert!(html.contains("Hello, &lt;b&gt;world&lt;/b&gt;"));
+/// ```
+pub fn write(
+    format: Format,
+    messages: &[ExportedMessage],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        Format::Jsonl => write_jsonl(messages, output),
+        Format::Csv => write_csv(messages, output),
+        Format::Html => write_html(messages, output),
+    }
+}
+
+fn write_jsonl(messages: &[ExportedMessage], output: &mut impl Write) -> io::Result<()> {
+    for message in messages {
+        let line = JsonLine {
+            id: message.id,
+            created_at: timestamp(message.created_at),
+            user_id: message.user_id,
+            username: &message.username,
+            color: message.color,
+            text: &message.text,
+        };
+
+        serde_json::to_writer(&mut *output, &line)?;
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+fn write_csv(messages: &[ExportedMessage], output: &mut impl Write) -> io::Result<()> {
+    writeln!(output, "id,created_at,user_id,username,color,text")?;
+
+    for message in messages {
+        let user_id = message
+            .user_id
+            .map(|user_id| user_id.to_string())
+            .unwrap_or_default();
+
+        writeln!(
+            output,
+            "{},{},{},{},{},{}",
+            message.id,
+            timestamp(message.created_at),
+            user_id,
+            csv_field(&message.username),
+            hex_color(message.color),
+            csv_field(&message.text)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_html(messages: &[ExportedMessage], output: &mut impl Write) -> io::Result<()> {
+    write!(
+        output,
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Chat history</title>
+<style>
+body {{ background: #1e1e1e; color: #d4d4d4; font-family: monospace; margin: 2em; }}
+.message {{ margin: 0.2em 0; white-space: pre-wrap; }}
+.time {{ color: #808080; }}
+.username {{ font-weight: bold; }}
+</style>
+</head>
+<body>
+<h1>Chat history</h1>
+<p>{} messages</p>
+"#,
+        messages.len()
+    )?;
+
+    for message in messages {
+        let time = timestamp(message.created_at);
+
+        writeln!(
+            output,
+            r#"<div class="message" id="message-{}"><time class="time" datetime="{}">{}</time> <span class="username" style="color: {}">{}</span>: {}</div>"#,
+            message.id,
+            time,
+            time,
+            hex_color(message.color),
+            html_escape(&message.username),
+            html_escape(&message.text)
+        )?;
+    }
+
+    writeln!(output, "</body>\n</html>")
+}
+
+/// Formats time as RFC 3339 in UTC with seconds.
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn hex_color((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Quotes field that contains separator, quote or new line (RFC 4180).
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
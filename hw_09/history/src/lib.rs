@@ -0,0 +1,94 @@
+//! Export of chat history for archiving.
+//!
+//! Messages are read from `ChatStore`, optionally only messages of one user and from some time
+//! range, and written as JSON lines, CSV or standalone HTML transcript (see `format` module).
+//! Every exported message has username and color of its author.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use database::{
+    models::{User, DEFAULT_COLOR, DELETED_USERNAME},
+    store::{ChatStore, MessageFilter, StoreError},
+};
+
+/// Program arguments
+pub mod args;
+/// Output formats
+pub mod format;
+
+/// Stored message together with its author.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedMessage {
+    pub id: i32,
+    /// `None` when author deleted his account.
+    pub user_id: Option<i32>,
+    pub username: String,
+    pub color: (u8, u8, u8),
+    pub text: String,
+    pub created_at: SystemTime,
+}
+
+/// Returns messages selected by filter with their authors, oldest first.
+///
+/// # Errors
+///
+/// Returns error when messages or their authors can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore, MessageFilter};
+/// use history::exported_messages;
+///
+/// let store = MemoryStore::default();
+/// let alice = store.register("alice", "password", (255, 0, 0)).unwrap();
+///
+/// store.insert_message(alice.id, "Hello").unwrap();
+///
+/// let messages = exported_messages(&store, &MessageFilter::default()).unwrap();
+///
+/// assert_eq!(messages[0].username, "alice");
+/// assert_eq!(messages[0].color, (255, 0, 0));
+/// assert_eq!(messages[0].text, "Hello");
+/// ```
+pub fn exported_messages(
+    store: &dyn ChatStore,
+    filter: &MessageFilter,
+) -> Result<Vec<ExportedMessage>, StoreError> {
+    // authors are read once, they usually have many messages
+    let mut authors: HashMap<i32, (String, (u8, u8, u8))> = HashMap::new();
+    let mut exported = vec![];
+
+    for message in store.messages_matching(filter)? {
+        let (username, color) = match message.user_id {
+            Some(user_id) => match authors.get(&user_id) {
+                Some(author) => author.clone(),
+                None => {
+                    let author = author(store, store.user_by_id(user_id)?)?;
+
+                    authors.insert(user_id, author.clone());
+
+                    author
+                }
+            },
+            None => (DELETED_USERNAME.to_string(), DEFAULT_COLOR),
+        };
+
+        exported.push(ExportedMessage {
+            id: message.id,
+            user_id: message.user_id,
+            username,
+            color,
+            text: message.text,
+            created_at: message.created_at,
+        });
+    }
+
+    Ok(exported)
+}
+
+fn author(store: &dyn ChatStore, user: User) -> Result<(String, (u8, u8, u8)), StoreError> {
+    let color = store.user_color(&user)?.unwrap_or(DEFAULT_COLOR);
+
+    Ok((user.username, color))
+}
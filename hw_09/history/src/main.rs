@@ -0,0 +1,58 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use database::store::{postgres::PgStore, ChatStore, MessageFilter, StoreError};
+use history::{args::Args, exported_messages, format::write};
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if let (Some(since), Some(until)) = (args.since, args.until) {
+        if since >= until {
+            bail!("--since must be before --until");
+        }
+    }
+
+    let store = match &args.database_url {
+        Some(url) => PgStore::connect(url)?,
+        None => PgStore::connect_from_env()?,
+    };
+
+    let author_id = match &args.user {
+        Some(username) => match store.user_by_username(username) {
+            Ok(user) => Some(user.id),
+            Err(StoreError::NotFound) => bail!("User '{}' does not exist.", username),
+            Err(e) => return Err(e.into()),
+        },
+        None => None,
+    };
+
+    let filter = MessageFilter {
+        author_id,
+        since: args.since,
+        until: args.until,
+    };
+
+    let messages = exported_messages(&store, &filter)?;
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("Could not create {}", path.display())
+            })?))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    write(args.format, &messages, &mut output)?;
+    output.flush()?;
+
+    eprintln!("{} messages exported.", messages.len());
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
-use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc,
+};
 
 use crate::{errors::MessageError, receiver::MessageReceiver, sender::MessageSender};
 
@@ -27,6 +30,20 @@ impl MessageReceiverSenderBuilder {
         })
     }
 
+    /// Creates receiver and sender that exchange JSON encoded messages over channels.
+    ///
+    /// Other ends of channels are used by transport that is not tcp stream with bincode messages
+    /// (for example WebSocket connection).
+    pub fn from_json_channels(
+        rx: mpsc::Receiver<String>,
+        tx: mpsc::Sender<String>,
+    ) -> MessageReceiverSenderBuilder {
+        MessageReceiverSenderBuilder {
+            message_receiver: MessageReceiver::from_json_channel(rx),
+            message_sender: MessageSender::from_json_channel(tx),
+        }
+    }
+
     pub fn message_receiver(&self) -> MessageReceiver {
         self.message_receiver.clone()
     }
@@ -0,0 +1,215 @@
+//! End-to-end encryption of direct messages.
+//!
+//! Every user has X25519 key pair, private key never leaves his client and public key is published
+//! to server. Sender and recipient derive the same shared key from own private key and public key
+//! of the other one, message is encrypted by XChaCha20-Poly1305 with random nonce, so server
+//! stores and relays only ciphertext.
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::errors::E2eError;
+
+/// Length of public and private X25519 key.
+pub const KEY_LENGTH: usize = 32;
+
+/// Separates keys derived here from other uses of the same shared secret.
+const KEY_DERIVATION_CONTEXT: &[u8] = b"chat direct message v1";
+
+/// Key pair of user, it is generated and kept by client.
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates new random key pair.
+    pub fn generate() -> Self {
+        Self::from_secret(StaticSecret::random_from_rng(OsRng).to_bytes())
+    }
+
+    /// Restores key pair from stored private key.
+    pub fn from_secret(secret: [u8; KEY_LENGTH]) -> Self {
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+
+        KeyPair { secret, public }
+    }
+
+    /// Private key, it has to be stored only by its owner.
+    pub fn secret(&self) -> [u8; KEY_LENGTH] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; KEY_LENGTH] {
+        self.public.to_bytes()
+    }
+
+    /// Encrypts message for owner of `recipient_key`, returns nonce and ciphertext.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use libs::e2e::KeyPair;
+    /// use libs::errors::E2eError;
+    ///
+    /// let alice = KeyPair::generate();
+    /// let bob = KeyPair::generate();
+    /// let eve = KeyPair::generate();
+    ///
+    /// let (nonce, ciphertext) = alice.encrypt(&bob.public_key(), b"Hello Bob").unwrap();
+    ///
+    /// assert_eq!(
+    ///     bob.decrypt(&alice.public_key(), &nonce, &ciphertext).unwrap(),
+    ///     b"Hello Bob"
+    /// );
+    /// assert!(eve.decrypt(&alice.public_key(), &nonce, &ciphertext).is_err());
+    ///
+    /// // key of small order would make shared key known to everybody
+    /// assert_eq!(
+    ///     alice.encrypt(&[0; 32], b"Hello Bob"),
+    ///     Err(E2eError::WeakKey)
+    /// );
+    /// ```
+    pub fn encrypt(
+        &self,
+        recipient_key: &[u8],
+        plaintext: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), E2eError> {
+        let recipient_key = parse_public_key(recipient_key)?;
+        let cipher = self.cipher(&recipient_key)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        // both public keys are authenticated, so message can not be passed off as sent by other key
+        let aad = [self.public.to_bytes(), recipient_key.to_bytes()].concat();
+
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| E2eError::Encryption)?;
+
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    /// Decrypts message from owner of `sender_key` that was encrypted for this key pair.
+    pub fn decrypt(
+        &self,
+        sender_key: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, E2eError> {
+        let sender_key = parse_public_key(sender_key)?;
+        let cipher = self.cipher(&sender_key)?;
+
+        if nonce.len() != XNonce::default().len() {
+            return Err(E2eError::Decryption);
+        }
+
+        let aad = [sender_key.to_bytes(), self.public.to_bytes()].concat();
+
+        cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| E2eError::Decryption)
+    }
+
+    /// Returns cipher with key derived from shared secret and both public keys (ordered, so both
+    /// sides derive the same key). Key of small order (for example all zeros) is refused, shared
+    /// secret with it does not depend on private key.
+    fn cipher(&self, other_key: &PublicKey) -> Result<XChaCha20Poly1305, E2eError> {
+        let shared_secret = self.secret.diffie_hellman(other_key);
+
+        if !shared_secret.was_contributory() {
+            return Err(E2eError::WeakKey);
+        }
+
+        let (first, second) = match self.public.as_bytes() <= other_key.as_bytes() {
+            true => (self.public.as_bytes(), other_key.as_bytes()),
+            false => (other_key.as_bytes(), self.public.as_bytes()),
+        };
+
+        let key = Sha256::new()
+            .chain_update(KEY_DERIVATION_CONTEXT)
+            .chain_update(shared_secret.as_bytes())
+            .chain_update(first)
+            .chain_update(second)
+            .finalize();
+
+        Ok(XChaCha20Poly1305::new(&key))
+    }
+}
+
+fn parse_public_key(key: &[u8]) -> Result<PublicKey, E2eError> {
+    let key: [u8; KEY_LENGTH] = key.try_into().map_err(|_| E2eError::InvalidKey)?;
+
+    Ok(PublicKey::from(key))
+}
+
+/// Returns fingerprint of public key that users compare over other channel, it is first 16 bytes
+/// of SHA-256 of key in groups of 4 hex digits.
+///
+/// # Example
+///
+/// ```
+/// use libs::e2e::{fingerprint, KeyPair};
+///
+/// let key_pair = KeyPair::generate();
+/// let fingerprint = fingerprint(&key_pair.public_key());
+///
+/// assert_eq!(fingerprint.len(), 39);
+/// assert_eq!(fingerprint.split(' ').count(), 8);
+/// ```
+pub fn fingerprint(public_key: &[u8]) -> String {
+    Sha256::digest(public_key)[..16]
+        .chunks(2)
+        .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Encodes key as hex, for example to store it in file.
+pub fn key_to_hex(key: &[u8]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes key encoded by `key_to_hex`, `None` when it is not valid key.
+///
+/// # Example
+///
+/// ```
+/// use libs::e2e::{key_from_hex, key_to_hex, KeyPair};
+///
+/// let key = KeyPair::generate().public_key();
+///
+/// assert_eq!(key_from_hex(&key_to_hex(&key)), Some(key));
+/// assert_eq!(key_from_hex("abcd"), None);
+/// ```
+pub fn key_from_hex(hex: &str) -> Option<[u8; KEY_LENGTH]> {
+    if hex.len() != KEY_LENGTH * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; KEY_LENGTH];
+
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(key)
+}
@@ -6,6 +6,8 @@ use thiserror::Error;
 pub enum MessageError {
     Io(#[from] std::io::Error),
     DeserializeSerialize(#[from] Box<bincode::ErrorKind>),
+    Json(#[from] serde_json::Error),
+    ChannelClosed,
 }
 
 impl Display for MessageError {
@@ -13,3 +15,15 @@ impl Display for MessageError {
         write!(f, "MessageError")
     }
 }
+
+#[derive(Error, Debug, PartialEq)]
+pub enum E2eError {
+    #[error("Public key does not have 32 bytes")]
+    InvalidKey,
+    #[error("Public key is weak (shared secret would not depend on private key)")]
+    WeakKey,
+    #[error("Message could not be encrypted")]
+    Encryption,
+    #[error("Message could not be decrypted (it was changed or encrypted for other key)")]
+    Decryption,
+}
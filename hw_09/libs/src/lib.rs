@@ -1,9 +1,12 @@
 pub mod builder;
+pub mod e2e;
 pub mod errors;
 pub mod message;
 pub mod password;
 pub mod receiver;
+pub mod role;
 pub mod sender;
+pub mod token;
 
 pub fn remove_new_line(string: &mut String) {
     *string = string
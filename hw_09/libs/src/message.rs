@@ -1,10 +1,13 @@
-use std::time::SystemTime;
+use std::{
+    fmt::{Display, Formatter},
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::errors::MessageError;
+use crate::{errors::MessageError, role::Role};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Message {
     pub message: MessageType,
     pub user_info: UserInfo,
@@ -17,7 +20,15 @@ impl Message {
     }
 
     pub fn deserialize(bytes: &[u8]) -> Result<Self, MessageError> {
-        Ok(bincode::deserialize(&bytes)?)
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, MessageError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, MessageError> {
+        Ok(serde_json::from_str(json)?)
     }
 }
 
@@ -31,7 +42,30 @@ impl From<MessageType> for Message {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Version of protocol, client sends it in `ClientHello` as first message and server refuses
+/// other versions.
+///
+/// Messages are encoded by bincode, which identifies variant of `MessageType` by its position. New
+/// variants are appended at the end, so older ones keep their positions, and version is increased
+/// whenever data of existing variant changes.
+///
+/// # Example
+///
+/// ```
+/// use libs::message::MessageType;
+///
+/// // old clients send login first and have to understand refusal
+/// let login = bincode::serialize(&MessageType::LoginRequest(String::new(), String::new())).unwrap();
+/// let refusal = bincode::serialize(&MessageType::UnrecoverableError(String::new())).unwrap();
+///
+/// assert_eq!(login[..4], 9u32.to_le_bytes());
+/// assert_eq!(refusal[..4], 8u32.to_le_bytes());
+/// ```
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Type of message with its data, new variants have to be appended at the end (see
+/// `PROTOCOL_VERSION`).
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum MessageType {
     Text(String),
     Image(Vec<u8>),
@@ -43,16 +77,180 @@ pub enum MessageType {
     RecoverableError(String),
     UnrecoverableError(String),
     LoginRequest(String, String),
-    LoginResponse(Option<UserInfo>),
+    LoginResponse(Result<(UserInfo, String), String>),
     RegisterRequest(String, String, u8, u8, u8),
-    RegisterResponse(Option<UserInfo>),
+    RegisterResponse(Result<(UserInfo, String), String>),
     OldMessagesRequest(),
     OldMessagesResponse(Vec<(String, UserInfo)>),
+    PresenceRequest(),
+    PresenceResponse(Vec<PresenceInfo>),
+    PasswordChangeRequest(String, String),
+    PasswordChangeResponse(Result<(), String>),
+    AccountDeleteRequest(String),
+    AccountDeleteResponse(Result<(), String>),
+    TokenLoginRequest(String),
+    LogoutRequest(),
+    SessionsRequest(),
+    SessionsResponse(Vec<SessionInfo>),
+    SessionRevokeRequest(i32),
+    SessionRevokeResponse(Result<(), String>),
+    RoleChangeRequest(String, Role),
+    KickRequest(String),
+    /// Username, duration (`None` is permanent) and reason.
+    BanRequest(String, Option<Duration>, String),
+    UnbanRequest(String),
+    /// Username, duration (`None` is permanent) and reason.
+    MuteRequest(String, Option<Duration>, String),
+    UnmuteRequest(String),
+    MessagesDeleteRequest(String, u32),
+    ActionResponse(Result<String, String>),
+    PublicKeyPublish(Vec<u8>),
+    PublicKeyRequest(String),
+    /// Username and his public key.
+    PublicKeyResponse(String, Result<Vec<u8>, String>),
+    DirectMessage(EncryptedMessage),
+    /// Direct messages and mentions received while user was offline, oldest first.
+    OfflineMessages(Vec<Message>),
+    UserTyping(bool),
+    UserStatusChange(UserStatus),
+    /// Protocol version of client (`PROTOCOL_VERSION`), it is the first message of connection.
+    ClientHello(u32),
+}
+
+/// Direct message encrypted by sender for one recipient (see `e2e` module), server can read only
+/// recipient and public keys.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct EncryptedMessage {
+    pub recipient: String,
+    /// Id of recipient, it is filled by server.
+    pub recipient_id: i32,
+    pub sender_key: Vec<u8>,
+    /// Key of recipient used for encryption, recipient can decrypt message only with this key.
+    pub recipient_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct UserInfo {
     pub id: i32,
     pub username: String,
     pub color: (u8, u8, u8),
 }
+
+impl MessageType {
+    /// Name of the message type (without data), usable for example as metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::Text(_) => "Text",
+            MessageType::Image(_) => "Image",
+            MessageType::File(..) => "File",
+            MessageType::UserConnect() => "UserConnect",
+            MessageType::UserDisconnect() => "UserDisconnect",
+            MessageType::UserNameChange(_) => "UserNameChange",
+            MessageType::UserColorChange(..) => "UserColorChange",
+            MessageType::UserTyping(_) => "UserTyping",
+            MessageType::UserStatusChange(_) => "UserStatusChange",
+            MessageType::RecoverableError(_) => "RecoverableError",
+            MessageType::UnrecoverableError(_) => "UnrecoverableError",
+            MessageType::LoginRequest(..) => "LoginRequest",
+            MessageType::LoginResponse(_) => "LoginResponse",
+            MessageType::RegisterRequest(..) => "RegisterRequest",
+            MessageType::RegisterResponse(_) => "RegisterResponse",
+            MessageType::OldMessagesRequest() => "OldMessagesRequest",
+            MessageType::OldMessagesResponse(_) => "OldMessagesResponse",
+            MessageType::PresenceRequest() => "PresenceRequest",
+            MessageType::PresenceResponse(_) => "PresenceResponse",
+            MessageType::PasswordChangeRequest(..) => "PasswordChangeRequest",
+            MessageType::PasswordChangeResponse(_) => "PasswordChangeResponse",
+            MessageType::AccountDeleteRequest(_) => "AccountDeleteRequest",
+            MessageType::AccountDeleteResponse(_) => "AccountDeleteResponse",
+            MessageType::TokenLoginRequest(_) => "TokenLoginRequest",
+            MessageType::LogoutRequest() => "LogoutRequest",
+            MessageType::SessionsRequest() => "SessionsRequest",
+            MessageType::SessionsResponse(_) => "SessionsResponse",
+            MessageType::SessionRevokeRequest(_) => "SessionRevokeRequest",
+            MessageType::SessionRevokeResponse(_) => "SessionRevokeResponse",
+            MessageType::RoleChangeRequest(..) => "RoleChangeRequest",
+            MessageType::KickRequest(_) => "KickRequest",
+            MessageType::BanRequest(..) => "BanRequest",
+            MessageType::UnbanRequest(_) => "UnbanRequest",
+            MessageType::MuteRequest(..) => "MuteRequest",
+            MessageType::UnmuteRequest(_) => "UnmuteRequest",
+            MessageType::MessagesDeleteRequest(..) => "MessagesDeleteRequest",
+            MessageType::ActionResponse(_) => "ActionResponse",
+            MessageType::PublicKeyPublish(_) => "PublicKeyPublish",
+            MessageType::PublicKeyRequest(_) => "PublicKeyRequest",
+            MessageType::PublicKeyResponse(..) => "PublicKeyResponse",
+            MessageType::DirectMessage(_) => "DirectMessage",
+            MessageType::OfflineMessages(_) => "OfflineMessages",
+            MessageType::ClientHello(_) => "ClientHello",
+        }
+    }
+
+    /// Returns whether message type is response that server sends only to client that requested
+    /// it.
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            MessageType::LoginResponse(_)
+                | MessageType::RegisterResponse(_)
+                | MessageType::OldMessagesResponse(_)
+                | MessageType::PresenceResponse(_)
+                | MessageType::PasswordChangeResponse(_)
+                | MessageType::AccountDeleteResponse(_)
+                | MessageType::SessionsResponse(_)
+                | MessageType::SessionRevokeResponse(_)
+                | MessageType::ActionResponse(_)
+                | MessageType::PublicKeyResponse(..)
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum UserStatus {
+    #[default]
+    Online,
+    Away,
+    Busy,
+    Custom(String),
+}
+
+impl Display for UserStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            UserStatus::Online => write!(f, "online"),
+            UserStatus::Away => write!(f, "away"),
+            UserStatus::Busy => write!(f, "busy"),
+            UserStatus::Custom(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+impl From<&str> for UserStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "online" => UserStatus::Online,
+            "away" => UserStatus::Away,
+            "busy" => UserStatus::Busy,
+            _ => UserStatus::Custom(status.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PresenceInfo {
+    pub user_info: UserInfo,
+    pub status: UserStatus,
+    pub idle: Duration,
+}
+
+/// Session (login on one device) of user, `current` is session of client that asked for list.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SessionInfo {
+    pub id: i32,
+    pub created_at: SystemTime,
+    pub last_used_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub current: bool,
+}
@@ -1,21 +1,159 @@
+//! Hashing of passwords.
+//!
+//! New hashes are Argon2id with cost set by `set_password_cost`. Verification accepts Argon2 and
+//! legacy PBKDF2 hashes, algorithm is read from PHC string (`$argon2id$...`, `$pbkdf2-sha256$...`).
+
+use std::sync::RwLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
 use pbkdf2::{
     password_hash::{Error, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Pbkdf2,
 };
 use rand_core::OsRng;
 
+/// Cost of Argon2id hashing, defaults are recommended by OWASP (19 MiB, 2 iterations, 1 lane).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordCost {
+    /// Memory in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    /// Degree of parallelism (lanes).
+    pub parallelism: u32,
+}
+
+const DEFAULT_COST: PasswordCost = PasswordCost {
+    memory_kib: Params::DEFAULT_M_COST,
+    iterations: Params::DEFAULT_T_COST,
+    parallelism: Params::DEFAULT_P_COST,
+};
+
+impl Default for PasswordCost {
+    fn default() -> Self {
+        DEFAULT_COST
+    }
+}
+
+impl PasswordCost {
+    /// Checks that Argon2 accepts this cost (for example memory has to be at least 8 KiB per
+    /// lane).
+    pub fn validate(&self) -> Result<(), Error> {
+        self.params().map(|_| ())
+    }
+
+    fn params(&self) -> Result<Params, Error> {
+        Ok(Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )?)
+    }
+}
+
+/// Cost used by `hash_password`, it is set once at startup.
+static PASSWORD_COST: RwLock<PasswordCost> = RwLock::new(DEFAULT_COST);
+
+/// Sets cost of new hashes, existing hashes with other cost are rehashed at next login (see
+/// `needs_rehash`).
+pub fn set_password_cost(cost: PasswordCost) -> Result<(), Error> {
+    cost.validate()?;
+
+    *PASSWORD_COST.write().unwrap_or_else(|e| e.into_inner()) = cost;
+
+    Ok(())
+}
+
+/// Returns cost of new hashes.
+pub fn password_cost() -> PasswordCost {
+    *PASSWORD_COST.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Hashes password by Argon2id with cost set by `set_password_cost`.
 pub fn hash_password(password: &str) -> Result<String, Error> {
+    hash_password_with_cost(password, password_cost())
+}
+
+/// Hashes password by Argon2id with given cost.
+pub fn hash_password_with_cost(password: &str, cost: PasswordCost) -> Result<String, Error> {
     let salt = SaltString::generate(&mut OsRng);
 
-    Ok(Pbkdf2
-        .hash_password(password.as_bytes(), &salt)?
-        .to_string())
+    Ok(
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, cost.params()?)
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string(),
+    )
 }
 
+/// Verifies password against Argon2 or PBKDF2 hash, error is returned only for malformed hash.
+///
+/// # Example
+///
+/// ```
+/// use libs::password::{hash_password_with_cost, verify_password, PasswordCost};
+///
+/// let cost = PasswordCost {
+///     memory_kib: 8,
+///     iterations: 1,
+///     parallelism: 1,
+/// };
+/// let argon2_hash = hash_password_with_cost("password", cost).unwrap();
+/// let pbkdf2_hash =
+///     "$pbkdf2-sha256$i=1000,l=32$dGVzdHNhbHR0ZXN0c2FsdA$RB2dpfVEWj2VJoCXbPbSQzxcn+20ILr/HCSkvD7/EF8";
+///
+/// assert!(verify_password("password", &argon2_hash).unwrap());
+/// assert!(verify_password("password", pbkdf2_hash).unwrap());
+/// assert!(!verify_password("wrong", &argon2_hash).unwrap());
+/// assert!(verify_password("password", "not a hash").is_err());
+/// ```
 pub fn verify_password(password: &str, password_hash: &str) -> Result<bool, Error> {
-    let parsed_hash = PasswordHash::new(&password_hash)?;
+    let parsed_hash = PasswordHash::new(password_hash)?;
 
-    Ok(Pbkdf2
-        .verify_password(password.as_bytes(), &parsed_hash)
+    // only verifier of algorithm from PHC string accepts the hash, parameters are read from it too
+    let verifiers: [&dyn PasswordVerifier; 2] = [&Argon2::default(), &Pbkdf2];
+
+    Ok(parsed_hash
+        .verify_password(&verifiers, password.as_bytes())
         .is_ok())
 }
+
+/// Returns true when hash is not Argon2id with current cost (legacy PBKDF2 hash, changed cost or
+/// malformed hash), so password should be hashed again after it is verified.
+///
+/// # Example
+///
+/// ```
+/// use libs::password::{hash_password_with_cost, needs_rehash, password_cost, PasswordCost};
+///
+/// let cheap = PasswordCost {
+///     memory_kib: 8,
+///     iterations: 1,
+///     parallelism: 1,
+/// };
+///
+/// assert!(needs_rehash(&hash_password_with_cost("password", cheap).unwrap()));
+/// assert!(!needs_rehash(&hash_password_with_cost("password", password_cost()).unwrap()));
+/// assert!(needs_rehash(
+///     "$pbkdf2-sha256$i=1000,l=32$dGVzdHNhbHR0ZXN0c2FsdA$RB2dpfVEWj2VJoCXbPbSQzxcn+20ILr/HCSkvD7/EF8"
+/// ));
+/// ```
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+
+    let cost = password_cost();
+
+    match Params::try_from(&parsed_hash) {
+        Ok(params) => {
+            params.m_cost() != cost.memory_kib
+                || params.t_cost() != cost.iterations
+                || params.p_cost() != cost.parallelism
+        }
+        Err(_) => true,
+    }
+}
@@ -1,34 +1,68 @@
 use std::sync::Arc;
 
-use tokio::{io::AsyncReadExt, net::tcp::OwnedReadHalf, sync::Mutex};
+use tokio::{
+    io::AsyncReadExt,
+    net::tcp::OwnedReadHalf,
+    sync::{mpsc, Mutex},
+};
 
 use crate::{errors::MessageError, message::Message};
 
 #[derive(Clone)]
 pub struct MessageReceiver {
-    stream: Arc<Mutex<OwnedReadHalf>>,
+    stream: ReceiverStream,
+}
+
+/// Source of messages, either tcp stream with length prefixed bincode messages or channel with
+/// JSON encoded messages (used for example by WebSocket connections).
+#[derive(Clone)]
+enum ReceiverStream {
+    Tcp(Arc<Mutex<OwnedReadHalf>>),
+    Json(Arc<Mutex<mpsc::Receiver<String>>>),
 }
 
 impl MessageReceiver {
     pub fn from_owned_read_half(stream: OwnedReadHalf) -> Self {
         Self {
-            stream: Arc::new(Mutex::new(stream)),
+            stream: ReceiverStream::Tcp(Arc::new(Mutex::new(stream))),
+        }
+    }
+
+    pub fn from_json_channel(rx: mpsc::Receiver<String>) -> Self {
+        Self {
+            stream: ReceiverStream::Json(Arc::new(Mutex::new(rx))),
         }
     }
 
     pub async fn receive_message(&mut self) -> Result<Message, MessageError> {
-        let mut len = [0; 4];
+        Ok(self.receive_message_with_size().await?.0)
+    }
+
+    /// Receives message and returns it with number of read bytes (including length prefix).
+    pub async fn receive_message_with_size(&mut self) -> Result<(Message, usize), MessageError> {
+        match &self.stream {
+            ReceiverStream::Tcp(stream) => {
+                let mut len = [0; 4];
 
-        let mut stream = self.stream.lock().await;
+                let mut stream = stream.lock().await;
 
-        stream.read_exact(&mut len).await?;
+                stream.read_exact(&mut len).await?;
 
-        let len = u32::from_be_bytes(len) as usize;
+                let len = u32::from_be_bytes(len) as usize;
 
-        let mut buffer = vec![0; len];
+                let mut buffer = vec![0; len];
 
-        stream.read_exact(&mut buffer).await?;
+                stream.read_exact(&mut buffer).await?;
 
-        Ok(Message::deserialize(&buffer)?)
+                Ok((Message::deserialize(&buffer)?, 4 + len))
+            }
+            ReceiverStream::Json(rx) => {
+                let Some(json) = rx.lock().await.recv().await else {
+                    return Err(MessageError::ChannelClosed);
+                };
+
+                Ok((Message::from_json(&json)?, json.len()))
+            }
+        }
     }
 }
@@ -0,0 +1,94 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Role of user, every role has fixed set of permissions (see `Role::has`).
+///
+/// Roles are ordered by rank, user can kick or ban only users with lower role.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Guest,
+    #[default]
+    Member,
+    Moderator,
+    Admin,
+}
+
+/// Action that is allowed only to some roles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    DeleteOthersMessages,
+    Kick,
+    Ban,
+    /// There are no rooms yet, it is reserved for them.
+    ManageRooms,
+    UploadFiles,
+    ManageRoles,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Admin, Role::Moderator, Role::Member, Role::Guest];
+
+    /// Returns true when role has permission.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use libs::role::{Permission, Role};
+    ///
+    /// assert!(Role::Admin.has(Permission::ManageRoles));
+    /// assert!(Role::Moderator.has(Permission::Kick));
+    /// assert!(!Role::Moderator.has(Permission::ManageRoles));
+    /// assert!(Role::Member.has(Permission::UploadFiles));
+    /// assert!(!Role::Guest.has(Permission::UploadFiles));
+    /// ```
+    pub fn has(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Moderator => matches!(
+                permission,
+                Permission::DeleteOthersMessages
+                    | Permission::Kick
+                    | Permission::Ban
+                    | Permission::UploadFiles
+            ),
+            Role::Member => permission == Permission::UploadFiles,
+            Role::Guest => false,
+        }
+    }
+
+    /// Name of role stored in database and used in commands.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::Member => "member",
+            Role::Guest => "guest",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|known| known.as_str() == role)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown role '{}', roles are admin, moderator, member and guest",
+                    role
+                )
+            })
+    }
+}
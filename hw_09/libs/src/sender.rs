@@ -1,30 +1,63 @@
 use std::sync::Arc;
 
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::Mutex};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::{mpsc, Mutex},
+};
 
 use crate::{errors::MessageError, message::Message};
 
 #[derive(Clone)]
 pub struct MessageSender {
-    stream: Arc<Mutex<OwnedWriteHalf>>,
+    stream: SenderStream,
+}
+
+/// Destination of messages, either tcp stream with length prefixed bincode messages or channel
+/// with JSON encoded messages (used for example by WebSocket connections).
+#[derive(Clone)]
+enum SenderStream {
+    Tcp(Arc<Mutex<OwnedWriteHalf>>),
+    Json(mpsc::Sender<String>),
 }
 
 impl MessageSender {
     pub fn from_owned_write_half(stream: OwnedWriteHalf) -> Self {
         Self {
-            stream: Arc::new(Mutex::new(stream)),
+            stream: SenderStream::Tcp(Arc::new(Mutex::new(stream))),
+        }
+    }
+
+    pub fn from_json_channel(tx: mpsc::Sender<String>) -> Self {
+        Self {
+            stream: SenderStream::Json(tx),
         }
     }
 
-    pub async fn send_message(&mut self, message: &Message) -> Result<(), MessageError> {
-        let serialized = message.serialize()?;
-        let len = serialized.len() as u32;
+    /// Sends message and returns number of written bytes (including length prefix).
+    pub async fn send_message(&mut self, message: &Message) -> Result<usize, MessageError> {
+        match &self.stream {
+            SenderStream::Tcp(stream) => {
+                let serialized = message.serialize()?;
+                let len = serialized.len() as u32;
+
+                let mut stream = stream.lock().await;
 
-        let mut stream = self.stream.lock().await;
+                stream.write_all(&len.to_be_bytes()).await?;
+                stream.write_all(serialized.as_slice()).await?;
 
-        stream.write(&len.to_be_bytes()).await?;
-        stream.write_all(serialized.as_slice()).await?;
+                Ok(4 + serialized.len())
+            }
+            SenderStream::Json(tx) => {
+                let json = message.to_json()?;
+                let len = json.len();
 
-        Ok(())
+                tx.send(json)
+                    .await
+                    .map_err(|_| MessageError::ChannelClosed)?;
+
+                Ok(len)
+            }
+        }
     }
 }
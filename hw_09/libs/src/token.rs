@@ -0,0 +1,36 @@
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Number of random bytes in session token.
+const TOKEN_BYTES: usize = 32;
+
+/// Generates random session token (hex encoded).
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+
+    to_hex(&bytes)
+}
+
+/// Hashes session token, only hash of token is stored.
+///
+/// Token is random and long, so fast hash is enough (unlike passwords).
+///
+/// # Example
+///
+/// ```
+/// use libs::token::{generate_token, hash_token};
+///
+/// let token = generate_token();
+///
+/// assert_eq!(token.len(), 64);
+/// assert_eq!(hash_token(&token), hash_token(&token));
+/// assert_ne!(hash_token(&token), token);
+/// ```
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
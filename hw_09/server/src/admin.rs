@@ -0,0 +1,597 @@
+//! Provides REST admin API for users, connected sessions and messages.
+//!
+//! All endpoints require header `Authorization: Bearer <admin token>` and exchange JSON:
+//!
+//! - `GET /users` - lists users
+//! - `POST /users` - creates user (`{"username": "...", "password": "...", "color": [r, g, b]}`),
+//!   returns `409 Conflict` when username (case of letters does not matter) is taken
+//! - `POST /users/:id/disable`, `POST /users/:id/enable` - disables or enables user, disabled user
+//!   can not log in and his sessions are kicked
+//! - `POST /users/:id/password` - resets password of user (`{"password": "..."}`)
+//! - `DELETE /users/:id` - deletes user with his messages, his sessions are kicked
+//! - `POST /users/:id/kick` - kicks all sessions of user
+//! - `GET /sessions` - lists clients connected to this instance
+//! - `GET /messages?user_id=<id>&limit=<limit>` - lists newest messages (both parameters are
+//!   optional, default limit is 100), `user_id` of messages of deleted accounts is `null`
+//! - `DELETE /messages/:id` - deletes message
+//! - `POST /announcements` - sends text to all connected clients (of all instances) from
+//!   `server-bot`, for example
+//!   deploy notification (`{"text": "..."}`)
+//! - `GET /moderation/flags` - lists messages flagged by moderation rules, newest first
+//! - `GET /sanctions` - lists bans and mutes that did not expire, newest first
+//! - `POST /sanctions` - bans or mutes user or IP address (`{"kind": "ban", "user_id": 1,
+//!   "ip": "10.0.0.1", "duration_secs": 3600, "reason": "..."}`, one of `user_id` and `ip` is
+//!   required, sanction without `duration_secs` is permanent), affected clients are kicked (ban)
+//!   or notified (mute)
+//! - `DELETE /sanctions/:id` - lifts sanction
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::broadcast::Sender};
+use tracing::{error, info};
+
+use database::{
+    models::{Sanction, SanctionKind, User},
+    store::{ChatStore, StoreError},
+};
+
+use libs::message::{Message, MessageType};
+
+use crate::{
+    cluster::{self, Cluster, ClusterEventKind, Target},
+    config::Config,
+    hub::{Hub, Recipients},
+    moderation::Moderation,
+    plugins::bot_user_info,
+    sanctions, with_password_hashing,
+};
+
+/// Structures shared by all admin API requests.
+#[derive(Clone)]
+struct AdminState {
+    hub: Hub,
+    cluster: Arc<Cluster>,
+    config: Arc<Config>,
+    store: Arc<dyn ChatStore>,
+    moderation: Arc<Moderation>,
+}
+
+/// Error returned from admin API handlers, it is converted to HTTP response.
+enum AdminError {
+    NotFound,
+    BadRequest(String),
+    Conflict(String),
+    Database(StoreError),
+}
+
+impl From<StoreError> for AdminError {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::NotFound => AdminError::NotFound,
+            StoreError::UsernameTaken => AdminError::Conflict(format!("{}.", error)),
+            StoreError::Invalid(_) | StoreError::WrongPassword => {
+                AdminError::BadRequest(format!("{}.", error))
+            }
+            error => AdminError::Database(error),
+        }
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AdminError::NotFound => (StatusCode::NOT_FOUND, "Not found.".to_string()),
+            AdminError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AdminError::Conflict(message) => (StatusCode::CONFLICT, message),
+            AdminError::Database(e) => {
+                error!(event = "error", error = %e, "Admin API database error.");
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Database error.".to_string(),
+                )
+            }
+        };
+
+        (status, Json(ErrorResponse { error: message })).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct UserResponse {
+    id: i32,
+    username: String,
+    color: Option<(u8, u8, u8)>,
+    disabled: bool,
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    password: String,
+    color: Option<(u8, u8, u8)>,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordRequest {
+    password: String,
+}
+
+#[derive(Serialize)]
+struct SessionResponse {
+    addr: SocketAddr,
+    user_id: i32,
+    username: String,
+    status: String,
+    idle_secs: u64,
+}
+
+#[derive(Serialize)]
+struct KickResponse {
+    kicked: usize,
+}
+
+#[derive(Serialize)]
+struct FlagResponse {
+    user_id: i32,
+    username: String,
+    text: String,
+    reasons: Vec<String>,
+    flagged_at: u64,
+}
+
+#[derive(Deserialize)]
+struct AnnouncementRequest {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct AnnouncementResponse {
+    recipients: usize,
+}
+
+#[derive(Serialize)]
+struct SanctionResponse {
+    id: i32,
+    kind: String,
+    user_id: Option<i32>,
+    ip: Option<String>,
+    reason: String,
+    created_by: Option<i32>,
+    created_at: u64,
+    expires_at: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CreateSanctionRequest {
+    kind: String,
+    user_id: Option<i32>,
+    ip: Option<String>,
+    duration_secs: Option<u64>,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    user_id: Option<i32>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct MessageResponse {
+    id: i32,
+    user_id: Option<i32>,
+    text: String,
+    created_at: u64,
+}
+
+/// Handles requests to REST admin API.
+///
+/// Function ends when signal from termination channel is received.
+///
+/// # Arguments
+///
+/// * `listener` - TcpListener for HTTP connections
+/// * `hub` - Registry of connected clients
+/// * `cluster` - Connection to other server instances, kicks and announcements are published to
+///   them
+/// * `config` - Server configuration, `features.admin_token` has to be sent in
+///   `Authorization: Bearer <token>` header of every request
+/// * `store` - Storage of users and messages
+/// * `moderation` - Moderation of messages, its flagged messages are listed
+/// * `tx` - Sender side of broadcast channel for .quit command
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::admin::handle_admin_requests;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::moderation::Moderation;
+/// use tokio::net::TcpListener;
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // registry of connected clients
+///     let hub = Hub::start();
+///
+///     let listener = TcpListener::bind(("localhost", 8080)).await.unwrap();
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     // server configuration
+///     let mut config = Config::default();
+///     config.features.admin_token = Some("secret".to_string());
+///
+///     // storage of users and messages
+///     let store = Arc::new(MemoryStore::default());
+///
+///     // moderation of messages
+///     let moderation = Arc::new(Moderation::new(&config.moderation).unwrap());
+///
+///     // connection to other server instances
+///     let cluster = Arc::new(Cluster::standalone());
+///
+///     handle_admin_requests(listener, hub, cluster, Arc::new(config), store, moderation, &mut tx).await;
+/// }
+/// ```
+pub async fn handle_admin_requests(
+    listener: TcpListener,
+    hub: Hub,
+    cluster: Arc<Cluster>,
+    config: Arc<Config>,
+    store: Arc<dyn ChatStore>,
+    moderation: Arc<Moderation>,
+    tx: &mut Sender<bool>,
+) {
+    let mut rx = tx.subscribe();
+
+    let state = AdminState {
+        hub,
+        cluster,
+        config,
+        store,
+        moderation,
+    };
+
+    let router = Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/:id", axum::routing::delete(delete_user))
+        .route("/users/:id/disable", post(disable_user))
+        .route("/users/:id/enable", post(enable_user))
+        .route("/users/:id/password", post(reset_password))
+        .route("/users/:id/kick", post(kick_user))
+        .route("/sessions", get(list_sessions))
+        .route("/messages", get(list_messages))
+        .route("/messages/:id", axum::routing::delete(delete_message))
+        .route("/announcements", post(announce))
+        .route("/moderation/flags", get(list_flags))
+        .route("/sanctions", get(list_sanctions).post(create_sanction))
+        .route("/sanctions/:id", axum::routing::delete(delete_sanction))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    info!("Serving admin API.");
+
+    if let Err(e) = axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = rx.recv().await;
+        })
+        .await
+    {
+        error!(event = "error", error = %e, "Could not serve admin API.");
+    }
+}
+
+/// Rejects requests without correct admin token.
+async fn authorize(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .zip(state.config.features.admin_token.as_deref())
+        .is_some_and(|(token, admin_token)| {
+            // empty token is refused by `Config::validate`, but never accept it here either
+            !admin_token.is_empty() && constant_time_eq(token.as_bytes(), admin_token.as_bytes())
+        });
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Invalid admin token.".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Compares tokens in time that does not depend on position of first different byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn user_response(state: &AdminState, user: User) -> Result<UserResponse, AdminError> {
+    Ok(UserResponse {
+        color: state.store.user_color(&user)?,
+        id: user.id,
+        role: user.role().to_string(),
+        username: user.username,
+        disabled: user.disabled,
+    })
+}
+
+/// Kicks all sessions of user (also on other instances), returns number of kicked sessions.
+async fn kick_sessions(state: &AdminState, user_id: i32, reason: &str) -> usize {
+    cluster::kick(&state.hub, &state.cluster, Target::user(user_id), reason).await
+}
+
+async fn list_users(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<UserResponse>>, AdminError> {
+    let users = state
+        .store
+        .users()?
+        .into_iter()
+        .map(|user| user_response(&state, user))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Json(users))
+}
+
+async fn create_user(
+    State(state): State<AdminState>,
+    Json(request): Json<CreateUserRequest>,
+) -> Result<(StatusCode, Json<UserResponse>), AdminError> {
+    let color = request.color.unwrap_or(state.config.features.default_color);
+    let user = with_password_hashing(&state.store, move |store| {
+        store.register(&request.username, &request.password, color)
+    })
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(user_response(&state, user)?)))
+}
+
+async fn disable_user(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+) -> Result<Json<UserResponse>, AdminError> {
+    let user = state.store.set_user_disabled(id, true)?;
+
+    kick_sessions(&state, id, "Your account was disabled.").await;
+
+    Ok(Json(user_response(&state, user)?))
+}
+
+async fn enable_user(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+) -> Result<Json<UserResponse>, AdminError> {
+    let user = state.store.set_user_disabled(id, false)?;
+
+    Ok(Json(user_response(&state, user)?))
+}
+
+async fn reset_password(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+    Json(request): Json<ResetPasswordRequest>,
+) -> Result<Json<UserResponse>, AdminError> {
+    let user = with_password_hashing(&state.store, move |store| {
+        store.change_password(id, &request.password)
+    })
+    .await?;
+
+    Ok(Json(user_response(&state, user)?))
+}
+
+async fn delete_user(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AdminError> {
+    state.store.delete_user(id)?;
+
+    kick_sessions(&state, id, "Your account was deleted.").await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn kick_user(State(state): State<AdminState>, Path(id): Path<i32>) -> Json<KickResponse> {
+    let kicked = kick_sessions(&state, id, "You were kicked by administrator.").await;
+
+    Json(KickResponse { kicked })
+}
+
+async fn list_sessions(State(state): State<AdminState>) -> Json<Vec<SessionResponse>> {
+    let sessions = state
+        .hub
+        .clients()
+        .await
+        .into_iter()
+        .map(|(addr, client)| SessionResponse {
+            addr,
+            user_id: client.user_info.id,
+            username: client.user_info.username,
+            status: client.status.to_string(),
+            idle_secs: client.last_active.elapsed().as_secs(),
+        })
+        .collect();
+
+    Json(sessions)
+}
+
+async fn list_messages(
+    State(state): State<AdminState>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<Vec<MessageResponse>>, AdminError> {
+    let messages = state
+        .store
+        .messages(query.user_id, query.limit.unwrap_or(100))?
+        .into_iter()
+        .map(|message| MessageResponse {
+            id: message.id,
+            user_id: message.user_id,
+            text: message.text,
+            created_at: unix_timestamp(message.created_at),
+        })
+        .collect();
+
+    Ok(Json(messages))
+}
+
+async fn delete_message(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AdminError> {
+    state.store.delete_message(id)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+async fn announce(
+    State(state): State<AdminState>,
+    Json(request): Json<AnnouncementRequest>,
+) -> Result<Json<AnnouncementResponse>, AdminError> {
+    if request.text.is_empty() {
+        return Err(AdminError::BadRequest(
+            "Text must not be empty.".to_string(),
+        ));
+    }
+
+    let mut message = Message::from(MessageType::Text(request.text));
+    message.user_info = bot_user_info("server");
+
+    let recipients = state.hub.send(Recipients::All, None, message.clone()).await;
+
+    state.cluster.publish(ClusterEventKind::Broadcast(message));
+
+    info!(event = "announcement", recipients, "Announcement sent.");
+
+    Ok(Json(AnnouncementResponse { recipients }))
+}
+
+async fn list_flags(State(state): State<AdminState>) -> Json<Vec<FlagResponse>> {
+    let flags = state
+        .moderation
+        .flags()
+        .into_iter()
+        .map(|flag| FlagResponse {
+            user_id: flag.user_id,
+            username: flag.username,
+            text: flag.text,
+            reasons: flag.reasons,
+            flagged_at: unix_timestamp(flag.flagged_at),
+        })
+        .collect();
+
+    Json(flags)
+}
+
+fn sanction_response(sanction: Sanction) -> SanctionResponse {
+    SanctionResponse {
+        id: sanction.id,
+        kind: sanction.kind,
+        user_id: sanction.user_id,
+        ip: sanction.ip,
+        reason: sanction.reason,
+        created_by: sanction.created_by,
+        created_at: unix_timestamp(sanction.created_at),
+        expires_at: sanction.expires_at.map(unix_timestamp),
+    }
+}
+
+async fn list_sanctions(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<SanctionResponse>>, AdminError> {
+    let sanctions = state
+        .store
+        .sanctions()?
+        .into_iter()
+        .map(sanction_response)
+        .collect();
+
+    Ok(Json(sanctions))
+}
+
+async fn create_sanction(
+    State(state): State<AdminState>,
+    Json(request): Json<CreateSanctionRequest>,
+) -> Result<(StatusCode, Json<SanctionResponse>), AdminError> {
+    let kind = match request.kind.as_str() {
+        "ban" => SanctionKind::Ban,
+        "mute" => SanctionKind::Mute,
+        kind => {
+            return Err(AdminError::BadRequest(format!(
+                "Unknown kind '{}', kinds are ban and mute.",
+                kind
+            )))
+        }
+    };
+
+    let ip =
+        match request.ip {
+            Some(ip) => Some(ip.parse::<IpAddr>().map_err(|_| {
+                AdminError::BadRequest(format!("'{}' is not valid IP address.", ip))
+            })?),
+            None => None,
+        };
+
+    let sanction = sanctions::create(
+        state.store.as_ref(),
+        kind,
+        request.user_id,
+        ip,
+        request.duration_secs.map(Duration::from_secs),
+        &request.reason,
+        None,
+    )?;
+
+    let affected = sanctions::enforce(&state.hub, &state.cluster, &sanction).await;
+
+    info!(event = "audit", action = "sanction", kind = %sanction.kind, affected, "Sanction created by administrator.");
+
+    Ok((StatusCode::CREATED, Json(sanction_response(sanction))))
+}
+
+async fn delete_sanction(
+    State(state): State<AdminState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AdminError> {
+    state.store.delete_sanction(id)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
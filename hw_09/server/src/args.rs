@@ -1,11 +1,32 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::config::{DatabaseBackend, LogFormat};
+
 /// Structure representing program arguments.
 ///
+/// Arguments override values from configuration file (see `config` module), values that are set
+/// neither in arguments nor in configuration file have default values.
+///
 /// # Fields
 ///
+/// * `config` - path to TOML configuration file (optional)
 /// * `port` - network port (default = 11111)
 /// * `hostname` - ip address (default = "localhost")
+/// * `database_backend` - `postgres` or `memory` (default = "postgres")
+/// * `database_url` - database connection string (default = `DATABASE_URL` environment variable)
+/// * `log_level` - filter of printed logs in `RUST_LOG` format (default = `RUST_LOG` environment
+///   variable or "info")
+/// * `log_format` - output format of logs, `pretty`, `compact` or `json` (default = "pretty")
+/// * `metrics_port` - network port for HTTP endpoint with Prometheus metrics (optional, metrics are
+///   not served when not set)
+/// * `websocket_port` - network port for WebSocket clients (optional, WebSocket clients are not
+///   accepted when not set)
+/// * `admin_port` - network port for REST admin API (optional, admin API is not served when not
+///   set)
+/// * `admin_token` - token required by admin API, can be set also by `ADMIN_TOKEN` environment
+///   variable (required when `admin_port` is set)
 ///
 /// # Example
 ///
@@ -16,15 +37,42 @@ use clap::Parser;
 /// fn main() {
 ///     let args = Args::parse();
 ///
-///     println!("{}:{}", args.hostname, args.port);
+///     println!("{:?}:{:?}", args.hostname, args.port);
 /// }
 /// ```
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    #[arg(long, default_value_t = 11111)]
-    pub port: u16,
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    #[arg(long)]
+    pub hostname: Option<String>,
+
+    #[arg(long, value_enum)]
+    pub database_backend: Option<DatabaseBackend>,
+
+    #[arg(long)]
+    pub database_url: Option<String>,
+
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    #[arg(long)]
+    pub websocket_port: Option<u16>,
+
+    #[arg(long)]
+    pub admin_port: Option<u16>,
 
-    #[arg(long, default_value = "localhost")]
-    pub hostname: String,
+    #[arg(long, env = "ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
 }
@@ -0,0 +1,384 @@
+//! Fan-out between server instances sharing one database.
+//!
+//! Every instance publishes broadcast messages, direct messages, kicks, notices and messages for
+//! chosen users to `PubSub` and relays events of other instances to its own clients. Logged in
+//! clients of every instance are published periodically (and right after they change), so
+//! presence list and offline delivery see users of all instances. Presence of instance that stopped publishing expires.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, broadcast::Sender, Notify},
+    time,
+};
+use tracing::{debug, error, info, warn};
+
+use database::pubsub::{memory::MemoryPubSub, PubSub};
+use libs::message::{Message, MessageType, PresenceInfo};
+
+use crate::{
+    hub::{Hub, Recipients},
+    metrics::Metrics,
+    Client,
+};
+
+/// Presence of other instance is forgotten when it was not published for this many intervals.
+const PRESENCE_EXPIRY_INTERVALS: u32 = 3;
+
+/// Event published by one instance to others.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClusterEvent {
+    /// Id of instance that published event, instances ignore their own events.
+    pub instance: String,
+    pub kind: ClusterEventKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ClusterEventKind {
+    /// Message sent to all clients.
+    Broadcast(Message),
+    /// Direct message sent to connections of its recipient.
+    Direct(Message),
+    /// Clients matching target are disconnected with reason.
+    Kick(Target, String),
+    /// Clients matching target receive `RecoverableError` with text.
+    Notice(Target, String),
+    /// Message sent only to clients matching target.
+    Targeted(Target, Message),
+    /// Logged in clients of instance.
+    Presence(Vec<PresenceInfo>),
+}
+
+/// Clients affected by kick or notice, client matches when any of set values matches.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Target {
+    pub user_id: Option<i32>,
+    pub session_id: Option<i32>,
+    pub ip: Option<IpAddr>,
+}
+
+impl Target {
+    /// All connections of user.
+    pub fn user(user_id: i32) -> Self {
+        Target {
+            user_id: Some(user_id),
+            ..Default::default()
+        }
+    }
+
+    /// Connections logged in with session.
+    pub fn session(session_id: i32) -> Self {
+        Target {
+            session_id: Some(session_id),
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether client connected from `addr` is targeted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    /// use std::sync::Arc;
+    /// use libs::builder::MessageReceiverSenderBuilder;
+    /// use server::{cluster::Target, config::LimitsConfig, metrics::Metrics, Client};
+    /// use tokio::sync::mpsc;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let (tx, rx) = mpsc::channel(1);
+    ///     let builder = MessageReceiverSenderBuilder::from_json_channels(rx, tx);
+    ///     let limits = LimitsConfig::default();
+    ///     let mut client = Client::new(builder.message_sender(), &limits, Arc::new(Metrics::default()));
+    ///     client.user_info.id = 1;
+    ///
+    ///     let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    ///     let addr = SocketAddr::new(ip, 5000);
+    ///
+    ///     assert!(Target::user(1).matches(addr, &client));
+    ///     assert!(!Target::user(2).matches(addr, &client));
+    ///     assert!(Target { ip: Some(ip), ..Default::default() }.matches(addr, &client));
+    ///     // clients that are not logged in are not users
+    ///     client.user_info.id = 0;
+    ///     assert!(!Target::user(0).matches(addr, &client));
+    /// }
+    /// ```
+    pub fn matches(&self, addr: SocketAddr, client: &Client) -> bool {
+        (client.user_info.id != 0 && self.user_id == Some(client.user_info.id))
+            || (client.session_id.is_some() && self.session_id == client.session_id)
+            || self.ip == Some(addr.ip())
+    }
+}
+
+/// Presence published by other instance.
+struct RemotePresence {
+    received_at: Instant,
+    clients: Vec<PresenceInfo>,
+}
+
+/// Connection of this instance to other instances.
+pub struct Cluster {
+    instance: String,
+    pubsub: Arc<dyn PubSub>,
+    presence_interval: Duration,
+    remote: StdMutex<HashMap<String, RemotePresence>>,
+    presence_changed: Notify,
+}
+
+impl Cluster {
+    /// Creates instance with random id that shares events through `pubsub`, its presence is
+    /// published every `presence_interval`.
+    pub fn new(pubsub: Arc<dyn PubSub>, presence_interval: Duration) -> Self {
+        Cluster {
+            instance: format!("{:016x}", rand::random::<u64>()),
+            pubsub,
+            presence_interval,
+            remote: StdMutex::new(HashMap::new()),
+            presence_changed: Notify::new(),
+        }
+    }
+
+    /// Creates single instance, its events are not received by anybody.
+    pub fn standalone() -> Self {
+        Cluster::new(Arc::new(MemoryPubSub::default()), Duration::from_secs(5))
+    }
+
+    /// Random id of this instance.
+    pub fn instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// Publishes event to other instances.
+    pub fn publish(&self, kind: ClusterEventKind) {
+        let event = ClusterEvent {
+            instance: self.instance.clone(),
+            kind,
+        };
+
+        match serde_json::to_string(&event) {
+            Ok(payload) => self.pubsub.publish(payload),
+            Err(e) => error!(event = "error", error = %e, "Could not serialize cluster event."),
+        }
+    }
+
+    /// Publishes presence of this instance as soon as possible, it is called when client logs in,
+    /// changes his status or disconnects.
+    pub fn presence_changed(&self) {
+        self.presence_changed.notify_one();
+    }
+
+    /// Returns logged in clients of other instances, their idle time includes time since presence
+    /// was received.
+    pub fn remote_presence(&self) -> Vec<PresenceInfo> {
+        let expiry = self.presence_interval * PRESENCE_EXPIRY_INTERVALS;
+
+        self.remote()
+            .values()
+            .filter(|remote| remote.received_at.elapsed() < expiry)
+            .flat_map(|remote| {
+                remote.clients.iter().map(|presence| PresenceInfo {
+                    idle: presence.idle + remote.received_at.elapsed(),
+                    ..presence.clone()
+                })
+            })
+            .collect()
+    }
+
+    /// Returns number of connections of user to other instances.
+    pub fn remote_connections(&self, user_id: i32) -> usize {
+        self.remote_presence()
+            .iter()
+            .filter(|presence| presence.user_info.id == user_id)
+            .count()
+    }
+
+    /// Returns whether user is logged in to other instance.
+    pub fn is_online(&self, user_id: i32) -> bool {
+        self.remote_connections(user_id) > 0
+    }
+
+    /// Stores presence of other instance, returns `true` when instance was not known yet.
+    fn set_remote_presence(&self, instance: String, clients: Vec<PresenceInfo>) -> bool {
+        let remote = RemotePresence {
+            received_at: Instant::now(),
+            clients,
+        };
+
+        self.remote().insert(instance, remote).is_none()
+    }
+
+    fn remote(&self) -> std::sync::MutexGuard<'_, HashMap<String, RemotePresence>> {
+        // presence is replaced as whole, so it is consistent even when other thread panicked
+        self.remote.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Kicks local clients matching target and publishes kick to other instances. Returns number of
+/// kicked connections (connections to other instances are counted by their presence).
+pub(crate) async fn kick(hub: &Hub, cluster: &Cluster, target: Target, reason: &str) -> usize {
+    let kicked = hub.kick(Recipients::Matching(target.clone()), reason).await;
+    let remote = target
+        .user_id
+        .map_or(0, |user_id| cluster.remote_connections(user_id));
+
+    cluster.publish(ClusterEventKind::Kick(target, reason.to_string()));
+
+    kicked + remote
+}
+
+/// Sends `RecoverableError` to local clients matching target and publishes notice to other
+/// instances. Returns number of local notified connections.
+pub(crate) async fn notice(hub: &Hub, cluster: &Cluster, target: Target, text: &str) -> usize {
+    let notified = notice_local(hub, &target, text).await;
+
+    cluster.publish(ClusterEventKind::Notice(target, text.to_string()));
+
+    notified
+}
+
+/// Sends message to local clients matching target and publishes it to other instances. Returns
+/// number of local recipients.
+pub(crate) async fn send(hub: &Hub, cluster: &Cluster, target: Target, message: Message) -> usize {
+    let recipients = hub
+        .send(Recipients::Matching(target.clone()), None, message.clone())
+        .await;
+
+    cluster.publish(ClusterEventKind::Targeted(target, message));
+
+    recipients
+}
+
+async fn notice_local(hub: &Hub, target: &Target, text: &str) -> usize {
+    let notice = Message::from(MessageType::RecoverableError(text.to_string()));
+
+    hub.send(Recipients::Matching(target.clone()), None, notice)
+        .await
+}
+
+/// Handles events of other instances and publishes presence of this instance.
+///
+/// Function cycles until termination signal is received. Presence is published every presence
+/// interval and after `Cluster::presence_changed`, instance that appears for the first time gets
+/// presence of this instance immediately.
+///
+/// # Arguments
+///
+/// * `cluster` - Connection to other instances
+/// * `hub` - Registry of connected clients
+/// * `metrics` - Metrics collected by the server
+/// * `tx` - Sender side of broadcast channel for termination signal
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use server::cluster::{handle_cluster_events, Cluster};
+/// use server::hub::Hub;
+/// use server::metrics::Metrics;
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // registry of connected clients
+///     let hub = Hub::start();
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     // connection to other server instances
+///     let cluster = Arc::new(Cluster::standalone());
+///
+///     handle_cluster_events(cluster, hub, Arc::new(Metrics::default()), &mut tx).await;
+/// }
+/// ```
+pub async fn handle_cluster_events(
+    cluster: Arc<Cluster>,
+    hub: Hub,
+    metrics: Arc<Metrics>,
+    tx: &mut Sender<bool>,
+) {
+    let mut rx = tx.subscribe();
+    let mut events = cluster.pubsub.subscribe();
+    let mut presence_interval = time::interval(cluster.presence_interval);
+
+    info!(instance = cluster.instance(), "Cluster instance started.");
+
+    loop {
+        select! {
+            Ok(_) = rx.recv() => break,
+            _ = presence_interval.tick() => {
+                cluster.publish(ClusterEventKind::Presence(hub.presence().await));
+            }
+            _ = cluster.presence_changed.notified() => {
+                cluster.publish(ClusterEventKind::Presence(hub.presence().await));
+            }
+            event = events.recv() => match event {
+                Ok(payload) => handle_event(&cluster, &hub, &metrics, &payload).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Cluster events were skipped.");
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+async fn handle_event(cluster: &Cluster, hub: &Hub, metrics: &Metrics, payload: &str) {
+    let event: ClusterEvent = match serde_json::from_str(payload) {
+        Ok(event) => event,
+        Err(e) => {
+            error!(event = "error", error = %e, "Could not parse cluster event.");
+            metrics.error();
+            return;
+        }
+    };
+
+    if event.instance == cluster.instance {
+        return;
+    }
+
+    match event.kind {
+        ClusterEventKind::Broadcast(message) => {
+            let recipients = hub.send(Recipients::All, None, message.clone()).await;
+
+            debug!(event = "relay", message_type = message.message.name(), recipients, instance = %event.instance, "Message of other instance relayed.");
+        }
+        ClusterEventKind::Direct(message) => {
+            let MessageType::DirectMessage(ref encrypted) = message.message else {
+                return;
+            };
+
+            let recipient = Target::user(encrypted.recipient_id);
+
+            hub.send(Recipients::Matching(recipient), None, message)
+                .await;
+        }
+        ClusterEventKind::Kick(target, reason) => {
+            hub.kick(Recipients::Matching(target), &reason).await;
+        }
+        ClusterEventKind::Notice(target, text) => {
+            notice_local(hub, &target, &text).await;
+        }
+        ClusterEventKind::Targeted(target, message) => {
+            let recipients = hub
+                .send(Recipients::Matching(target), None, message.clone())
+                .await;
+
+            debug!(event = "relay", message_type = message.message.name(), recipients, instance = %event.instance, "Message of other instance relayed.");
+        }
+        ClusterEventKind::Presence(presence) => {
+            if cluster.set_remote_presence(event.instance, presence) {
+                // new instance does not know presence of this one
+                cluster.presence_changed();
+            }
+        }
+    }
+}
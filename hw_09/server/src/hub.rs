@@ -0,0 +1,333 @@
+//! Registry of connected clients owned by one task.
+//!
+//! Hub task owns every `Client` and is the only place where their user, status and session are
+//! changed. Others talk to it through `Hub` handle: they register and unregister connections,
+//! update users, send messages and kick clients. Returned clients are snapshots, so they are
+//! used only for one operation and never kept. Messages are only queued for clients (see
+//! `Client::send`), so hub is never blocked by slow client. Delayed messages (see
+//! `Hub::send_later`) wait in one timer queue of hub task, they are dropped together with it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+};
+
+use libs::message::{Message, PresenceInfo, UserInfo, UserStatus};
+
+use crate::{anonymous_user_info, cluster::Target, Client};
+
+/// Clients that message is sent to or that are kicked.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recipients {
+    /// All connected clients
+    All,
+    /// Client connected from address
+    Address(SocketAddr),
+    /// Clients matching target (logged in user, session or IP address)
+    Matching(Target),
+}
+
+impl Recipients {
+    fn matches(&self, addr: SocketAddr, client: &Client) -> bool {
+        match self {
+            Recipients::All => true,
+            Recipients::Address(address) => *address == addr,
+            Recipients::Matching(target) => target.matches(addr, client),
+        }
+    }
+}
+
+/// Change of connected client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserUpdate {
+    /// User logged in with session
+    LoggedIn(UserInfo, i32),
+    /// User logged out, client stays connected as anonymous user
+    LoggedOut,
+    Username(String),
+    Color(u8, u8, u8),
+    Status(UserStatus),
+    /// Message was received from client
+    Active(Instant),
+}
+
+enum Command {
+    Register(SocketAddr, Client),
+    Unregister(SocketAddr),
+    UpdateUser(SocketAddr, UserUpdate, oneshot::Sender<Option<Client>>),
+    Send(
+        Recipients,
+        Option<SocketAddr>,
+        Message,
+        oneshot::Sender<usize>,
+    ),
+    SendLater(SocketAddr, Message, Instant),
+    Kick(Recipients, String, oneshot::Sender<usize>),
+    Get(SocketAddr, oneshot::Sender<Option<Client>>),
+    List(oneshot::Sender<Vec<(SocketAddr, Client)>>),
+}
+
+/// Handle of hub task, it is cheap to clone. Hub task ends when all handles are dropped.
+///
+/// # Example
+///
+/// ```
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use libs::builder::MessageReceiverSenderBuilder;
+/// use libs::message::{Message, MessageType, UserInfo};
+/// use server::config::LimitsConfig;
+/// use server::hub::{Hub, Recipients, UserUpdate};
+/// use server::metrics::Metrics;
+/// use server::Client;
+/// use tokio::sync::mpsc;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let hub = Hub::start();
+///
+///     let (incoming_tx, incoming_rx) = mpsc::channel(8);
+///     let (outgoing_tx, mut outgoing_rx) = mpsc::channel(8);
+///     let builder = MessageReceiverSenderBuilder::from_json_channels(incoming_rx, outgoing_tx);
+///     let client = Client::new(builder.message_sender(), &LimitsConfig::default(), Arc::new(Metrics::default()));
+///
+///     let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+///     hub.register(addr, client);
+///
+///     let alice = UserInfo { id: 1, username: "alice".to_string(), color: (255, 0, 0) };
+///     let client = hub.update_user(addr, UserUpdate::LoggedIn(alice.clone(), 1)).await.unwrap();
+///     assert_eq!(client.user_info, alice);
+///
+///     let message = Message::from(MessageType::Text("hello".to_string()));
+///     assert_eq!(hub.send(Recipients::All, None, message).await, 1);
+///     assert!(outgoing_rx.recv().await.unwrap().contains("hello"));
+///
+///     hub.unregister(addr);
+///     assert!(hub.client(addr).await.is_none());
+///     # drop(incoming_tx);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Hub {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl Hub {
+    /// Starts hub task with no clients, it has to be called inside tokio runtime.
+    pub fn start() -> Hub {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(run(rx));
+
+        Hub { tx }
+    }
+
+    /// Adds client connected from address, client with the same address is replaced.
+    pub fn register(&self, addr: SocketAddr, client: Client) {
+        self.command(Command::Register(addr, client));
+    }
+
+    /// Removes client, its writer task ends when its last snapshot is dropped.
+    pub fn unregister(&self, addr: SocketAddr) {
+        self.command(Command::Unregister(addr));
+    }
+
+    /// Changes client and returns its snapshot after change, `None` when client is not connected.
+    pub async fn update_user(&self, addr: SocketAddr, update: UserUpdate) -> Option<Client> {
+        self.request(|reply| Command::UpdateUser(addr, update, reply))
+            .await
+            .flatten()
+    }
+
+    /// Queues message for recipients except client connected from `except`, returns number of
+    /// clients it was queued for.
+    pub async fn send(
+        &self,
+        recipients: Recipients,
+        except: Option<SocketAddr>,
+        message: Message,
+    ) -> usize {
+        self.request(|reply| Command::Send(recipients, except, message, reply))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Queues message for client connected from address after delay. Message is dropped when
+    /// client disconnects meanwhile or hub task ends.
+    pub fn send_later(&self, addr: SocketAddr, message: Message, delay: Duration) {
+        self.command(Command::SendLater(addr, message, Instant::now() + delay));
+    }
+
+    /// Kicks recipients with reason, returns number of kicked clients.
+    pub async fn kick(&self, recipients: Recipients, reason: &str) -> usize {
+        let reason = reason.to_string();
+
+        self.request(|reply| Command::Kick(recipients, reason, reply))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Returns snapshot of client connected from address.
+    pub async fn client(&self, addr: SocketAddr) -> Option<Client> {
+        self.request(|reply| Command::Get(addr, reply))
+            .await
+            .flatten()
+    }
+
+    /// Returns snapshots of all connected clients.
+    pub async fn clients(&self) -> Vec<(SocketAddr, Client)> {
+        self.request(Command::List).await.unwrap_or_default()
+    }
+
+    /// Returns logged in clients with their status and idle time.
+    pub async fn presence(&self) -> Vec<PresenceInfo> {
+        self.clients()
+            .await
+            .into_iter()
+            // users who are not logged in yet have id 0
+            .filter(|(_, client)| client.user_info.id != 0)
+            .map(|(_, client)| PresenceInfo {
+                user_info: client.user_info,
+                status: client.status,
+                idle: client.last_active.elapsed(),
+            })
+            .collect()
+    }
+
+    /// Returns whether user is logged in from at least one connection.
+    pub async fn is_online(&self, user_id: i32) -> bool {
+        self.clients()
+            .await
+            .iter()
+            .any(|(_, client)| client.user_info.id == user_id)
+    }
+
+    fn command(&self, command: Command) {
+        // hub task ends only when all handles are dropped
+        let _ = self.tx.send(command);
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Option<T> {
+        let (reply, rx) = oneshot::channel();
+
+        self.command(command(reply));
+
+        rx.await.ok()
+    }
+}
+
+/// Handles commands and sends delayed messages until all handles are dropped.
+async fn run(mut rx: mpsc::UnboundedReceiver<Command>) {
+    let mut clients: HashMap<SocketAddr, Client> = HashMap::new();
+    // delayed messages ordered by time when they are sent, number keeps order of equal times
+    let mut delayed: BTreeMap<(Instant, u64), (SocketAddr, Message)> = BTreeMap::new();
+    let mut delayed_count: u64 = 0;
+
+    loop {
+        let next_due = delayed.keys().next().map(|(due, _)| *due);
+        let sleep = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now).into());
+
+        let command = select! {
+            command = rx.recv() => match command {
+                Some(command) => command,
+                None => break,
+            },
+            _ = sleep, if next_due.is_some() => {
+                let now = Instant::now();
+
+                while let Some(entry) = delayed.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+
+                    let (addr, message) = entry.remove();
+
+                    if let Some(client) = clients.get(&addr) {
+                        client.send(&message);
+                    }
+                }
+
+                continue;
+            }
+        };
+
+        match command {
+            Command::Register(addr, client) => {
+                clients.insert(addr, client);
+            }
+            Command::Unregister(addr) => {
+                clients.remove(&addr);
+
+                // new client from the same address must not get them
+                delayed.retain(|_, (delayed_addr, _)| *delayed_addr != addr);
+            }
+            Command::UpdateUser(addr, update, reply) => {
+                let client = clients.get_mut(&addr).map(|client| {
+                    update_user(client, update);
+                    client.clone()
+                });
+
+                let _ = reply.send(client);
+            }
+            Command::Send(recipients, except, message, reply) => {
+                let sent = clients
+                    .iter()
+                    .filter(|(addr, client)| {
+                        Some(**addr) != except && recipients.matches(**addr, client)
+                    })
+                    .filter(|(_, client)| client.send(&message))
+                    .count();
+
+                let _ = reply.send(sent);
+            }
+            Command::SendLater(addr, message, due) => {
+                delayed.insert((due, delayed_count), (addr, message));
+                delayed_count += 1;
+            }
+            Command::Kick(recipients, reason, reply) => {
+                let kicked = clients
+                    .iter()
+                    .filter(|(addr, client)| recipients.matches(**addr, client))
+                    .map(|(_, client)| client.kick(&reason))
+                    .count();
+
+                let _ = reply.send(kicked);
+            }
+            Command::Get(addr, reply) => {
+                let _ = reply.send(clients.get(&addr).cloned());
+            }
+            Command::List(reply) => {
+                let _ = reply.send(
+                    clients
+                        .iter()
+                        .map(|(addr, client)| (*addr, client.clone()))
+                        .collect(),
+                );
+            }
+        }
+    }
+}
+
+fn update_user(client: &mut Client, update: UserUpdate) {
+    match update {
+        UserUpdate::LoggedIn(user_info, session_id) => {
+            client.user_info = user_info;
+            client.session_id = Some(session_id);
+        }
+        UserUpdate::LoggedOut => {
+            client.user_info = anonymous_user_info();
+            client.status = UserStatus::Online;
+            client.session_id = None;
+        }
+        UserUpdate::Username(username) => client.user_info.username = username,
+        UserUpdate::Color(r, g, b) => client.user_info.color = (r, g, b),
+        UserUpdate::Status(status) => client.status = status,
+        UserUpdate::Active(last_active) => client.last_active = last_active,
+    }
+}
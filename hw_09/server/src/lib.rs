@@ -1,44 +1,186 @@
 //! Provides structs and methods for handling client connections.
 
-use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc};
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use cluster::{Cluster, ClusterEventKind, Target};
+use config::{Config, LimitsConfig, SlowClientPolicy};
+use metrics::Metrics;
 
 use tokio::{
     net::TcpListener,
     select,
     sync::{
         broadcast::{Receiver, Sender},
-        mpsc, Mutex,
+        mpsc, oneshot, watch,
     },
     task::JoinHandle,
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use database::{
-    establish_connection,
-    models::{Color, Message as MessageDb, MessageNew, User, UserNew},
+    models::{DeliveryNew, DirectMessageNew, SanctionKind, User, DELETED_USERNAME},
+    store::{ChatStore, StoreError},
 };
+use hub::{Hub, Recipients, UserUpdate};
+use libs::role::{Permission, Role};
 use libs::{
     builder::MessageReceiverSenderBuilder,
-    message::{Message, MessageType, UserInfo},
+    e2e,
+    errors::MessageError,
+    message::{Message, MessageType, SessionInfo, UserInfo, UserStatus, PROTOCOL_VERSION},
     receiver::MessageReceiver,
     sender::MessageSender,
 };
+use lockout::LoginGuard;
+use logging::{connection_span, record_user};
+use outbox::Outgoing;
+use plugins::{PluginReply, Plugins, ReplyTarget};
 
+/// REST admin API
+pub mod admin;
 /// Program arugments
 pub mod args;
+/// Fan-out between server instances
+pub mod cluster;
+/// Server configuration
+pub mod config;
+/// Registry of connected clients
+pub mod hub;
+/// Login brute-force protection
+pub mod lockout;
+/// Logging initialization and connection spans
+pub mod logging;
+/// Server metrics
+pub mod metrics;
+/// Content moderation
+pub mod moderation;
+/// Offline delivery of direct messages and mentions
+pub mod offline;
+/// Outbound queues of clients
+mod outbox;
+/// Roles and permissions
+pub mod permissions;
+/// Server plugins (bots)
+pub mod plugins;
+/// Deletion of old messages
+pub mod retention;
+/// Bans and mutes
+pub mod sanctions;
+/// WebSocket gateway
+pub mod websocket;
+
+/// Response to every failed login, it does not tell whether user exists.
+pub const INVALID_LOGIN: &str = "Invalid username or password.";
+
+/// Response to login with token of session that does not exist, expired or was revoked.
+pub const INVALID_SESSION: &str = "Session is not valid, log in with password.";
+
+/// How often expired sessions and sanctions are deleted from store.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Minimal time between two relayed typing notifications `true` of one connection (clients repeat
+/// them every 3 seconds while user is typing).
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Structures shared by all connections (tcp and WebSocket), it is cheap to clone.
+///
+/// # Fields
+///
+/// * `hub` - Registry of connected clients
+/// * `shutdown` - Sender side of broadcast channel signaling that server should stop
+/// * `msg_db_tx` - Sender side of multiple producer single consumer channel for sending new
+///   text messages to database handler
+/// * `metrics` - Metrics collected by the server
+/// * `config` - Server configuration
+/// * `store` - Storage of users and messages
+/// * `login_guard` - Counter of failed logins shared by all connections
+/// * `plugins` - Plugins registered at startup, messages go through them before they are broadcast
+/// * `cluster` - Connection to other server instances, broadcast and direct messages are published
+///   to them
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::lockout::LoginGuard;
+/// use server::metrics::Metrics;
+/// use server::plugins::Plugins;
+/// use server::ServerContext;
+/// use tokio::sync::{broadcast, mpsc};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let config = Arc::new(Config::default());
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (shutdown, _) = broadcast::channel(8);
+///
+///     // multiple produces and single consumer channel for sending messages to database handler
+///     let (msg_db_tx, _) = mpsc::channel(64);
+///
+///     let context = ServerContext {
+///         hub: Hub::start(),
+///         shutdown,
+///         msg_db_tx,
+///         metrics: Arc::new(Metrics::default()),
+///         login_guard: Arc::new(LoginGuard::new(config.auth.clone())),
+///         plugins: Arc::new(Plugins::from_config(&config.plugins)),
+///         store: Arc::new(MemoryStore::default()),
+///         cluster: Arc::new(Cluster::standalone()),
+///         config,
+///     };
+///
+///     assert!(context.hub.clients().await.is_empty());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ServerContext {
+    pub hub: Hub,
+    pub shutdown: Sender<bool>,
+    pub msg_db_tx: mpsc::Sender<TextToStore>,
+    pub metrics: Arc<Metrics>,
+    pub config: Arc<Config>,
+    pub store: Arc<dyn ChatStore>,
+    pub login_guard: Arc<LoginGuard>,
+    pub plugins: Arc<Plugins>,
+    pub cluster: Arc<Cluster>,
+}
 
 /// Structure containing informations about connected client.
 ///
+/// Connected clients are owned by hub task (see `hub` module) and changed only by it, clones are
+/// snapshots that can be used for sending messages.
+///
 /// # Fields
 ///
-/// * `message_sender` - structure for sending messages over tcp stream
+/// * `outbox` - Sender side of bounded queue of messages for this client, they are written to
+///   connection by writer task of client
+/// * `slow_client` - what happens to message when queue is full
+/// * `metrics` - Metrics collected by the server
 /// * `user_info` - structure that stores informations about user
+/// * `status` - status set by user (online, away, busy or custom text)
+/// * `last_active` - time when server received last message from this client
+/// * `session_id` - id of session that client logged in with, `None` before login
+/// * `kick_tx` - Sender side of watch channel, reason of kick is sent to it when client should be
+///   disconnected
 ///
 /// # Example
 ///
-/// ```
+/// ```no_run
+/// use std::sync::Arc;
 /// use libs::builder::MessageReceiverSenderBuilder;
-/// use libs::message::UserInfo;
+/// use libs::message::{Message, MessageType, UserInfo};
+/// use server::config::LimitsConfig;
+/// use server::metrics::Metrics;
 /// use server::Client;
 /// use tokio::net::TcpListener;
 ///
@@ -51,33 +193,145 @@ pub mod args;
 ///
 ///     let receiver_sender_builder = MessageReceiverSenderBuilder::from_tcp_stream(stream).unwrap();
 ///
-///     let client = Client {
-///         message_sender: receiver_sender_builder.message_sender(),
-///         user_info: UserInfo {
-///             id: 1,
-///             username: "Alice".to_string(),
-///             color: (255, 255, 255),
-///         },
+///     let mut client = Client::new(
+///         receiver_sender_builder.message_sender(),
+///         &LimitsConfig::default(),
+///         Arc::new(Metrics::default()),
+///     );
+///
+///     client.user_info = UserInfo {
+///         id: 1,
+///         username: "Alice".to_string(),
+///         color: (255, 255, 255),
 ///     };
+///
+///     // message is only queued, writer task of client sends it
+///     client.send(&Message::from(MessageType::Text("Hello".to_string())));
 /// }
 /// ```
 #[derive(Clone)]
 pub struct Client {
-    pub message_sender: MessageSender,
+    outbox: mpsc::Sender<Outgoing>,
+    slow_client: SlowClientPolicy,
+    metrics: Arc<Metrics>,
     pub user_info: UserInfo,
+    pub status: UserStatus,
+    pub last_active: Instant,
+    pub session_id: Option<i32>,
+    kick_tx: Arc<watch::Sender<Option<String>>>,
+}
+
+impl Client {
+    /// Creates client that is not logged in yet and starts its writer task, it has to be called
+    /// inside tokio runtime. Writer task ends when client is kicked or all its clones are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_sender` - structure for sending messages over tcp stream
+    /// * `limits` - capacity of outbound queue and policy for slow clients
+    /// * `metrics` - Metrics collected by the server
+    pub fn new(
+        message_sender: MessageSender,
+        limits: &LimitsConfig,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let (outbox, rx) = mpsc::channel(limits.outbound_queue_capacity);
+        let (kick_tx, kicked) = watch::channel(None);
+
+        tokio::spawn(
+            outbox::write_messages(message_sender, rx, kicked, metrics.clone()).in_current_span(),
+        );
+
+        Client {
+            outbox,
+            slow_client: limits.slow_client,
+            metrics,
+            user_info: anonymous_user_info(),
+            status: UserStatus::Online,
+            last_active: Instant::now(),
+            session_id: None,
+            kick_tx: Arc::new(kick_tx),
+        }
+    }
+
+    /// Queues message for client without waiting, returns whether it was queued.
+    ///
+    /// When queue is full, message is dropped and with `SlowClientPolicy::Disconnect` client is
+    /// also kicked. Message for client whose connection is closed is dropped too.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - message to be sent
+    pub fn send(&self, message: &Message) -> bool {
+        self.queue(message, None)
+    }
+
+    /// Queues message like `send`, returned receiver gets confirmation after writer task sends
+    /// message. It is closed without confirmation when message is dropped. Returns `None` when
+    /// message was not queued.
+    pub(crate) fn send_confirmed(&self, message: &Message) -> Option<oneshot::Receiver<()>> {
+        let (sent_tx, sent_rx) = oneshot::channel();
+
+        self.queue(message, Some(sent_tx)).then_some(sent_rx)
+    }
+
+    fn queue(&self, message: &Message, sent: Option<oneshot::Sender<()>>) -> bool {
+        let outgoing = Outgoing {
+            message: message.clone(),
+            sent,
+        };
+
+        match self.outbox.try_send(outgoing) {
+            Ok(()) => {
+                self.metrics.outbound_queued();
+
+                true
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.metrics.outbound_dropped();
+
+                if self.slow_client == SlowClientPolicy::Disconnect {
+                    warn!(
+                        event = "connection",
+                        user_id = self.user_info.id,
+                        "Slow client disconnected."
+                    );
+
+                    self.metrics.slow_client_disconnected();
+                    self.kick("Your connection is too slow.");
+                }
+
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Disconnects client, reason is sent to him in `UnrecoverableError` message.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - reason why client is disconnected
+    pub fn kick(&self, reason: &str) {
+        let _ = self.kick_tx.send(Some(reason.to_string()));
+    }
+
+    /// Returns Receiver side of watch channel that is changed when client is kicked.
+    pub fn kicked(&self) -> watch::Receiver<Option<String>> {
+        self.kick_tx.subscribe()
+    }
 }
 
 /// Handles connection of new cliets.
 ///
-/// Waits until new client want to connect. When new client occurs creates necessary structures and adds new client
-/// to `clients` hash map. Then creates new task for this clients.
+/// Waits until new client want to connect. When new client occurs creates necessary structures and registers new
+/// client in `hub` of `context`. Then creates new task for this clients. Function ends when signal
+/// from termination channel is received.
 ///
 /// # Arguments
 ///
 /// * `listener` - TcpListener on with waits for new clients to connect
-/// * `clients` - Hash map of all connected clients
-/// * `tx` - Sender side of broadcast channel for .quit command
-/// * `msg_db_tx` - Sender side of multiple producer single consumer channel for sending new messages to database handler
+/// * `context` - Structures shared by all connections
 ///
 /// # Panics
 ///
@@ -85,39 +339,50 @@ pub struct Client {
 ///
 /// # Example
 ///
-/// ```
-/// use std::collections::HashMap;
+/// ```no_run
 /// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::lockout::LoginGuard;
+/// use server::metrics::Metrics;
+/// use server::plugins::Plugins;
+/// use server::ServerContext;
+/// use tokio::sync::{broadcast, mpsc};
 /// use server::handle_new_clients;
 /// use tokio::net::TcpListener;
-/// use tokio::sync::broadcast;
-/// use tokio::sync::mpsc;
-/// use tokio::sync::Mutex;
 ///
 /// #[tokio::main]
 /// async fn main() {
-///     // hash map for storing informations (SocketAddr and TcpStream) about connected clients
-///     let mut connected_clients = Arc::new(Mutex::new(HashMap::new()));
-///
 ///     // create tcp connection on specified address and port
 ///     let listener = TcpListener::bind(("localhost", 11111)).await.unwrap();
 ///
+///     let config = Arc::new(Config::default());
+///
 ///     // broadcast channel for notifying tasks that they should stop
-///     let (mut tx, _) = broadcast::channel(8);
+///     let (shutdown, _) = broadcast::channel(8);
 ///
 ///     // multiple produces and single consumer channel for sending messages to database handler
-///     let (mut msg_db_tx, _) = mpsc::channel(64);
+///     let (msg_db_tx, _) = mpsc::channel(64);
 ///
-///     handle_new_clients(listener, &mut connected_clients, &mut tx, &mut msg_db_tx).await;
+///     let context = ServerContext {
+///         hub: Hub::start(),
+///         shutdown,
+///         msg_db_tx,
+///         metrics: Arc::new(Metrics::default()),
+///         login_guard: Arc::new(LoginGuard::new(config.auth.clone())),
+///         plugins: Arc::new(Plugins::from_config(&config.plugins)),
+///         store: Arc::new(MemoryStore::default()),
+///         cluster: Arc::new(Cluster::standalone()),
+///         config,
+///     };
+///
+///     handle_new_clients(listener, context).await;
 /// }
 /// ```
-pub async fn handle_new_clients(
-    listener: TcpListener,
-    clients: &mut Arc<Mutex<HashMap<SocketAddr, Client>>>,
-    tx: &mut Sender<bool>,
-    msg_db_tx: &mut mpsc::Sender<Message>,
-) {
-    let mut rx = tx.subscribe();
+pub async fn handle_new_clients(listener: TcpListener, context: ServerContext) {
+    let mut rx = context.shutdown.subscribe();
     let mut handles: Vec<JoinHandle<()>> = vec![];
 
     loop {
@@ -130,36 +395,29 @@ pub async fn handle_new_clients(
                 let receiver_sender_builder =
                     MessageReceiverSenderBuilder::from_tcp_stream(stream).unwrap();
                 let mut message_receiver = receiver_sender_builder.message_receiver();
-                let mut message_sender = receiver_sender_builder.message_sender();
-
-                // adding new client into clients list
-                match clients.lock().await {
-                    mut clients => {
-                        info!("Client connected.");
-
-                        clients.insert(
-                            addr,
-                            Client {
-                                message_sender: message_sender.clone(),
-                                user_info: UserInfo {
-                                    // TODO: fix
-                                    id: 0,
-                                    username: "<anonymous user>".to_string(),
-                                    color: (255, 255, 255),
-                                },
-                            },
-                        );
-                    }
+                let message_sender = receiver_sender_builder.message_sender();
+
+                let span = connection_span(addr, "tcp");
+
+                // registering new client in hub
+                {
+                    let client = Client::new(message_sender, &context.config.limits, context.metrics.clone());
+
+                    info!(parent: &span, event = "connection", "Client connected.");
+
+                    context.metrics.client_connected();
+
+                    context.hub.register(addr, client);
                 }
 
                 // create task for handling new client
                 {
-                    let mut clients = clients.clone();
-                    let mut rx = tx.subscribe();
-                    let mut msg_db_tx = msg_db_tx.clone();
+                    let mut rx = context.shutdown.subscribe();
+                    let context = context.clone();
 
                     handles.push(tokio::spawn(async move {
-                        handle_connected_client(addr, &mut message_receiver, &mut message_sender, &mut clients, &mut rx, &mut msg_db_tx)
+                        handle_connected_client(addr, &mut message_receiver, &mut rx, context)
+                            .instrument(span)
                             .await;
                     }));
                 }
@@ -174,39 +432,61 @@ pub async fn handle_new_clients(
 
 /// Handles connected client.
 ///
-/// Waits until receives message from connected client or gets signal from termination channel.
+/// Waits until receives message from connected client, gets signal from termination channel or
+/// client is kicked (`Hub::kick`). Client has to be registered in `hub` of `context` before, its user is
+/// changed only through `hub`. Function ends also when client is unregistered (it logged out) and
+/// client is unregistered when function ends.
 ///
 /// # Arguments
 ///
 /// * `addr` - client's socket address
 /// * `message_receiver` - client's `MessageReceiver`
-/// * `message_sender` - client's `MessageSender`
-/// * `clients` - Hash map of all connected clients
 /// * `rx` - Receiver side of broadcast channel for .quit command
-/// * `msg_db_tx` - Sender side of multiple producer single consumer channel for sending new messages to database handler
+/// * `context` - Structures shared by all connections
 ///
 /// # Panics
 ///
-/// Panics when can't send message to `msg_db_tx` channel.
+/// Panics when can't send message to `msg_db_tx` channel of `context`.
 ///
 /// # Example
 ///
-/// ```
-/// use std::collections::HashMap;
+/// ```no_run
 /// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::lockout::LoginGuard;
+/// use server::metrics::Metrics;
+/// use server::plugins::Plugins;
+/// use server::ServerContext;
+/// use tokio::sync::{broadcast, mpsc};
 /// use libs::builder::MessageReceiverSenderBuilder;
-/// use libs::message::UserInfo;
 /// use server::Client;
 /// use server::handle_connected_client;
 /// use tokio::net::TcpListener;
-/// use tokio::sync::broadcast;
-/// use tokio::sync::mpsc;
-/// use tokio::sync::Mutex;
 ///
 /// #[tokio::main]
 /// async fn main() {
-///     // hash map for storing informations (SocketAddr and TcpStream) about connected clients
-///     let mut connected_clients = Arc::new(Mutex::new(HashMap::new()));
+///     let config = Arc::new(Config::default());
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (shutdown, _) = broadcast::channel(8);
+///
+///     // multiple produces and single consumer channel for sending messages to database handler
+///     let (msg_db_tx, _) = mpsc::channel(64);
+///
+///     let context = ServerContext {
+///         hub: Hub::start(),
+///         shutdown,
+///         msg_db_tx,
+///         metrics: Arc::new(Metrics::default()),
+///         login_guard: Arc::new(LoginGuard::new(config.auth.clone())),
+///         plugins: Arc::new(Plugins::from_config(&config.plugins)),
+///         store: Arc::new(MemoryStore::default()),
+///         cluster: Arc::new(Cluster::standalone()),
+///         config,
+///     };
 ///
 ///     let listener = TcpListener::bind(("localhost", 11111)).await.unwrap();
 ///
@@ -215,157 +495,455 @@ pub async fn handle_new_clients(
 ///
 ///     let receiver_sender_builder = MessageReceiverSenderBuilder::from_tcp_stream(stream).unwrap();
 ///     let mut message_receiver = receiver_sender_builder.message_receiver();
-///     let mut message_sender = receiver_sender_builder.message_sender();
-///
-///     let mut client = Client {
-///         message_sender: message_sender.clone(),
-///         user_info: UserInfo {
-///             id: 1,
-///             username: "Alice".to_string(),
-///             color: (255, 255, 255),
-///         },
-///     };
 ///
-///     connected_clients.lock().await.insert(addr, client.clone());
+///     let client = Client::new(
+///         receiver_sender_builder.message_sender(),
+///         &context.config.limits,
+///         context.metrics.clone(),
+///     );
 ///
-///     // broadcast channel for notifying tasks that they should stop
-///     let (_, mut rx) = broadcast::channel(8);
+///     context.hub.register(addr, client);
 ///
-///     // multiple produces and single consumer channel for sending messages to database handler
-///     let (mut msg_db_tx, _) = mpsc::channel(64);
+///     let mut rx = context.shutdown.subscribe();
 ///
-///     handle_connected_client(addr, &mut message_receiver, &mut message_sender, &mut connected_clients, &mut rx, &mut msg_db_tx).await;
+///     handle_connected_client(addr, &mut message_receiver, &mut rx, context).await;
 /// }
 /// ```
 pub async fn handle_connected_client(
     addr: SocketAddr,
     message_receiver: &mut MessageReceiver,
-    message_sender: &mut MessageSender,
-    clients: &mut Arc<Mutex<HashMap<SocketAddr, Client>>>,
     rx: &mut Receiver<bool>,
-    msg_db_tx: &mut mpsc::Sender<Message>,
+    context: ServerContext,
 ) {
-    let mut user = Client {
-        message_sender: message_sender.clone(),
-        user_info: UserInfo {
-            // TODO: fix
-            id: 0,
-            username: "<anonymous user>".to_string(),
-            color: (255, 255, 255),
-        },
+    let ServerContext {
+        hub,
+        msg_db_tx,
+        metrics,
+        config,
+        store,
+        plugins,
+        cluster,
+        ..
+    } = &context;
+
+    // snapshot shares queue and kick channel with client in hub, it is used only for sending
+    let Some(connection) = hub.client(addr).await else {
+        error!(
+            event = "error",
+            "Connected client is not registered in hub."
+        );
+        return;
     };
 
+    let mut kicked = connection.kicked();
+
+    // typing notifications of this connection that are relayed to others
+    let mut typing = TypingThrottle::default();
+
+    // whether client sent supported protocol version
+    let mut hello_received = false;
+
     loop {
         select! {
+            // check watch channel signaling that client was kicked, writer task of client sends
+            // him reason
+            Ok(_) = kicked.changed() => {
+                let reason = kicked.borrow().clone().unwrap_or_default();
+
+                info!(event = "connection", reason = %reason, "Client kicked.");
+
+                break;
+            }
             // check broadcast channel signaling termination
             Ok(_) = rx.recv() => {
-                let _ = message_sender.send_message(&Message::from(
+                connection.send(&Message::from(
                     MessageType::UnrecoverableError(
                         "Server is stopped. Try connect later.".to_string(),
                     ),
-                )).await;
+                ));
 
-                info!("Stoped handling connected client.");
+                info!(event = "connection", "Stoped handling connected client.");
 
                 break;
             }
-            message = message_receiver.receive_message() => match message {
-                Ok(message) => {
-                    let mut message = match match_message_type_and_do_server_side_actions(message, &mut user, addr, clients.clone()).await {
+            message = message_receiver.receive_message_with_size() => match message {
+                Ok((message, size)) => {
+                    metrics.message_received(message.message.name(), size);
+
+                    // client with other version of protocol would not understand responses, old
+                    // clients do not send version at all
+                    if !hello_received {
+                        if message.message == MessageType::ClientHello(PROTOCOL_VERSION) {
+                            hello_received = true;
+
+                            continue;
+                        }
+
+                        warn!(event = "connection", message_type = message.message.name(), "Unsupported protocol version.");
+
+                        connection.send(&Message::from(MessageType::UnrecoverableError(format!(
+                            "Unsupported version of client, server requires protocol version {}.",
+                            PROTOCOL_VERSION
+                        ))));
+
+                        break;
+                    }
+
+                    // every received message means that user is not idle
+                    let Some(user) = hub.update_user(addr, UserUpdate::Active(Instant::now())).await else {
+                        info!(event = "connection", "Client left chat.");
+
+                        break;
+                    };
+
+                    // typing indicator is relayed only for logged in users and not more often than
+                    // clients send it, others are dropped without response
+                    if let MessageType::UserTyping(is_typing) = message.message {
+                        if user.user_info.id == 0 || !typing.allow(is_typing, Instant::now()) {
+                            continue;
+                        }
+                    }
+
+                    // plugins can refuse or change username and status before they are applied
+                    let message = match message.message {
+                        MessageType::UserNameChange(_) | MessageType::UserStatusChange(_) => {
+                            let (change, replies) = plugins.user_change(&user.user_info, message.message);
+
+                            send_plugin_replies(addr, replies, hub, cluster, store.as_ref()).await;
+
+                            let Some(change) = change else {
+                                continue;
+                            };
+
+                            Message { message: change, ..message }
+                        }
+                        _ => message,
+                    };
+
+                    let message = match match_message_type_and_do_server_side_actions(message, &user, addr, &context).await {
                         Ok(m) => m,
                         Err(e) => {
-                            error!("Could not process message: {}", e);
+                            error!(event = "error", error = %e, "Could not process message.");
+                            metrics.error();
                             continue;
                         }
                     };
 
+                    if let MessageType::LoginResponse(Err(_)) | MessageType::RegisterResponse(Err(_)) = message.message {
+                        metrics.auth_failed();
+                    }
+
                     match message.message {
                         // messages to send only to requester
-                        MessageType::LoginResponse(_) | MessageType::RegisterResponse(_) | MessageType::OldMessagesResponse(..) => {
-                            message_sender.send_message(&message).await.unwrap();
+                        _ if message.message.is_response() => {
+                            connection.send(&message);
+
+                            if let MessageType::AccountDeleteResponse(Ok(())) = message.message {
+                                close_deleted_account(addr, &message.user_info, hub, cluster).await;
+
+                                info!(event = "auth", action = "delete_account", "Account deleted.");
+
+                                break;
+                            }
+
+                            // let others know that user is online
+                            if let MessageType::LoginResponse(Ok(_)) | MessageType::RegisterResponse(Ok(_)) = message.message {
+                                let connect_message = Message {
+                                    message: MessageType::UserConnect(),
+                                    user_info: message.user_info.clone(),
+                                    datetime: message.datetime,
+                                };
+
+                                hub.send(Recipients::All, Some(addr), connect_message.clone()).await;
+
+                                cluster.publish(ClusterEventKind::Broadcast(connect_message));
+                                cluster.presence_changed();
+
+                                send_plugin_replies(addr, plugins.connected(&message.user_info), hub, cluster, store.as_ref()).await;
+
+                                // direct messages and mentions received while user was offline
+                                if let Some(user) = hub.client(addr).await {
+                                    if let Err(e) = offline::deliver(store, config, metrics, &user) {
+                                        error!(event = "error", error = %e, "Could not deliver offline messages.");
+                                        metrics.error();
+                                    }
+                                }
+                            }
+                        }
+                        // direct messages are sent only to other connections of recipient
+                        MessageType::DirectMessage(ref encrypted) => {
+                            let recipient = Recipients::Matching(Target::user(encrypted.recipient_id));
+                            let recipients = hub.send(recipient, Some(addr), message.clone()).await;
+
+                            debug!(event = "relay", message_type = message.message.name(), recipients, "Direct message relayed.");
+
+                            cluster.publish(ClusterEventKind::Direct(message));
                         }
                         // send messages to all connected clients
                         _ => {
-                            for (addr_target, user) in clients.lock().await.iter_mut() {
-                                if *addr_target == addr {
-                                    match message.message {
-                                        // when iterating over client that is author of this message, send
-                                        // this message to database handler, also fixes user id
-                                        MessageType::Text(_) => {
-                                            message.user_info.id = user.user_info.id;
-
-                                            msg_db_tx.send(message.clone()).await.unwrap();
-                                        }
-
-                                        _ => {}
-                                    }
+                            // plugins can transform, drop or answer message
+                            let (plugin_message, replies) = plugins.process(message);
 
-                                    continue;
-                                }
+                            send_plugin_replies(addr, replies, hub, cluster, store.as_ref()).await;
+
+                            let Some(message) = plugin_message else {
+                                continue;
+                            };
 
-                                let sender = user.message_sender.clone();
+                            // author of message is taken from hub, so it has id of logged in user
+                            if let MessageType::Text(ref text) = message.message {
+                                // decided before broadcast, user who logs in meanwhile gets it at his next login
+                                let offline_recipients = match offline::offline_mentions(store.as_ref(), hub, cluster, message.user_info.id, text).await {
+                                    Ok(recipients) => recipients,
+                                    Err(e) => {
+                                        error!(event = "error", error = %e, "Could not find offline mentioned users.");
+                                        metrics.error();
 
-                                // do not send login, register and old messages to everyone
-                                match message.message {
-                                    MessageType::LoginResponse(_) | MessageType::RegisterResponse(_) | MessageType::OldMessagesResponse(..) => {}
-                                    _ => {
-                                        if let Err(e) = sender.clone().send_message(&message).await {
-                                            error!("Could not send message: {}", e);
-                                        }
+                                        vec![]
                                     }
-                                }
+                                };
+
+                                msg_db_tx
+                                    .send(TextToStore {
+                                        message: message.clone(),
+                                        offline_recipients,
+                                    })
+                                    .await
+                                    .unwrap();
+                                metrics.db_message_queued();
                             }
+
+                            // do not send responses to everyone
+                            let recipients = if message.message.is_response() {
+                                0
+                            } else {
+                                hub.send(Recipients::All, Some(addr), message.clone()).await
+                            };
+
+                            debug!(event = "relay", message_type = message.message.name(), recipients, "Message relayed.");
+
+                            cluster.publish(ClusterEventKind::Broadcast(message));
                         }
                     }
                 },
+                Err(MessageError::Json(e)) => {
+                    // invalid JSON in one WebSocket frame does not break following frames
+                    error!(event = "error", error = %e, "Could not parse message.");
+                    metrics.error();
+
+                    connection.send(&Message::from(MessageType::RecoverableError("Invalid message.".to_string())));
+                }
                 Err(_) => {
-                    // println!("{:?}", e);
+                    // connection was closed (or stream contains invalid data)
+                    info!(event = "connection", "Client disconnected.");
+
+                    break;
                 }
             }
         }
     }
+
+    // client would stay in presence list forever otherwise, writer task of client ends when
+    // last snapshot of client is dropped
+    hub.unregister(addr);
+
+    cluster.presence_changed();
+    metrics.client_disconnected();
+}
+
+/// Limits typing notifications of one connection.
+///
+/// Notification `true` is relayed at most once per `TYPING_INTERVAL`, notification `false` only
+/// when the last relayed one was `true`, so alternating them does not bypass the limit.
+#[derive(Default)]
+struct TypingThrottle {
+    last_typing: Option<Instant>,
+    is_typing: bool,
+}
+
+impl TypingThrottle {
+    /// Returns true when notification should be relayed.
+    fn allow(&mut self, is_typing: bool, now: Instant) -> bool {
+        let allowed = if is_typing {
+            self.last_typing
+                .is_none_or(|last| now.duration_since(last) >= TYPING_INTERVAL)
+        } else {
+            self.is_typing
+        };
+
+        if allowed {
+            self.is_typing = is_typing;
+
+            if is_typing {
+                self.last_typing = Some(now);
+            }
+        }
+
+        allowed
+    }
+}
+
+/// Sends answers of plugins, delayed answers are queued in `hub` (see `Hub::send_later`).
+///
+/// Delayed answer is sent only to author, it is lost when author disconnects meanwhile.
+async fn send_plugin_replies(
+    addr: SocketAddr,
+    replies: Vec<PluginReply>,
+    hub: &Hub,
+    cluster: &Arc<Cluster>,
+    store: &dyn ChatStore,
+) {
+    for reply in replies {
+        match reply.delay {
+            Some(delay) => hub.send_later(addr, reply.message, delay),
+            None => send_plugin_reply(addr, &reply, hub, cluster, store).await,
+        }
+    }
+}
+
+async fn send_plugin_reply(
+    addr: SocketAddr,
+    reply: &PluginReply,
+    hub: &Hub,
+    cluster: &Cluster,
+    store: &dyn ChatStore,
+) {
+    let recipients = match reply.target {
+        ReplyTarget::Author => Recipients::Address(addr),
+        ReplyTarget::Everyone => Recipients::All,
+        ReplyTarget::Moderators => {
+            send_to_moderators(&reply.message, hub, cluster, store).await;
+
+            return;
+        }
+    };
+
+    hub.send(recipients, None, reply.message.clone()).await;
+
+    if reply.target == ReplyTarget::Everyone {
+        cluster.publish(ClusterEventKind::Broadcast(reply.message.clone()));
+    }
+}
+
+/// Sends message to all connections of moderators and admins, also on other instances.
+async fn send_to_moderators(
+    message: &Message,
+    hub: &Hub,
+    cluster: &Cluster,
+    store: &dyn ChatStore,
+) {
+    let users = match store.users() {
+        Ok(users) => users,
+        Err(e) => {
+            error!(event = "error", error = %e, "Could not find moderators.");
+            return;
+        }
+    };
+
+    for user in users {
+        if user.role() >= Role::Moderator && !user.disabled {
+            cluster::send(hub, cluster, Target::user(user.id), message.clone()).await;
+        }
+    }
 }
 
 /// Matches message type and do server side actions.
 ///
 /// Returns Message with message content and informations about author of message. On some message
 /// types does special actions on server side:
-/// - `UserNameChange` - changes username of client (`user` variable, not in database)
-/// - `UserColorChange` - changes color of client's username (`user` variable, not in database)
-/// - `UserDisconnect` - removes this client from `clients` hash map
-/// - `LoginRequest` - logs in user and updates data about user in `clients` hash map
-/// - `RegisterRequest` - registers user and updates data about user in `clients` hash map
-/// - `OldMessagesRequest` - gets last 20 messages from database and returns them
-/// - `LoginResponse`, `RegisterResponse`, `OldMessagesResponse` - returns error
+/// - `UserNameChange` - changes username of client (in `hub`, not in database)
+/// - `UserColorChange` - changes color of client's username (in `hub`, not in database)
+/// - `UserStatusChange` - changes status of client (in `hub`)
+/// - `UserDisconnect` - unregisters this client from `hub`
+/// - `UserTyping` - nothing, typing indicator is only relayed to other clients and never stored
+///   (`handle_connected_client` drops it for clients that are not logged in and throttles it)
+/// - `Text` - nothing when user is not muted, `ActionResponse` with reason of mute otherwise
+/// - `File`, `Image` - nothing when user is not muted and has permission to upload files,
+///   `ActionResponse` with reason otherwise (clients that are not logged in are guests, so they can
+///   not upload files)
+/// - `LoginRequest` - logs in user and updates data about user in `hub`,
+///   failed logins are counted by `login_guard`, response to them is delayed and too many of them
+///   lock username or IP address for some time
+///   (new session is created and its token is sent in response)
+/// - `TokenLoginRequest` - logs in user with token of his session, invalid tokens are not counted
+///   by `login_guard` (they can not be guessed)
+/// - `RegisterRequest` - registers user and updates data about user in `hub`
+///   (new session is created and its token is sent in response)
+/// - banned users and IP addresses get `LoginResponse` or `RegisterResponse` with reason of ban
+///   for all three requests above
+/// - `LogoutRequest` - deletes current session, client stays connected as anonymous user
+/// - `SessionsRequest` - returns sessions of logged in user
+/// - `SessionRevokeRequest` - deletes other session of logged in user and disconnects clients
+///   logged in with it
+/// - `RoleChangeRequest`, `KickRequest`, `BanRequest`, `UnbanRequest`, `MuteRequest`,
+///   `UnmuteRequest`, `MessagesDeleteRequest` - does action when user has permission for it (see `permissions` module) and returns
+///   `ActionResponse` with result
+/// - `PublicKeyPublish` - stores public key of logged in user, returns `ActionResponse`
+/// - `PublicKeyRequest` - returns public key of user in `PublicKeyResponse`
+/// - `DirectMessage` - stores encrypted message and fills id of recipient, it is sent only to
+///   recipient (muted users and clients that are not logged in get `ActionResponse` with reason)
+/// - `OldMessagesRequest` - gets last messages (`limits.history_size` from configuration) from database and returns them
+/// - `PresenceRequest` - returns logged in users of all instances with their status and idle time
+/// - `PasswordChangeRequest` - changes password of logged in user when his old password is correct,
+///   his other sessions are deleted
+/// - `AccountDeleteRequest` - deletes account of logged in user when his password is correct (his
+///   messages are kept without author) and unregisters this client from `hub`
+/// - `LoginResponse`, `RegisterResponse`, `OldMessagesResponse`, `PresenceResponse`,
+///   `PasswordChangeResponse`, `AccountDeleteResponse`, `SessionsResponse`,
+///   `SessionRevokeResponse`, `ActionResponse`, `PublicKeyResponse` - returns error
 ///
 /// # Arguments
 ///
 /// * `message` - Message to be processed
-/// * `user` - Client that send this message
+/// * `user` - Snapshot of client that send this message, returned message has user of client after
+///   changes
 /// * `addr` - Socket address of this client
-/// * `clients` - Hash map of all connected clients
+/// * `context` - Structures shared by all connections
 ///
-/// # Panics
+/// # Errors
 ///
-/// Panics when can't read message, user or color records from database.
+/// Returns error when can't read or write message, user, color, session or sanction records in
+/// `store`.
 ///
 /// # Example
 ///
-/// ```
-/// use std::collections::HashMap;
+/// ```no_run
 /// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::lockout::LoginGuard;
+/// use server::metrics::Metrics;
+/// use server::plugins::Plugins;
+/// use server::ServerContext;
+/// use tokio::sync::{broadcast, mpsc};
 /// use libs::builder::MessageReceiverSenderBuilder;
-/// use libs::message::UserInfo;
 /// use server::Client;
 /// use server::match_message_type_and_do_server_side_actions;
 /// use tokio::net::TcpListener;
-/// use tokio::sync::mpsc;
-/// use tokio::sync::Mutex;
 ///
 /// #[tokio::main]
 /// async fn main() {
-///     // hash map for storing informations (SocketAddr and TcpStream) about connected clients
-///     let connected_clients = Arc::new(Mutex::new(HashMap::new()));
+///     let config = Arc::new(Config::default());
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (shutdown, _) = broadcast::channel(8);
+///
+///     // multiple produces and single consumer channel for sending messages to database handler
+///     let (msg_db_tx, _) = mpsc::channel(64);
+///
+///     let context = ServerContext {
+///         hub: Hub::start(),
+///         shutdown,
+///         msg_db_tx,
+///         metrics: Arc::new(Metrics::default()),
+///         login_guard: Arc::new(LoginGuard::new(config.auth.clone())),
+///         plugins: Arc::new(Plugins::from_config(&config.plugins)),
+///         store: Arc::new(MemoryStore::default()),
+///         cluster: Arc::new(Cluster::standalone()),
+///         config,
+///     };
 ///
 ///     let listener = TcpListener::bind(("localhost", 11111)).await.unwrap();
 ///
@@ -374,36 +952,48 @@ pub async fn handle_connected_client(
 ///
 ///     let receiver_sender_builder = MessageReceiverSenderBuilder::from_tcp_stream(stream).unwrap();
 ///
-///     let mut client = Client {
-///         message_sender: receiver_sender_builder.message_sender(),
-///         user_info: UserInfo {
-///             id: 1,
-///             username: "Alice".to_string(),
-///             color: (255, 255, 255),
-///         },
-///     };
+///     let client = Client::new(
+///         receiver_sender_builder.message_sender(),
+///         &context.config.limits,
+///         context.metrics.clone(),
+///     );
 ///
-///     connected_clients.lock().await.insert(addr, client.clone());
+///     context.hub.register(addr, client.clone());
 ///
 ///     let mut message = match receiver_sender_builder.message_receiver().receive_message().await {
 ///         Ok(message) => message,
 ///         Err(_) => { return; },
 ///     };
 ///
-///     let message = match_message_type_and_do_server_side_actions(message, &mut client, addr, connected_clients).await.unwrap();
+///     let message = match_message_type_and_do_server_side_actions(message, &client, addr, &context).await.unwrap();
 /// }
 /// ```
 pub async fn match_message_type_and_do_server_side_actions(
     message: Message,
-    user: &mut Client,
+    user: &Client,
     addr: SocketAddr,
-    clients: Arc<Mutex<HashMap<SocketAddr, Client>>>,
+    context: &ServerContext,
 ) -> Result<Message, Box<dyn Error>> {
+    let ServerContext {
+        hub,
+        config,
+        store,
+        login_guard,
+        cluster,
+        ..
+    } = context;
+    let store = store.as_ref();
+
     let message_type = match message.message {
         MessageType::UserNameChange(new_username) => {
             let message_type = MessageType::UserNameChange(user.user_info.username.clone());
 
-            user.user_info.username = new_username;
+            record_user(user.user_info.id, &new_username);
+
+            hub.update_user(addr, UserUpdate::Username(new_username))
+                .await;
+
+            cluster.presence_changed();
 
             message_type
         }
@@ -414,104 +1004,314 @@ pub async fn match_message_type_and_do_server_side_actions(
                 user.user_info.color.2,
             );
 
-            user.user_info.color = (r, g, b);
+            hub.update_user(addr, UserUpdate::Color(r, g, b)).await;
+
+            cluster.presence_changed();
 
             message_type
         }
-        MessageType::Text(_)
-        | MessageType::File(..)
-        | MessageType::Image(_)
-        | MessageType::UserConnect()
+        MessageType::UserStatusChange(status) => {
+            hub.update_user(addr, UserUpdate::Status(status.clone()))
+                .await;
+
+            cluster.presence_changed();
+
+            MessageType::UserStatusChange(status)
+        }
+        MessageType::Text(_) | MessageType::File(..) | MessageType::Image(_) => {
+            if let Some(reason) =
+                sanctions::check(store, SanctionKind::Mute, user.user_info.id, addr.ip())?
+            {
+                return Ok(Message {
+                    message: MessageType::ActionResponse(Err(reason)),
+                    user_info: user.user_info.clone(),
+                    datetime: message.datetime,
+                });
+            }
+
+            if let MessageType::Text(_) = message.message {
+                return Ok(Message {
+                    message: message.message,
+                    user_info: user.user_info.clone(),
+                    datetime: message.datetime,
+                });
+            }
+
+            if let Err(reason) = permissions::require(
+                store,
+                user.user_info.id,
+                Permission::UploadFiles,
+                "upload files",
+            )? {
+                return Ok(Message {
+                    message: MessageType::ActionResponse(Err(reason)),
+                    user_info: user.user_info.clone(),
+                    datetime: message.datetime,
+                });
+            }
+
+            message.message
+        }
+        MessageType::UserConnect()
+        | MessageType::UserTyping(_)
         | MessageType::UnrecoverableError(_)
         | MessageType::RecoverableError(_) => message.message,
         MessageType::UserDisconnect() => {
-            clients.lock().await.remove(&addr);
+            hub.unregister(addr);
+            cluster.presence_changed();
             message.message
         }
 
         MessageType::LoginRequest(username, password) => {
-            let connection = &mut establish_connection();
+            // locked login is not checked at all, so password can not be guessed by it
+            if let Some(remaining) = login_guard.locked_for(&username, addr.ip(), Instant::now()) {
+                warn!(event = "auth", action = "login", success = false, username = %username, "Login is locked.");
 
-            match User::login(connection, username.as_str(), password.as_str()) {
-                Ok(user) => {
-                    let color;
+                MessageType::LoginResponse(Err(format!(
+                    "Too many failed login attempts, try again in {} seconds.",
+                    remaining.as_secs() + 1
+                )))
+            } else if let Some(reason) = sanctions::check(store, SanctionKind::Ban, 0, addr.ip())? {
+                warn!(event = "auth", action = "login", success = false, username = %username, "IP address is banned.");
 
-                    match user.color_id {
-                        Some(color_id) => {
-                            let res = Color::read(connection, color_id).unwrap();
-                            color = (res.r as u8, res.g as u8, res.b as u8);
-                        }
-                        None => {
-                            color = (255, 255, 255);
-                        }
-                    }
+                MessageType::LoginResponse(Err(reason))
+            } else if let Some(reason) = user_ban(store, &username, addr)? {
+                // ban is checked before password, so response does not tell whether password is
+                // correct and failed logins of banned user are not cleared
+                warn!(event = "auth", action = "login", success = false, username = %username, "User is banned.");
+
+                MessageType::LoginResponse(Err(reason))
+            } else {
+                let login = {
+                    let username = username.clone();
+
+                    with_password_hashing(&context.store, move |store| {
+                        store.login(&username, &password)
+                    })
+                    .await?
+                };
 
-                    // updates user id in clients (Arc<Mutex<HashMap<SocketAddr, Client>>>)
-                    // otherwise there is 0 and when inserting to database throws error
-                    // because user with id 0 does not exist
-                    if let Some(client) = clients.lock().await.get_mut(&addr) {
-                        client.user_info.id = user.id;
+                match login {
+                    Some(db_user) => {
+                        let (user_info, token) =
+                            start_session(db_user, addr, hub, config, store).await?;
+
+                        login_guard.succeeded(&username);
+
+                        info!(
+                            event = "auth",
+                            action = "login",
+                            success = true,
+                            "User logged in."
+                        );
+
+                        MessageType::LoginResponse(Ok((user_info, token)))
                     }
+                    None => {
+                        warn!(event = "auth", action = "login", success = false, username = %username, "Login failed.");
 
-                    MessageType::LoginResponse(Some(UserInfo {
-                        id: user.id,
-                        username: username,
-                        color: color,
-                    }))
+                        // unknown user, wrong password and disabled user get the same response
+                        tokio::time::sleep(login_guard.failed(
+                            &username,
+                            addr.ip(),
+                            Instant::now(),
+                        ))
+                        .await;
+
+                        MessageType::LoginResponse(Err(INVALID_LOGIN.to_string()))
+                    }
                 }
-                Err(_) => MessageType::LoginResponse(None),
             }
         }
 
         MessageType::RegisterRequest(username, password, r, g, b) => {
-            let connection = &mut establish_connection();
+            if let Some(reason) = sanctions::check(store, SanctionKind::Ban, 0, addr.ip())? {
+                warn!(event = "auth", action = "register", success = false, username = %username, "IP address is banned.");
 
-            let user = UserNew::register(connection, username.as_str(), password.as_str(), r, g, b);
+                return Ok(Message {
+                    message: MessageType::RegisterResponse(Err(reason)),
+                    user_info: user.user_info.clone(),
+                    datetime: message.datetime,
+                });
+            }
 
-            let color;
+            let registration = {
+                let username = username.clone();
 
-            match user.color_id {
-                Some(color_id) => {
-                    let res = Color::read(connection, color_id).unwrap();
-                    color = (res.r as u8, res.g as u8, res.b as u8);
+                with_password_hashing(&context.store, move |store| {
+                    store.register(&username, &password, (r, g, b))
+                })
+                .await
+            };
+
+            let db_user = match registration {
+                Ok(db_user) => db_user,
+                Err(e) => {
+                    let reason = account_error_reason(e)?;
+
+                    warn!(event = "auth", action = "register", success = false, username = %username, reason = %reason, "Registration failed.");
+
+                    return Ok(Message {
+                        message: MessageType::RegisterResponse(Err(reason)),
+                        user_info: user.user_info.clone(),
+                        datetime: message.datetime,
+                    });
                 }
-                None => {
-                    color = (255, 255, 255);
+            };
+
+            let (user_info, token) = start_session(db_user, addr, hub, config, store).await?;
+
+            info!(
+                event = "auth",
+                action = "register",
+                success = true,
+                "User registered."
+            );
+
+            MessageType::RegisterResponse(Ok((user_info, token)))
+        }
+
+        MessageType::TokenLoginRequest(token) => match store.login_with_token(&token)? {
+            Some((db_user, session)) => {
+                // session is kept, so it can be used again when ban expires
+                if let Some(reason) =
+                    sanctions::check(store, SanctionKind::Ban, db_user.id, addr.ip())?
+                {
+                    warn!(
+                        event = "auth",
+                        action = "token_login",
+                        success = false,
+                        "User is banned."
+                    );
+
+                    return Ok(Message {
+                        message: MessageType::LoginResponse(Err(reason)),
+                        user_info: user.user_info.clone(),
+                        datetime: message.datetime,
+                    });
                 }
+
+                let user_info =
+                    set_logged_in_user(db_user, session.id, addr, hub, config, store).await?;
+
+                info!(
+                    event = "auth",
+                    action = "token_login",
+                    success = true,
+                    "User logged in with session token."
+                );
+
+                MessageType::LoginResponse(Ok((user_info, token)))
             }
+            None => {
+                warn!(
+                    event = "auth",
+                    action = "token_login",
+                    success = false,
+                    "Login with session token failed."
+                );
 
-            // updates user id in clients (Arc<Mutex<HashMap<SocketAddr, Client>>>)
-            // otherwise there is 0 and when inserting to database throws error
-            // because user with id 0 does not exist
-            if let Some(client) = clients.lock().await.get_mut(&addr) {
-                client.user_info.id = user.id;
+                MessageType::LoginResponse(Err(INVALID_SESSION.to_string()))
             }
+        },
 
-            MessageType::RegisterResponse(Some(UserInfo {
-                id: user.id,
-                username: user.username,
-                color: color,
-            }))
+        MessageType::LogoutRequest() => {
+            if let Some(session_id) = user.session_id {
+                // session could be revoked or expired meanwhile
+                match store.delete_session(session_id) {
+                    Ok(()) | Err(StoreError::NotFound) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            // connection is kept, so client can log in again
+            hub.update_user(addr, UserUpdate::LoggedOut).await;
+            cluster.presence_changed();
+
+            info!(event = "auth", action = "logout", "User logged out.");
+
+            // others are notified same way as when user quits, hub has anonymous user already
+            return Ok(Message {
+                message: MessageType::UserDisconnect(),
+                user_info: user.user_info.clone(),
+                datetime: message.datetime,
+            });
         }
 
-        MessageType::OldMessagesRequest() => {
-            let connection = &mut establish_connection();
+        MessageType::SessionsRequest() => {
+            let now = SystemTime::now();
 
-            let msgs = MessageDb::read(connection, 20).unwrap();
+            let sessions = match user.user_info.id {
+                0 => vec![],
+                user_id => store
+                    .sessions_of_user(user_id)?
+                    .into_iter()
+                    // expired sessions are deleted later
+                    .filter(|session| session.expires_at > now)
+                    .map(|session| SessionInfo {
+                        id: session.id,
+                        created_at: session.created_at,
+                        last_used_at: session.last_used_at,
+                        expires_at: session.expires_at,
+                        current: Some(session.id) == user.session_id,
+                    })
+                    .collect(),
+            };
+
+            MessageType::SessionsResponse(sessions)
+        }
+
+        MessageType::SessionRevokeRequest(session_id) => {
+            let result = match user.user_info.id {
+                0 => Err("You are not logged in.".to_string()),
+                _ if Some(session_id) == user.session_id => {
+                    Err("Current session can not be revoked, log out instead.".to_string())
+                }
+                user_id => {
+                    // only own sessions can be revoked
+                    let is_own = store
+                        .sessions_of_user(user_id)?
+                        .iter()
+                        .any(|session| session.id == session_id);
+
+                    match is_own {
+                        true => {
+                            store.delete_session(session_id)?;
+
+                            // session could be used on other instance
+                            cluster::kick(
+                                hub,
+                                cluster,
+                                Target::session(session_id),
+                                "Your session was revoked.",
+                            )
+                            .await;
+
+                            info!(
+                                event = "auth",
+                                action = "revoke_session",
+                                session_id,
+                                "Session revoked."
+                            );
+
+                            Ok(())
+                        }
+                        false => Err(format!("Session {} does not exist.", session_id)),
+                    }
+                }
+            };
+
+            MessageType::SessionRevokeResponse(result)
+        }
+
+        MessageType::OldMessagesRequest() => {
+            let msgs = store.recent_messages(config.limits.history_size)?;
             let mut content = vec![];
 
             for msg in msgs {
-                let user = User::read_by_id(connection, msg.user_id).unwrap();
-                let color = Color::read(connection, user.color_id.unwrap()).unwrap();
-
-                content.push((
-                    msg.text,
-                    UserInfo {
-                        id: msg.user_id,
-                        username: user.username,
-                        color: (color.r as u8, color.g as u8, color.b as u8),
-                    },
-                ));
+                let user_info = author_info(store, config, msg.user_id)?;
+
+                content.push((msg.text, user_info));
             }
 
             let message_template = Message {
@@ -523,66 +1323,498 @@ pub async fn match_message_type_and_do_server_side_actions(
             return Ok(message_template);
         }
 
+        MessageType::PresenceRequest() => {
+            let mut presence = hub.presence().await;
+
+            presence.extend(cluster.remote_presence());
+
+            let message_template = Message {
+                message: MessageType::PresenceResponse(presence),
+                user_info: user.user_info.clone(),
+                datetime: message.datetime,
+            };
+
+            return Ok(message_template);
+        }
+
+        MessageType::PasswordChangeRequest(old_password, new_password) => {
+            let result = match user.user_info.id {
+                0 => Err("You are not logged in.".to_string()),
+                user_id => match with_password_hashing(&context.store, move |store| {
+                    store.change_own_password(user_id, &old_password, &new_password)
+                })
+                .await
+                {
+                    Ok(_) => {
+                        // tokens of other sessions could be stolen together with old password
+                        for session in store.sessions_of_user(user_id)? {
+                            if Some(session.id) != user.session_id {
+                                store.delete_session(session.id)?;
+                            }
+                        }
+
+                        Ok(())
+                    }
+                    Err(e) => Err(account_error_reason(e)?),
+                },
+            };
+
+            info!(
+                event = "auth",
+                action = "change_password",
+                success = result.is_ok(),
+                "Password change requested."
+            );
+
+            MessageType::PasswordChangeResponse(result)
+        }
+
+        MessageType::AccountDeleteRequest(password) => {
+            let result = match user.user_info.id {
+                0 => Err("You are not logged in.".to_string()),
+                user_id => match with_password_hashing(&context.store, move |store| {
+                    store.delete_account(user_id, &password)
+                })
+                .await
+                {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(account_error_reason(e)?),
+                },
+            };
+
+            if result.is_ok() {
+                hub.unregister(addr);
+                cluster.presence_changed();
+            }
+
+            MessageType::AccountDeleteResponse(result)
+        }
+
+        MessageType::RoleChangeRequest(..)
+        | MessageType::KickRequest(_)
+        | MessageType::BanRequest(..)
+        | MessageType::UnbanRequest(_)
+        | MessageType::MuteRequest(..)
+        | MessageType::UnmuteRequest(_)
+        | MessageType::MessagesDeleteRequest(..) => MessageType::ActionResponse(
+            permissions::handle_action(message.message, &user.user_info, hub, cluster, store)
+                .await?,
+        ),
+
+        MessageType::PublicKeyPublish(public_key) => {
+            let result = match user.user_info.id {
+                0 => Err("You are not logged in.".to_string()),
+                _ if public_key.len() != e2e::KEY_LENGTH => {
+                    Err(format!("Public key has to have {} bytes.", e2e::KEY_LENGTH))
+                }
+                user_id => {
+                    store.set_public_key(user_id, &public_key)?;
+
+                    Ok("Public key was published.".to_string())
+                }
+            };
+
+            MessageType::ActionResponse(result)
+        }
+
+        MessageType::PublicKeyRequest(username) => match store.user_by_username(&username) {
+            Ok(owner) => match store.public_key(owner.id) {
+                Ok(key) => MessageType::PublicKeyResponse(owner.username, Ok(key.public_key)),
+                Err(StoreError::NotFound) => MessageType::PublicKeyResponse(
+                    owner.username.clone(),
+                    Err(format!(
+                        "{} has not published public key yet.",
+                        owner.username
+                    )),
+                ),
+                Err(e) => return Err(e.into()),
+            },
+            Err(StoreError::NotFound) => MessageType::PublicKeyResponse(
+                username.clone(),
+                Err(format!("User '{}' does not exist.", username)),
+            ),
+            Err(e) => return Err(e.into()),
+        },
+
+        MessageType::DirectMessage(mut encrypted) => {
+            let refusal = match user.user_info.id {
+                0 => Some("You are not logged in.".to_string()),
+                user_id => sanctions::check(store, SanctionKind::Mute, user_id, addr.ip())?,
+            };
+
+            let recipient = match refusal {
+                Some(reason) => Err(reason),
+                None => match store.user_by_username(&encrypted.recipient) {
+                    Ok(recipient) => Ok(recipient),
+                    Err(StoreError::NotFound) => {
+                        Err(format!("User '{}' does not exist.", encrypted.recipient))
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            match recipient {
+                Ok(recipient) => {
+                    // server can not read message, only ciphertext is stored
+                    let direct_message = store.insert_direct_message(DirectMessageNew {
+                        sender_id: user.user_info.id,
+                        recipient_id: recipient.id,
+                        sender_key: encrypted.sender_key.clone(),
+                        recipient_key: encrypted.recipient_key.clone(),
+                        nonce: encrypted.nonce.clone(),
+                        ciphertext: encrypted.ciphertext.clone(),
+                    })?;
+
+                    // offline recipient gets message at his next login
+                    if !offline::is_online(hub, cluster, recipient.id).await {
+                        store.insert_delivery(DeliveryNew {
+                            recipient_id: recipient.id,
+                            message_id: None,
+                            direct_message_id: Some(direct_message.id),
+                        })?;
+                    }
+
+                    encrypted.recipient = recipient.username;
+                    encrypted.recipient_id = recipient.id;
+
+                    MessageType::DirectMessage(encrypted)
+                }
+                Err(reason) => MessageType::ActionResponse(Err(reason)),
+            }
+        }
+
         MessageType::LoginResponse(..)
         | MessageType::RegisterResponse(..)
-        | MessageType::OldMessagesResponse(..) => {
+        | MessageType::OldMessagesResponse(..)
+        | MessageType::PresenceResponse(..)
+        | MessageType::PasswordChangeResponse(_)
+        | MessageType::AccountDeleteResponse(_)
+        | MessageType::SessionsResponse(_)
+        | MessageType::SessionRevokeResponse(_)
+        | MessageType::ActionResponse(_)
+        | MessageType::PublicKeyResponse(..)
+        | MessageType::OfflineMessages(_) => {
             return Err("only server -> client message type".into());
         }
+
+        MessageType::ClientHello(_) => {
+            return Err("protocol version can be sent only in first message".into());
+        }
+    };
+
+    // message could change user, hub has the current one (client that left has the last one)
+    let user_info = match hub.client(addr).await {
+        Some(client) => client.user_info,
+        None => user.user_info.clone(),
     };
 
     let message_template = Message {
         message: message_type,
-        user_info: user.user_info.clone(),
+        user_info,
         datetime: message.datetime,
     };
 
     Ok(message_template)
 }
 
-/// Handles storage of messages received from channel to database.
+/// Creates new session of user who logged in with password (or registered) and stores him in
+/// `hub`, returns logged in user and token of session.
+async fn start_session(
+    db_user: User,
+    addr: SocketAddr,
+    hub: &Hub,
+    config: &Config,
+    store: &dyn ChatStore,
+) -> Result<(UserInfo, String), StoreError> {
+    let ttl = Duration::from_secs(config.auth.session_ttl_secs);
+    let (token, session) = store.create_session(db_user.id, ttl)?;
+
+    let user_info = set_logged_in_user(db_user, session.id, addr, hub, config, store).await?;
+
+    Ok((user_info, token))
+}
+
+/// Stores logged in user and his session in `hub`, returns logged in user.
+async fn set_logged_in_user(
+    db_user: User,
+    session_id: i32,
+    addr: SocketAddr,
+    hub: &Hub,
+    config: &Config,
+    store: &dyn ChatStore,
+) -> Result<UserInfo, StoreError> {
+    let color = store
+        .user_color(&db_user)?
+        .unwrap_or(config.features.default_color);
+
+    // username is matched case insensitively, stored one is used
+    let user_info = UserInfo {
+        id: db_user.id,
+        username: db_user.username,
+        color,
+    };
+
+    hub.update_user(addr, UserUpdate::LoggedIn(user_info.clone(), session_id))
+        .await;
+
+    record_user(user_info.id, &user_info.username);
+
+    Ok(user_info)
+}
+
+/// Returns user of client that is not logged in.
+fn anonymous_user_info() -> UserInfo {
+    UserInfo {
+        // users who are not logged in have id 0, they are never stored
+        id: 0,
+        username: "<anonymous user>".to_string(),
+        color: (255, 255, 255),
+    }
+}
+
+/// Returns reason of ban of user with `username` (or of his IP address), unknown user is not
+/// banned.
+fn user_ban(
+    store: &dyn ChatStore,
+    username: &str,
+    addr: SocketAddr,
+) -> Result<Option<String>, StoreError> {
+    match store.user_by_username(username) {
+        Ok(db_user) => sanctions::check(store, SanctionKind::Ban, db_user.id, addr.ip()),
+        Err(StoreError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns reason of failed account operation that can be shown to user, errors that are not
+/// caused by user are returned as errors.
+fn account_error_reason(error: StoreError) -> Result<String, StoreError> {
+    match error {
+        StoreError::UsernameTaken | StoreError::Invalid(_) | StoreError::WrongPassword => {
+            Ok(format!("{}.", error))
+        }
+        error => Err(error),
+    }
+}
+
+/// Runs store call that hashes or verifies password (Argon2 takes tens of milliseconds) on thread
+/// for blocking tasks, so it does not stall other connections handled by the same worker.
+pub(crate) async fn with_password_hashing<T, F>(
+    store: &Arc<dyn ChatStore>,
+    call: F,
+) -> Result<T, StoreError>
+where
+    F: FnOnce(&dyn ChatStore) -> Result<T, StoreError> + Send + 'static,
+    T: Send + 'static,
+{
+    let store = store.clone();
+
+    tokio::task::spawn_blocking(move || call(store.as_ref()))
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Returns author of stored message, author who deleted his account is shown as
+/// `DELETED_USERNAME`.
+fn author_info(
+    store: &dyn ChatStore,
+    config: &Config,
+    user_id: Option<i32>,
+) -> Result<UserInfo, StoreError> {
+    match user_id {
+        Some(user_id) => {
+            let user = store.user_by_id(user_id)?;
+            let color = store
+                .user_color(&user)?
+                .unwrap_or(config.features.default_color);
+
+            Ok(UserInfo {
+                id: user_id,
+                username: user.username,
+                color,
+            })
+        }
+        None => Ok(UserInfo {
+            id: 0,
+            username: DELETED_USERNAME.to_string(),
+            color: config.features.default_color,
+        }),
+    }
+}
+
+/// Disconnects all other clients of user whose account was deleted (also on other instances) and
+/// lets others know that he is offline.
+async fn close_deleted_account(
+    addr: SocketAddr,
+    user_info: &UserInfo,
+    hub: &Hub,
+    cluster: &Cluster,
+) {
+    let disconnect_message = Message {
+        message: MessageType::UserDisconnect(),
+        user_info: user_info.clone(),
+        datetime: SystemTime::now(),
+    };
+
+    // this client is already unregistered
+    hub.kick(
+        Recipients::Matching(Target::user(user_info.id)),
+        "Your account was deleted.",
+    )
+    .await;
+    hub.send(Recipients::All, Some(addr), disconnect_message.clone())
+        .await;
+
+    cluster.publish(ClusterEventKind::Kick(
+        Target::user(user_info.id),
+        "Your account was deleted.".to_string(),
+    ));
+    cluster.publish(ClusterEventKind::Broadcast(disconnect_message));
+}
+
+/// Text message for database handler.
 ///
-/// Function establishes connection with database. Then cycles endlessly and tries to receive new
-/// messages from channel. When new message appears, it is inserted into the database.
+/// # Fields
+///
+/// * `message` - Text message, its author is taken from hub
+/// * `offline_recipients` - Ids of mentioned users who were offline when message was routed
+pub struct TextToStore {
+    pub message: Message,
+    pub offline_recipients: Vec<i32>,
+}
+
+/// Handles storage of messages received from channel to `store`.
+///
+/// Function cycles until channel is closed and tries to receive new messages from channel. When
+/// new text message appears, it is inserted into the store and queued for its offline recipients.
 ///
 /// # Arguments
 ///
 /// * `rx` - Receiver side of multi producer single consumer channel
+/// * `metrics` - Metrics collected by the server
+/// * `store` - Storage of users and messages
 ///
-/// # Panics
+/// # Errors
 ///
-/// If it is not possible to establish connection with database or insertion of new record to
-/// database fails, function will panic.
+/// Failed insertions are only logged and counted in metrics.
 ///
 /// # Example
 ///
 /// ```
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
 /// use server::handle_saving_messages_to_database;
+/// use server::metrics::Metrics;
 /// use tokio::sync::mpsc;
 ///
 /// #[tokio::main]
 /// async fn main() {
+///     // sender side is dropped, so function ends immediately
 ///     let (_, rx) = mpsc::channel(8);
 ///
-///     handle_saving_messages_to_database(rx).await;
+///     handle_saving_messages_to_database(rx, Arc::new(Metrics::default()), Arc::new(MemoryStore::default())).await;
 /// }
 /// ```
-pub async fn handle_saving_messages_to_database(mut rx: mpsc::Receiver<Message>) {
-    let connection = &mut establish_connection();
+pub async fn handle_saving_messages_to_database(
+    mut rx: mpsc::Receiver<TextToStore>,
+    metrics: Arc<Metrics>,
+    store: Arc<dyn ChatStore>,
+) {
+    async move {
+        while let Some(TextToStore {
+            message,
+            offline_recipients,
+        }) = rx.recv().await
+        {
+            metrics.db_message_dequeued();
+
+            if let MessageType::Text(text) = message.message {
+                let start = Instant::now();
+
+                match store.insert_message(message.user_info.id, &text) {
+                    Ok(message_db) => {
+                        let duration = start.elapsed();
+
+                        metrics.db_insert_finished(duration);
+
+                        debug!(
+                            event = "db_write",
+                            message_id = message_db.id,
+                            user_id = message_db.user_id,
+                            duration_us = duration.as_micros() as u64,
+                            "Message stored."
+                        );
+
+                        if let Err(e) = offline::queue_mentions(store.as_ref(), &message_db, &offline_recipients) {
+                            error!(event = "error", error = %e, message_id = message_db.id, "Could not queue mentions.");
+                            metrics.error();
+                        }
+                    }
+                    Err(e) => {
+                        error!(event = "error", error = %e, user_id = message.user_info.id, "Could not store message.");
+                        metrics.error();
+                    }
+                }
+            }
+        }
+    }
+    .instrument(info_span!("database_writer"))
+    .await;
+}
+
+/// Deletes expired sessions and sanctions from `store` every hour.
+///
+/// Expired session can not be used for login and expired sanction is not applied even before they
+/// are deleted, this only keeps tables small.
+///
+/// # Arguments
+///
+/// * `store` - Storage of sessions and sanctions
+/// * `tx` - Sender side of broadcast channel for .quit command
+///
+/// # Errors
+///
+/// Failed deletions are only logged.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::purge_expired;
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     purge_expired(Arc::new(MemoryStore::default()), &mut tx).await;
+/// }
+/// ```
+pub async fn purge_expired(store: Arc<dyn ChatStore>, tx: &mut Sender<bool>) {
+    let mut rx = tx.subscribe();
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
 
     loop {
-        match rx.recv().await {
-            Some(message) => match message.message {
-                MessageType::Text(text) => {
-                    let message_new = MessageNew {
-                        user_id: message.user_info.id,
-                        text: text,
-                    };
+        select! {
+            // check broadcast channel signaling termination
+            Ok(_) = rx.recv() => {
+                break;
+            }
+            _ = interval.tick() => {
+                match store.delete_expired_sessions() {
+                    Ok(0) => {}
+                    Ok(count) => info!(event = "auth", action = "purge_sessions", count, "Expired sessions deleted."),
+                    Err(e) => error!(event = "error", error = %e, "Could not delete expired sessions."),
+                }
 
-                    message_new.insert(connection).unwrap();
+                match store.delete_expired_sanctions() {
+                    Ok(0) => {}
+                    Ok(count) => info!(event = "audit", action = "purge_sanctions", count, "Expired sanctions deleted."),
+                    Err(e) => error!(event = "error", error = %e, "Could not delete expired sanctions."),
                 }
-                _ => {}
-            },
-            None => {}
+            }
         }
     }
 }
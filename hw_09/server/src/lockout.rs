@@ -0,0 +1,214 @@
+//! Provides protection of login against guessing of passwords.
+//!
+//! Failed logins are counted per username and per IP address in `[auth]` window (see
+//! `config::AuthConfig`). Every failure delays response (delay doubles with every failure) and
+//! when there are too many failures, username or IP address is locked for some time. Lockouts
+//! are logged with `event = "audit"`.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::config::{AuthConfig, MAX_LOCKOUT_SECS};
+
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn fail(&mut self, now: Instant, config: &AuthConfig) {
+        let window = Duration::from_secs(config.failure_window_secs);
+
+        // failures older than window are forgotten
+        if matches!(self.last_failure, Some(last) if now.duration_since(last) > window) {
+            self.failures = 0;
+        }
+
+        self.failures += 1;
+        self.last_failure = Some(now);
+    }
+
+    fn lock(&mut self, now: Instant, config: &AuthConfig) {
+        // lockout longer than `MAX_LOCKOUT_SECS` is refused by `Config::validate`
+        let locked_until = now
+            .checked_add(Duration::from_secs(config.lockout_secs))
+            .or_else(|| now.checked_add(Duration::from_secs(MAX_LOCKOUT_SECS)));
+
+        self.failures = 0;
+        self.locked_until = locked_until;
+    }
+
+    fn is_expired(&self, now: Instant, config: &AuthConfig) -> bool {
+        let window = Duration::from_secs(config.failure_window_secs);
+
+        self.locked_for(now).is_none()
+            && self
+                .last_failure
+                .is_none_or(|last| now.duration_since(last) > window)
+    }
+}
+
+#[derive(Default)]
+struct GuardState {
+    usernames: HashMap<String, Attempts>,
+    ips: HashMap<IpAddr, Attempts>,
+}
+
+/// Counts failed logins and decides when login is locked.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use server::config::Config;
+/// use server::lockout::LoginGuard;
+///
+/// let config = Config::from_toml_str(
+///     r#"
+///     [auth]
+///     max_failures_per_user = 2
+///     lockout_secs = 60
+///     base_delay_ms = 100
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let guard = LoginGuard::new(config.auth);
+/// let ip = "127.0.0.1".parse().unwrap();
+/// let now = Instant::now();
+///
+/// assert_eq!(guard.locked_for("alice", ip, now), None);
+///
+/// // delay doubles with every failure
+/// assert_eq!(guard.failed("alice", ip, now), Duration::from_millis(100));
+/// assert_eq!(guard.failed("Alice", ip, now), Duration::from_millis(200));
+///
+/// // second failure locked username (case of letters does not matter)
+/// assert_eq!(guard.locked_for("ALICE", ip, now), Some(Duration::from_secs(60)));
+/// assert_eq!(guard.locked_for("bob", ip, now), None);
+///
+/// // lockout ends
+/// assert_eq!(guard.locked_for("alice", ip, now + Duration::from_secs(60)), None);
+/// ```
+pub struct LoginGuard {
+    config: AuthConfig,
+    state: Mutex<GuardState>,
+}
+
+impl LoginGuard {
+    /// Creates guard without any failed logins.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - limits of failed logins, delays and lockout duration
+    pub fn new(config: AuthConfig) -> Self {
+        LoginGuard {
+            config,
+            state: Mutex::new(GuardState::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, GuardState> {
+        // counters stay consistent even when other thread panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns remaining time of lockout of username or IP address (the longer one), `None` when
+    /// login is allowed.
+    pub fn locked_for(&self, username: &str, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let state = self.state();
+
+        let username_lock = state
+            .usernames
+            .get(&username.to_lowercase())
+            .and_then(|attempts| attempts.locked_for(now));
+        let ip_lock = state
+            .ips
+            .get(&ip)
+            .and_then(|attempts| attempts.locked_for(now));
+
+        username_lock.max(ip_lock)
+    }
+
+    /// Counts failed login, locks username or IP address when limit is reached and returns delay
+    /// before client should be answered.
+    pub fn failed(&self, username: &str, ip: IpAddr, now: Instant) -> Duration {
+        let config = &self.config;
+        let mut state = self.state();
+
+        // keep only counters that still matter, so memory does not grow forever
+        state
+            .usernames
+            .retain(|_, attempts| !attempts.is_expired(now, config));
+        state
+            .ips
+            .retain(|_, attempts| !attempts.is_expired(now, config));
+
+        let username_attempts = state.usernames.entry(username.to_lowercase()).or_default();
+        username_attempts.fail(now, config);
+        let username_failures = username_attempts.failures;
+
+        if username_failures >= config.max_failures_per_user {
+            username_attempts.lock(now, config);
+
+            warn!(
+                event = "audit",
+                action = "lockout",
+                target = "username",
+                username = %username,
+                ip = %ip,
+                failures = username_failures,
+                lockout_secs = config.lockout_secs,
+                "Username locked after too many failed logins."
+            );
+        }
+
+        let ip_attempts = state.ips.entry(ip).or_default();
+        ip_attempts.fail(now, config);
+        let ip_failures = ip_attempts.failures;
+
+        if ip_failures >= config.max_failures_per_ip {
+            ip_attempts.lock(now, config);
+
+            warn!(
+                event = "audit",
+                action = "lockout",
+                target = "ip",
+                username = %username,
+                ip = %ip,
+                failures = ip_failures,
+                lockout_secs = config.lockout_secs,
+                "IP address locked after too many failed logins."
+            );
+        }
+
+        self.delay(username_failures.max(ip_failures))
+    }
+
+    /// Forgets failed logins of username after successful login, failures of IP address are kept.
+    pub fn succeeded(&self, username: &str) {
+        self.state().usernames.remove(&username.to_lowercase());
+    }
+
+    /// Delay after `failures` failed logins, it doubles with every failure up to `max_delay_ms`.
+    fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(16);
+        let delay = self.config.base_delay_ms.saturating_mul(1 << exponent);
+
+        Duration::from_millis(delay.min(self.config.max_delay_ms))
+    }
+}
@@ -0,0 +1,82 @@
+//! Provides initialization of logging and spans shared by all connections.
+//!
+//! Every connection is handled inside `connection` span with fields `peer`, `transport`,
+//! `user_id` and `username` (user fields are filled after login or registration). Events have
+//! field `event` with one of values:
+//!
+//! - `connection` - client connected, disconnected or was kicked
+//! - `auth` - login or registration, field `success` tells result
+//! - `audit` - username or IP address was locked after too many failed logins, fields `target`,
+//!   `username`, `ip` and `failures`
+//! - `relay` - message was sent to other clients, fields `message_type` and `recipients`
+//! - `db_write` - message was inserted into database, field `duration_us`
+//! - `plugin` - plugin dropped message or handled command, field `plugin`
+//! - `announcement` - announcement from admin API was sent, field `recipients`
+//! - `moderation` - message was rejected, masked or flagged, field `action`
+//! - `reload` - moderation rules were reloaded from configuration file
+//! - `error` - message could not be parsed, processed, sent or stored, field `error`
+
+use std::net::SocketAddr;
+
+use tracing::{field, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LoggingConfig};
+
+/// Turns on printing of logs to command line.
+///
+/// # Arguments
+///
+/// * `config` - logging section of configuration, `level` is filter in `RUST_LOG` format
+///
+/// # Panics
+///
+/// Panics when logging was already initialized.
+pub fn init_logging(config: &LoggingConfig) {
+    // level is checked by `Config::validate`
+    let filter = EnvFilter::try_new(&config.level).unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Json => builder.json().with_current_span(true).init(),
+    }
+}
+
+/// Creates span for one client connection.
+///
+/// # Arguments
+///
+/// * `addr` - client's socket address
+/// * `transport` - `tcp` or `websocket`
+///
+/// # Example
+///
+/// ```
+/// use server::logging::connection_span;
+/// use tracing::Instrument;
+///
+/// let addr = "127.0.0.1:50000".parse().unwrap();
+///
+/// // every event in this future contains address of client
+/// let future = async { tracing::info!("Client connected.") }.instrument(connection_span(addr, "tcp"));
+/// ```
+pub fn connection_span(addr: SocketAddr, transport: &'static str) -> Span {
+    info_span!(
+        "connection",
+        peer = %addr,
+        transport,
+        user_id = field::Empty,
+        username = field::Empty,
+    )
+}
+
+/// Fills user fields of current connection span.
+pub fn record_user(user_id: i32, username: &str) {
+    let span = Span::current();
+
+    span.record("user_id", user_id);
+    span.record("username", username);
+}
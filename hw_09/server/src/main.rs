@@ -1,66 +1,210 @@
-use std::{collections::HashMap, io, sync::Arc};
+use std::{io, sync::Arc};
 
 use anyhow::Result;
 use clap::Parser;
 use tokio::{
     net::TcpListener,
-    sync::{broadcast, mpsc, Mutex},
+    sync::{broadcast, mpsc},
+};
+use tracing::info;
+
+use libs::{password::set_password_cost, remove_new_line};
+use server::{
+    admin::handle_admin_requests,
+    args::Args,
+    cluster::handle_cluster_events,
+    config::Config,
+    handle_new_clients, handle_saving_messages_to_database,
+    hub::Hub,
+    lockout::LoginGuard,
+    logging::init_logging,
+    metrics::{handle_metrics_requests, Metrics},
+    moderation::{watch_configuration, Moderation},
+    permissions::change_role,
+    plugins::Plugins,
+    purge_expired,
+    retention::purge_messages,
+    websocket::handle_new_websocket_clients,
+    ServerContext,
 };
-use tracing_subscriber;
-
-use libs::remove_new_line;
-use server::{args::Args, handle_new_clients, handle_saving_messages_to_database};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // turn on printing of logs to command line
-    tracing_subscriber::fmt().pretty().init();
-
     // parse program arguments
     let args = Args::parse();
 
-    // <hostname>:<port>
-    let server_address = args.hostname + ":" + args.port.to_string().as_str();
+    // load configuration file and override it by program arguments
+    let config = Arc::new(Config::from_args(&args)?);
+
+    // turn on printing of logs to command line
+    init_logging(&config.logging);
+
+    // storage of users and messages
+    let store = config.database.open_store()?;
+
+    // store used by commands from console
+    let console_store = store.clone();
 
-    //  hash map for storing informations (SocketAddr and TcpStream) about connected clients
-    let mut connected_clients = Arc::new(Mutex::new(HashMap::new()));
+    // moderation of text messages, it runs as plugin
+    let moderation = Arc::new(Moderation::new(&config.moderation).map_err(anyhow::Error::msg)?);
+
+    // plugins (bots) enabled in configuration, moderation is first, so other plugins get only
+    // moderated messages
+    let mut plugins = Plugins::default();
+    plugins
+        .register(moderation.clone())
+        .register_from_config(&config.plugins);
+    let plugins = Arc::new(plugins);
+
+    info!(plugins = ?plugins.names(), "Plugins registered.");
+
+    let hostname = config.network.hostname.as_str();
+
+    // registry of connected clients, task owning it ends when all its handles are dropped
+    let hub = Hub::start();
 
     // broadcast channel for notifying tasks that they should stop
-    let (tx, _) = broadcast::channel(8);
+    let (tx, _) = broadcast::channel(config.limits.shutdown_channel_capacity);
 
     // multiple produces and single consumer channel for sending messages to database handler
-    let (msg_db_tx, msg_db_rx) = mpsc::channel(64);
+    let (msg_db_tx, msg_db_rx) = mpsc::channel(config.limits.database_channel_capacity);
+
+    // metrics collected by the server
+    let metrics = Arc::new(Metrics::default());
+
+    // cost of new password hashes (configuration is already validated)
+    set_password_cost(config.auth.password_cost()).map_err(anyhow::Error::msg)?;
+
+    // counter of failed logins shared by tcp and WebSocket clients
+    let login_guard = Arc::new(LoginGuard::new(config.auth.clone()));
+
+    // connection to other server instances sharing the database
+    let cluster = config.cluster.open_cluster(&config.database)?;
+
+    // structures shared by tcp and WebSocket clients
+    let context = ServerContext {
+        hub: hub.clone(),
+        shutdown: tx.clone(),
+        msg_db_tx,
+        metrics: metrics.clone(),
+        config: config.clone(),
+        store: store.clone(),
+        login_guard,
+        plugins,
+        cluster: cluster.clone(),
+    };
 
     // create tcp connection on specified address and port
-    let tcp_listener = TcpListener::bind(server_address).await?;
+    let tcp_listener = TcpListener::bind((hostname, config.network.port)).await?;
 
     // task handle
     let mut handles = vec![];
 
     // create task for saving text messages to database
     {
+        let metrics = metrics.clone();
+        let store = store.clone();
+
         handles.push(tokio::spawn(async move {
-            handle_saving_messages_to_database(msg_db_rx).await;
+            handle_saving_messages_to_database(msg_db_rx, metrics, store).await;
         }));
     }
 
-    // create task for accepting new connections
+    // create task for relaying events of other instances and publishing presence
     {
+        let hub = hub.clone();
+        let cluster = cluster.clone();
+        let metrics = metrics.clone();
         let mut tx = tx.clone();
-        let mut msg_db_tx = msg_db_tx.clone();
 
         handles.push(tokio::spawn(async move {
-            handle_new_clients(
-                tcp_listener,
-                &mut connected_clients,
+            handle_cluster_events(cluster, hub, metrics, &mut tx).await;
+        }));
+    }
+
+    // create task for deleting expired sessions and sanctions
+    {
+        let mut tx = tx.clone();
+        let store = store.clone();
+
+        handles.push(tokio::spawn(async move {
+            purge_expired(store, &mut tx).await;
+        }));
+    }
+
+    // create task for deleting expired messages, only when retention limit is set
+    if config.retention.is_enabled() {
+        let mut tx = tx.clone();
+        let store = store.clone();
+        let retention = config.retention.clone();
+
+        handles.push(tokio::spawn(async move {
+            purge_messages(store, retention, &mut tx).await;
+        }));
+    }
+
+    // create task for reloading moderation rules, only when configuration file is used
+    if let Some(path) = args.config.clone() {
+        let mut tx = tx.clone();
+        let moderation = moderation.clone();
+
+        handles.push(tokio::spawn(async move {
+            watch_configuration(path, moderation, &mut tx).await;
+        }));
+    }
+
+    // create task for serving metrics, only when metrics port is set
+    if let Some(metrics_port) = config.features.metrics_port {
+        let metrics_listener = TcpListener::bind((hostname, metrics_port)).await?;
+        let mut tx = tx.clone();
+        let metrics = metrics.clone();
+
+        handles.push(tokio::spawn(async move {
+            handle_metrics_requests(metrics_listener, metrics, &mut tx).await;
+        }));
+    }
+
+    // create task for accepting new WebSocket connections, only when WebSocket port is set
+    if let Some(websocket_port) = config.features.websocket_port {
+        let websocket_listener = TcpListener::bind((hostname, websocket_port)).await?;
+        let context = context.clone();
+
+        handles.push(tokio::spawn(async move {
+            handle_new_websocket_clients(websocket_listener, context).await;
+        }));
+    }
+
+    // create task for serving admin API, only when admin port is set
+    if let Some(admin_port) = config.features.admin_port {
+        let admin_listener = TcpListener::bind((hostname, admin_port)).await?;
+        let hub = hub.clone();
+        let cluster = cluster.clone();
+        let mut tx = tx.clone();
+        let config = config.clone();
+        let store = store.clone();
+        let moderation = moderation.clone();
+
+        handles.push(tokio::spawn(async move {
+            handle_admin_requests(
+                admin_listener,
+                hub,
+                cluster,
+                config,
+                store,
+                moderation,
                 &mut tx,
-                &mut msg_db_tx,
             )
             .await;
         }));
     }
 
-    // loop waiting for ".quit" input that terminates server
+    // create task for accepting new connections
+    handles.push(tokio::spawn(async move {
+        handle_new_clients(tcp_listener, context).await;
+    }));
+
+    // loop waiting for ".quit" input that terminates server and ".role <username> <role>" that
+    // grants or revokes role
     loop {
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
@@ -71,6 +215,21 @@ async fn main() -> Result<()> {
 
             break;
         }
+
+        if let Some(arguments) = input.strip_prefix(".role ") {
+            let result = match arguments.split_once(' ') {
+                Some((username, role)) => match role.trim().parse() {
+                    Ok(role) => match change_role(console_store.as_ref(), username, role) {
+                        Ok(user) => format!("Role of {} is now {}.", user.username, role),
+                        Err(e) => format!("Could not change role: {}", e),
+                    },
+                    Err(reason) => reason,
+                },
+                None => "Usage: .role <username> <admin|moderator|member|guest>".to_string(),
+            };
+
+            println!("{}", result);
+        }
     }
 
     // wait until all tasks are done
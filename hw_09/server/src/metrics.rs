@@ -0,0 +1,370 @@
+//! Provides collection of server metrics and their export in Prometheus text format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use tokio::{net::TcpListener, sync::broadcast::Sender};
+use tracing::{error, info};
+
+/// Upper bounds (in seconds) of buckets of database insert latency histogram.
+const DB_INSERT_LATENCY_BUCKETS: [f64; 8] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5];
+
+/// Structure containing metrics collected by the server.
+///
+/// All values are updated atomically, so one instance (wrapped in `Arc`) is shared by all tasks.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use server::metrics::Metrics;
+///
+/// let metrics = Metrics::default();
+///
+/// metrics.client_connected();
+/// metrics.message_received("Text", 42);
+/// metrics.db_insert_finished(Duration::from_millis(3));
+///
+/// let output = metrics.render();
+///
+/// assert!(output.contains("chat_connected_clients 1\n"));
+/// assert!(output.contains("chat_messages_received_total{type=\"Text\"} 1\n"));
+/// assert!(output.contains("chat_bytes_received_total 42\n"));
+/// assert!(output.contains("chat_db_insert_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+/// ```
+#[derive(Default)]
+pub struct Metrics {
+    connected_clients: AtomicI64,
+    messages_received: Mutex<BTreeMap<&'static str, u64>>,
+    messages_sent: Mutex<BTreeMap<&'static str, u64>>,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    db_insert_latency_buckets: [AtomicU64; DB_INSERT_LATENCY_BUCKETS.len()],
+    db_insert_latency_sum_micros: AtomicU64,
+    db_insert_count: AtomicU64,
+    db_queue_depth: AtomicI64,
+    outbound_queue_depth: AtomicI64,
+    outbound_dropped: AtomicU64,
+    slow_clients_disconnected: AtomicU64,
+    auth_failures: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts message received from client.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_type` - name of message type (`MessageType::name`)
+    /// * `bytes` - size of message on the wire
+    pub fn message_received(&self, message_type: &'static str, bytes: usize) {
+        *self
+            .messages_received
+            .lock()
+            .unwrap()
+            .entry(message_type)
+            .or_default() += 1;
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts message sent to client.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_type` - name of message type (`MessageType::name`)
+    /// * `bytes` - size of message on the wire
+    pub fn message_sent(&self, message_type: &'static str, bytes: usize) {
+        *self
+            .messages_sent
+            .lock()
+            .unwrap()
+            .entry(message_type)
+            .or_default() += 1;
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Message was sent to database handler and waits in channel.
+    pub fn db_message_queued(&self) {
+        self.db_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Database handler took message from channel.
+    pub fn db_message_dequeued(&self) {
+        self.db_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records duration of one insert into database.
+    pub fn db_insert_finished(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, upper_bound) in self
+            .db_insert_latency_buckets
+            .iter()
+            .zip(DB_INSERT_LATENCY_BUCKETS)
+        {
+            if seconds <= upper_bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.db_insert_latency_sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.db_insert_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Message was queued for client and waits for its writer task.
+    pub fn outbound_queued(&self) {
+        self.outbound_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Writer task took message from queue of client.
+    pub fn outbound_dequeued(&self) {
+        self.outbound_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Message was not queued because queue of slow client was full.
+    pub fn outbound_dropped(&self) {
+        self.outbound_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn slow_client_disconnected(&self) {
+        self.slow_clients_disconnected
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn auth_failed(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders all metrics in Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        write_metric(
+            &mut output,
+            "chat_connected_clients",
+            "gauge",
+            "Number of connected clients.",
+            self.connected_clients.load(Ordering::Relaxed),
+        );
+
+        write_labeled_metric(
+            &mut output,
+            "chat_messages_received_total",
+            "Number of messages received from clients by message type.",
+            &self.messages_received.lock().unwrap(),
+        );
+
+        write_labeled_metric(
+            &mut output,
+            "chat_messages_sent_total",
+            "Number of messages sent to clients by message type.",
+            &self.messages_sent.lock().unwrap(),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_bytes_received_total",
+            "counter",
+            "Number of bytes received from clients.",
+            self.bytes_received.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_bytes_sent_total",
+            "counter",
+            "Number of bytes sent to clients.",
+            self.bytes_sent.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP chat_db_insert_duration_seconds Duration of message inserts into database."
+        );
+        let _ = writeln!(output, "# TYPE chat_db_insert_duration_seconds histogram");
+
+        for (bucket, upper_bound) in self
+            .db_insert_latency_buckets
+            .iter()
+            .zip(DB_INSERT_LATENCY_BUCKETS)
+        {
+            let _ = writeln!(
+                output,
+                "chat_db_insert_duration_seconds_bucket{{le=\"{}\"}} {}",
+                upper_bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+
+        let count = self.db_insert_count.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            output,
+            "chat_db_insert_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            output,
+            "chat_db_insert_duration_seconds_sum {}",
+            self.db_insert_latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(output, "chat_db_insert_duration_seconds_count {}", count);
+
+        write_metric(
+            &mut output,
+            "chat_db_queue_depth",
+            "gauge",
+            "Number of messages waiting in channel for database handler.",
+            self.db_queue_depth.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_outbound_queue_depth",
+            "gauge",
+            "Number of messages waiting in outbound queues of clients.",
+            self.outbound_queue_depth.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_outbound_dropped_total",
+            "counter",
+            "Number of messages not sent because outbound queue of client was full.",
+            self.outbound_dropped.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_slow_clients_disconnected_total",
+            "counter",
+            "Number of clients disconnected because their outbound queue was full.",
+            self.slow_clients_disconnected.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_auth_failures_total",
+            "counter",
+            "Number of failed logins and registrations.",
+            self.auth_failures.load(Ordering::Relaxed),
+        );
+
+        write_metric(
+            &mut output,
+            "chat_errors_total",
+            "counter",
+            "Number of errors while processing or sending messages.",
+            self.errors.load(Ordering::Relaxed),
+        );
+
+        output
+    }
+}
+
+fn write_metric<T: std::fmt::Display>(
+    output: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    value: T,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+    let _ = writeln!(output, "{} {}", name, value);
+}
+
+fn write_labeled_metric(
+    output: &mut String,
+    name: &str,
+    help: &str,
+    values: &BTreeMap<&'static str, u64>,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} counter", name);
+
+    for (message_type, value) in values {
+        let _ = writeln!(output, "{}{{type=\"{}\"}} {}", name, message_type, value);
+    }
+}
+
+/// Serves metrics over HTTP.
+///
+/// Metrics are available on `GET /metrics` in Prometheus text format. Function ends when signal
+/// from termination channel is received.
+///
+/// # Arguments
+///
+/// * `listener` - TcpListener for HTTP connections
+/// * `metrics` - Metrics collected by the server
+/// * `tx` - Sender side of broadcast channel for .quit command
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use server::metrics::{handle_metrics_requests, Metrics};
+/// use tokio::net::TcpListener;
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let listener = TcpListener::bind(("localhost", 9100)).await.unwrap();
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     handle_metrics_requests(listener, Arc::new(Metrics::default()), &mut tx).await;
+/// }
+/// ```
+pub async fn handle_metrics_requests(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    tx: &mut Sender<bool>,
+) {
+    let mut rx = tx.subscribe();
+
+    let router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(metrics);
+
+    info!("Serving metrics.");
+
+    if let Err(e) = axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = rx.recv().await;
+        })
+        .await
+    {
+        error!(event = "error", error = %e, "Could not serve metrics.");
+    }
+}
+
+async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
@@ -0,0 +1,577 @@
+//! Provides content moderation of text messages, usernames, custom statuses and file names.
+//!
+//! Rules are defined in `[[moderation.rules]]` of configuration (see `config::ModerationRule`),
+//! every rule has action `reject`, `mask` or `flag` (see `config::ModerationAction`). `Moderation`
+//! is registered as first plugin, so it checks every text message and file name before it is
+//! broadcast and every username and custom status before it is applied. Flagged content is
+//! reported to moderators and admins who are online and kept for admin API. When server runs
+//! with configuration file, `watch_configuration` reloads rules whenever the file changes.
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use regex::Regex;
+use tokio::{select, sync::broadcast::Sender};
+use tracing::{error, info, warn};
+
+use libs::message::{Message, MessageType, UserStatus};
+
+use crate::{
+    config::{Config, ModerationAction, ModerationConfig, ModerationRule},
+    plugins::{PluginContext, ServerPlugin},
+};
+
+/// Number of flagged messages kept for admins, older ones are forgotten.
+const MAX_FLAGS: usize = 1000;
+
+/// Character that replaces masked characters.
+const MASK: char = '*';
+
+/// How often `watch_configuration` checks modification time of configuration file.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Result of moderation of one text.
+#[derive(Debug, PartialEq)]
+pub enum ModerationVerdict {
+    /// Text can be sent (it may be masked), `flags` are reasons why admins should check it.
+    Accept { text: String, flags: Vec<String> },
+    /// Text must not be sent.
+    Reject { reason: String },
+}
+
+/// Message flagged by rule with `flag` action.
+#[derive(Clone, Debug)]
+pub struct FlaggedMessage {
+    pub user_id: i32,
+    pub username: String,
+    pub text: String,
+    pub reasons: Vec<String>,
+    pub flagged_at: SystemTime,
+}
+
+enum Matcher {
+    Pattern(Regex),
+    MaxLength(usize),
+    Links {
+        regex: Regex,
+        allowed_domains: Vec<String>,
+    },
+    RepeatedChars(usize),
+}
+
+struct CompiledRule {
+    matcher: Matcher,
+    action: ModerationAction,
+    reason: String,
+}
+
+/// Compiled moderation rules.
+///
+/// # Example
+///
+/// ```
+/// use server::config::Config;
+/// use server::moderation::{ModerationRules, ModerationVerdict};
+///
+/// let config = Config::from_toml_str(
+///     r#"
+///     [[moderation.rules]]
+///     kind = "words"
+///     words = ["darn"]
+///     action = "mask"
+///
+///     [[moderation.rules]]
+///     kind = "links"
+///     allowed_domains = ["rust-lang.org"]
+///     action = "reject"
+///
+///     [[moderation.rules]]
+///     kind = "repeated_chars"
+///     limit = 3
+///     action = "flag"
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let rules = ModerationRules::new(&config.moderation).unwrap();
+///
+/// assert_eq!(
+///     rules.check("Darn, see https://doc.rust-lang.org/book"),
+///     ModerationVerdict::Accept {
+///         text: "****, see https://doc.rust-lang.org/book".to_string(),
+///         flags: vec![],
+///     }
+/// );
+///
+/// assert!(matches!(
+///     rules.check("buy at www.example.com"),
+///     ModerationVerdict::Reject { .. }
+/// ));
+///
+/// assert_eq!(
+///     rules.check("nooooo"),
+///     ModerationVerdict::Accept {
+///         text: "nooooo".to_string(),
+///         flags: vec!["more than 3 same characters in row".to_string()],
+///     }
+/// );
+/// ```
+pub struct ModerationRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl ModerationRules {
+    /// Compiles rules, fails when some rule is invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - moderation section of configuration
+    pub fn new(config: &ModerationConfig) -> Result<ModerationRules, String> {
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| compile_rule(rule).map_err(|e| format!("rule {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(ModerationRules { rules })
+    }
+
+    /// Checks text by all rules in order, masked text is checked by following rules.
+    pub fn check(&self, text: &str) -> ModerationVerdict {
+        let mut text = text.to_string();
+        let mut flags = vec![];
+
+        for rule in self.rules.iter() {
+            if !rule.matches(&text) {
+                continue;
+            }
+
+            match rule.action {
+                ModerationAction::Reject => {
+                    return ModerationVerdict::Reject {
+                        reason: rule.reason.clone(),
+                    }
+                }
+                ModerationAction::Mask => text = rule.mask(&text),
+                ModerationAction::Flag => flags.push(rule.reason.clone()),
+            }
+        }
+
+        ModerationVerdict::Accept { text, flags }
+    }
+}
+
+fn compile_rule(rule: &ModerationRule) -> Result<CompiledRule, String> {
+    let (matcher, action, reason) = match rule {
+        ModerationRule::Words { words, action } => {
+            if words.is_empty() || words.iter().any(|word| word.trim().is_empty()) {
+                return Err("words must not be empty".to_string());
+            }
+
+            let words: Vec<String> = words
+                .iter()
+                .map(|word| regex::escape(word.trim()))
+                .collect();
+            let regex = Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|")))
+                .map_err(|e| e.to_string())?;
+
+            (Matcher::Pattern(regex), action, "blocked word".to_string())
+        }
+        ModerationRule::Regex { pattern, action } => {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+            (
+                Matcher::Pattern(regex),
+                action,
+                "blocked content".to_string(),
+            )
+        }
+        ModerationRule::MaxLength { limit, action } => (
+            Matcher::MaxLength(positive(*limit)?),
+            action,
+            format!("longer than {} characters", limit),
+        ),
+        ModerationRule::Links {
+            allowed_domains,
+            action,
+        } => {
+            if allowed_domains
+                .iter()
+                .any(|domain| domain.trim().is_empty())
+            {
+                return Err("allowed domains must not be empty".to_string());
+            }
+
+            (
+                Matcher::Links {
+                    regex: Regex::new(r#"(?i)\b(?:https?://|www\.)[^\s<>"]+"#).unwrap(),
+                    allowed_domains: allowed_domains
+                        .iter()
+                        .map(|domain| domain.trim().to_lowercase())
+                        .collect(),
+                },
+                action,
+                "link that is not allowed".to_string(),
+            )
+        }
+        ModerationRule::RepeatedChars { limit, action } => (
+            Matcher::RepeatedChars(positive(*limit)?),
+            action,
+            format!("more than {} same characters in row", limit),
+        ),
+    };
+
+    Ok(CompiledRule {
+        matcher,
+        action: *action,
+        reason,
+    })
+}
+
+fn positive(limit: usize) -> Result<usize, String> {
+    match limit {
+        0 => Err("limit must be greater than 0".to_string()),
+        limit => Ok(limit),
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, text: &str) -> bool {
+        match &self.matcher {
+            Matcher::Pattern(regex) => regex.is_match(text),
+            Matcher::MaxLength(limit) => text.chars().count() > *limit,
+            Matcher::Links {
+                regex,
+                allowed_domains,
+            } => regex
+                .find_iter(text)
+                .any(|link| !link_is_allowed(link.as_str(), allowed_domains)),
+            Matcher::RepeatedChars(limit) => collapse_repeated(text, *limit) != text,
+        }
+    }
+
+    fn mask(&self, text: &str) -> String {
+        match &self.matcher {
+            Matcher::Pattern(regex) => regex
+                .replace_all(text, |captures: &regex::Captures| {
+                    MASK.to_string().repeat(captures[0].chars().count())
+                })
+                .to_string(),
+            Matcher::MaxLength(limit) => text.chars().take(*limit).collect(),
+            Matcher::Links {
+                regex,
+                allowed_domains,
+            } => regex
+                .replace_all(text, |captures: &regex::Captures| {
+                    match link_is_allowed(&captures[0], allowed_domains) {
+                        true => captures[0].to_string(),
+                        false => "[link removed]".to_string(),
+                    }
+                })
+                .to_string(),
+            Matcher::RepeatedChars(limit) => collapse_repeated(text, *limit),
+        }
+    }
+}
+
+fn link_is_allowed(link: &str, allowed_domains: &[String]) -> bool {
+    let link = link.to_lowercase();
+    let without_scheme = link
+        .strip_prefix("https://")
+        .or_else(|| link.strip_prefix("http://"))
+        .unwrap_or(&link);
+    let host = without_scheme
+        .split(['/', '?', '#', ':'])
+        .next()
+        .unwrap_or_default();
+
+    allowed_domains
+        .iter()
+        .any(|domain| host == domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Shortens runs of same character to `limit` characters, `MASK` is kept, it can be result of
+/// masking by previous rule.
+fn collapse_repeated(text: &str, limit: usize) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = None;
+    let mut count = 0;
+
+    for c in text.chars() {
+        count = match last == Some(c) {
+            true => count + 1,
+            false => 1,
+        };
+        last = Some(c);
+
+        if count <= limit || c == MASK {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Moderation of text messages, rules can be replaced while server runs.
+///
+/// # Example
+///
+/// ```
+/// use libs::message::{Message, MessageType};
+/// use server::config::Config;
+/// use server::moderation::Moderation;
+/// use server::plugins::Plugins;
+/// use std::sync::Arc;
+///
+/// let config = Config::from_toml_str(
+///     r#"
+///     [[moderation.rules]]
+///     kind = "max_length"
+///     limit = 5
+///     action = "reject"
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let moderation = Arc::new(Moderation::new(&config.moderation).unwrap());
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(moderation.clone());
+///
+/// let (message, replies) = plugins.process(Message::from(MessageType::Text("too long".to_string())));
+///
+/// assert!(message.is_none());
+/// assert_eq!(
+///     replies[0].message.message,
+///     MessageType::RecoverableError("Message was rejected: longer than 5 characters.".to_string())
+/// );
+///
+/// // rules are replaced, for example after configuration file changed
+/// moderation.reload(&Config::default().moderation).unwrap();
+///
+/// let (message, _) = plugins.process(Message::from(MessageType::Text("too long".to_string())));
+///
+/// assert!(message.is_some());
+/// ```
+pub struct Moderation {
+    rules: RwLock<Arc<ModerationRules>>,
+    flags: Mutex<VecDeque<FlaggedMessage>>,
+}
+
+impl Moderation {
+    /// Creates moderation with rules from configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - moderation section of configuration
+    pub fn new(config: &ModerationConfig) -> Result<Moderation, String> {
+        Ok(Moderation {
+            rules: RwLock::new(Arc::new(ModerationRules::new(config)?)),
+            flags: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Replaces rules, old rules stay when new ones are invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - moderation section of configuration
+    pub fn reload(&self, config: &ModerationConfig) -> Result<(), String> {
+        let rules = Arc::new(ModerationRules::new(config)?);
+
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+
+        Ok(())
+    }
+
+    /// Checks text by current rules.
+    pub fn check(&self, text: &str) -> ModerationVerdict {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+        rules.check(text)
+    }
+
+    /// Returns flagged messages, newest first.
+    pub fn flags(&self) -> Vec<FlaggedMessage> {
+        self.flags
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    fn flag(&self, flagged: FlaggedMessage) {
+        let mut flags = self.flags.lock().unwrap_or_else(|e| e.into_inner());
+
+        if flags.len() == MAX_FLAGS {
+            flags.pop_front();
+        }
+
+        flags.push_back(flagged);
+    }
+
+    /// Checks text written by author of context, returns text that can be used (it may be
+    /// masked) or `None` when it was rejected and author was told why.
+    fn moderate(&self, subject: &str, text: &str, context: &mut PluginContext) -> Option<String> {
+        match self.check(text) {
+            ModerationVerdict::Reject { reason } => {
+                info!(event = "moderation", action = "reject", subject, reason = %reason, "Content rejected.");
+
+                context.reply_error(&format!("{} was rejected: {}.", subject, reason));
+
+                None
+            }
+            ModerationVerdict::Accept {
+                text: masked,
+                flags,
+            } => {
+                if !flags.is_empty() {
+                    warn!(event = "moderation", action = "flag", subject, reasons = ?flags, "Content flagged.");
+
+                    context.notify_moderators(&format!(
+                        "{} of {} was flagged: {}.",
+                        subject,
+                        context.author().username,
+                        flags.join(", ")
+                    ));
+
+                    self.flag(FlaggedMessage {
+                        user_id: context.author().id,
+                        username: context.author().username.clone(),
+                        text: text.to_string(),
+                        reasons: flags,
+                        flagged_at: SystemTime::now(),
+                    });
+                }
+
+                if masked != text {
+                    info!(
+                        event = "moderation",
+                        action = "mask",
+                        subject,
+                        "Content masked."
+                    );
+                }
+
+                Some(masked)
+            }
+        }
+    }
+}
+
+impl ServerPlugin for Moderation {
+    fn name(&self) -> &str {
+        "moderation"
+    }
+
+    fn on_message(&self, mut message: Message, context: &mut PluginContext) -> Option<Message> {
+        message.message = match message.message {
+            MessageType::Text(text) => MessageType::Text(self.moderate("Message", &text, context)?),
+            MessageType::File(name, data) => {
+                MessageType::File(self.moderate("File name", &name, context)?, data)
+            }
+            message_type => message_type,
+        };
+
+        Some(message)
+    }
+
+    fn on_user_change(
+        &self,
+        change: MessageType,
+        context: &mut PluginContext,
+    ) -> Option<MessageType> {
+        match change {
+            MessageType::UserNameChange(username) => Some(MessageType::UserNameChange(
+                self.moderate("Username", &username, context)?,
+            )),
+            MessageType::UserStatusChange(UserStatus::Custom(status)) => {
+                Some(MessageType::UserStatusChange(UserStatus::Custom(
+                    self.moderate("Status", &status, context)?,
+                )))
+            }
+            change => Some(change),
+        }
+    }
+}
+
+/// Reloads moderation rules whenever configuration file changes.
+///
+/// Only moderation rules are reloaded, other values need restart of the server. Invalid
+/// configuration is logged and old rules stay. Function ends when signal from termination channel
+/// is received.
+///
+/// # Arguments
+///
+/// * `path` - path to TOML configuration file
+/// * `moderation` - moderation used by the server
+/// * `tx` - Sender side of broadcast channel for .quit command
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use server::config::Config;
+/// use server::moderation::{watch_configuration, Moderation};
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let config = Config::load("server.toml").unwrap();
+///     let moderation = Arc::new(Moderation::new(&config.moderation).unwrap());
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     watch_configuration("server.toml".into(), moderation, &mut tx).await;
+/// }
+/// ```
+pub async fn watch_configuration(
+    path: PathBuf,
+    moderation: Arc<Moderation>,
+    tx: &mut Sender<bool>,
+) {
+    let mut rx = tx.subscribe();
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    let mut modified = modification_time(&path);
+
+    loop {
+        select! {
+            // check broadcast channel signaling termination
+            Ok(_) = rx.recv() => {
+                break;
+            }
+            _ = interval.tick() => {
+                let current = modification_time(&path);
+
+                if current == modified {
+                    continue;
+                }
+
+                modified = current;
+
+                let result = Config::load(&path)
+                    .map_err(|e| format!("{:?}", e))
+                    .and_then(|config| moderation.reload(&config.moderation));
+
+                match result {
+                    Ok(()) => info!(event = "reload", path = %path.display(), "Moderation rules reloaded."),
+                    Err(e) => error!(event = "error", error = %e, "Could not reload moderation rules."),
+                }
+            }
+        }
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
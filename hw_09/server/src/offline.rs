@@ -0,0 +1,278 @@
+//! Offline delivery of direct messages and mentions.
+//!
+//! Direct message for user who is not connected and text mentioning him (`@username`) are queued
+//! in store as deliveries. Whether user is connected is decided when message is routed, before it
+//! is broadcast. At his next login they are sent to him in one `OfflineMessages` message (oldest
+//! first) and marked as delivered once writer of his connection sends it.
+
+use std::sync::Arc;
+
+use tracing::{debug, error, Instrument};
+
+use database::{
+    models::{DeliveryNew, Message as MessageDb},
+    store::{ChatStore, StoreError},
+};
+use libs::message::{EncryptedMessage, Message, MessageType, UserInfo};
+
+use crate::{author_info, cluster::Cluster, config::Config, hub::Hub, metrics::Metrics, Client};
+
+/// Returns lowercase usernames mentioned in text (`@username`), every username once. Punctuation
+/// at the end of mention is not part of username.
+///
+/// # Example
+///
+/// ```
+/// use server::offline::mentions;
+///
+/// assert_eq!(mentions("@Alice, @bob and @alice: look"), vec!["alice", "bob"]);
+/// assert!(mentions("alice@example.com @").is_empty());
+/// ```
+pub fn mentions(text: &str) -> Vec<String> {
+    let mut usernames: Vec<String> = vec![];
+
+    for word in text.split_whitespace() {
+        let Some(username) = word.strip_prefix('@') else {
+            continue;
+        };
+
+        let username = username
+            .trim_end_matches(|c: char| c.is_ascii_punctuation())
+            .to_lowercase();
+
+        if !username.is_empty() && !usernames.contains(&username) {
+            usernames.push(username);
+        }
+    }
+
+    usernames
+}
+
+/// Returns whether user is logged in from at least one connection to this or other instance.
+pub(crate) async fn is_online(hub: &Hub, cluster: &Cluster, user_id: i32) -> bool {
+    hub.is_online(user_id).await || cluster.is_online(user_id)
+}
+
+/// Returns ids of users mentioned in text who are offline, author and unknown usernames are
+/// skipped. It is called before text is broadcast, so user who logs in later gets it from store.
+///
+/// # Errors
+///
+/// Returns error when users can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use server::{cluster::Cluster, hub::Hub, offline::offline_mentions};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let store = MemoryStore::default();
+///     let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+///     let bob = store.create_user("bob", "<hash>", (0, 0, 255)).unwrap();
+///     let hub = Hub::start();
+///     let cluster = Cluster::standalone();
+///
+///     let recipients = offline_mentions(&store, &hub, &cluster, alice.id, "@bob @alice @carol hi")
+///         .await
+///         .unwrap();
+///
+///     assert_eq!(recipients, vec![bob.id]);
+/// }
+/// ```
+pub async fn offline_mentions(
+    store: &dyn ChatStore,
+    hub: &Hub,
+    cluster: &Cluster,
+    author_id: i32,
+    text: &str,
+) -> Result<Vec<i32>, StoreError> {
+    let mut recipients = vec![];
+
+    for username in mentions(text) {
+        let recipient = match store.user_by_username(&username) {
+            Ok(recipient) => recipient,
+            Err(StoreError::NotFound) => continue,
+            Err(e) => return Err(e),
+        };
+
+        if recipient.id == author_id || is_online(hub, cluster, recipient.id).await {
+            continue;
+        }
+
+        recipients.push(recipient.id);
+    }
+
+    Ok(recipients)
+}
+
+/// Queues stored text for users returned by `offline_mentions` when it was routed. Returns number
+/// of queued deliveries.
+///
+/// # Errors
+///
+/// Returns error when deliveries can't be written to `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use server::offline::queue_mentions;
+///
+/// let store = MemoryStore::default();
+/// let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// let bob = store.create_user("bob", "<hash>", (0, 0, 255)).unwrap();
+///
+/// let message = store.insert_message(alice.id, "@bob hi").unwrap();
+///
+/// assert_eq!(queue_mentions(&store, &message, &[bob.id]).unwrap(), 1);
+/// assert_eq!(store.pending_deliveries(bob.id).unwrap().len(), 1);
+/// ```
+pub fn queue_mentions(
+    store: &dyn ChatStore,
+    message: &MessageDb,
+    recipients: &[i32],
+) -> Result<usize, StoreError> {
+    for recipient_id in recipients {
+        store.insert_delivery(DeliveryNew {
+            recipient_id: *recipient_id,
+            message_id: Some(message.id),
+            direct_message_id: None,
+        })?;
+    }
+
+    Ok(recipients.len())
+}
+
+/// Returns ids of deliveries of user that were not delivered yet and their messages (texts with
+/// mentions and direct messages), oldest first.
+///
+/// # Errors
+///
+/// Returns error when deliveries or authors can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::{models::DeliveryNew, store::{memory::MemoryStore, ChatStore}};
+/// use libs::message::{MessageType, UserInfo};
+/// use server::{config::Config, offline::pending_messages};
+///
+/// let store = MemoryStore::default();
+/// let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// let bob = store.create_user("bob", "<hash>", (0, 0, 255)).unwrap();
+/// let message = store.insert_message(alice.id, "@bob hi").unwrap();
+///
+/// store
+///     .insert_delivery(DeliveryNew {
+///         recipient_id: bob.id,
+///         message_id: Some(message.id),
+///         direct_message_id: None,
+///     })
+///     .unwrap();
+///
+/// let bob_info = UserInfo { id: bob.id, username: "bob".to_string(), color: (0, 0, 255) };
+/// let (ids, messages) = pending_messages(&store, &Config::default(), &bob_info).unwrap();
+///
+/// assert_eq!(ids.len(), 1);
+/// assert_eq!(messages[0].message, MessageType::Text("@bob hi".to_string()));
+/// assert_eq!(messages[0].user_info.username, "alice");
+/// ```
+pub fn pending_messages(
+    store: &dyn ChatStore,
+    config: &Config,
+    recipient: &UserInfo,
+) -> Result<(Vec<i32>, Vec<Message>), StoreError> {
+    let mut ids = vec![];
+    let mut messages = vec![];
+
+    for (delivery, message, direct_message) in store.pending_deliveries(recipient.id)? {
+        let message = match (message, direct_message) {
+            (Some(message), _) => Message {
+                message: MessageType::Text(message.text),
+                user_info: author_info(store, config, message.user_id)?,
+                datetime: message.created_at,
+            },
+            (None, Some(direct_message)) => Message {
+                message: MessageType::DirectMessage(EncryptedMessage {
+                    recipient: recipient.username.clone(),
+                    recipient_id: recipient.id,
+                    sender_key: direct_message.sender_key,
+                    recipient_key: direct_message.recipient_key,
+                    nonce: direct_message.nonce,
+                    ciphertext: direct_message.ciphertext,
+                }),
+                user_info: author_info(store, config, direct_message.sender_id)?,
+                datetime: direct_message.created_at,
+            },
+            // can't happen, database checks that delivery has message
+            (None, None) => continue,
+        };
+
+        ids.push(delivery.id);
+        messages.push(message);
+    }
+
+    Ok((ids, messages))
+}
+
+/// Queues pending deliveries for user who just logged in, they are marked as delivered after
+/// writer of client sends them. They stay pending when they can't be queued or client disconnects
+/// before they are sent. Returns number of queued messages.
+pub(crate) fn deliver(
+    store: &Arc<dyn ChatStore>,
+    config: &Config,
+    metrics: &Arc<Metrics>,
+    recipient: &Client,
+) -> Result<usize, StoreError> {
+    let (ids, messages) = pending_messages(store.as_ref(), config, &recipient.user_info)?;
+
+    if messages.is_empty() {
+        return Ok(0);
+    }
+
+    let queued = messages.len();
+
+    let Some(sent) =
+        recipient.send_confirmed(&Message::from(MessageType::OfflineMessages(messages)))
+    else {
+        error!(event = "error", "Could not queue offline messages.");
+
+        return Ok(0);
+    };
+
+    let store = store.clone();
+    let metrics = metrics.clone();
+
+    tokio::spawn(
+        async move {
+            // writer drops confirmation of message it did not send
+            if sent.await.is_err() {
+                debug!(
+                    event = "relay",
+                    message_type = "OfflineMessages",
+                    "Offline messages were not sent, they stay pending."
+                );
+
+                return;
+            }
+
+            match store.mark_delivered(&ids) {
+                Ok(delivered) => debug!(
+                    event = "relay",
+                    message_type = "OfflineMessages",
+                    delivered,
+                    "Offline messages delivered."
+                ),
+                Err(e) => {
+                    error!(event = "error", error = %e, "Could not mark offline messages as delivered.");
+                    metrics.error();
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    Ok(queued)
+}
@@ -0,0 +1,134 @@
+//! Outbound queues of clients.
+//!
+//! Every client has bounded queue of messages drained by its own writer task, so tasks sending
+//! message to many clients only queue it and slow client does not stall others. When queue of
+//! client is full, message is dropped or client is disconnected (`limits.slow_client`).
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, watch},
+    time,
+};
+use tracing::{debug, error};
+
+use libs::{
+    message::{Message, MessageType},
+    sender::MessageSender,
+};
+
+use crate::metrics::Metrics;
+
+/// How long writer tries to send reason of kick, kicked client could be the slow one.
+const KICK_MESSAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Message waiting in queue of client.
+pub(crate) struct Outgoing {
+    pub message: Message,
+    /// Confirmation that message was sent, it is dropped when message is not sent
+    pub sent: Option<oneshot::Sender<()>>,
+}
+
+/// Writes queued messages to client until all senders of queue are dropped or client is kicked.
+///
+/// Kicked client gets reason in `UnrecoverableError` message and connection is closed, messages
+/// waiting in queue are dropped. Message that is being written when client is kicked is finished
+/// unless client is too slow, then connection is closed in the middle of it. Connection is closed
+/// also when message can't be written.
+///
+/// # Arguments
+///
+/// * `message_sender` - structure for sending messages to client
+/// * `rx` - Receiver side of queue of client
+/// * `kicked` - Receiver side of watch channel that is changed when client is kicked
+/// * `metrics` - Metrics collected by the server
+pub(crate) async fn write_messages(
+    mut message_sender: MessageSender,
+    mut rx: mpsc::Receiver<Outgoing>,
+    mut kicked: watch::Receiver<Option<String>>,
+    metrics: Arc<Metrics>,
+) {
+    let mut is_kicked = false;
+
+    loop {
+        let Outgoing { message, sent } = select! {
+            // kicked client is removed and its queue is closed at the same time
+            biased;
+            Ok(_) = kicked.changed() => {
+                is_kicked = true;
+                break;
+            }
+            message = rx.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+        };
+
+        metrics.outbound_dequeued();
+
+        let send = message_sender.send_message(&message);
+        tokio::pin!(send);
+
+        let result = select! {
+            result = &mut send => result,
+            Ok(_) = kicked.changed() => {
+                is_kicked = true;
+
+                match time::timeout(KICK_MESSAGE_TIMEOUT, send).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        drop_queued(&mut rx, &metrics);
+
+                        return;
+                    }
+                }
+            }
+        };
+
+        match result {
+            Ok(bytes) => {
+                metrics.message_sent(message.message.name(), bytes);
+
+                if let Some(sent) = sent {
+                    let _ = sent.send(());
+                }
+            }
+            Err(e) => {
+                error!(event = "error", error = %e, "Could not send message.");
+                metrics.error();
+
+                drop_queued(&mut rx, &metrics);
+
+                return;
+            }
+        }
+
+        if is_kicked {
+            break;
+        }
+    }
+
+    drop_queued(&mut rx, &metrics);
+
+    if is_kicked {
+        let reason = kicked.borrow().clone().unwrap_or_default();
+        let message = Message::from(MessageType::UnrecoverableError(format!(
+            "You were disconnected: {}",
+            reason
+        )));
+
+        let _ = time::timeout(KICK_MESSAGE_TIMEOUT, message_sender.send_message(&message)).await;
+    }
+
+    debug!(event = "connection", "Writer of client stopped.");
+}
+
+/// Drops messages waiting in queue, nothing can be queued afterwards.
+fn drop_queued(rx: &mut mpsc::Receiver<Outgoing>, metrics: &Metrics) {
+    rx.close();
+
+    while rx.try_recv().is_ok() {
+        metrics.outbound_dequeued();
+    }
+}
@@ -0,0 +1,270 @@
+//! Roles of users and actions that need permission.
+//!
+//! Role is read from store for every checked action, so change of role applies immediately also
+//! to connected clients. Clients that are not logged in are guests. Users can kick, ban, mute or
+//! delete messages only of users with lower role, nobody can change his own role.
+
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use database::{
+    models::{SanctionKind, User},
+    store::{ChatStore, StoreError},
+};
+use libs::{
+    message::{MessageType, UserInfo},
+    role::{Permission, Role},
+};
+
+use crate::{
+    cluster::{self, Cluster, Target},
+    hub::Hub,
+    sanctions::{self, format_duration},
+};
+
+/// Result of action shown to user who requested it, `Err` contains reason why it was refused.
+pub type ActionResult = Result<String, String>;
+
+/// Returns role of user, user id 0 (not logged in) is guest.
+///
+/// # Errors
+///
+/// Returns error when user can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use libs::role::Role;
+/// use server::permissions::user_role;
+///
+/// let store = MemoryStore::default();
+/// let user = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+///
+/// assert_eq!(user_role(&store, 0).unwrap(), Role::Guest);
+/// assert_eq!(user_role(&store, user.id).unwrap(), Role::Member);
+///
+/// store.set_user_role(user.id, Role::Moderator).unwrap();
+///
+/// assert_eq!(user_role(&store, user.id).unwrap(), Role::Moderator);
+/// ```
+pub fn user_role(store: &dyn ChatStore, user_id: i32) -> Result<Role, StoreError> {
+    match user_id {
+        0 => Ok(Role::Guest),
+        user_id => Ok(store.user_by_id(user_id)?.role()),
+    }
+}
+
+/// Returns `Err` with reason when user does not have permission.
+pub(crate) fn require(
+    store: &dyn ChatStore,
+    user_id: i32,
+    permission: Permission,
+    action: &str,
+) -> Result<Result<Role, String>, StoreError> {
+    let role = user_role(store, user_id)?;
+
+    if role.has(permission) {
+        return Ok(Ok(role));
+    }
+
+    warn!(event = "audit", action = "permission_denied", role = %role, permission = ?permission, "Action refused.");
+
+    Ok(Err(format!("You are not allowed to {}.", action)))
+}
+
+/// Sets role of user, it is used by chat command and server console.
+///
+/// # Errors
+///
+/// Returns `StoreError::NotFound` when user does not exist.
+///
+/// # Example
+///
+/// ```
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use libs::role::Role;
+/// use server::permissions::change_role;
+///
+/// let store = MemoryStore::default();
+/// store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+///
+/// let user = change_role(&store, "alice", Role::Admin).unwrap();
+///
+/// assert_eq!(user.role(), Role::Admin);
+/// ```
+pub fn change_role(store: &dyn ChatStore, username: &str, role: Role) -> Result<User, StoreError> {
+    let user = store.user_by_username(username)?;
+
+    store.set_user_role(user.id, role)
+}
+
+/// Handles request of action that needs permission (role change, kick, ban, mute, their lifting
+/// or deletion of messages), returns text about result for requester.
+///
+/// # Errors
+///
+/// Returns error when can't read or write users or messages in `store`.
+pub(crate) async fn handle_action(
+    request: MessageType,
+    user: &UserInfo,
+    hub: &Hub,
+    cluster: &Cluster,
+    store: &dyn ChatStore,
+) -> Result<ActionResult, StoreError> {
+    let user_id = user.id;
+
+    if user_id == 0 {
+        return Ok(Err("You are not logged in.".to_string()));
+    }
+
+    let (permission, action, username) = match &request {
+        MessageType::RoleChangeRequest(username, _) => {
+            (Permission::ManageRoles, "change roles", username)
+        }
+        MessageType::KickRequest(username) => (Permission::Kick, "kick users", username),
+        MessageType::BanRequest(username, ..) | MessageType::UnbanRequest(username) => {
+            (Permission::Ban, "ban users", username)
+        }
+        MessageType::MuteRequest(username, ..) | MessageType::UnmuteRequest(username) => {
+            (Permission::Ban, "mute users", username)
+        }
+        MessageType::MessagesDeleteRequest(username, _) => (
+            Permission::DeleteOthersMessages,
+            "delete messages of others",
+            username,
+        ),
+        _ => return Ok(Err("Unknown action.".to_string())),
+    };
+
+    let target = match store.user_by_username(username) {
+        Ok(target) => target,
+        Err(StoreError::NotFound) => {
+            return Ok(Err(format!("User '{}' does not exist.", username)))
+        }
+        Err(e) => return Err(e),
+    };
+
+    // everybody can delete his own messages
+    let own_messages =
+        matches!(request, MessageType::MessagesDeleteRequest(..)) && target.id == user_id;
+
+    if !own_messages {
+        let role = match require(store, user_id, permission, action)? {
+            Ok(role) => role,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        if target.id == user_id {
+            return Ok(Err("You can not do this to yourself.".to_string()));
+        }
+
+        // role change is allowed only to admins, they can change role of other admins too
+        if permission != Permission::ManageRoles && target.role() >= role {
+            return Ok(Err(format!(
+                "You can not do this to {} with role {}.",
+                target.username,
+                target.role()
+            )));
+        }
+    }
+
+    let result = match request {
+        MessageType::RoleChangeRequest(_, role) => {
+            store.set_user_role(target.id, role)?;
+
+            format!("Role of {} is now {}.", target.username, role)
+        }
+        MessageType::KickRequest(_) => {
+            let reason = format!("You were kicked by {}.", user.username);
+            let kicked = cluster::kick(hub, cluster, Target::user(target.id), &reason).await;
+
+            format!("{} was kicked ({} connections).", target.username, kicked)
+        }
+        MessageType::BanRequest(_, duration, reason) => {
+            let sanction = match sanctions::create(
+                store,
+                SanctionKind::Ban,
+                Some(target.id),
+                None,
+                duration,
+                &reason,
+                Some(user_id),
+            ) {
+                Ok(sanction) => sanction,
+                Err(StoreError::Invalid(reason)) => return Ok(Err(format!("{}.", reason))),
+                Err(e) => return Err(e),
+            };
+
+            sanctions::enforce(hub, cluster, &sanction).await;
+
+            format!(
+                "{} was banned {}.",
+                target.username,
+                sanction_length(duration)
+            )
+        }
+        MessageType::UnbanRequest(_) => {
+            match store.delete_user_sanctions(SanctionKind::Ban, target.id)? {
+                0 => return Ok(Err(format!("{} is not banned.", target.username))),
+                _ => format!("{} was unbanned.", target.username),
+            }
+        }
+        MessageType::MuteRequest(_, duration, reason) => {
+            let sanction = match sanctions::create(
+                store,
+                SanctionKind::Mute,
+                Some(target.id),
+                None,
+                duration,
+                &reason,
+                Some(user_id),
+            ) {
+                Ok(sanction) => sanction,
+                Err(StoreError::Invalid(reason)) => return Ok(Err(format!("{}.", reason))),
+                Err(e) => return Err(e),
+            };
+
+            sanctions::enforce(hub, cluster, &sanction).await;
+
+            format!(
+                "{} was muted {}.",
+                target.username,
+                sanction_length(duration)
+            )
+        }
+        MessageType::UnmuteRequest(_) => {
+            match store.delete_user_sanctions(SanctionKind::Mute, target.id)? {
+                0 => return Ok(Err(format!("{} is not muted.", target.username))),
+                _ => format!("{} was unmuted.", target.username),
+            }
+        }
+        MessageType::MessagesDeleteRequest(_, count) => {
+            let messages = store.messages(Some(target.id), count as i64)?;
+
+            for message in messages.iter() {
+                store.delete_message(message.id)?;
+            }
+
+            format!(
+                "{} messages of {} were deleted.",
+                messages.len(),
+                target.username
+            )
+        }
+        _ => return Ok(Err("Unknown action.".to_string())),
+    };
+
+    info!(event = "audit", action = "moderation", result = %result, "Action done.");
+
+    Ok(Ok(result))
+}
+
+/// Describes length of sanction for moderator, for example `for 1h` or `permanently`.
+fn sanction_length(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("for {}", format_duration(duration)),
+        None => "permanently".to_string(),
+    }
+}
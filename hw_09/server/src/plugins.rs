@@ -0,0 +1,764 @@
+//! Provides `ServerPlugin` trait, plugins (bots) are hooked into message pipeline of the server.
+//!
+//! Plugins are registered at startup into `Plugins`, built-in plugins are enabled in `[plugins]`
+//! section of configuration. Every message that would be broadcast to other clients goes through
+//! `ServerPlugin::on_message` of all plugins in order of registration first, plugins can inspect,
+//! transform or drop it. Text message starting with `/` is then command, it is offered to
+//! `ServerPlugin::on_command` of plugins until one of them handles it. Handled command is not
+//! broadcast, unhandled command is broadcast as ordinary text. After login or registration
+//! `ServerPlugin::on_connect` of all plugins is called. Change of username or status goes through
+//! `ServerPlugin::on_user_change` of all plugins before it is applied.
+//!
+//! Plugins answer through `PluginContext`, answers are text messages from bot with plugin name.
+//!
+//! Built-in plugins:
+//!
+//! - `RollPlugin` - `/roll [<count>d<sides>]` rolls dice, result is sent to everyone
+//! - `RemindPlugin` - `/remind <delay> <text>` reminds author after delay (`30s`, `10m`, `2h`,
+//!   number without unit is minutes), user can have at most 10 pending reminders
+//! - `FaqPlugin` - `/faq [<topic>]` answers question from configured list
+//! - `WelcomePlugin` - sends welcome text to every user after login
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tracing::debug;
+
+use libs::message::{Message, MessageType, UserInfo};
+
+use crate::config::PluginsConfig;
+
+/// Color of username of bots.
+const BOT_COLOR: (u8, u8, u8) = (128, 128, 128);
+
+/// Returns user info used as author of messages from bot.
+///
+/// # Arguments
+///
+/// * `name` - name of plugin (or other source of messages)
+pub fn bot_user_info(name: &str) -> UserInfo {
+    UserInfo {
+        id: 0,
+        username: format!("{}-bot", name),
+        color: BOT_COLOR,
+    }
+}
+
+/// Who receives answer of plugin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplyTarget {
+    /// Only author of processed message (or connected user)
+    Author,
+    /// All connected clients including author
+    Everyone,
+    /// Logged in moderators and admins (also on other instances)
+    Moderators,
+}
+
+/// Answer of plugin, it is sent by the server after processing of message.
+#[derive(Clone, Debug)]
+pub struct PluginReply {
+    pub target: ReplyTarget,
+    pub message: Message,
+    /// Answer is sent only to author after delay when set.
+    pub delay: Option<Duration>,
+}
+
+/// Context of one processed message (or connection), it collects answers of plugins.
+pub struct PluginContext {
+    author: UserInfo,
+    bot: UserInfo,
+    replies: Vec<PluginReply>,
+}
+
+impl PluginContext {
+    /// Creates context for message sent by `author`.
+    pub fn new(author: UserInfo) -> Self {
+        PluginContext {
+            author,
+            bot: bot_user_info("server"),
+            replies: vec![],
+        }
+    }
+
+    /// Author of processed message (or user that connected).
+    pub fn author(&self) -> &UserInfo {
+        &self.author
+    }
+
+    /// Sends text only to author.
+    pub fn reply(&mut self, text: &str) {
+        self.push(ReplyTarget::Author, text, None);
+    }
+
+    /// Sends text to everyone.
+    pub fn broadcast(&mut self, text: &str) {
+        self.push(ReplyTarget::Everyone, text, None);
+    }
+
+    /// Sends text to moderators and admins who are online.
+    pub fn notify_moderators(&mut self, text: &str) {
+        self.push(ReplyTarget::Moderators, text, None);
+    }
+
+    /// Sends text only to author after delay, it is lost when author disconnects meanwhile.
+    pub fn reply_later(&mut self, text: &str, delay: Duration) {
+        self.push(ReplyTarget::Author, text, Some(delay));
+    }
+
+    /// Sends `RecoverableError` only to author.
+    pub fn reply_error(&mut self, text: &str) {
+        self.push_message(
+            ReplyTarget::Author,
+            MessageType::RecoverableError(text.to_string()),
+            None,
+        );
+    }
+
+    /// Returns collected answers.
+    pub fn into_replies(self) -> Vec<PluginReply> {
+        self.replies
+    }
+
+    fn push(&mut self, target: ReplyTarget, text: &str, delay: Option<Duration>) {
+        self.push_message(target, MessageType::Text(text.to_string()), delay);
+    }
+
+    fn push_message(
+        &mut self,
+        target: ReplyTarget,
+        message_type: MessageType,
+        delay: Option<Duration>,
+    ) {
+        let mut message = Message::from(message_type);
+        message.user_info = self.bot.clone();
+
+        self.replies.push(PluginReply {
+            target,
+            message,
+            delay,
+        });
+    }
+}
+
+/// Plugin hooked into message pipeline of the server, all hooks do nothing by default.
+///
+/// # Example
+///
+/// ```
+/// use libs::message::{Message, MessageType, UserInfo};
+/// use server::plugins::{PluginContext, Plugins, ServerPlugin};
+///
+/// struct ShoutPlugin;
+///
+/// impl ServerPlugin for ShoutPlugin {
+///     fn name(&self) -> &str {
+///         "shout"
+///     }
+///
+///     fn on_message(&self, mut message: Message, _context: &mut PluginContext) -> Option<Message> {
+///         if let MessageType::Text(text) = &message.message {
+///             message.message = MessageType::Text(text.to_uppercase());
+///         }
+///
+///         Some(message)
+///     }
+///
+///     fn on_command(&self, command: &str, args: &str, context: &mut PluginContext) -> bool {
+///         if command != "echo" {
+///             return false;
+///         }
+///
+///         context.reply(args);
+///
+///         true
+///     }
+/// }
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(ShoutPlugin);
+///
+/// let (message, replies) = plugins.process(Message::from(MessageType::Text("hello".to_string())));
+///
+/// assert_eq!(message.unwrap().message, MessageType::Text("HELLO".to_string()));
+/// assert!(replies.is_empty());
+///
+/// // commands are transformed too, `on_message` runs before `on_command`
+/// let (message, replies) = plugins.process(Message::from(MessageType::Text("/echo hi".to_string())));
+///
+/// assert!(message.is_none());
+/// assert_eq!(replies[0].message.message, MessageType::Text("HI".to_string()));
+/// assert_eq!(replies[0].message.user_info.username, "shout-bot");
+/// ```
+pub trait ServerPlugin: Send + Sync {
+    /// Name of plugin, it is used in logs and as name of bot.
+    fn name(&self) -> &str;
+
+    /// Called after user logs in or registers.
+    fn on_connect(&self, _context: &mut PluginContext) {}
+
+    /// Called for every message that would be broadcast, returns message that continues through
+    /// pipeline (`None` drops it).
+    fn on_message(&self, message: Message, _context: &mut PluginContext) -> Option<Message> {
+        Some(message)
+    }
+
+    /// Called for command (text `/<command> <args>`, command is lowercase), returns `true` when
+    /// command was handled.
+    fn on_command(&self, _command: &str, _args: &str, _context: &mut PluginContext) -> bool {
+        false
+    }
+
+    /// Called for `UserNameChange` or `UserStatusChange` before it is applied, returns change that
+    /// is applied (`None` refuses it).
+    fn on_user_change(
+        &self,
+        change: MessageType,
+        _context: &mut PluginContext,
+    ) -> Option<MessageType> {
+        Some(change)
+    }
+}
+
+/// Plugin shared with other parts of the server (for example with task that reloads it).
+impl<T: ServerPlugin> ServerPlugin for Arc<T> {
+    fn name(&self) -> &str {
+        self.as_ref().name()
+    }
+
+    fn on_connect(&self, context: &mut PluginContext) {
+        self.as_ref().on_connect(context)
+    }
+
+    fn on_message(&self, message: Message, context: &mut PluginContext) -> Option<Message> {
+        self.as_ref().on_message(message, context)
+    }
+
+    fn on_command(&self, command: &str, args: &str, context: &mut PluginContext) -> bool {
+        self.as_ref().on_command(command, args, context)
+    }
+
+    fn on_user_change(
+        &self,
+        change: MessageType,
+        context: &mut PluginContext,
+    ) -> Option<MessageType> {
+        self.as_ref().on_user_change(change, context)
+    }
+}
+
+/// Registered plugins, they are called in order of registration.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn ServerPlugin>>,
+}
+
+impl Plugins {
+    /// Registers built-in plugins enabled in configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - plugins section of configuration
+    pub fn from_config(config: &PluginsConfig) -> Plugins {
+        let mut plugins = Plugins::default();
+        plugins.register_from_config(config);
+
+        plugins
+    }
+
+    /// Registers built-in plugins enabled in configuration after already registered ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - plugins section of configuration
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use server::config::Config;
+    /// use server::moderation::Moderation;
+    /// use server::plugins::Plugins;
+    ///
+    /// let config = Config::default();
+    /// let moderation = Arc::new(Moderation::new(&config.moderation).unwrap());
+    ///
+    /// // moderation is first, so other plugins get only moderated messages
+    /// let mut plugins = Plugins::default();
+    /// plugins.register(moderation).register_from_config(&config.plugins);
+    ///
+    /// assert_eq!(plugins.names(), vec!["moderation", "roll", "remind"]);
+    /// ```
+    pub fn register_from_config(&mut self, config: &PluginsConfig) -> &mut Self {
+        if let Some(text) = &config.welcome {
+            self.register(WelcomePlugin { text: text.clone() });
+        }
+        if config.roll {
+            self.register(RollPlugin);
+        }
+        if config.remind {
+            self.register(RemindPlugin::default());
+        }
+        if !config.faq.is_empty() {
+            self.register(FaqPlugin {
+                answers: config.faq.clone(),
+            });
+        }
+
+        self
+    }
+
+    pub fn register(&mut self, plugin: impl ServerPlugin + 'static) -> &mut Self {
+        self.plugins.push(Box::new(plugin));
+        self
+    }
+
+    /// Returns names of registered plugins.
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|plugin| plugin.name()).collect()
+    }
+
+    /// Calls `on_connect` of all plugins, returns their answers.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_info` - user that logged in or registered
+    pub fn connected(&self, user_info: &UserInfo) -> Vec<PluginReply> {
+        let mut context = PluginContext::new(user_info.clone());
+
+        for plugin in self.plugins.iter() {
+            context.bot = bot_user_info(plugin.name());
+            plugin.on_connect(&mut context);
+        }
+
+        context.into_replies()
+    }
+
+    /// Passes change of username or status through all plugins, returns change that should be
+    /// applied (`None` when it was refused) and answers of plugins.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_info` - user that requested change
+    /// * `change` - `UserNameChange` or `UserStatusChange` received from client
+    pub fn user_change(
+        &self,
+        user_info: &UserInfo,
+        change: MessageType,
+    ) -> (Option<MessageType>, Vec<PluginReply>) {
+        let mut context = PluginContext::new(user_info.clone());
+        let mut change = change;
+
+        for plugin in self.plugins.iter() {
+            context.bot = bot_user_info(plugin.name());
+
+            change = match plugin.on_user_change(change, &mut context) {
+                Some(change) => change,
+                None => {
+                    debug!(
+                        event = "plugin",
+                        plugin = plugin.name(),
+                        "User change refused."
+                    );
+
+                    return (None, context.into_replies());
+                }
+            };
+        }
+
+        (Some(change), context.into_replies())
+    }
+
+    /// Passes message through all plugins, returns message that should be broadcast (`None` when
+    /// it was dropped or it was handled command) and answers of plugins.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - message received from client, `user_info` is its author
+    pub fn process(&self, message: Message) -> (Option<Message>, Vec<PluginReply>) {
+        let mut context = PluginContext::new(message.user_info.clone());
+        let mut message = Some(message);
+
+        for plugin in self.plugins.iter() {
+            context.bot = bot_user_info(plugin.name());
+
+            message = plugin.on_message(message.unwrap(), &mut context);
+
+            if message.is_none() {
+                debug!(event = "plugin", plugin = plugin.name(), "Message dropped.");
+
+                return (None, context.into_replies());
+            }
+        }
+
+        let message = message.unwrap();
+
+        if let MessageType::Text(text) = &message.message {
+            if let Some((command, args)) = parse_command(text) {
+                let command = command.to_lowercase();
+
+                for plugin in self.plugins.iter() {
+                    context.bot = bot_user_info(plugin.name());
+
+                    if plugin.on_command(&command, args, &mut context) {
+                        debug!(
+                            event = "plugin",
+                            plugin = plugin.name(),
+                            command,
+                            "Command handled."
+                        );
+
+                        return (None, context.into_replies());
+                    }
+                }
+            }
+        }
+
+        (Some(message), context.into_replies())
+    }
+}
+
+/// Splits `/<command> <args>` into command and trimmed args.
+fn parse_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim().strip_prefix('/')?;
+    let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+
+    match command.is_empty() {
+        true => None,
+        false => Some((command, args.trim())),
+    }
+}
+
+/// `/roll [<count>d<sides>]` rolls dice (one six-sided by default), result is sent to everyone.
+///
+/// # Example
+///
+/// ```
+/// use libs::message::{Message, MessageType};
+/// use server::plugins::{Plugins, ReplyTarget, RollPlugin};
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(RollPlugin);
+///
+/// let mut message = Message::from(MessageType::Text("/roll 3d1".to_string()));
+/// message.user_info.username = "alice".to_string();
+///
+/// let (message, replies) = plugins.process(message);
+///
+/// assert!(message.is_none());
+/// assert_eq!(replies[0].target, ReplyTarget::Everyone);
+/// assert_eq!(
+///     replies[0].message.message,
+///     MessageType::Text("alice rolled 3d1: 1 + 1 + 1 = 3".to_string())
+/// );
+/// ```
+pub struct RollPlugin;
+
+impl RollPlugin {
+    const MAX_COUNT: u32 = 100;
+    const MAX_SIDES: u32 = 1000;
+
+    fn parse(args: &str) -> Option<(u32, u32)> {
+        if args.is_empty() {
+            return Some((1, 6));
+        }
+
+        let (count, sides) = args.split_once('d')?;
+        let count = match count {
+            "" => 1,
+            count => count.parse().ok()?,
+        };
+        let sides = sides.parse().ok()?;
+
+        match (1..=Self::MAX_COUNT).contains(&count) && (1..=Self::MAX_SIDES).contains(&sides) {
+            true => Some((count, sides)),
+            false => None,
+        }
+    }
+}
+
+impl ServerPlugin for RollPlugin {
+    fn name(&self) -> &str {
+        "roll"
+    }
+
+    fn on_command(&self, command: &str, args: &str, context: &mut PluginContext) -> bool {
+        if command != "roll" {
+            return false;
+        }
+
+        let Some((count, sides)) = Self::parse(args) else {
+            context.reply(&format!(
+                "Usage: /roll [<count>d<sides>], at most {}d{}.",
+                Self::MAX_COUNT,
+                Self::MAX_SIDES
+            ));
+
+            return true;
+        };
+
+        let mut rng = rand::thread_rng();
+        let rolls: Vec<u32> = (0..count).map(|_| rng.gen_range(1..=sides)).collect();
+        let total: u32 = rolls.iter().sum();
+
+        let text = match rolls.len() {
+            1 => format!(
+                "{} rolled {}d{}: {}",
+                context.author().username,
+                count,
+                sides,
+                total
+            ),
+            _ => format!(
+                "{} rolled {}d{}: {} = {}",
+                context.author().username,
+                count,
+                sides,
+                rolls
+                    .iter()
+                    .map(|roll| roll.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" + "),
+                total
+            ),
+        };
+
+        context.broadcast(&text);
+
+        true
+    }
+}
+
+/// `/remind <delay> <text>` sends text back to author after delay.
+///
+/// Reminders wait in timer queue of hub (see `Hub::send_later`), they are lost when author
+/// disconnects or server stops. Every user (all users who are not logged in together) can have at
+/// most `MAX_PENDING` reminders that were not sent yet, lost reminders count until their time.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use libs::message::{Message, MessageType};
+/// use server::plugins::{Plugins, RemindPlugin, ReplyTarget};
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(RemindPlugin::default());
+///
+/// let (_, replies) = plugins.process(Message::from(MessageType::Text("/remind 2h stand up".to_string())));
+///
+/// assert_eq!(replies[0].delay, None);
+/// assert_eq!(replies[1].target, ReplyTarget::Author);
+/// assert_eq!(replies[1].delay, Some(Duration::from_secs(2 * 60 * 60)));
+/// assert_eq!(replies[1].message.message, MessageType::Text("Reminder: stand up".to_string()));
+///
+/// // invalid delay is answered by usage
+/// let (_, replies) = plugins.process(Message::from(MessageType::Text("/remind 2d tea".to_string())));
+///
+/// assert_eq!(replies.len(), 1);
+/// assert_eq!(replies[0].delay, None);
+///
+/// // too many pending reminders
+/// for _ in 1..RemindPlugin::MAX_PENDING {
+///     plugins.process(Message::from(MessageType::Text("/remind 10m tea".to_string())));
+/// }
+///
+/// let (_, replies) = plugins.process(Message::from(MessageType::Text("/remind 10m tea".to_string())));
+///
+/// assert_eq!(replies.len(), 1);
+/// assert_eq!(replies[0].delay, None);
+/// ```
+#[derive(Default)]
+pub struct RemindPlugin {
+    /// Times when pending reminders are sent by id of user
+    pending: Mutex<HashMap<i32, Vec<Instant>>>,
+}
+
+impl RemindPlugin {
+    const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Maximal number of pending reminders of one user.
+    pub const MAX_PENDING: usize = 10;
+
+    /// Adds reminder of user sent after delay, returns `false` when user has too many pending
+    /// reminders.
+    fn add_pending(&self, user_id: i32, delay: Duration) -> bool {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+
+        // sent reminders are forgotten
+        pending.retain(|_, due| {
+            due.retain(|due| *due > now);
+            !due.is_empty()
+        });
+
+        let due = pending.entry(user_id).or_default();
+
+        if due.len() >= Self::MAX_PENDING {
+            return false;
+        }
+
+        due.push(now + delay);
+
+        true
+    }
+
+    /// Parses `30s`, `10m`, `2h` or number of minutes.
+    fn parse_delay(delay: &str) -> Option<Duration> {
+        let (number, unit) = match delay.char_indices().last()? {
+            (i, unit) if unit.is_ascii_alphabetic() => (&delay[..i], unit),
+            _ => (delay, 'm'),
+        };
+
+        let number: u64 = number.parse().ok()?;
+        let seconds = match unit {
+            's' => number,
+            'm' => number.checked_mul(60)?,
+            'h' => number.checked_mul(60 * 60)?,
+            _ => return None,
+        };
+
+        match seconds > 0 && seconds <= Self::MAX_DELAY.as_secs() {
+            true => Some(Duration::from_secs(seconds)),
+            false => None,
+        }
+    }
+}
+
+impl ServerPlugin for RemindPlugin {
+    fn name(&self) -> &str {
+        "remind"
+    }
+
+    fn on_command(&self, command: &str, args: &str, context: &mut PluginContext) -> bool {
+        if command != "remind" {
+            return false;
+        }
+
+        let parsed = args
+            .split_once(char::is_whitespace)
+            .and_then(|(delay, text)| Some((Self::parse_delay(delay)?, text.trim())));
+
+        match parsed {
+            Some((delay, text)) if !text.is_empty() => {
+                if !self.add_pending(context.author().id, delay) {
+                    context.reply(&format!(
+                        "You have {} pending reminders, wait until some of them is sent.",
+                        Self::MAX_PENDING
+                    ));
+
+                    return true;
+                }
+
+                context.reply(&format!(
+                    "I will remind you in {} seconds.",
+                    delay.as_secs()
+                ));
+                context.reply_later(&format!("Reminder: {}", text), delay);
+            }
+            _ => context.reply(
+                "Usage: /remind <delay> <text>, delay is for example 30s, 10m or 2h (at most 24h).",
+            ),
+        }
+
+        true
+    }
+}
+
+/// `/faq [<topic>]` answers to author, without topic it lists known topics.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use libs::message::{Message, MessageType};
+/// use server::plugins::{FaqPlugin, Plugins};
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(FaqPlugin {
+///     answers: BTreeMap::from([
+///         ("rules".to_string(), "Be nice.".to_string()),
+///         ("help".to_string(), "Type .help".to_string()),
+///     ]),
+/// });
+///
+/// let (_, replies) = plugins.process(Message::from(MessageType::Text("/faq Rules".to_string())));
+/// assert_eq!(replies[0].message.message, MessageType::Text("Be nice.".to_string()));
+///
+/// let (_, replies) = plugins.process(Message::from(MessageType::Text("/faq".to_string())));
+/// assert_eq!(replies[0].message.message, MessageType::Text("Topics: help, rules".to_string()));
+/// ```
+pub struct FaqPlugin {
+    /// Answers by topic
+    pub answers: BTreeMap<String, String>,
+}
+
+impl ServerPlugin for FaqPlugin {
+    fn name(&self) -> &str {
+        "faq"
+    }
+
+    fn on_command(&self, command: &str, args: &str, context: &mut PluginContext) -> bool {
+        if command != "faq" {
+            return false;
+        }
+
+        let topics = self
+            .answers
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let answer = self
+            .answers
+            .iter()
+            .find(|(topic, _)| topic.eq_ignore_ascii_case(args));
+
+        match answer {
+            Some((_, answer)) => context.reply(answer),
+            None if args.is_empty() => context.reply(&format!("Topics: {}", topics)),
+            None => context.reply(&format!("Unknown topic, topics: {}", topics)),
+        }
+
+        true
+    }
+}
+
+/// Sends welcome text to user after login or registration, `{username}` in text is replaced by
+/// username.
+///
+/// # Example
+///
+/// ```
+/// use libs::message::{MessageType, UserInfo};
+/// use server::plugins::{bot_user_info, Plugins, WelcomePlugin};
+///
+/// let mut plugins = Plugins::default();
+/// plugins.register(WelcomePlugin { text: "Hi {username}!".to_string() });
+///
+/// let alice = UserInfo { id: 1, username: "alice".to_string(), color: (255, 0, 0) };
+/// let replies = plugins.connected(&alice);
+///
+/// assert_eq!(replies[0].message.message, MessageType::Text("Hi alice!".to_string()));
+/// assert_eq!(replies[0].message.user_info, bot_user_info("welcome"));
+/// ```
+pub struct WelcomePlugin {
+    pub text: String,
+}
+
+impl ServerPlugin for WelcomePlugin {
+    fn name(&self) -> &str {
+        "welcome"
+    }
+
+    fn on_connect(&self, context: &mut PluginContext) {
+        let text = self.text.replace("{username}", &context.author().username);
+
+        context.reply(&text);
+    }
+}
@@ -0,0 +1,338 @@
+//! Retention of messages.
+//!
+//! Background task periodically deletes messages older than `retention.max_age_secs` and oldest
+//! messages over `retention.max_messages`, deliveries of deleted messages are deleted with them.
+//! When `retention.archive_path` is set, expired messages are appended to it as JSON lines and
+//! they are deleted only after the archive is written, so failed write is retried at next purge.
+//!
+//! Per-room limits, exemption of pinned messages and cleanup of attachments are deliberately not
+//! implemented: chat has only one room and messages can't be pinned or have attachments (files
+//! and images are never stored), so limits are global and apply to every message.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use thiserror::Error;
+use tokio::{select, sync::broadcast::Sender};
+use tracing::{debug, error, info};
+
+use database::{
+    models::Message,
+    store::{ChatStore, MessageFilter, StoreError},
+};
+
+use crate::config::RetentionConfig;
+
+/// Error of purge of expired messages, nothing is deleted when it occurs.
+#[derive(Error, Debug)]
+pub enum PurgeError {
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("archive error: {0}")]
+    Archive(#[from] io::Error),
+}
+
+/// Line of archive file.
+#[derive(Serialize)]
+struct ArchivedMessage<'a> {
+    id: i32,
+    user_id: Option<i32>,
+    text: &'a str,
+    /// Unix timestamp in seconds
+    created_at: u64,
+    deleted_at: u64,
+}
+
+/// Returns messages that are expired at `now` according to configuration in order in which they
+/// were sent, messages are not deleted. Nothing is expired when no limit is set.
+///
+/// # Arguments
+///
+/// * `store` - store of messages
+/// * `config` - retention limits
+/// * `now` - time that age of messages is measured to
+///
+/// # Errors
+///
+/// Returns error when messages can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use server::{config::RetentionConfig, retention::expired_messages};
+///
+/// let store = MemoryStore::default();
+/// let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// let now = SystemTime::now();
+///
+/// store.import_message(Some(alice.id), "old", now - Duration::from_secs(3600)).unwrap();
+/// for text in ["first", "second", "third"] {
+///     store.insert_message(alice.id, text).unwrap();
+/// }
+///
+/// let config = RetentionConfig {
+///     max_age_secs: Some(60),
+///     max_messages: Some(2),
+///     ..RetentionConfig::default()
+/// };
+///
+/// let expired = expired_messages(&store, &config, now).unwrap();
+///
+/// let texts: Vec<&str> = expired.iter().map(|message| message.text.as_str()).collect();
+/// assert_eq!(texts, vec!["old", "first"]);
+/// assert_eq!(store.recent_messages(10).unwrap().len(), 4);
+///
+/// // nothing is expired without limits
+/// assert!(expired_messages(&store, &RetentionConfig::default(), now)
+///     .unwrap()
+///     .is_empty());
+/// ```
+pub fn expired_messages(
+    store: &dyn ChatStore,
+    config: &RetentionConfig,
+    now: SystemTime,
+) -> Result<Vec<Message>, StoreError> {
+    let mut expired = vec![];
+
+    if let Some(max_age_secs) = config.max_age_secs {
+        let before = now
+            .checked_sub(Duration::from_secs(max_age_secs))
+            .unwrap_or(UNIX_EPOCH);
+
+        expired.extend(store.messages_matching(&MessageFilter {
+            until: Some(before),
+            ..MessageFilter::default()
+        })?);
+    }
+
+    if let Some(max_messages) = config.max_messages {
+        expired.extend(store.messages_except_newest(max_messages)?);
+    }
+
+    // message can be expired by both limits
+    expired.sort_by_key(|message| (message.created_at, message.id));
+    expired.dedup_by_key(|message| message.id);
+
+    Ok(expired)
+}
+
+/// Deletes messages that are expired at `now` (see `expired_messages`), returns deleted messages.
+/// When `config.archive_path` is set, messages are appended to archive first and they are deleted
+/// only when archive is written.
+///
+/// # Arguments
+///
+/// * `store` - store of messages
+/// * `config` - retention configuration
+/// * `now` - time that age of messages is measured to and time of deletion in archive
+///
+/// # Errors
+///
+/// Returns error when messages can't be read or deleted from `store` or when archive can't be
+/// written, expired messages are kept in `store` in the latter case.
+///
+/// # Example
+///
+/// ```
+/// use std::time::SystemTime;
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use server::{config::RetentionConfig, retention::purge_expired_messages};
+///
+/// let store = MemoryStore::default();
+/// let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// for text in ["first", "second", "third"] {
+///     store.insert_message(alice.id, text).unwrap();
+/// }
+///
+/// let config = RetentionConfig {
+///     max_messages: Some(1),
+///     ..RetentionConfig::default()
+/// };
+///
+/// let deleted = purge_expired_messages(&store, &config, SystemTime::now()).unwrap();
+///
+/// assert_eq!(deleted.len(), 2);
+/// assert_eq!(store.recent_messages(10).unwrap()[0].text, "third");
+/// ```
+pub fn purge_expired_messages(
+    store: &dyn ChatStore,
+    config: &RetentionConfig,
+    now: SystemTime,
+) -> Result<Vec<Message>, PurgeError> {
+    let expired = expired_messages(store, config, now)?;
+
+    if expired.is_empty() {
+        return Ok(expired);
+    }
+
+    if let Some(path) = &config.archive_path {
+        append_to_archive(path, &expired, now)?;
+    }
+
+    // only archived messages are deleted, messages that expired meanwhile wait for next purge
+    let ids: Vec<i32> = expired.iter().map(|message| message.id).collect();
+
+    store.delete_messages(&ids)?;
+
+    Ok(expired)
+}
+
+/// Appends messages to archive file as JSON lines, file is created when it does not exist.
+///
+/// # Arguments
+///
+/// * `path` - path to archive file
+/// * `messages` - deleted messages
+/// * `deleted_at` - time when messages were deleted
+///
+/// # Errors
+///
+/// Returns error when archive file can't be opened or written.
+///
+/// # Example
+///
+/// ```
+/// use std::{fs, time::SystemTime};
+/// use database::store::{memory::MemoryStore, ChatStore};
+/// use server::retention::append_to_archive;
+///
+/// let store = MemoryStore::default();
+/// let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// let message = store.insert_message(alice.id, "Hello").unwrap();
+/// let line_start = format!("{{\"id\":{},\"user_id\":{},\"text\":\"Hello\"", message.id, alice.id);
+///
+/// let path = std::env::temp_dir().join(format!("archive-{}.jsonl", std::process::id()));
+///
+/// append_to_archive(&path, &[message.clone()], SystemTime::now()).unwrap();
+/// append_to_archive(&path, &[message], SystemTime::now()).unwrap();
+///
+/// let archive = fs::read_to_string(&path).unwrap();
+/// fs::remove_file(&path).unwrap();
+///
+/// assert_eq!(archive.lines().count(), 2);
+/// assert!(archive.starts_with(&line_start));
+/// ```
+pub fn append_to_archive(
+    path: &Path,
+    messages: &[Message],
+    deleted_at: SystemTime,
+) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+
+    for message in messages {
+        let line = ArchivedMessage {
+            id: message.id,
+            user_id: message.user_id,
+            text: &message.text,
+            created_at: unix_timestamp(message.created_at),
+            deleted_at: unix_timestamp(deleted_at),
+        };
+
+        serde_json::to_writer(&mut writer, &line)?;
+        writeln!(writer)?;
+    }
+
+    writer.flush()
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Periodically deletes expired messages (see `purge_expired_messages`) and logs what was removed,
+/// messages that could not be archived are kept and purge is retried at next tick. It returns
+/// immediately when no limit is set.
+///
+/// # Arguments
+///
+/// * `store` - store of messages
+/// * `config` - retention configuration
+/// * `tx` - Sender side of broadcast channel, it is used for creating receiver that receives
+///   signal that server should stop
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::{config::RetentionConfig, retention::purge_messages};
+/// use tokio::sync::broadcast;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // broadcast channel for notifying tasks that they should stop
+///     let (mut tx, _) = broadcast::channel(8);
+///
+///     let config = RetentionConfig {
+///         max_messages: Some(1000),
+///         ..RetentionConfig::default()
+///     };
+///
+///     purge_messages(Arc::new(MemoryStore::default()), config, &mut tx).await;
+/// }
+/// ```
+pub async fn purge_messages(
+    store: Arc<dyn ChatStore>,
+    config: RetentionConfig,
+    tx: &mut Sender<bool>,
+) {
+    if !config.is_enabled() {
+        return;
+    }
+
+    let mut rx = tx.subscribe();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+
+    loop {
+        select! {
+            // check broadcast channel signaling termination
+            Ok(_) = rx.recv() => {
+                break;
+            }
+            _ = interval.tick() => {
+                let deleted = match purge_expired_messages(store.as_ref(), &config, SystemTime::now()) {
+                    Ok(deleted) => deleted,
+                    Err(PurgeError::Archive(e)) => {
+                        error!(event = "error", error = %e, "Could not archive expired messages, they are kept until next purge.");
+
+                        continue;
+                    }
+                    Err(e) => {
+                        error!(event = "error", error = %e, "Could not delete expired messages.");
+
+                        continue;
+                    }
+                };
+
+                let (Some(oldest), Some(newest)) = (deleted.first(), deleted.last()) else {
+                    continue;
+                };
+
+                for message in &deleted {
+                    debug!(event = "retention", message_id = message.id, user_id = message.user_id, "Expired message deleted.");
+                }
+
+                info!(
+                    event = "retention",
+                    action = "purge_messages",
+                    count = deleted.len(),
+                    oldest_id = oldest.id,
+                    newest_id = newest.id,
+                    archived = config.archive_path.is_some(),
+                    "Expired messages deleted."
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Bans and mutes of users and IP addresses.
+//!
+//! Banned user or IP address can not log in or register, muted one can not send texts, files or
+//! images. Sanctions are checked at every login and message, so sanction is lifted as soon as it
+//! expires, expired sanctions are deleted from store later by `purge_expired`.
+
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use database::{
+    models::{Sanction, SanctionKind, SanctionNew},
+    store::{ChatStore, StoreError},
+};
+
+use crate::{
+    cluster::{self, Cluster, Target},
+    hub::Hub,
+};
+
+/// Longest sanction that expires (100 years), longer one has to be permanent.
+pub const MAX_SANCTION_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Formats duration for users by two largest units, for example `1d 2h`, `5m` or `30s`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use server::sanctions::format_duration;
+///
+/// assert_eq!(format_duration(Duration::from_secs(30)), "30s");
+/// assert_eq!(format_duration(Duration::from_secs(90 * 60)), "1h 30m");
+/// assert_eq!(format_duration(Duration::from_secs(2 * 86400 + 3601)), "2d 1h");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+
+    let formatted: Vec<String> = parts
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+
+    match formatted.is_empty() {
+        true => "0s".to_string(),
+        false => formatted.join(" "),
+    }
+}
+
+/// Describes sanction for user who is affected by it, with reason and time until it expires.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use database::models::Sanction;
+/// use server::sanctions::describe;
+///
+/// let now = SystemTime::now();
+/// let mut sanction = Sanction {
+///     id: 1,
+///     kind: "mute".to_string(),
+///     user_id: Some(2),
+///     ip: None,
+///     reason: "spam".to_string(),
+///     created_by: None,
+///     created_at: now,
+///     expires_at: Some(now + Duration::from_secs(600)),
+/// };
+///
+/// assert_eq!(describe(&sanction, now), "You are muted: spam (expires in 10m).");
+///
+/// sanction.kind = "ban".to_string();
+/// sanction.expires_at = None;
+///
+/// assert_eq!(describe(&sanction, now), "You are banned: spam (permanent).");
+/// ```
+pub fn describe(sanction: &Sanction, now: SystemTime) -> String {
+    let kind = match sanction.kind() {
+        SanctionKind::Ban => "banned",
+        SanctionKind::Mute => "muted",
+    };
+
+    let reason = match sanction.reason.is_empty() {
+        true => "no reason given",
+        false => sanction.reason.as_str(),
+    };
+
+    let expiry = match sanction.expires_at {
+        Some(expires_at) => format!(
+            "expires in {}",
+            format_duration(expires_at.duration_since(now).unwrap_or_default())
+        ),
+        None => "permanent".to_string(),
+    };
+
+    format!("You are {}: {} ({}).", kind, reason, expiry)
+}
+
+/// Returns description of active sanction of `kind` for user or his IP address, `None` when
+/// there is none. User id 0 (not logged in) is not checked, only IP address.
+///
+/// # Errors
+///
+/// Returns error when sanctions can't be read from `store`.
+///
+/// # Example
+///
+/// ```
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use database::{models::SanctionKind, store::{memory::MemoryStore, ChatStore}};
+/// use server::sanctions::{check, create};
+///
+/// let store = MemoryStore::default();
+/// let user = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+/// let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+///
+/// assert!(check(&store, SanctionKind::Mute, user.id, ip).unwrap().is_none());
+///
+/// create(&store, SanctionKind::Mute, Some(user.id), None, None, "spam", None).unwrap();
+///
+/// assert!(check(&store, SanctionKind::Mute, user.id, ip).unwrap().is_some());
+/// assert!(check(&store, SanctionKind::Mute, 0, ip).unwrap().is_none());
+/// assert!(check(&store, SanctionKind::Ban, user.id, ip).unwrap().is_none());
+/// ```
+pub fn check(
+    store: &dyn ChatStore,
+    kind: SanctionKind,
+    user_id: i32,
+    ip: IpAddr,
+) -> Result<Option<String>, StoreError> {
+    let user_id = Some(user_id).filter(|user_id| *user_id != 0);
+    let ip = ip.to_string();
+
+    Ok(store
+        .active_sanction(kind, user_id, Some(&ip))?
+        .map(|sanction| describe(&sanction, SystemTime::now())))
+}
+
+/// Creates sanction of user or IP address that expires after `duration` (`None` is permanent).
+///
+/// # Arguments
+///
+/// * `store` - Storage of users and sanctions
+/// * `kind` - Ban or mute
+/// * `user_id` - Sanctioned user
+/// * `ip` - Sanctioned IP address
+/// * `duration` - Time after which sanction expires, `None` for permanent sanction
+/// * `reason` - Reason shown to sanctioned user
+/// * `created_by` - Moderator who created sanction, `None` for server administrator
+///
+/// # Errors
+///
+/// Returns `StoreError::Invalid` when neither user nor IP address is given or when `duration` is
+/// longer than `MAX_SANCTION_DURATION` and `StoreError::NotFound` when user does not exist.
+pub fn create(
+    store: &dyn ChatStore,
+    kind: SanctionKind,
+    user_id: Option<i32>,
+    ip: Option<IpAddr>,
+    duration: Option<Duration>,
+    reason: &str,
+    created_by: Option<i32>,
+) -> Result<Sanction, StoreError> {
+    if user_id.is_none() && ip.is_none() {
+        return Err(StoreError::Invalid(
+            "Sanction has to target user or IP address".to_string(),
+        ));
+    }
+
+    let expires_at = match duration {
+        Some(duration) => match SystemTime::now().checked_add(duration) {
+            Some(expires_at) if duration <= MAX_SANCTION_DURATION => Some(expires_at),
+            _ => {
+                return Err(StoreError::Invalid(format!(
+                    "Sanction can last at most {} days, longer one has to be permanent",
+                    MAX_SANCTION_DURATION.as_secs() / (24 * 60 * 60)
+                )))
+            }
+        },
+        None => None,
+    };
+
+    store.insert_sanction(SanctionNew {
+        kind: kind.as_str().to_string(),
+        user_id,
+        ip: ip.map(|ip| ip.to_string()),
+        reason: reason.to_string(),
+        created_by,
+        expires_at,
+    })
+}
+
+/// Applies new sanction to connected clients of all instances, banned clients are kicked and
+/// muted ones are told why. Returns number of affected connections.
+pub(crate) async fn enforce(hub: &Hub, cluster: &Cluster, sanction: &Sanction) -> usize {
+    let reason = describe(sanction, SystemTime::now());
+    let target = Target {
+        user_id: sanction.user_id,
+        session_id: None,
+        ip: sanction.ip.as_deref().and_then(|ip| ip.parse().ok()),
+    };
+
+    match sanction.kind() {
+        SanctionKind::Ban => cluster::kick(hub, cluster, target, &reason).await,
+        SanctionKind::Mute => cluster::notice(hub, cluster, target, &reason).await,
+    }
+}
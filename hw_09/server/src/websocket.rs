@@ -0,0 +1,170 @@
+//! Provides WebSocket gateway, so browser clients can join the chat.
+//!
+//! Every WebSocket text frame contains one JSON encoded `Message`, the first one is `ClientHello`
+//! like on tcp. Frames are converted to messages and handled by the same `handle_connected_client` as tcp clients, so browser and tcp
+//! users share one `hub` and can talk to each other.
+
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        ws::{Message as WebSocketMessage, WebSocket},
+        ConnectInfo, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::{net::TcpListener, sync::mpsc};
+use tracing::{error, info, Instrument};
+
+use libs::builder::MessageReceiverSenderBuilder;
+
+use crate::{handle_connected_client, logging::connection_span, Client, ServerContext};
+
+/// Capacity of channels between WebSocket connection and `handle_connected_client`.
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Handles connection of new WebSocket clients.
+///
+/// Accepts WebSocket connections on path `/`. For every connection registers new client in `hub`
+/// of `context` and handles it by `handle_connected_client`. Function ends when signal from
+/// termination channel is received.
+///
+/// # Arguments
+///
+/// * `listener` - TcpListener for HTTP connections that are upgraded to WebSocket
+/// * `context` - Structures shared by all connections, the same as tcp clients have
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use database::store::memory::MemoryStore;
+/// use server::cluster::Cluster;
+/// use server::config::Config;
+/// use server::hub::Hub;
+/// use server::lockout::LoginGuard;
+/// use server::metrics::Metrics;
+/// use server::plugins::Plugins;
+/// use server::ServerContext;
+/// use tokio::sync::{broadcast, mpsc};
+/// use server::websocket::handle_new_websocket_clients;
+/// use tokio::net::TcpListener;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let listener = TcpListener::bind(("localhost", 11112)).await.unwrap();
+///
+///     let config = Arc::new(Config::default());
+///
+///     // broadcast channel for notifying tasks that they should stop
+///     let (shutdown, _) = broadcast::channel(8);
+///
+///     // multiple produces and single consumer channel for sending messages to database handler
+///     let (msg_db_tx, _) = mpsc::channel(64);
+///
+///     let context = ServerContext {
+///         hub: Hub::start(),
+///         shutdown,
+///         msg_db_tx,
+///         metrics: Arc::new(Metrics::default()),
+///         login_guard: Arc::new(LoginGuard::new(config.auth.clone())),
+///         plugins: Arc::new(Plugins::from_config(&config.plugins)),
+///         store: Arc::new(MemoryStore::default()),
+///         cluster: Arc::new(Cluster::standalone()),
+///         config,
+///     };
+///
+///     handle_new_websocket_clients(listener, context).await;
+/// }
+/// ```
+pub async fn handle_new_websocket_clients(listener: TcpListener, context: ServerContext) {
+    let mut rx = context.shutdown.subscribe();
+
+    let router = Router::new()
+        .route("/", get(upgrade_connection))
+        .with_state(context);
+
+    info!("Accepting WebSocket clients.");
+
+    if let Err(e) = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        let _ = rx.recv().await;
+    })
+    .await
+    {
+        error!(event = "error", error = %e, "Could not accept WebSocket clients.");
+    }
+}
+
+async fn upgrade_connection(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(context): State<ServerContext>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| {
+        handle_websocket(socket, addr, context).instrument(connection_span(addr, "websocket"))
+    })
+}
+
+/// Bridges WebSocket connection to channels of `MessageReceiver` and `MessageSender`.
+async fn handle_websocket(socket: WebSocket, addr: SocketAddr, context: ServerContext) {
+    let (mut ws_sink, mut ws_stream) = socket.split();
+
+    let (incoming_tx, incoming_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+
+    let receiver_sender_builder =
+        MessageReceiverSenderBuilder::from_json_channels(incoming_rx, outgoing_tx);
+    let mut message_receiver = receiver_sender_builder.message_receiver();
+    let message_sender = receiver_sender_builder.message_sender();
+
+    // registering new client in hub
+    {
+        let client = Client::new(
+            message_sender,
+            &context.config.limits,
+            context.metrics.clone(),
+        );
+
+        info!(event = "connection", "WebSocket client connected.");
+
+        context.metrics.client_connected();
+
+        context.hub.register(addr, client);
+    }
+
+    // task for forwarding messages from server to WebSocket
+    tokio::spawn(async move {
+        while let Some(json) = outgoing_rx.recv().await {
+            if ws_sink.send(WebSocketMessage::Text(json)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // task for forwarding frames from WebSocket to server, when it ends `incoming_tx` is dropped
+    // and `handle_connected_client` handles it as closed connection
+    tokio::spawn(async move {
+        while let Some(Ok(frame)) = ws_stream.next().await {
+            let json = match frame {
+                WebSocketMessage::Text(json) => json,
+                WebSocketMessage::Close(_) => break,
+                _ => continue,
+            };
+
+            if incoming_tx.send(json).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut rx = context.shutdown.subscribe();
+
+    handle_connected_client(addr, &mut message_receiver, &mut rx, context).await;
+}
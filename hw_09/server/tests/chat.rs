@@ -0,0 +1,1883 @@
+//! End-to-end tests of server, scripted clients communicate with server over tcp.
+
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use database::{
+    models::{SanctionKind, SanctionNew, DELETED_USERNAME},
+    store::ChatStore,
+};
+use libs::{
+    e2e::KeyPair,
+    message::{EncryptedMessage, Message, MessageType, UserInfo, UserStatus, PROTOCOL_VERSION},
+    role::Role,
+    token::hash_token,
+};
+use server::{
+    config::{Config, SlowClientPolicy},
+    moderation::Moderation,
+    plugins::{bot_user_info, PluginContext, Plugins, RemindPlugin, RollPlugin, ServerPlugin},
+    sanctions, INVALID_LOGIN, INVALID_SESSION,
+};
+
+use common::{anonymous, TestClient, TestServer, TEST_ADMIN_TOKEN, TEST_PASSWORD};
+
+#[tokio::test]
+async fn register_creates_user_and_notifies_others() {
+    let server = TestServer::start().await;
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&bob]).await;
+
+    let mut alice_client = server.connect().await;
+
+    alice_client
+        .send(MessageType::RegisterRequest(
+            "alice".to_string(),
+            "secret password".to_string(),
+            1,
+            2,
+            3,
+        ))
+        .await;
+
+    let message = alice_client.receive().await;
+
+    let alice = UserInfo {
+        id: server.store.user_by_username("alice").unwrap().id,
+        username: "alice".to_string(),
+        color: (1, 2, 3),
+    };
+
+    let MessageType::RegisterResponse(Ok((registered, token))) = message.message else {
+        panic!("unexpected register response: {:?}", message.message);
+    };
+
+    assert_eq!(registered, alice);
+    assert_eq!(message.user_info, alice);
+
+    // registered user is logged in with new session
+    let (user, _) = server.store.login_with_token(&token).unwrap().unwrap();
+
+    assert_eq!(user.id, alice.id);
+
+    clients[0].expect(MessageType::UserConnect(), &alice).await;
+
+    assert!(server
+        .store
+        .login("alice", "secret password")
+        .unwrap()
+        .is_some());
+
+    alice_client.expect_nothing().await;
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn register_failure_has_reason() {
+    let server = TestServer::start().await;
+    server.add_user("alice", (255, 0, 0));
+
+    let mut client = server.connect().await;
+
+    for (username, password, reason) in [
+        ("ALICE", TEST_PASSWORD, "Username is already taken."),
+        ("bob", "short", "Password must have at least 8 characters."),
+        (
+            "bob smith",
+            TEST_PASSWORD,
+            "Username must not contain spaces.",
+        ),
+    ] {
+        client
+            .send(MessageType::RegisterRequest(
+                username.to_string(),
+                password.to_string(),
+                0,
+                0,
+                0,
+            ))
+            .await;
+
+        client
+            .expect(
+                MessageType::RegisterResponse(Err(reason.to_string())),
+                &anonymous(),
+            )
+            .await;
+    }
+
+    assert_eq!(server.store.users().unwrap().len(), 1);
+
+    client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_without_supported_protocol_version_are_refused() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+
+    let refusal = MessageType::UnrecoverableError(format!(
+        "Unsupported version of client, server requires protocol version {}.",
+        PROTOCOL_VERSION
+    ));
+
+    // client older than `ClientHello` starts with login
+    let mut old_client = TestClient::connect_without_hello(server.addr).await;
+    old_client
+        .send(MessageType::LoginRequest(
+            "alice".to_string(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+    old_client
+        .expect(refusal.clone(), &UserInfo::default())
+        .await;
+
+    let mut other_client = TestClient::connect_without_hello(server.addr).await;
+    other_client
+        .send(MessageType::ClientHello(PROTOCOL_VERSION + 1))
+        .await;
+    other_client
+        .expect(refusal.clone(), &UserInfo::default())
+        .await;
+
+    server.wait_for_clients(0).await;
+    assert!(server.store.sessions_of_user(alice.id).unwrap().is_empty());
+
+    // hello is accepted only as first message
+    let mut clients = server.login_all(&[&alice]).await;
+
+    clients[0]
+        .send(MessageType::ClientHello(PROTOCOL_VERSION))
+        .await;
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn login_accepts_only_correct_credentials() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&bob]).await;
+
+    let disabled = server.add_user("carol", (0, 255, 0));
+    server.store.set_user_disabled(disabled.id, true).unwrap();
+
+    let mut client = server.connect().await;
+
+    for (username, password) in [
+        ("nobody", TEST_PASSWORD),
+        ("alice", "wrong"),
+        ("carol", TEST_PASSWORD),
+    ] {
+        client
+            .send(MessageType::LoginRequest(
+                username.to_string(),
+                password.to_string(),
+            ))
+            .await;
+
+        client
+            .expect(
+                MessageType::LoginResponse(Err(INVALID_LOGIN.to_string())),
+                &anonymous(),
+            )
+            .await;
+    }
+
+    client.login(&alice).await;
+    clients[0].expect(MessageType::UserConnect(), &alice).await;
+
+    // legacy PBKDF2 hash was upgraded after successful login only
+    let password_hash = |user: &UserInfo| server.store.user_by_id(user.id).unwrap().password;
+
+    assert!(password_hash(&alice).starts_with("$argon2id$"));
+    assert!(password_hash(&disabled).starts_with("$pbkdf2-sha256$"));
+
+    client.expect_nothing().await;
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn repeated_failed_logins_lock_username() {
+    let config = Config::from_toml_str(
+        r#"
+        [auth]
+        max_failures_per_user = 2
+        lockout_secs = 300
+        base_delay_ms = 10
+        max_delay_ms = 10
+        "#,
+    )
+    .unwrap();
+
+    let server = TestServer::start_with_config(config, Plugins::default()).await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+
+    let mut client = server.connect().await;
+
+    for _ in 0..2 {
+        client
+            .send(MessageType::LoginRequest(
+                "alice".to_string(),
+                "wrong".to_string(),
+            ))
+            .await;
+        client
+            .expect(
+                MessageType::LoginResponse(Err(INVALID_LOGIN.to_string())),
+                &anonymous(),
+            )
+            .await;
+    }
+
+    // even correct password is not accepted
+    client
+        .send(MessageType::LoginRequest(
+            "Alice".to_string(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+    client
+        .expect(
+            MessageType::LoginResponse(Err(
+                "Too many failed login attempts, try again in 300 seconds.".to_string(),
+            )),
+            &anonymous(),
+        )
+        .await;
+
+    // other users are not affected
+    client.login(&bob).await;
+
+    assert!(server.store.user_by_id(alice.id).is_ok());
+
+    client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn text_messages_are_relayed_and_stored() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    clients[0]
+        .send(MessageType::Text("Hello".to_string()))
+        .await;
+
+    clients[1]
+        .expect(MessageType::Text("Hello".to_string()), &alice)
+        .await;
+    clients[2]
+        .expect(MessageType::Text("Hello".to_string()), &alice)
+        .await;
+
+    clients[1].send(MessageType::Text("Hi".to_string())).await;
+
+    clients[0]
+        .expect(MessageType::Text("Hi".to_string()), &bob)
+        .await;
+    clients[2]
+        .expect(MessageType::Text("Hi".to_string()), &bob)
+        .await;
+
+    server.wait_for_stored_messages(2).await;
+
+    clients[2].send(MessageType::OldMessagesRequest()).await;
+
+    clients[2]
+        .expect(
+            MessageType::OldMessagesResponse(vec![
+                ("Hello".to_string(), alice.clone()),
+                ("Hi".to_string(), bob.clone()),
+            ]),
+            &carol,
+        )
+        .await;
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn files_and_images_are_relayed_but_not_stored() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    // bigger than one tcp segment
+    let content: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+    clients[0]
+        .send(MessageType::File("notes.txt".to_string(), content.clone()))
+        .await;
+    clients[0].send(MessageType::Image(content.clone())).await;
+    clients[0].send(MessageType::Text("Sent".to_string())).await;
+
+    clients[1]
+        .expect(
+            MessageType::File("notes.txt".to_string(), content.clone()),
+            &alice,
+        )
+        .await;
+    clients[1].expect(MessageType::Image(content), &alice).await;
+    clients[1]
+        .expect(MessageType::Text("Sent".to_string()), &alice)
+        .await;
+
+    // messages are stored in order, so file and image would be stored before the text
+    server.wait_for_stored_messages(1).await;
+
+    let texts: Vec<String> = server
+        .store
+        .recent_messages(10)
+        .unwrap()
+        .into_iter()
+        .map(|message| message.text)
+        .collect();
+
+    assert_eq!(texts, vec!["Sent"]);
+
+    clients[0].expect_nothing().await;
+    clients[1].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn typing_is_relayed_only_for_logged_in_users_and_throttled() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+    let mut guest = server.connect().await;
+
+    // author is taken from connection, not from message
+    clients[0]
+        .send_message(&Message {
+            user_info: bob.clone(),
+            ..Message::from(MessageType::UserTyping(true))
+        })
+        .await;
+
+    clients[1]
+        .expect(MessageType::UserTyping(true), &alice)
+        .await;
+    guest.expect(MessageType::UserTyping(true), &alice).await;
+
+    // repeated notification is throttled, stop is relayed
+    clients[0].send(MessageType::UserTyping(true)).await;
+    clients[0].send(MessageType::UserTyping(false)).await;
+    clients[0].send(MessageType::UserTyping(false)).await;
+
+    clients[1]
+        .expect(MessageType::UserTyping(false), &alice)
+        .await;
+    guest.expect(MessageType::UserTyping(false), &alice).await;
+
+    // client that is not logged in can not type
+    guest.send(MessageType::UserTyping(true)).await;
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+    guest.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn disconnected_clients_are_removed() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    // bob says goodbye
+    clients[1].send(MessageType::UserDisconnect()).await;
+
+    clients[0].expect(MessageType::UserDisconnect(), &bob).await;
+    clients[2].expect(MessageType::UserDisconnect(), &bob).await;
+
+    // carol only closes connection
+    drop(clients.pop());
+
+    server.wait_for_clients(1).await;
+
+    clients[0].send(MessageType::PresenceRequest()).await;
+
+    let message = clients[0].receive().await;
+
+    assert_eq!(message.user_info, alice);
+
+    match message.message {
+        MessageType::PresenceResponse(presence) => {
+            let users: Vec<UserInfo> = presence.into_iter().map(|p| p.user_info).collect();
+
+            assert_eq!(users, vec![alice]);
+        }
+        message_type => panic!("unexpected message received: {:?}", message_type),
+    }
+
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn profile_changes_are_used_by_relays_presence_and_store() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    let renamed = UserInfo {
+        username: "alicia".to_string(),
+        ..alice.clone()
+    };
+    let recolored = UserInfo {
+        color: (0, 255, 0),
+        ..renamed.clone()
+    };
+
+    // others get previous value from author who has already changed
+    clients[0]
+        .send(MessageType::UserNameChange("alicia".to_string()))
+        .await;
+    clients[1]
+        .expect(MessageType::UserNameChange("alice".to_string()), &renamed)
+        .await;
+
+    clients[0]
+        .send(MessageType::UserColorChange(0, 255, 0))
+        .await;
+    clients[1]
+        .expect(MessageType::UserColorChange(255, 0, 0), &recolored)
+        .await;
+
+    clients[0]
+        .send(MessageType::UserStatusChange(UserStatus::Busy))
+        .await;
+    clients[1]
+        .expect(MessageType::UserStatusChange(UserStatus::Busy), &recolored)
+        .await;
+
+    clients[0]
+        .send(MessageType::Text("Hello".to_string()))
+        .await;
+    clients[1]
+        .expect(MessageType::Text("Hello".to_string()), &recolored)
+        .await;
+
+    server.wait_for_stored_messages(1).await;
+
+    let stored = server.store.recent_messages(10).unwrap();
+
+    assert_eq!(stored[0].user_id, Some(alice.id));
+
+    clients[1].send(MessageType::PresenceRequest()).await;
+
+    let message = clients[1].receive().await;
+
+    match message.message {
+        MessageType::PresenceResponse(presence) => {
+            let alice_presence = presence
+                .into_iter()
+                .find(|p| p.user_info.id == alice.id)
+                .unwrap();
+
+            assert_eq!(alice_presence.user_info, recolored);
+            assert_eq!(alice_presence.status, UserStatus::Busy);
+        }
+        message_type => panic!("unexpected message received: {:?}", message_type),
+    }
+
+    clients[0].expect_nothing().await;
+    clients[1].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn websocket_clients_talk_with_tcp_clients() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&bob]).await;
+
+    let mut browser = server.connect_websocket().await;
+
+    browser.login(&alice).await;
+    clients[0].expect(MessageType::UserConnect(), &alice).await;
+
+    // messages are relayed in both directions
+    browser
+        .send(MessageType::Text("Hello from browser".to_string()))
+        .await;
+    clients[0]
+        .expect(MessageType::Text("Hello from browser".to_string()), &alice)
+        .await;
+
+    clients[0]
+        .send(MessageType::Text("Hello from terminal".to_string()))
+        .await;
+    browser
+        .expect(MessageType::Text("Hello from terminal".to_string()), &bob)
+        .await;
+
+    // invalid frame is refused, but following frames are handled
+    browser.send_text("{not json".to_string()).await;
+    browser
+        .expect(
+            MessageType::RecoverableError("Invalid message.".to_string()),
+            &UserInfo::default(),
+        )
+        .await;
+
+    browser
+        .send(MessageType::Text("Still here".to_string()))
+        .await;
+    clients[0]
+        .expect(MessageType::Text("Still here".to_string()), &alice)
+        .await;
+
+    server.wait_for_stored_messages(3).await;
+
+    browser.expect_nothing().await;
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn stopped_server_notifies_clients() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let mut clients = server.login_all(&[&alice]).await;
+    let mut anonymous = server.connect().await;
+
+    server.stop().await;
+
+    for client in clients.iter_mut().chain([&mut anonymous]) {
+        client
+            .expect(
+                MessageType::UnrecoverableError(
+                    "Server is stopped. Try connect later.".to_string(),
+                ),
+                &UserInfo::default(),
+            )
+            .await;
+        client.expect_nothing().await;
+    }
+}
+
+/// Plugin that hides secrets and answers `/ping` only to author.
+struct TestPlugin;
+
+impl ServerPlugin for TestPlugin {
+    fn name(&self) -> &str {
+        "test"
+    }
+
+    fn on_connect(&self, context: &mut PluginContext) {
+        let text = format!("Hello {}", context.author().username);
+
+        context.reply(&text);
+    }
+
+    fn on_message(&self, mut message: Message, _context: &mut PluginContext) -> Option<Message> {
+        match &message.message {
+            MessageType::Text(text) if text.contains("drop") => None,
+            MessageType::Text(text) => {
+                message.message = MessageType::Text(text.replace("secret", "******"));
+                Some(message)
+            }
+            _ => Some(message),
+        }
+    }
+
+    fn on_command(&self, command: &str, _args: &str, context: &mut PluginContext) -> bool {
+        if command != "ping" {
+            return false;
+        }
+
+        context.reply("pong");
+
+        true
+    }
+}
+
+#[tokio::test]
+async fn plugins_process_messages_before_broadcast() {
+    let mut plugins = Plugins::default();
+    plugins
+        .register(TestPlugin)
+        .register(RollPlugin)
+        .register(RemindPlugin::default());
+
+    let server = TestServer::start_with_plugins(plugins).await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let bot = bot_user_info("test");
+
+    let mut alice_client = server.connect().await;
+    alice_client.login(&alice).await;
+    alice_client
+        .expect(MessageType::Text("Hello alice".to_string()), &bot)
+        .await;
+
+    let mut bob_client = server.connect().await;
+    bob_client.login(&bob).await;
+    bob_client
+        .expect(MessageType::Text("Hello bob".to_string()), &bot)
+        .await;
+    alice_client.expect(MessageType::UserConnect(), &bob).await;
+
+    // transformed message
+    alice_client
+        .send(MessageType::Text("my secret".to_string()))
+        .await;
+    bob_client
+        .expect(MessageType::Text("my ******".to_string()), &alice)
+        .await;
+
+    // dropped message
+    alice_client
+        .send(MessageType::Text("drop this".to_string()))
+        .await;
+
+    // command answered only to author
+    alice_client
+        .send(MessageType::Text("/ping".to_string()))
+        .await;
+    alice_client
+        .expect(MessageType::Text("pong".to_string()), &bot)
+        .await;
+
+    // command answered to everyone by second plugin
+    bob_client
+        .send(MessageType::Text("/roll 2d1".to_string()))
+        .await;
+
+    let roll = MessageType::Text("bob rolled 2d1: 1 + 1 = 2".to_string());
+
+    alice_client
+        .expect(roll.clone(), &bot_user_info("roll"))
+        .await;
+    bob_client.expect(roll, &bot_user_info("roll")).await;
+
+    // delayed answer is sent only to author
+    alice_client
+        .send(MessageType::Text("/remind 1s tea".to_string()))
+        .await;
+    alice_client
+        .expect(
+            MessageType::Text("I will remind you in 1 seconds.".to_string()),
+            &bot_user_info("remind"),
+        )
+        .await;
+    alice_client
+        .expect(
+            MessageType::Text("Reminder: tea".to_string()),
+            &bot_user_info("remind"),
+        )
+        .await;
+
+    // unknown command is ordinary text
+    bob_client
+        .send(MessageType::Text("/shrug".to_string()))
+        .await;
+    alice_client
+        .expect(MessageType::Text("/shrug".to_string()), &bob)
+        .await;
+
+    // only messages that were broadcast are stored
+    server.wait_for_stored_messages(2).await;
+
+    let texts: Vec<String> = server
+        .store
+        .recent_messages(10)
+        .unwrap()
+        .into_iter()
+        .map(|message| message.text)
+        .collect();
+
+    assert_eq!(texts, vec!["my ******", "/shrug"]);
+
+    alice_client.expect_nothing().await;
+    bob_client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn moderation_rejects_and_masks_messages() {
+    let config = Config::from_toml_str(
+        r#"
+        [[moderation.rules]]
+        kind = "links"
+        action = "reject"
+
+        [[moderation.rules]]
+        kind = "words"
+        words = ["darn"]
+        action = "mask"
+
+        [[moderation.rules]]
+        kind = "max_length"
+        limit = 20
+        action = "flag"
+        "#,
+    )
+    .unwrap();
+
+    let mut plugins = Plugins::default();
+    plugins.register(Moderation::new(&config.moderation).unwrap());
+
+    let server = TestServer::start_with_plugins(plugins).await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    server
+        .store
+        .set_user_role(carol.id, Role::Moderator)
+        .unwrap();
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    clients[0]
+        .send(MessageType::Text("visit www.example.com".to_string()))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::RecoverableError(
+                "Message was rejected: link that is not allowed.".to_string(),
+            ),
+            &bot_user_info("moderation"),
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::Text("Darn it".to_string()))
+        .await;
+    for client in clients[1..].iter_mut() {
+        client
+            .expect(MessageType::Text("**** it".to_string()), &alice)
+            .await;
+    }
+
+    // flagged message is sent and only moderators are told about it
+    let long_text = "This message is rather long".to_string();
+
+    clients[0].send(MessageType::Text(long_text.clone())).await;
+    clients[2]
+        .expect(
+            MessageType::Text(
+                "Message of alice was flagged: longer than 20 characters.".to_string(),
+            ),
+            &bot_user_info("moderation"),
+        )
+        .await;
+    for client in clients[1..].iter_mut() {
+        client
+            .expect(MessageType::Text(long_text.clone()), &alice)
+            .await;
+    }
+
+    // usernames and statuses are moderated before they are applied
+    clients[0]
+        .send(MessageType::UserNameChange("www.example.com".to_string()))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::RecoverableError(
+                "Username was rejected: link that is not allowed.".to_string(),
+            ),
+            &bot_user_info("moderation"),
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::UserStatusChange(UserStatus::Custom(
+            "darn busy".to_string(),
+        )))
+        .await;
+    for client in clients[1..].iter_mut() {
+        client
+            .expect(
+                MessageType::UserStatusChange(UserStatus::Custom("**** busy".to_string())),
+                &alice,
+            )
+            .await;
+    }
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn password_change_requires_old_password() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let mut clients = server.login_all(&[&alice]).await;
+    let mut anonymous_client = server.connect().await;
+
+    anonymous_client
+        .send(MessageType::PasswordChangeRequest(
+            TEST_PASSWORD.to_string(),
+            "new password".to_string(),
+        ))
+        .await;
+    anonymous_client
+        .expect(
+            MessageType::PasswordChangeResponse(Err("You are not logged in.".to_string())),
+            &anonymous(),
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::PasswordChangeRequest(
+            "wrong password".to_string(),
+            "new password".to_string(),
+        ))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::PasswordChangeResponse(Err("Password is not correct.".to_string())),
+            &alice,
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::PasswordChangeRequest(
+            TEST_PASSWORD.to_string(),
+            "new password".to_string(),
+        ))
+        .await;
+    clients[0]
+        .expect(MessageType::PasswordChangeResponse(Ok(())), &alice)
+        .await;
+
+    assert!(server
+        .store
+        .login("alice", TEST_PASSWORD)
+        .unwrap()
+        .is_none());
+
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn deleted_account_keeps_anonymous_messages() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    // second session of alice
+    let mut alice_phone = server.connect().await;
+    alice_phone.login(&alice).await;
+    clients[0].expect(MessageType::UserConnect(), &alice).await;
+    clients[1].expect(MessageType::UserConnect(), &alice).await;
+
+    clients[0].send(MessageType::Text("Bye".to_string())).await;
+    clients[1]
+        .expect(MessageType::Text("Bye".to_string()), &alice)
+        .await;
+    alice_phone
+        .expect(MessageType::Text("Bye".to_string()), &alice)
+        .await;
+
+    server.wait_for_stored_messages(1).await;
+
+    clients[0]
+        .send(MessageType::AccountDeleteRequest(
+            "wrong password".to_string(),
+        ))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::AccountDeleteResponse(Err("Password is not correct.".to_string())),
+            &alice,
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::AccountDeleteRequest(TEST_PASSWORD.to_string()))
+        .await;
+    clients[0]
+        .expect(MessageType::AccountDeleteResponse(Ok(())), &alice)
+        .await;
+
+    alice_phone
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: Your account was deleted.".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+    clients[1]
+        .expect(MessageType::UserDisconnect(), &alice)
+        .await;
+
+    server.wait_for_clients(1).await;
+
+    assert!(server.store.user_by_username("alice").is_err());
+
+    clients[1].send(MessageType::OldMessagesRequest()).await;
+    clients[1]
+        .expect(
+            MessageType::OldMessagesResponse(vec![(
+                "Bye".to_string(),
+                UserInfo {
+                    id: 0,
+                    username: DELETED_USERNAME.to_string(),
+                    color: (255, 255, 255),
+                },
+            )]),
+            &bob,
+        )
+        .await;
+
+    clients[0].expect_nothing().await;
+    clients[1].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn session_tokens_can_be_listed_revoked_and_logged_out() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+
+    let mut laptop = server.connect().await;
+    let laptop_token = laptop.login(&alice).await;
+
+    let mut phone = server.connect().await;
+    let phone_token = phone.login(&alice).await;
+    laptop.expect(MessageType::UserConnect(), &alice).await;
+
+    // token is used instead of password
+    let mut tablet = server.connect().await;
+    tablet
+        .send(MessageType::TokenLoginRequest(laptop_token.clone()))
+        .await;
+    assert_eq!(tablet.expect_login(&alice).await, laptop_token);
+    laptop.expect(MessageType::UserConnect(), &alice).await;
+    phone.expect(MessageType::UserConnect(), &alice).await;
+
+    let session_id = |token: &str| {
+        server
+            .store
+            .session_by_token_hash(&hash_token(token))
+            .unwrap()
+            .id
+    };
+    let laptop_session = session_id(&laptop_token);
+    let phone_session = session_id(&phone_token);
+
+    phone.send(MessageType::SessionsRequest()).await;
+
+    let message = phone.receive().await;
+    let MessageType::SessionsResponse(sessions) = message.message else {
+        panic!("unexpected sessions response: {:?}", message.message);
+    };
+    let mut sessions: Vec<(i32, bool)> = sessions
+        .iter()
+        .map(|session| (session.id, session.current))
+        .collect();
+    sessions.sort();
+
+    assert_eq!(
+        sessions,
+        vec![(laptop_session, false), (phone_session, true)]
+    );
+
+    phone
+        .send(MessageType::SessionRevokeRequest(phone_session))
+        .await;
+    phone
+        .expect(
+            MessageType::SessionRevokeResponse(Err(
+                "Current session can not be revoked, log out instead.".to_string(),
+            )),
+            &alice,
+        )
+        .await;
+
+    // every connection logged in with revoked session is closed
+    phone
+        .send(MessageType::SessionRevokeRequest(laptop_session))
+        .await;
+    phone
+        .expect(MessageType::SessionRevokeResponse(Ok(())), &alice)
+        .await;
+
+    for client in [&mut laptop, &mut tablet] {
+        client
+            .expect(
+                MessageType::UnrecoverableError(
+                    "You were disconnected: Your session was revoked.".to_string(),
+                ),
+                &UserInfo::default(),
+            )
+            .await;
+    }
+
+    server.wait_for_clients(1).await;
+
+    phone.send(MessageType::LogoutRequest()).await;
+
+    // neither revoked nor logged out session can be used again, logged out client stays connected
+    for token in [laptop_token, phone_token] {
+        phone.send(MessageType::TokenLoginRequest(token)).await;
+        phone
+            .expect(
+                MessageType::LoginResponse(Err(INVALID_SESSION.to_string())),
+                &anonymous(),
+            )
+            .await;
+    }
+
+    assert!(server.store.sessions_of_user(alice.id).unwrap().is_empty());
+    assert!(server.hub.presence().await.is_empty());
+
+    // logged out client can log in again on the same connection
+    phone.login(&alice).await;
+
+    server.wait_for_clients(1).await;
+    assert_eq!(server.store.sessions_of_user(alice.id).unwrap().len(), 1);
+
+    phone.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn roles_limit_moderation_and_uploads() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    server.store.set_user_role(alice.id, Role::Admin).unwrap();
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    let action_response = |result: Result<&str, &str>| {
+        MessageType::ActionResponse(result.map(str::to_string).map_err(str::to_string))
+    };
+
+    // members can not moderate
+    clients[1]
+        .send(MessageType::KickRequest("carol".to_string()))
+        .await;
+    clients[1]
+        .expect(
+            action_response(Err("You are not allowed to kick users.")),
+            &bob,
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::RoleChangeRequest(
+            "bob".to_string(),
+            Role::Moderator,
+        ))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("Role of bob is now moderator.")), &alice)
+        .await;
+
+    // moderator can kick only users with lower role
+    clients[1]
+        .send(MessageType::KickRequest("alice".to_string()))
+        .await;
+    clients[1]
+        .expect(
+            action_response(Err("You can not do this to alice with role admin.")),
+            &bob,
+        )
+        .await;
+
+    clients[1]
+        .send(MessageType::KickRequest("carol".to_string()))
+        .await;
+    clients[1]
+        .expect(
+            action_response(Ok("carol was kicked (1 connections).")),
+            &bob,
+        )
+        .await;
+    clients[2]
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: You were kicked by bob.".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+
+    server.wait_for_clients(2).await;
+
+    // guests can not upload files
+    clients[0]
+        .send(MessageType::RoleChangeRequest(
+            "bob".to_string(),
+            Role::Guest,
+        ))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("Role of bob is now guest.")), &alice)
+        .await;
+
+    clients[1].send(MessageType::Image(vec![1, 2, 3])).await;
+    clients[1]
+        .expect(
+            action_response(Err("You are not allowed to upload files.")),
+            &bob,
+        )
+        .await;
+
+    assert_eq!(
+        server.store.user_by_id(carol.id).unwrap().role(),
+        Role::Member
+    );
+
+    clients[0].expect_nothing().await;
+    clients[1].expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn sanctions_block_posting_and_login_until_lifted() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    server.store.set_user_role(alice.id, Role::Admin).unwrap();
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    let action_response = |result: Result<&str, &str>| {
+        MessageType::ActionResponse(result.map(str::to_string).map_err(str::to_string))
+    };
+
+    // muted user is told why and his messages are not relayed
+    clients[0]
+        .send(MessageType::MuteRequest(
+            "bob".to_string(),
+            None,
+            "spam".to_string(),
+        ))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("bob was muted permanently.")), &alice)
+        .await;
+    clients[1]
+        .expect(
+            MessageType::RecoverableError("You are muted: spam (permanent).".to_string()),
+            &UserInfo::default(),
+        )
+        .await;
+
+    clients[1]
+        .send(MessageType::Text("Hello".to_string()))
+        .await;
+    clients[1]
+        .expect(
+            action_response(Err("You are muted: spam (permanent).")),
+            &bob,
+        )
+        .await;
+    clients[2].expect_nothing().await;
+
+    clients[0]
+        .send(MessageType::UnmuteRequest("bob".to_string()))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("bob was unmuted.")), &alice)
+        .await;
+
+    clients[1]
+        .send(MessageType::Text("Hello".to_string()))
+        .await;
+    clients[0]
+        .expect(MessageType::Text("Hello".to_string()), &bob)
+        .await;
+    clients[2]
+        .expect(MessageType::Text("Hello".to_string()), &bob)
+        .await;
+
+    // banned user is kicked and can not log in again
+    clients[0]
+        .send(MessageType::BanRequest(
+            "carol".to_string(),
+            None,
+            "trolling".to_string(),
+        ))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("carol was banned permanently.")), &alice)
+        .await;
+    clients[2]
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: You are banned: trolling (permanent).".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+
+    server.wait_for_clients(2).await;
+
+    let mut carol_client = server.connect().await;
+
+    carol_client
+        .send(MessageType::LoginRequest(
+            "carol".to_string(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+    carol_client
+        .expect(
+            MessageType::LoginResponse(Err("You are banned: trolling (permanent).".to_string())),
+            &anonymous(),
+        )
+        .await;
+
+    // response does not tell whether password is correct
+    carol_client
+        .send(MessageType::LoginRequest(
+            "carol".to_string(),
+            "wrong password".to_string(),
+        ))
+        .await;
+    carol_client
+        .expect(
+            MessageType::LoginResponse(Err("You are banned: trolling (permanent).".to_string())),
+            &anonymous(),
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::UnbanRequest("carol".to_string()))
+        .await;
+    clients[0]
+        .expect(action_response(Ok("carol was unbanned.")), &alice)
+        .await;
+    clients[0]
+        .send(MessageType::UnbanRequest("carol".to_string()))
+        .await;
+    clients[0]
+        .expect(action_response(Err("carol is not banned.")), &alice)
+        .await;
+
+    // expired sanction is not applied
+    server
+        .store
+        .insert_sanction(SanctionNew {
+            kind: SanctionKind::Ban.as_str().to_string(),
+            user_id: Some(carol.id),
+            ip: None,
+            reason: "expired".to_string(),
+            created_by: Some(alice.id),
+            expires_at: Some(SystemTime::now()),
+        })
+        .unwrap();
+
+    carol_client.login(&carol).await;
+    clients[0].expect(MessageType::UserConnect(), &carol).await;
+    clients[1].expect(MessageType::UserConnect(), &carol).await;
+
+    // banned IP address can not register
+    sanctions::create(
+        server.store.as_ref(),
+        SanctionKind::Ban,
+        None,
+        Some(server.addr.ip()),
+        None,
+        "",
+        None,
+    )
+    .unwrap();
+
+    let mut dave_client = server.connect().await;
+
+    dave_client
+        .send(MessageType::RegisterRequest(
+            "dave".to_string(),
+            TEST_PASSWORD.to_string(),
+            1,
+            2,
+            3,
+        ))
+        .await;
+    dave_client
+        .expect(
+            MessageType::RegisterResponse(Err(
+                "You are banned: no reason given (permanent).".to_string()
+            )),
+            &anonymous(),
+        )
+        .await;
+
+    assert_eq!(server.store.delete_expired_sanctions().unwrap(), 1);
+    assert_eq!(server.store.sanctions().unwrap().len(), 1);
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+
+    carol_client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn too_long_sanctions_are_refused() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    server.store.set_user_role(alice.id, Role::Admin).unwrap();
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    let refusal = "Sanction can last at most 36500 days, longer one has to be permanent.";
+
+    // `.ban bob 18446744073709551615s` in chat
+    clients[0]
+        .send(MessageType::BanRequest(
+            "bob".to_string(),
+            Some(Duration::from_secs(u64::MAX)),
+            String::new(),
+        ))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::ActionResponse(Err(refusal.to_string())),
+            &alice,
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::MuteRequest(
+            "bob".to_string(),
+            Some(sanctions::MAX_SANCTION_DURATION + Duration::from_secs(1)),
+            String::new(),
+        ))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::ActionResponse(Err(refusal.to_string())),
+            &alice,
+        )
+        .await;
+
+    // admin API
+    let (status, body) = server
+        .admin_request(
+            "POST",
+            "/sanctions",
+            Some(serde_json::json!({
+                "kind": "ban",
+                "user_id": bob.id,
+                "duration_secs": u64::MAX,
+            })),
+            None,
+        )
+        .await;
+
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], refusal);
+
+    assert!(server.store.sanctions().unwrap().is_empty());
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn admin_api_manages_users_sessions_and_messages() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    // requests without correct token are refused
+    for token in [Some(""), Some("wrong-token"), Some("admin-token-")] {
+        let (status, body) = server.admin_request("GET", "/users", None, token).await;
+
+        assert_eq!(status, 401);
+        assert_eq!(body["error"], "Invalid admin token.");
+    }
+
+    let (status, users) = server
+        .admin_request("GET", "/users", None, Some(TEST_ADMIN_TOKEN))
+        .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(users.as_array().unwrap().len(), 2);
+
+    // kicked user is disconnected, but can log in again
+    let (status, sessions) = server.admin_request("GET", "/sessions", None, None).await;
+
+    assert_eq!(status, 200);
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+
+    let (status, body) = server
+        .admin_request("POST", &format!("/users/{}/kick", bob.id), None, None)
+        .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["kicked"], 1);
+
+    clients[1]
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: You were kicked by administrator.".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+
+    server.wait_for_clients(1).await;
+
+    let mut bob_client = server.connect().await;
+    let token = bob_client.login(&bob).await;
+    clients[0].expect(MessageType::UserConnect(), &bob).await;
+
+    // disabled user is disconnected and neither password nor token logs him in
+    let (status, body) = server
+        .admin_request("POST", &format!("/users/{}/disable", bob.id), None, None)
+        .await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["disabled"], true);
+
+    bob_client
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: Your account was disabled.".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+
+    server.wait_for_clients(1).await;
+
+    let mut bob_client = server.connect().await;
+    bob_client
+        .send(MessageType::LoginRequest(
+            "bob".to_string(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+    bob_client
+        .expect(
+            MessageType::LoginResponse(Err(INVALID_LOGIN.to_string())),
+            &anonymous(),
+        )
+        .await;
+    bob_client.send(MessageType::TokenLoginRequest(token)).await;
+    bob_client
+        .expect(
+            MessageType::LoginResponse(Err(INVALID_SESSION.to_string())),
+            &anonymous(),
+        )
+        .await;
+
+    // deleted message is not listed any more
+    clients[0]
+        .send(MessageType::Text("Hello".to_string()))
+        .await;
+    bob_client
+        .expect(MessageType::Text("Hello".to_string()), &alice)
+        .await;
+    server.wait_for_stored_messages(1).await;
+
+    let (status, messages) = server.admin_request("GET", "/messages", None, None).await;
+
+    assert_eq!(status, 200);
+    assert_eq!(messages[0]["text"], "Hello");
+    assert_eq!(messages[0]["user_id"], alice.id);
+
+    let path = format!("/messages/{}", messages[0]["id"]);
+    let (status, _) = server.admin_request("DELETE", &path, None, None).await;
+
+    assert_eq!(status, 204);
+    assert!(server.store.recent_messages(10).unwrap().is_empty());
+
+    let (status, body) = server.admin_request("DELETE", &path, None, None).await;
+
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "Not found.");
+
+    clients[0].expect_nothing().await;
+    bob_client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn direct_messages_are_encrypted_and_sent_only_to_recipient() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    let mut clients = server.login_all(&[&alice, &bob, &carol]).await;
+
+    let alice_keys = KeyPair::generate();
+    let bob_keys = KeyPair::generate();
+
+    clients[1]
+        .send(MessageType::PublicKeyPublish(
+            bob_keys.public_key().to_vec(),
+        ))
+        .await;
+    clients[1]
+        .expect(
+            MessageType::ActionResponse(Ok("Public key was published.".to_string())),
+            &bob,
+        )
+        .await;
+
+    // key of recipient is requested by username
+    clients[0]
+        .send(MessageType::PublicKeyRequest("BOB".to_string()))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::PublicKeyResponse("bob".to_string(), Ok(bob_keys.public_key().to_vec())),
+            &alice,
+        )
+        .await;
+
+    clients[0]
+        .send(MessageType::PublicKeyRequest("carol".to_string()))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::PublicKeyResponse(
+                "carol".to_string(),
+                Err("carol has not published public key yet.".to_string()),
+            ),
+            &alice,
+        )
+        .await;
+
+    let (nonce, ciphertext) = alice_keys
+        .encrypt(&bob_keys.public_key(), b"Hello Bob")
+        .unwrap();
+    let encrypted = EncryptedMessage {
+        recipient: "bob".to_string(),
+        recipient_id: 0,
+        sender_key: alice_keys.public_key().to_vec(),
+        recipient_key: bob_keys.public_key().to_vec(),
+        nonce,
+        ciphertext,
+    };
+
+    clients[0]
+        .send(MessageType::DirectMessage(encrypted.clone()))
+        .await;
+
+    // only recipient gets message and can decrypt it
+    let message = clients[1].receive().await;
+    let MessageType::DirectMessage(received) = message.message else {
+        panic!("direct message expected, got {}", message.message.name());
+    };
+
+    assert_eq!(message.user_info, alice);
+    assert_eq!(received.recipient_id, bob.id);
+    assert_eq!(
+        bob_keys
+            .decrypt(&received.sender_key, &received.nonce, &received.ciphertext)
+            .unwrap(),
+        b"Hello Bob"
+    );
+
+    clients[0].expect_nothing().await;
+    clients[2].expect_nothing().await;
+
+    // server stores only ciphertext
+    let stored = server.store.direct_messages(bob.id).unwrap();
+
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].sender_id, Some(alice.id));
+    assert_eq!(stored[0].ciphertext, encrypted.ciphertext);
+    assert_ne!(stored[0].ciphertext, b"Hello Bob");
+
+    // unknown recipient and muted sender are refused
+    clients[0]
+        .send(MessageType::DirectMessage(EncryptedMessage {
+            recipient: "dave".to_string(),
+            ..encrypted.clone()
+        }))
+        .await;
+    clients[0]
+        .expect(
+            MessageType::ActionResponse(Err("User 'dave' does not exist.".to_string())),
+            &alice,
+        )
+        .await;
+
+    sanctions::create(
+        server.store.as_ref(),
+        SanctionKind::Mute,
+        Some(alice.id),
+        None,
+        None,
+        "spam",
+        None,
+    )
+    .unwrap();
+
+    clients[0].send(MessageType::DirectMessage(encrypted)).await;
+    clients[0]
+        .expect(
+            MessageType::ActionResponse(Err("You are muted: spam (permanent).".to_string())),
+            &alice,
+        )
+        .await;
+
+    for client in clients.iter_mut() {
+        client.expect_nothing().await;
+    }
+
+    assert_eq!(server.store.direct_messages(bob.id).unwrap().len(), 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn offline_users_get_direct_messages_and_mentions_at_next_login() {
+    let server = TestServer::start().await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let carol = server.add_user("carol", (0, 255, 0));
+    let mut clients = server.login_all(&[&alice, &carol]).await;
+
+    let alice_keys = KeyPair::generate();
+    let bob_keys = KeyPair::generate();
+
+    server
+        .store
+        .set_public_key(bob.id, &bob_keys.public_key())
+        .unwrap();
+
+    // bob is offline, so direct message and mention are queued for him
+    let (nonce, ciphertext) = alice_keys
+        .encrypt(&bob_keys.public_key(), b"Call me")
+        .unwrap();
+    let encrypted = EncryptedMessage {
+        recipient: "bob".to_string(),
+        recipient_id: 0,
+        sender_key: alice_keys.public_key().to_vec(),
+        recipient_key: bob_keys.public_key().to_vec(),
+        nonce,
+        ciphertext,
+    };
+
+    clients[0]
+        .send(MessageType::DirectMessage(encrypted.clone()))
+        .await;
+    clients[0]
+        .send(MessageType::Text("@bob @carol look at this!".to_string()))
+        .await;
+    clients[1]
+        .expect(
+            MessageType::Text("@bob @carol look at this!".to_string()),
+            &alice,
+        )
+        .await;
+
+    server.wait_for_pending_deliveries(bob.id, 2).await;
+
+    // online carol is not queued
+    assert!(server
+        .store
+        .pending_deliveries(carol.id)
+        .unwrap()
+        .is_empty());
+
+    let mut bob_client = server.connect().await;
+
+    bob_client.login(&bob).await;
+
+    let message = bob_client.receive().await;
+    let MessageType::OfflineMessages(messages) = message.message else {
+        panic!("offline messages expected, got {}", message.message.name());
+    };
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].user_info, alice);
+    assert_eq!(
+        messages[0].message,
+        MessageType::DirectMessage(EncryptedMessage {
+            recipient_id: bob.id,
+            ..encrypted
+        })
+    );
+    assert_eq!(messages[1].user_info, alice);
+    assert_eq!(
+        messages[1].message,
+        MessageType::Text("@bob @carol look at this!".to_string())
+    );
+
+    for client in clients.iter_mut() {
+        client.expect(MessageType::UserConnect(), &bob).await;
+    }
+
+    // sent messages are marked as delivered and not sent again
+    server.wait_for_pending_deliveries(bob.id, 0).await;
+
+    bob_client.send(MessageType::UserDisconnect()).await;
+
+    for client in clients.iter_mut() {
+        client.expect(MessageType::UserDisconnect(), &bob).await;
+    }
+
+    server.wait_for_clients(2).await;
+
+    let mut bob_client = server.connect().await;
+
+    bob_client.login(&bob).await;
+    bob_client.expect_nothing().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn cluster_instances_share_messages_presence_and_kicks() {
+    let mut servers = TestServer::start_cluster(2).await;
+    let alice = servers[0].add_user("alice", (255, 0, 0));
+    let bob = servers[1].add_user("bob", (0, 0, 255));
+    servers[0]
+        .store
+        .set_user_role(alice.id, Role::Moderator)
+        .unwrap();
+
+    let mut alice_client = servers[0].connect().await;
+    alice_client.login(&alice).await;
+
+    let mut bob_client = servers[1].connect().await;
+    bob_client.login(&bob).await;
+
+    // user connected to other instance is announced too
+    alice_client.expect(MessageType::UserConnect(), &bob).await;
+
+    alice_client
+        .send(MessageType::Text("hi from first instance".to_string()))
+        .await;
+    bob_client
+        .expect(
+            MessageType::Text("hi from first instance".to_string()),
+            &alice,
+        )
+        .await;
+
+    // presence lists users of all instances
+    servers[0].wait_for_remote_presence(1).await;
+    servers[1].wait_for_remote_presence(1).await;
+
+    bob_client.send(MessageType::PresenceRequest()).await;
+
+    let message = bob_client.receive().await;
+    let MessageType::PresenceResponse(presence) = message.message else {
+        panic!("presence expected, got {}", message.message.name());
+    };
+    let mut users: Vec<UserInfo> = presence.into_iter().map(|p| p.user_info).collect();
+    users.sort_by_key(|user| user.id);
+
+    assert_eq!(users, vec![alice.clone(), bob.clone()]);
+
+    // moderator on first instance kicks user connected to second one
+    alice_client
+        .send(MessageType::KickRequest("bob".to_string()))
+        .await;
+    alice_client
+        .expect(
+            MessageType::ActionResponse(Ok("bob was kicked (1 connections).".to_string())),
+            &alice,
+        )
+        .await;
+    bob_client
+        .expect(
+            MessageType::UnrecoverableError(
+                "You were disconnected: You were kicked by alice.".to_string(),
+            ),
+            &UserInfo::default(),
+        )
+        .await;
+
+    servers[1].wait_for_clients(0).await;
+    servers[0].wait_for_remote_presence(0).await;
+
+    alice_client.expect_nothing().await;
+
+    for server in servers.drain(..) {
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn slow_client_is_disconnected_without_stalling_others() {
+    let mut config = Config::default();
+    config.limits.outbound_queue_capacity = 4;
+    config.limits.slow_client = SlowClientPolicy::Disconnect;
+
+    let server =
+        TestServer::start_with_config(config, Plugins::from_config(&Default::default())).await;
+    let alice = server.add_user("alice", (255, 0, 0));
+    let bob = server.add_user("bob", (0, 0, 255));
+    let mut clients = server.login_all(&[&alice, &bob]).await;
+
+    // bob stops reading, images fill his socket buffers and then his queue
+    for _ in 0..32 {
+        clients[0]
+            .send(MessageType::Image(vec![0; 1024 * 1024]))
+            .await;
+    }
+
+    server.wait_for_clients(1).await;
+
+    // alice is served while bob's writer is stuck
+    clients[0].send(MessageType::PresenceRequest()).await;
+
+    let message = clients[0].receive().await;
+    let MessageType::PresenceResponse(presence) = message.message else {
+        panic!("presence expected, got {}", message.message.name());
+    };
+    let users: Vec<UserInfo> = presence.into_iter().map(|p| p.user_info).collect();
+
+    assert_eq!(users, vec![alice]);
+
+    clients[0].expect_nothing().await;
+
+    server.stop().await;
+}
@@ -0,0 +1,577 @@
+//! Harness for integration tests.
+//!
+//! `TestServer` runs `handle_new_clients`, `handle_new_websocket_clients`,
+//! `handle_saving_messages_to_database`, `handle_cluster_events` and `handle_admin_requests`
+//! in-process on ephemeral ports with `MemoryStore`, `TestClient` is scripted client connected to
+//! it over tcp and `TestWebSocketClient` over WebSocket. Instances started by
+//! `TestServer::start_cluster` share one store and `MemoryPubSub`.
+
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WebSocketMessage, MaybeTlsStream, WebSocketStream,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::{sleep, timeout, Instant},
+};
+
+use database::{
+    pubsub::memory::MemoryPubSub,
+    store::{memory::MemoryStore, ChatStore},
+};
+use libs::{
+    builder::MessageReceiverSenderBuilder,
+    message::{Message, MessageType, UserInfo, PROTOCOL_VERSION},
+    password::{set_password_cost, PasswordCost},
+    receiver::MessageReceiver,
+    sender::MessageSender,
+};
+use server::{
+    admin::handle_admin_requests,
+    cluster::{handle_cluster_events, Cluster},
+    config::Config,
+    handle_new_clients, handle_saving_messages_to_database,
+    hub::Hub,
+    lockout::LoginGuard,
+    metrics::Metrics,
+    moderation::Moderation,
+    plugins::Plugins,
+    websocket::handle_new_websocket_clients,
+    ServerContext,
+};
+
+/// Password of users created by `TestServer::add_user`.
+pub const TEST_PASSWORD: &str = "password";
+
+/// Token of admin API of every `TestServer`.
+pub const TEST_ADMIN_TOKEN: &str = "admin-token";
+
+/// Legacy PBKDF2 hash of `TEST_PASSWORD` with low number of rounds, verifying it is fast (it is
+/// replaced by Argon2id hash at first login).
+const TEST_PASSWORD_HASH: &str =
+    "$pbkdf2-sha256$i=1000,l=32$dGVzdHNhbHR0ZXN0c2FsdA$RB2dpfVEWj2VJoCXbPbSQzxcn+20ILr/HCSkvD7/EF8";
+
+/// Cheapest cost of password hashing, full cost would make every registration slow.
+const TEST_PASSWORD_COST: PasswordCost = PasswordCost {
+    memory_kib: 8,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// How long client waits for expected message.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long client waits to be sure that no other message arrives.
+const SILENCE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How often instances of cluster publish their presence.
+const PRESENCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Author of messages sent by clients that are not logged in.
+pub fn anonymous() -> UserInfo {
+    UserInfo {
+        id: 0,
+        username: "<anonymous user>".to_string(),
+        color: (255, 255, 255),
+    }
+}
+
+/// Server running in-process, it is stopped by `TestServer::stop`.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub websocket_addr: SocketAddr,
+    pub admin_addr: SocketAddr,
+    pub store: Arc<MemoryStore>,
+    pub hub: Hub,
+    pub cluster: Arc<Cluster>,
+    tx: broadcast::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts server with default configuration (and built-in plugins) on ephemeral port.
+    pub async fn start() -> TestServer {
+        TestServer::start_with_plugins(Plugins::from_config(&Config::default().plugins)).await
+    }
+
+    /// Starts server with default configuration and given plugins on ephemeral port.
+    pub async fn start_with_plugins(plugins: Plugins) -> TestServer {
+        TestServer::start_with_config(Config::default(), plugins).await
+    }
+
+    /// Starts server with given configuration and plugins on ephemeral port (network section and
+    /// admin token of configuration are ignored).
+    pub async fn start_with_config(config: Config, plugins: Plugins) -> TestServer {
+        TestServer::start_instance(
+            config,
+            plugins,
+            Arc::new(MemoryStore::default()),
+            Arc::new(Cluster::standalone()),
+        )
+        .await
+    }
+
+    /// Starts `count` instances with default configuration that share one store and relay events
+    /// to each other.
+    pub async fn start_cluster(count: usize) -> Vec<TestServer> {
+        let store = Arc::new(MemoryStore::default());
+        let pubsub = Arc::new(MemoryPubSub::default());
+        let mut servers = vec![];
+
+        for _ in 0..count {
+            let cluster = Arc::new(Cluster::new(pubsub.clone(), PRESENCE_INTERVAL));
+
+            servers.push(
+                TestServer::start_instance(
+                    Config::default(),
+                    Plugins::from_config(&Config::default().plugins),
+                    store.clone(),
+                    cluster,
+                )
+                .await,
+            );
+        }
+
+        servers
+    }
+
+    async fn start_instance(
+        mut config: Config,
+        plugins: Plugins,
+        store: Arc<MemoryStore>,
+        cluster: Arc<Cluster>,
+    ) -> TestServer {
+        // cost is global, so it is same for all tests and configured cost is ignored
+        set_password_cost(TEST_PASSWORD_COST).unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let websocket_listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let websocket_addr = websocket_listener.local_addr().unwrap();
+
+        let admin_listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let admin_addr = admin_listener.local_addr().unwrap();
+
+        config.features.admin_token = Some(TEST_ADMIN_TOKEN.to_string());
+
+        let hub = Hub::start();
+        let metrics = Arc::new(Metrics::default());
+        let login_guard = Arc::new(LoginGuard::new(config.auth.clone()));
+        let config = Arc::new(config);
+        let plugins = Arc::new(plugins);
+
+        let (tx, _) = broadcast::channel(8);
+        let (msg_db_tx, msg_db_rx) = mpsc::channel(64);
+
+        tokio::spawn(handle_saving_messages_to_database(
+            msg_db_rx,
+            metrics.clone(),
+            store.clone(),
+        ));
+
+        {
+            let cluster = cluster.clone();
+            let hub = hub.clone();
+            let metrics = metrics.clone();
+            let mut tx = tx.clone();
+
+            tokio::spawn(async move {
+                handle_cluster_events(cluster, hub, metrics, &mut tx).await;
+            });
+        }
+
+        {
+            let hub = hub.clone();
+            let cluster = cluster.clone();
+            let config = config.clone();
+            let store = store.clone();
+            let moderation = Arc::new(Moderation::new(&config.moderation).unwrap());
+            let mut tx = tx.clone();
+
+            tokio::spawn(async move {
+                handle_admin_requests(
+                    admin_listener,
+                    hub,
+                    cluster,
+                    config,
+                    store,
+                    moderation,
+                    &mut tx,
+                )
+                .await;
+            });
+        }
+
+        let context = ServerContext {
+            hub: hub.clone(),
+            shutdown: tx.clone(),
+            msg_db_tx,
+            metrics,
+            config,
+            store: store.clone(),
+            login_guard,
+            plugins,
+            cluster: cluster.clone(),
+        };
+
+        tokio::spawn(handle_new_websocket_clients(
+            websocket_listener,
+            context.clone(),
+        ));
+
+        let handle = tokio::spawn(handle_new_clients(listener, context));
+
+        TestServer {
+            addr,
+            websocket_addr,
+            admin_addr,
+            store,
+            hub,
+            cluster,
+            tx,
+            handle,
+        }
+    }
+
+    /// Inserts user with password `TEST_PASSWORD` directly into the store.
+    pub fn add_user(&self, username: &str, color: (u8, u8, u8)) -> UserInfo {
+        let user = self
+            .store
+            .create_user(username, TEST_PASSWORD_HASH, color)
+            .unwrap();
+
+        UserInfo {
+            id: user.id,
+            username: user.username,
+            color,
+        }
+    }
+
+    /// Connects new client and waits until server registers it.
+    pub async fn connect(&self) -> TestClient {
+        let count = self.hub.clients().await.len();
+        let client = TestClient::connect(self.addr).await;
+
+        self.wait_until("client is connected", || async {
+            self.hub.clients().await.len() > count
+        })
+        .await;
+
+        client
+    }
+
+    /// Connects new WebSocket client and waits until server registers it.
+    pub async fn connect_websocket(&self) -> TestWebSocketClient {
+        let count = self.hub.clients().await.len();
+        let (stream, _) = connect_async(format!("ws://{}/", self.websocket_addr))
+            .await
+            .unwrap();
+
+        self.wait_until("WebSocket client is connected", || async {
+            self.hub.clients().await.len() > count
+        })
+        .await;
+
+        let mut client = TestWebSocketClient { stream };
+        client
+            .send(MessageType::ClientHello(PROTOCOL_VERSION))
+            .await;
+
+        client
+    }
+
+    /// Connects and logs in every user, earlier clients are notified about every later one (these
+    /// notifications are already consumed).
+    pub async fn login_all(&self, users: &[&UserInfo]) -> Vec<TestClient> {
+        let mut clients: Vec<TestClient> = vec![];
+
+        for user_info in users {
+            let mut client = self.connect().await;
+
+            client.login(user_info).await;
+
+            for other in clients.iter_mut() {
+                other.expect(MessageType::UserConnect(), user_info).await;
+            }
+
+            clients.push(client);
+        }
+
+        clients
+    }
+
+    /// Sends request to admin API with `TEST_ADMIN_TOKEN` (or with given token when it is
+    /// `Some`), returns status code and JSON body (`null` when body is empty).
+    pub async fn admin_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+        token: Option<&str>,
+    ) -> (u16, serde_json::Value) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            self.admin_addr,
+            token.unwrap_or(TEST_ADMIN_TOKEN),
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect(self.admin_addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        timeout(RECEIVE_TIMEOUT, stream.read_to_string(&mut response))
+            .await
+            .expect("no admin response received")
+            .unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = match body.is_empty() {
+            true => serde_json::Value::Null,
+            false => serde_json::from_str(body).unwrap(),
+        };
+
+        (status, body)
+    }
+
+    /// Waits until database writer stores `count` messages.
+    pub async fn wait_for_stored_messages(&self, count: usize) {
+        self.wait_until("messages are stored", || async {
+            self.store.messages(None, i64::MAX).unwrap().len() >= count
+        })
+        .await;
+    }
+
+    /// Waits until user has `count` messages queued for delivery at his next login.
+    pub async fn wait_for_pending_deliveries(&self, user_id: i32, count: usize) {
+        self.wait_until("deliveries are queued", || async {
+            self.store.pending_deliveries(user_id).unwrap().len() >= count
+        })
+        .await;
+    }
+
+    /// Waits until server forgets all disconnected clients and has exactly `count` clients.
+    pub async fn wait_for_clients(&self, count: usize) {
+        self.wait_until("clients are removed", || async {
+            self.hub.clients().await.len() == count
+        })
+        .await;
+    }
+
+    /// Waits until presence of other instances contains `count` logged in clients.
+    pub async fn wait_for_remote_presence(&self, count: usize) {
+        self.wait_until("presence of other instances is received", || async {
+            self.cluster.remote_presence().len() == count
+        })
+        .await;
+    }
+
+    /// Sends termination signal and waits until all tasks handling clients end.
+    pub async fn stop(self) {
+        self.tx.send(true).unwrap();
+
+        timeout(RECEIVE_TIMEOUT, self.handle)
+            .await
+            .expect("server did not stop")
+            .unwrap();
+    }
+
+    async fn wait_until<F, Fut>(&self, what: &str, condition: F)
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+
+        while !condition().await {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Scripted client, every received message has to be consumed by `expect` (or other method
+/// receiving messages) and `expect_nothing` checks that there are no other messages.
+pub struct TestClient {
+    message_receiver: MessageReceiver,
+    message_sender: MessageSender,
+}
+
+impl TestClient {
+    /// Connects and sends supported protocol version like real client.
+    pub async fn connect(addr: SocketAddr) -> TestClient {
+        let mut client = TestClient::connect_without_hello(addr).await;
+        client
+            .send(MessageType::ClientHello(PROTOCOL_VERSION))
+            .await;
+
+        client
+    }
+
+    /// Connects without sending protocol version, like clients older than `ClientHello`.
+    pub async fn connect_without_hello(addr: SocketAddr) -> TestClient {
+        let builder = MessageReceiverSenderBuilder::from_socket_addr(addr)
+            .await
+            .unwrap();
+
+        TestClient {
+            message_receiver: builder.message_receiver(),
+            message_sender: builder.message_sender(),
+        }
+    }
+
+    pub async fn send(&mut self, message_type: MessageType) {
+        self.send_message(&Message::from(message_type)).await;
+    }
+
+    /// Sends whole message, so its author can be forged.
+    pub async fn send_message(&mut self, message: &Message) {
+        self.message_sender.send_message(message).await.unwrap();
+    }
+
+    /// Returns next received message, panics when nothing arrives in time.
+    pub async fn receive(&mut self) -> Message {
+        timeout(RECEIVE_TIMEOUT, self.message_receiver.receive_message())
+            .await
+            .expect("no message received")
+            .unwrap()
+    }
+
+    /// Receives next message and checks its type (with data) and author.
+    pub async fn expect(&mut self, message_type: MessageType, user_info: &UserInfo) {
+        let message = self.receive().await;
+
+        assert_eq!(message.message, message_type);
+        assert_eq!(&message.user_info, user_info);
+    }
+
+    /// Checks that server sent nothing else (closed connection is fine).
+    pub async fn expect_nothing(&mut self) {
+        if let Ok(Ok(message)) =
+            timeout(SILENCE_TIMEOUT, self.message_receiver.receive_message()).await
+        {
+            panic!("unexpected message received: {:?}", message);
+        }
+    }
+
+    /// Logs in user created by `TestServer::add_user`, returns token of new session.
+    pub async fn login(&mut self, user_info: &UserInfo) -> String {
+        self.send(MessageType::LoginRequest(
+            user_info.username.clone(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+
+        self.expect_login(user_info).await
+    }
+
+    /// Receives successful login response of user, returns token of session.
+    pub async fn expect_login(&mut self, user_info: &UserInfo) -> String {
+        let message = self.receive().await;
+
+        assert_eq!(&message.user_info, user_info);
+
+        match message.message {
+            MessageType::LoginResponse(Ok((logged_in, token))) => {
+                assert_eq!(&logged_in, user_info);
+
+                token
+            }
+            other => panic!("unexpected login response: {:?}", other),
+        }
+    }
+}
+
+/// Scripted browser client, every frame is one JSON encoded message.
+pub struct TestWebSocketClient {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestWebSocketClient {
+    pub async fn send(&mut self, message_type: MessageType) {
+        let json = serde_json::to_string(&Message::from(message_type)).unwrap();
+
+        self.send_text(json).await;
+    }
+
+    /// Sends text frame as it is, so it can contain invalid JSON.
+    pub async fn send_text(&mut self, text: String) {
+        self.stream
+            .send(WebSocketMessage::Text(text))
+            .await
+            .unwrap();
+    }
+
+    /// Returns next received message, panics when nothing arrives in time.
+    pub async fn receive(&mut self) -> Message {
+        loop {
+            let frame = timeout(RECEIVE_TIMEOUT, self.stream.next())
+                .await
+                .expect("no message received")
+                .expect("connection closed")
+                .unwrap();
+
+            if let WebSocketMessage::Text(json) = frame {
+                return serde_json::from_str(&json).unwrap();
+            }
+        }
+    }
+
+    /// Receives next message and checks its type (with data) and author.
+    pub async fn expect(&mut self, message_type: MessageType, user_info: &UserInfo) {
+        let message = self.receive().await;
+
+        assert_eq!(message.message, message_type);
+        assert_eq!(&message.user_info, user_info);
+    }
+
+    /// Checks that server sent nothing else.
+    pub async fn expect_nothing(&mut self) {
+        if let Ok(Some(Ok(WebSocketMessage::Text(json)))) =
+            timeout(SILENCE_TIMEOUT, self.stream.next()).await
+        {
+            panic!("unexpected message received: {}", json);
+        }
+    }
+
+    /// Logs in user created by `TestServer::add_user`, returns token of new session.
+    pub async fn login(&mut self, user_info: &UserInfo) -> String {
+        self.send(MessageType::LoginRequest(
+            user_info.username.clone(),
+            TEST_PASSWORD.to_string(),
+        ))
+        .await;
+
+        let message = self.receive().await;
+
+        assert_eq!(&message.user_info, user_info);
+
+        match message.message {
+            MessageType::LoginResponse(Ok((logged_in, token))) => {
+                assert_eq!(&logged_in, user_info);
+
+                token
+            }
+            other => panic!("unexpected login response: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Tests of retention of messages, purge runs directly on `MemoryStore`.
+
+use std::{fs, time::SystemTime};
+
+use database::store::{memory::MemoryStore, ChatStore};
+use server::{
+    config::RetentionConfig,
+    retention::{purge_expired_messages, PurgeError},
+};
+
+#[test]
+fn messages_are_kept_until_archive_is_written() {
+    let store = MemoryStore::default();
+    let alice = store.create_user("alice", "<hash>", (255, 0, 0)).unwrap();
+
+    for text in ["first", "second", "third"] {
+        store.insert_message(alice.id, text).unwrap();
+    }
+
+    // directory does not exist yet, so archive can't be created
+    let directory = std::env::temp_dir().join(format!("retention-{}", std::process::id()));
+    let archive_path = directory.join("archive.jsonl");
+    let config = RetentionConfig {
+        max_messages: Some(1),
+        archive_path: Some(archive_path.clone()),
+        ..RetentionConfig::default()
+    };
+
+    let result = purge_expired_messages(&store, &config, SystemTime::now());
+
+    assert!(matches!(result, Err(PurgeError::Archive(_))));
+    assert_eq!(store.recent_messages(10).unwrap().len(), 3);
+
+    // next purge succeeds when archive is writable
+    fs::create_dir_all(&directory).unwrap();
+
+    let deleted = purge_expired_messages(&store, &config, SystemTime::now()).unwrap();
+    let archive = fs::read_to_string(&archive_path).unwrap();
+
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(deleted.len(), 2);
+    assert_eq!(archive.lines().count(), 2);
+    assert_eq!(store.recent_messages(10).unwrap()[0].text, "third");
+}